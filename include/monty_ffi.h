@@ -7,9 +7,55 @@
 #include <stdint.h>
 #include <stdlib.h>
 
+/**
+ * Values of `MontyStatus::code` on failure, exported to the header as
+ * `MONTY_ERROR_*`; the code is zero on success.
+ */
+enum MontyError {
+  /**
+   * A failure without a more specific code.
+   */
+  MONTY_ERROR_GENERIC = 1,
+  /**
+   * A sealed dump that failed authentication on load.
+   */
+  MONTY_ERROR_TAMPERED = 2,
+  /**
+   * A resume whose result doesn't match the call's declared return type under the
+   * `fail` return mismatch; the snapshot (and any object handle) is left untouched.
+   */
+  MONTY_ERROR_RETURN_MISMATCH = 3,
+};
+typedef int32_t MontyError;
+
+/**
+ * Values of `ProgressResult::kind`, exported to the header as `MONTY_PROGRESS_*`. The
+ * field itself stays an `int32_t`, since the size of a C enum is up to the compiler.
+ */
+enum MontyProgress {
+  MONTY_PROGRESS_COMPLETE = 0,
+  MONTY_PROGRESS_FUNCTION_CALL = 1,
+  MONTY_PROGRESS_OS_CALL = 2,
+  MONTY_PROGRESS_RESOLVE_FUTURES = 3,
+  MONTY_PROGRESS_INPUT_REQUEST = 4,
+  MONTY_PROGRESS_EMIT = 5,
+  MONTY_PROGRESS_SLEEP = 6,
+  MONTY_PROGRESS_PROMPT = 7,
+};
+typedef int32_t MontyProgress;
+
 typedef struct MontyStatus {
   int32_t ok;
   char *error;
+  /**
+   * Zero on success, otherwise one of the `MONTY_ERROR_*` codes.
+   */
+  int32_t code;
+  /**
+   * Nonzero when this call decoded code or a JSON payload with lossy UTF-8 and had
+   * to replace invalid bytes; see the `lossy_utf8` run option.
+   */
+  int32_t lossy;
 } MontyStatus;
 
 typedef struct MontyRunHandle {
@@ -24,6 +70,91 @@ typedef struct FutureSnapshotHandle {
   void *inner;
 } FutureSnapshotHandle;
 
+/**
+ * A run waiting on the host for a lazy input before it can start.
+ */
+typedef struct InputRequestHandle {
+  void *inner;
+} InputRequestHandle;
+
+/**
+ * Opaque, reference-counted set of named globals that can be attached to many runs.
+ */
+typedef struct GlobalsHandle {
+  void *inner;
+} GlobalsHandle;
+
+/**
+ * Opaque, reference-counted budget shared by every run attached to it.
+ */
+typedef struct QuotaGroupHandle {
+  void *inner;
+} QuotaGroupHandle;
+
+/**
+ * A Monty value owned by the host and passed by handle, so large values skip the
+ * JSON round-trip. Handles are reference-counted: `monty_object_retain` and staged
+ * run inputs share the value, and mutating a shared value copies it first.
+ */
+typedef struct MontyObjectHandle {
+  void *inner;
+} MontyObjectHandle;
+
+/**
+ * A cursor over an object handle's items, as Python iterates them (dict keys for
+ * dicts). It borrows the object, which must outlive it.
+ */
+typedef struct MontyIteratorHandle {
+  void *inner;
+} MontyIteratorHandle;
+
+/**
+ * A content-addressed store of snapshot dumps, created with `monty_store_new`.
+ */
+typedef struct StoreHandle {
+  void *inner;
+} StoreHandle;
+
+/**
+ * Raw bytes for the input referenced as `{"$input_bytes": "<name>"}` in a start's
+ * inputs array, where they become a `bytes` value without going through JSON.
+ */
+typedef struct MontyInputBytes {
+  const char *name;
+  const uint8_t *data;
+  size_t len;
+} MontyInputBytes;
+
+/**
+ * Receives the next chunk of a streamed dump. Returning non-zero aborts the dump.
+ */
+typedef int32_t (*MontyWriteCallback)(void *userdata, const uint8_t *data, size_t len);
+
+/**
+ * Transforms `data` (seals or opens a dump), passing the result to `emit(sink, ...)` in
+ * one or more pieces. Returning non-zero fails the call.
+ */
+typedef int32_t (*MontyTransformCallback)(void *userdata,
+                                          const uint8_t *data,
+                                          size_t len,
+                                          MontyWriteCallback emit,
+                                          void *sink);
+
+/**
+ * Answers an external or OS call for `monty_run_drive`. It receives the function name
+ * and the JSON-encoded args and kwargs, writes a JSON answer through `emit(sink, ...)`,
+ * and returns `MONTY_DRIVE_RETURN` when the answer is the call's value or
+ * `MONTY_DRIVE_RAISE` when it is a `$exception` object to raise in the script. Any
+ * other status aborts the run. The callback may call any `monty_*` function, e.g. to
+ * run a nested script, whose output is then its own rather than `print_callback`'s.
+ */
+typedef int32_t (*MontyCallCallback)(void *userdata,
+                                     const char *function_name,
+                                     const char *args_json,
+                                     const char *kwargs_json,
+                                     MontyWriteCallback emit,
+                                     void *sink);
+
 typedef struct ProgressResult {
   int32_t kind;
   char *result_json;
@@ -36,6 +167,87 @@ typedef struct ProgressResult {
   struct SnapshotHandle *snapshot;
   char *pending_call_ids_json;
   struct FutureSnapshotHandle *future_snapshot;
+  char *pending_calls_json;
+  char *stats_json;
+  /**
+   * Timeout configured for the reported call in milliseconds, or 0 for none.
+   */
+  uint64_t timeout_ms;
+  char *input_name;
+  struct InputRequestHandle *input_request;
+  /**
+   * Encoded `self` of a method call (the first of `args_json`), or NULL.
+   */
+  char *receiver_json;
+  /**
+   * With the `object_handles` run option, these replace `result_json`, `args_json`
+   * (as a list), and `kwargs_json` (as a dict). The caller owns and frees them.
+   */
+  struct MontyObjectHandle *result_object;
+  struct MontyObjectHandle *args_object;
+  struct MontyObjectHandle *kwargs_object;
+  /**
+   * Chrome trace of the run on completion, when it was started with `trace`.
+   */
+  char *trace_json;
+  /**
+   * Audit log of attempted OS calls on completion, when the run was started with
+   * `audit_os_calls`.
+   */
+  char *audit_json;
+  /**
+   * Byte lengths of `result_json`, `args_json`, and `kwargs_json`, for bindings that
+   * take strings as (pointer, length). JSON escapes NUL as `\u0000`, so a Python
+   * string holding one crosses intact; the strings are still freed as before.
+   */
+  size_t result_len;
+  size_t args_len;
+  size_t kwargs_len;
+  /**
+   * JSON array of non-fatal warnings gathered since the last progress result, such
+   * as dropped output or a limit nearly used up, or NULL when there are none.
+   */
+  char *warnings_json;
+  /**
+   * What the script printed since the last progress result, unless the run was
+   * started with `print_to_stdout`, or NULL when it printed nothing. Output is kept
+   * per run, so concurrent runs don't interleave; it is freed with the strings.
+   */
+  uint8_t *output;
+  size_t output_len;
+  /**
+   * How long a `MONTY_PROGRESS_SLEEP` asks the host to wait before resuming, in
+   * milliseconds.
+   */
+  uint64_t delay_ms;
+  /**
+   * Priority configured for the reported call with `call_priorities`; higher runs
+   * sooner. Pending call ids of `MONTY_PROGRESS_RESOLVE_FUTURES` are listed in
+   * priority order, highest first.
+   */
+  int32_t priority;
+  /**
+   * Nonzero when a `MONTY_PROGRESS_FUNCTION_CALL` is to a builtin the host overrides
+   * with `monty_run_override_builtins`, rather than a declared external function.
+   */
+  int32_t builtin_override;
+  /**
+   * Prompt of a `MONTY_PROGRESS_PROMPT`, empty when `input()` was called without one.
+   */
+  char *prompt;
+  /**
+   * Idempotency key of a `MONTY_PROGRESS_FUNCTION_CALL`: the script's
+   * `idempotency_key=` string argument, or one derived from the run id and call id
+   * that stays the same when the call is reported again after a resume from the same
+   * snapshot. NULL for runs without a run id whose script passes none.
+   */
+  char *idempotency_key;
+  /**
+   * Cache key of a `MONTY_PROGRESS_FUNCTION_CALL` to a function listed in the
+   * `cache_calls` run option, or NULL. Hosts can keep results by it across runs and
+   * resume later calls with the same key from their own cache.
+   */
+  char *cache_key;
 } ProgressResult;
 
 struct MontyStatus monty_run_new(const char *code,
@@ -44,50 +256,851 @@ struct MontyStatus monty_run_new(const char *code,
                                  const char *const *ext_funcs,
                                  struct MontyRunHandle **out);
 
+/**
+ * Like `monty_run_new`, but code that is not valid UTF-8 is decoded with U+FFFD
+ * replacement characters instead of failing, and the status has `lossy` set when it
+ * was. Names stay strict.
+ */
+struct MontyStatus monty_run_new_lossy(const char *code,
+                                       const char *script_name,
+                                       const char *const *input_names,
+                                       const char *const *ext_funcs,
+                                       struct MontyRunHandle **out);
+
 struct MontyStatus monty_run_dump(struct MontyRunHandle *run, uint8_t **out_bytes, size_t *out_len);
 
 struct MontyStatus monty_run_load(const uint8_t *bytes, size_t len, struct MontyRunHandle **out);
 
 void monty_run_free(struct MontyRunHandle *run);
 
+/**
+ * Frees `*run` like `monty_run_free` and sets it to NULL, so freeing the same pointer again
+ * (e.g. from a finalizer that runs after an explicit free) does nothing. The other
+ * `_free_and_clear` functions work the same way.
+ */
+void monty_run_free_and_clear(struct MontyRunHandle **run);
+
+/**
+ * Decodes `globals_json`, a JSON object mapping names to tagged values, once into a
+ * handle that can be attached to any number of runs with `monty_run_attach_globals`.
+ */
+struct MontyStatus monty_globals_new(const char *globals_json, struct GlobalsHandle **out);
+
+/**
+ * Releases the caller's reference; runs the globals are attached to keep theirs.
+ */
+void monty_globals_free(struct GlobalsHandle *globals);
+
+/**
+ * `monty_globals_free`, then sets `*globals` to NULL.
+ */
+void monty_globals_free_and_clear(struct GlobalsHandle **globals);
+
+/**
+ * Writes the builtins scripts of `run` can use without declaring them, as a JSON array
+ * of `{"name", "kind", "enabled"}` objects. `kind` is `function`, `type`, or `host`
+ * for the builtins this library answers through progress results (`host_emit`,
+ * `host_sleep`).
+ */
+struct MontyStatus monty_run_list_builtins(const struct MontyRunHandle *run, char **out_json);
+
+/**
+ * Makes calls to each builtin named in the NULL-terminated `names` raise
+ * `PermissionError` in every later start of `run`, replacing the previously disabled
+ * set; NULL or an empty array enables them all again. Names must be listed by
+ * `monty_run_list_builtins` and not declared as external functions. The set is not
+ * kept by `monty_run_dump`, and runs restored from older dumps can't disable builtins.
+ */
+struct MontyStatus monty_run_disable_builtins(struct MontyRunHandle *run,
+                                              const char *const *names);
+
+/**
+ * Reports calls to each builtin named in the NULL-terminated `names` (e.g. `print`) as
+ * `MONTY_PROGRESS_FUNCTION_CALL` with `builtin_override` set in every later start of
+ * `run`, so the host can virtualize them without changing scripts; the host resumes
+ * them like any external call. Replaces the previously overridden set; NULL or an
+ * empty array restores them all. Host builtins can't be overridden, and a builtin
+ * that is also disabled stays disabled. The set is not kept by `monty_run_dump`.
+ */
+struct MontyStatus monty_run_override_builtins(struct MontyRunHandle *run,
+                                               const char *const *names);
+
+/**
+ * Binds the shared globals as module-level variables in every later start of `run`,
+ * or detaches them when `globals` is NULL. The handle is held by reference; each start
+ * copies the values into its own VM.
+ */
+struct MontyStatus monty_run_attach_globals(struct MontyRunHandle *run,
+                                            const struct GlobalsHandle *globals);
+
+/**
+ * Creates a quota group from `limits_json`
+ * (`{"max_instructions": 10000000, "max_memory": 67108864, "window_ms": 60000}`).
+ * Runs attached to it draw from the same instruction and memory budgets.
+ */
+struct MontyStatus monty_quota_group_new(const char *limits_json, struct QuotaGroupHandle **out);
+
+/**
+ * Releases the caller's reference; runs attached to the group keep theirs.
+ */
+void monty_quota_group_free(struct QuotaGroupHandle *group);
+
+/**
+ * `monty_quota_group_free`, then sets `*group` to NULL.
+ */
+void monty_quota_group_free_and_clear(struct QuotaGroupHandle **group);
+
+/**
+ * Writes what the group's runs have used in the current window, and what is left, as
+ * JSON.
+ */
+struct MontyStatus monty_quota_group_usage(const struct QuotaGroupHandle *group, char **out_json);
+
+/**
+ * Makes every later start of `run` draw from `group`, or from no group when it is
+ * NULL. Runs already started keep the group they started with.
+ */
+struct MontyStatus monty_run_attach_quota_group(struct MontyRunHandle *run,
+                                                const struct QuotaGroupHandle *group);
+
+/**
+ * Binds `object` to the input referenced as `{"$input_object": "<name>"}` in every
+ * later start's inputs array, or unbinds the name when `object` is NULL. The run
+ * holds a reference to the handle's value rather than a copy, and the caller keeps
+ * its handle, so binding is cheap and skips JSON entirely; each start still copies
+ * the value into its own VM. Safe to call while other threads start the run.
+ */
+struct MontyStatus monty_run_set_input_object(const struct MontyRunHandle *run,
+                                              const char *name,
+                                              const struct MontyObjectHandle *object);
+
 struct MontyStatus monty_run_start(struct MontyRunHandle *run,
                                    const char *inputs_json,
                                    struct ProgressResult *out);
 
+/**
+ * Like `monty_run_start`, with per-run options such as resource limits given as a
+ * JSON object (`{"limits": {"max_instructions": 100000}}`). NULL or empty options
+ * behave like `monty_run_start`.
+ */
+struct MontyStatus monty_run_start_with_options(struct MontyRunHandle *run,
+                                                const char *inputs_json,
+                                                const char *options_json,
+                                                struct ProgressResult *out);
+
+/**
+ * Like `monty_run_start_with_options`, binding the `count` byte inputs in
+ * `input_bytes` to the `$input_bytes` markers that name them. The data is copied and
+ * only used by this start, so concurrent starts of `run` never see each other's bytes.
+ */
+struct MontyStatus monty_run_start_with_input_bytes(struct MontyRunHandle *run,
+                                                    const char *inputs_json,
+                                                    const char *options_json,
+                                                    const struct MontyInputBytes *input_bytes,
+                                                    size_t count,
+                                                    struct ProgressResult *out);
+
+/**
+ * Runs a program to completion in one call, answering its external calls with
+ * `function_callback` and its OS calls with `os_callback`, and writes the encoded
+ * result to `out_result_json`. Options are as in `monty_run_start_with_options`, and
+ * may be NULL. Script output goes to `print_callback`, or stdout when it is NULL.
+ * Runs that need lazy inputs or futures fail.
+ */
+struct MontyStatus monty_run_drive(struct MontyRunHandle *run,
+                                   const char *inputs_json,
+                                   const char *options_json,
+                                   MontyCallCallback function_callback,
+                                   MontyCallCallback os_callback,
+                                   MontyWriteCallback print_callback,
+                                   void *userdata,
+                                   char **out_result_json);
+
+/**
+ * Rebuilds a run after a crash from `journal`, the append-only journal of its progress
+ * events written by the Go `Recorder` (`NewJournalWriter`): the program is started
+ * with `inputs_json` and `options_json`, as for `monty_run_start_with_options`, and
+ * each call is answered with its journaled resolution, so only the journal needs to be
+ * persisted at each suspension. `out` receives the first progress the journal doesn't
+ * resolve, or the completed result. A call that differs from the journaled one fails
+ * the recovery. Runs that need lazy inputs fail.
+ */
+struct MontyStatus monty_run_recover(struct MontyRunHandle *run,
+                                     const char *inputs_json,
+                                     const char *options_json,
+                                     const uint8_t *journal,
+                                     size_t journal_len,
+                                     struct ProgressResult *out);
+
+/**
+ * Compiles and runs `code` to completion in one call, for evaluating expressions that
+ * need nothing from the host, and writes the encoded result to `out_json`.
+ * `inputs_json` is an object of input values by name and `limits_json` a
+ * `ResourceLimits` object; either may be NULL. A script that makes any external or OS
+ * call fails instead of being suspended.
+ */
+struct MontyStatus monty_eval(const char *code,
+                              const char *inputs_json,
+                              const char *limits_json,
+                              char **out_json);
+
+/**
+ * Starts a run and returns `{"result": value}` when it completes without suspending,
+ * or `{"error": message}` when it fails or would suspend, as a string to free with
+ * `monty_free_string`. There is no status struct or out-pointer, for binding
+ * generators that handle neither well. Options are as in `monty_run_start_with_options`.
+ */
+char *monty_run_result_json(struct MontyRunHandle *run,
+                            const char *inputs_json,
+                            const char *options_json);
+
+/**
+ * Provides the value of the lazy input named in an `INPUT_REQUEST` progress and
+ * continues: either the next lazy input is requested or the run starts. Consumes
+ * the request handle.
+ */
+struct MontyStatus monty_input_request_resume(struct InputRequestHandle *request,
+                                              const char *value_json,
+                                              struct ProgressResult *out);
+
+void monty_input_request_free(struct InputRequestHandle *request);
+
+/**
+ * `monty_input_request_free`, then sets `*request` to NULL.
+ */
+void monty_input_request_free_and_clear(struct InputRequestHandle **request);
+
 void monty_progress_result_free_strings(struct ProgressResult *result);
 
+/**
+ * Frees everything a progress result still owns: its strings, and any snapshot,
+ * future snapshot, input request, or object handle the caller has not taken. Set a
+ * handle field to NULL to keep it. The struct itself is left zeroed.
+ */
+void monty_progress_result_free(struct ProgressResult *result);
+
+/**
+ * Moves the snapshot out of a progress result into `*out` and clears the field, so
+ * the caller owns it and `monty_progress_result_free` leaves it alone. `*out` is NULL
+ * when the result has no snapshot.
+ */
+struct MontyStatus monty_progress_result_take_snapshot(struct ProgressResult *result,
+                                                       struct SnapshotHandle **out);
+
+/**
+ * Moves the future snapshot out of a progress result, like
+ * `monty_progress_result_take_snapshot`.
+ */
+struct MontyStatus monty_progress_result_take_future_snapshot(struct ProgressResult *result,
+                                                              struct FutureSnapshotHandle **out);
+
+uint32_t monty_protocol_version(void);
+
+/**
+ * The C header this library was built with, so bindings can check the exact ABI of
+ * the library they loaded. The string is static and must not be freed.
+ */
+const char *monty_ffi_header(void);
+
+/**
+ * Writes process-wide counters (runs started and completed, snapshot handles held,
+ * instructions executed, bytes serialized) as JSON. Per-run counters are reported by
+ * `monty_snapshot_stats` and on completion.
+ */
+struct MontyStatus monty_metrics_json(char **out_json);
+
+/**
+ * Turns tracking of handles, strings, and byte buffers handed to the host on
+ * (nonzero) or off for the whole process, for finding leaks while debugging.
+ * Turning it off forgets everything recorded so far.
+ */
+void monty_debug_track_handles(int32_t enabled);
+
+/**
+ * Writes how many handles, strings, and byte buffers of each kind the host has been
+ * given and not freed since tracking was turned on, as a JSON object keyed by kind
+ * (`run`, `snapshot`, `string`, ...); `{}` means nothing has leaked.
+ */
+struct MontyStatus monty_debug_report_leaks(char **out_json);
+
+/**
+ * Writes everything the host holds since tracking was turned on, oldest first, as a
+ * JSON array of `{"kind", "age_ms", "size"}` objects. `size` is approximate and only
+ * known for snapshots (their VM heap), strings, and byte buffers.
+ */
+struct MontyStatus monty_debug_list_handles(char **out_json);
+
+/**
+ * Writes every OS function Monty can report as `MONTY_PROGRESS_OS_CALL`, as a JSON
+ * array of `{"name", "category"}` objects; categories are `filesystem` and
+ * `environment`. Hosts can check their dispatcher covers each one at startup and
+ * treat any other name as unknown.
+ */
+struct MontyStatus monty_list_os_functions(char **out_json);
+
+/**
+ * Writes the expected arguments of the OS function called `name` (e.g.
+ * `Path.read_text`) as a JSON object `{"name", "category", "args", "kwargs",
+ * "returns"}`, where each argument is `{"name", "type", "required"}` and types are
+ * Python annotations. The first argument of a `Path` method is the path it was called on. Fails for a
+ * name Monty has no OS function by.
+ */
+struct MontyStatus monty_os_call_schema(const char *name, char **out_json);
+
+/**
+ * Encodes a filled `ProgressResult` as a `protocol::ProgressMessage` JSON document.
+ * Snapshot handles are not part of the message and stay owned by `result`.
+ */
+struct MontyStatus monty_progress_result_to_json(const struct ProgressResult *result,
+                                                 char **out_json);
+
+/**
+ * Resumes a function or OS call. `error_message` raises a `RuntimeError` at the call
+ * site instead of returning `result_json`; both NULL leave the call pending as a future.
+ */
 struct MontyStatus monty_snapshot_resume(struct SnapshotHandle *snapshot,
-                                         uint32_t _call_id,
+                                         uint32_t call_id,
                                          const char *result_json,
                                          const char *error_message,
                                          struct ProgressResult *out);
 
+/**
+ * Resumes a function or OS call by raising `error_type(message)` at the call site.
+ * `error_type` names a builtin exception class and defaults to `RuntimeError` when
+ * NULL or empty.
+ */
+struct MontyStatus monty_snapshot_resume_error(struct SnapshotHandle *snapshot,
+                                               uint32_t call_id,
+                                               const char *error_type,
+                                               const char *message,
+                                               struct ProgressResult *out);
+
+/**
+ * Resumes a function or OS call by raising a structured exception.
+ * `exception_json` is a `{"$exception": {"type", "message", "args", "attributes"}}`
+ * object; an optional `cause` envelope is printed before the error when the run fails
+ * with it. The script sees the type and message; `args` and `attributes` are put back
+ * when the exception is reported to the host again.
+ */
+struct MontyStatus monty_snapshot_resume_exception(struct SnapshotHandle *snapshot,
+                                                   uint32_t _call_id,
+                                                   const char *exception_json,
+                                                   struct ProgressResult *out);
+
+/**
+ * Resumes a function or OS call with a value passed by handle, consuming `object`.
+ */
+struct MontyStatus monty_snapshot_resume_object(struct SnapshotHandle *snapshot,
+                                                uint32_t call_id,
+                                                struct MontyObjectHandle *object,
+                                                struct ProgressResult *out);
+
+/**
+ * Resumes a run waiting on futures with `results_json`, an array of resolutions.
+ * Under the `gather` future resolution, results are held back until every pending
+ * call has one; until then the progress is `MONTY_PROGRESS_RESOLVE_FUTURES` again,
+ * listing the calls still without a result.
+ */
 struct MontyStatus monty_future_snapshot_resume(struct FutureSnapshotHandle *snapshot,
                                                 const char *results_json,
                                                 struct ProgressResult *out);
 
+/**
+ * Tears down a paused run by raising `exc_type(message)` at its suspension point, so
+ * `finally:` blocks and context managers run. Consumes the snapshot like a resume.
+ */
+struct MontyStatus monty_snapshot_interrupt(struct SnapshotHandle *snapshot,
+                                            const char *exc_type,
+                                            const char *message,
+                                            struct ProgressResult *out);
+
+/**
+ * Future-snapshot counterpart of `monty_snapshot_interrupt`: every pending call is
+ * resolved by raising `exc_type(message)`.
+ */
+struct MontyStatus monty_future_snapshot_interrupt(struct FutureSnapshotHandle *snapshot,
+                                                   const char *exc_type,
+                                                   const char *message,
+                                                   struct ProgressResult *out);
+
+/**
+ * Resumes a function call and keeps going through consecutive function calls in one hop.
+ *
+ * `results_json` uses the same shape as `monty_future_snapshot_resume`. The suspended call
+ * (`call_id`) and every following function call with a matching entry are resolved
+ * in-process. A call without an entry to one of the run's `awaitable` functions is
+ * deferred as a future and described in `pending_calls_json`, so such calls are reported
+ * together on the next progress result; any other call without an entry ends the batch
+ * and is reported as usual, since the script uses its value directly. The suspended
+ * call itself is deferred when it has no entry, as with `monty_snapshot_resume`. A later
+ * result the `fail` return mismatch would reject also ends the batch at its call. When
+ * `include_os_calls` is non-zero, OS calls are batched the same way.
+ */
+struct MontyStatus monty_snapshot_resume_batch(struct SnapshotHandle *snapshot,
+                                               uint32_t call_id,
+                                               const char *results_json,
+                                               int32_t include_os_calls,
+                                               struct ProgressResult *out);
+
 struct MontyStatus monty_snapshot_dump(struct SnapshotHandle *snapshot,
                                        uint8_t **out_bytes,
                                        size_t *out_len);
 
+/**
+ * Reports the size `monty_snapshot_dump` would produce, without building the dump.
+ */
+struct MontyStatus monty_snapshot_estimated_size(struct SnapshotHandle *snapshot,
+                                                 size_t *out_size);
+
+/**
+ * Writes a SHA-256 hex digest of the suspended state: the VM state and run options,
+ * but not the run id or what the run has recorded so far (stats, trace, audit log,
+ * warnings). States whose dumps are identical apart from those hash equal. Only the
+ * FFI-side maps are sorted first; the VM state is hashed as Monty serializes it, so
+ * equivalent states reached by different paths may hash differently. For
+ * deduplicating stored states and spotting resumes that led back to a state already
+ * seen.
+ */
+struct MontyStatus monty_snapshot_hash(struct SnapshotHandle *snapshot, char **out_hash);
+
+/**
+ * Loads a snapshot from a dump. The buffer is only read during the call and is not
+ * kept after it returns, so the caller may free or reuse it straight away.
+ */
 struct MontyStatus monty_snapshot_load(const uint8_t *bytes,
                                        size_t len,
                                        struct SnapshotHandle **out);
 
+/**
+ * Format version a snapshot or future snapshot dump was written with; 0 for the bare
+ * VM state dumped by releases before dumps were versioned. Those still load, with a
+ * default run context, and the migrate functions rewrite them in the current version.
+ * Any other version than 0 or the current one is rejected.
+ */
+uint32_t monty_dump_format_version(const uint8_t *bytes, size_t len);
+
+/**
+ * Rewrites a snapshot dump from an earlier format version in the current one; see
+ * `monty_dump_format_version`.
+ */
+struct MontyStatus monty_snapshot_migrate(const uint8_t *bytes,
+                                          size_t len,
+                                          uint8_t **out_bytes,
+                                          size_t *out_len);
+
+/**
+ * Streams the same bytes as `monty_snapshot_dump` to `write` in chunks, without
+ * building the whole dump in memory first. While `write` runs, resuming or changing
+ * `snapshot` fails, and freeing it takes effect once the dump ends.
+ */
+struct MontyStatus monty_snapshot_dump_stream(struct SnapshotHandle *snapshot,
+                                              MontyWriteCallback write,
+                                              void *userdata);
+
+/**
+ * Dumps `snapshot` and passes the bytes through the host's `seal` callback, e.g. to
+ * encrypt and authenticate them.
+ */
+struct MontyStatus monty_snapshot_dump_sealed(struct SnapshotHandle *snapshot,
+                                              MontyTransformCallback seal,
+                                              void *userdata,
+                                              uint8_t **out_bytes,
+                                              size_t *out_len);
+
+/**
+ * Loads a dump made by `monty_snapshot_dump_sealed`. A failing `open` callback is
+ * reported with code `MONTY_ERROR_TAMPERED`.
+ */
+struct MontyStatus monty_snapshot_load_sealed(const uint8_t *bytes,
+                                              size_t len,
+                                              MontyTransformCallback open,
+                                              void *userdata,
+                                              struct SnapshotHandle **out);
+
+/**
+ * Dumps `snapshot` as a delta against `base_bytes`, an earlier dump of the same run
+ * (typically the one already persisted). Load it with `monty_snapshot_load_delta` and
+ * the same base bytes.
+ */
+struct MontyStatus monty_snapshot_dump_delta(const uint8_t *base_bytes,
+                                             size_t base_len,
+                                             struct SnapshotHandle *snapshot,
+                                             uint8_t **out_bytes,
+                                             size_t *out_len);
+
+struct MontyStatus monty_snapshot_load_delta(const uint8_t *base_bytes,
+                                             size_t base_len,
+                                             const uint8_t *delta_bytes,
+                                             size_t delta_len,
+                                             struct SnapshotHandle **out);
+
 struct MontyStatus monty_future_snapshot_dump(struct FutureSnapshotHandle *snapshot,
                                               uint8_t **out_bytes,
                                               size_t *out_len);
 
+/**
+ * `monty_snapshot_hash` for a future snapshot.
+ */
+struct MontyStatus monty_future_snapshot_hash(struct FutureSnapshotHandle *snapshot,
+                                              char **out_hash);
+
+/**
+ * Creates an empty in-memory snapshot store. Snapshots are put and got by the
+ * SHA-256 hex digest of their dump, so identical dumps are kept once.
+ */
+struct MontyStatus monty_store_new(struct StoreHandle **out);
+
+/**
+ * Opens a snapshot store kept as files under the directory `path`, creating it if
+ * needed, so stored snapshots survive restarts. Dumps are written atomically, and
+ * several stores (in one process or many) may share a directory.
+ */
+struct MontyStatus monty_store_open_dir(const char *path, struct StoreHandle **out);
+
+/**
+ * Closes the store; for a directory store, the files stay.
+ */
+void monty_store_free(struct StoreHandle *store);
+
+/**
+ * `monty_store_free`, then sets `*store` to NULL.
+ */
+void monty_store_free_and_clear(struct StoreHandle **store);
+
+/**
+ * Dumps `snapshot` into `store` without consuming it and writes the dump's hash, the
+ * key to get it back with; the hash is also listed under the snapshot's run id.
+ * Putting a snapshot whose dump is already stored keeps one copy and writes the same
+ * hash.
+ */
+struct MontyStatus monty_store_put(const struct StoreHandle *store,
+                                   struct SnapshotHandle *snapshot,
+                                   char **out_hash);
+
+/**
+ * Loads a new snapshot from the dump stored under `hash`; the store keeps the dump.
+ */
+struct MontyStatus monty_store_get(const struct StoreHandle *store,
+                                   const char *hash,
+                                   struct SnapshotHandle **out);
+
+/**
+ * Removes the dump stored under `hash`; does nothing if there is none.
+ */
+struct MontyStatus monty_store_delete(const struct StoreHandle *store, const char *hash);
+
+/**
+ * Writes the hashes of the stored snapshots of the run `run_id` (empty for runs
+ * started without one) as a JSON array, in the order they were put.
+ */
+struct MontyStatus monty_store_list(const struct StoreHandle *store,
+                                    const char *run_id,
+                                    char **out_json);
+
+/**
+ * Number of distinct dumps in `store`; 0 if it can't be read.
+ */
+size_t monty_store_count(const struct StoreHandle *store);
+
 struct MontyStatus monty_future_snapshot_load(const uint8_t *bytes,
                                               size_t len,
                                               struct FutureSnapshotHandle **out);
 
+/**
+ * Rewrites a future snapshot dump from an earlier format version in the current one;
+ * see `monty_dump_format_version`.
+ */
+struct MontyStatus monty_future_snapshot_migrate(const uint8_t *bytes,
+                                                 size_t len,
+                                                 uint8_t **out_bytes,
+                                                 size_t *out_len);
+
+/**
+ * Writes the run's tracker counters so far (instructions, memory high-water mark,
+ * calls, printed bytes) as JSON, without consuming the snapshot.
+ */
+struct MontyStatus monty_snapshot_stats(struct SnapshotHandle *snapshot, char **out_json);
+
+struct MontyStatus monty_future_snapshot_stats(struct FutureSnapshotHandle *snapshot,
+                                               char **out_json);
+
+/**
+ * Adds a policy (`{"functions": {...}, "os_functions": {...}}`, as in the run
+ * options) that every later call of the run must also pass. Restrictions travel with
+ * dumps and can't be lifted, so a snapshot handed to a less-trusted worker keeps them.
+ */
+struct MontyStatus monty_snapshot_restrict(struct SnapshotHandle *snapshot,
+                                           const char *policy_json);
+
+struct MontyStatus monty_future_snapshot_restrict(struct FutureSnapshotHandle *snapshot,
+                                                  const char *policy_json);
+
+/**
+ * Moves a suspended run into `group`, or out of any group when it is NULL. Group
+ * membership is not kept in dumps, so loaded snapshots must be attached again.
+ */
+struct MontyStatus monty_snapshot_attach_quota_group(struct SnapshotHandle *snapshot,
+                                                     const struct QuotaGroupHandle *group);
+
+struct MontyStatus monty_future_snapshot_attach_quota_group(struct FutureSnapshotHandle *snapshot,
+                                                            const struct QuotaGroupHandle *group);
+
+/**
+ * Writes the run id given in the run options (empty when none was set).
+ */
+struct MontyStatus monty_snapshot_run_id(struct SnapshotHandle *snapshot, char **out_id);
+
+struct MontyStatus monty_future_snapshot_run_id(struct FutureSnapshotHandle *snapshot,
+                                                char **out_id);
+
+/**
+ * Writes the run's Chrome trace so far, or `null` when it was started without the
+ * `trace` option, without consuming the snapshot.
+ */
+struct MontyStatus monty_snapshot_trace(struct SnapshotHandle *snapshot, char **out_json);
+
+struct MontyStatus monty_future_snapshot_trace(struct FutureSnapshotHandle *snapshot,
+                                               char **out_json);
+
+/**
+ * Writes the audit log of OS calls attempted so far, or `null` when the run was
+ * started without the `audit_os_calls` option, without consuming the snapshot.
+ */
+struct MontyStatus monty_snapshot_audit_log(struct SnapshotHandle *snapshot, char **out_json);
+
+struct MontyStatus monty_future_snapshot_audit_log(struct FutureSnapshotHandle *snapshot,
+                                                   char **out_json);
+
+/**
+ * Writes how much of each configured limit the run has left as JSON; resources
+ * without a limit are reported as `null`.
+ */
+struct MontyStatus monty_snapshot_remaining_budget(struct SnapshotHandle *snapshot,
+                                                   char **out_json);
+
+struct MontyStatus monty_future_snapshot_remaining_budget(struct FutureSnapshotHandle *snapshot,
+                                                          char **out_json);
+
+/**
+ * Replaces the run's resource limits (same shape as the `limits` run option) before
+ * it is resumed; budget already consumed still counts. Writes, per limit, whether it
+ * was `raised`, `lowered`, or left `unchanged` to `out_json` when non-NULL.
+ */
+struct MontyStatus monty_snapshot_set_limits(struct SnapshotHandle *snapshot,
+                                             const char *limits_json,
+                                             char **out_json);
+
+struct MontyStatus monty_future_snapshot_set_limits(struct FutureSnapshotHandle *snapshot,
+                                                    const char *limits_json,
+                                                    char **out_json);
+
+struct MontyStatus monty_object_from_json(const char *json, struct MontyObjectHandle **out);
+
+/**
+ * Encodes the object with the tagged JSON encoding, without consuming it.
+ */
+struct MontyStatus monty_object_to_json(const struct MontyObjectHandle *object, char **out_json);
+
+struct MontyObjectHandle *monty_object_none(void);
+
+struct MontyObjectHandle *monty_object_bool(int32_t value);
+
+struct MontyObjectHandle *monty_object_int(int64_t value);
+
+struct MontyObjectHandle *monty_object_float(double value);
+
+/**
+ * Copies `len` bytes of UTF-8 text into a `str` object.
+ */
+struct MontyStatus monty_object_string(const uint8_t *data,
+                                       size_t len,
+                                       struct MontyObjectHandle **out);
+
+/**
+ * Copies `len` bytes into a `bytes` object.
+ */
+struct MontyStatus monty_object_bytes(const uint8_t *data,
+                                      size_t len,
+                                      struct MontyObjectHandle **out);
+
+/**
+ * Builds a `list` from `len` item handles, consuming them. On failure none of them
+ * are consumed; passing the same handle twice fails.
+ */
+struct MontyStatus monty_object_list(struct MontyObjectHandle *const *items,
+                                     size_t len,
+                                     struct MontyObjectHandle **out);
+
+/**
+ * Builds a `dict` from `len` key and value handles, consuming them. On failure none
+ * of them are consumed; passing the same handle twice, as a key or a value, fails.
+ */
+struct MontyStatus monty_object_dict(struct MontyObjectHandle *const *keys,
+                                     struct MontyObjectHandle *const *values,
+                                     size_t len,
+                                     struct MontyObjectHandle **out);
+
+/**
+ * Returns an empty `list` to fill with `monty_list_push`.
+ */
+struct MontyObjectHandle *monty_list_new(void);
+
+/**
+ * Appends `item` to a list, consuming the item handle.
+ */
+struct MontyStatus monty_list_push(struct MontyObjectHandle *list, struct MontyObjectHandle *item);
+
+/**
+ * Returns an empty `dict` to fill with `monty_dict_set`.
+ */
+struct MontyObjectHandle *monty_dict_new(void);
+
+/**
+ * Sets `dict[key] = value`, replacing an equal key's value, and consumes both
+ * handles. Keys are compared linearly; build large dicts with `monty_object_dict`.
+ */
+struct MontyStatus monty_dict_set(struct MontyObjectHandle *dict,
+                                  struct MontyObjectHandle *key,
+                                  struct MontyObjectHandle *value);
+
+/**
+ * Returns the object's `MONTY_OBJECT_*` kind; values without a dedicated accessor
+ * are `MONTY_OBJECT_OTHER` and can be read with `monty_object_to_json`.
+ */
+int32_t monty_object_kind(const struct MontyObjectHandle *object);
+
+/**
+ * Number of items in a container, or of bytes in a string or bytes object; 0 for
+ * anything else.
+ */
+size_t monty_object_len(const struct MontyObjectHandle *object);
+
+/**
+ * Writes the Python class name of the object (`list`, `NoneType`, a dataclass name).
+ */
+struct MontyStatus monty_object_type(const struct MontyObjectHandle *object, char **out_name);
+
+struct MontyStatus monty_object_as_int(const struct MontyObjectHandle *object, int64_t *out);
+
+struct MontyStatus monty_object_as_float(const struct MontyObjectHandle *object, double *out);
+
+/**
+ * Points `out_data` at the UTF-8 contents of a string or the contents of a bytes
+ * object. The data is borrowed and stays valid until the handle is freed or consumed.
+ */
+struct MontyStatus monty_object_as_bytes(const struct MontyObjectHandle *object,
+                                         const uint8_t **out_data,
+                                         size_t *out_len);
+
+/**
+ * Copies item `index` of a list or tuple into a new handle.
+ */
+struct MontyStatus monty_object_item(const struct MontyObjectHandle *object,
+                                     size_t index,
+                                     struct MontyObjectHandle **out);
+
+/**
+ * Copies entry `index` of a dict, in insertion order, into new key and value handles.
+ */
+struct MontyStatus monty_object_dict_item(const struct MontyObjectHandle *object,
+                                          size_t index,
+                                          struct MontyObjectHandle **out_key,
+                                          struct MontyObjectHandle **out_value);
+
+/**
+ * Copies `object[key]` into a new handle: sequences take int keys (negative counts
+ * from the end), dicts any key. `key` is not consumed.
+ */
+struct MontyStatus monty_object_getitem(const struct MontyObjectHandle *object,
+                                        const struct MontyObjectHandle *key,
+                                        struct MontyObjectHandle **out);
+
+/**
+ * Copies attribute `name` of a dataclass or named tuple into a new handle.
+ */
+struct MontyStatus monty_object_getattr(const struct MontyObjectHandle *object,
+                                        const char *name,
+                                        struct MontyObjectHandle **out);
+
+struct MontyStatus monty_object_iter(const struct MontyObjectHandle *object,
+                                     struct MontyIteratorHandle **out);
+
+/**
+ * Copies the next item into a new handle, or writes NULL once the iterator is exhausted.
+ */
+struct MontyStatus monty_iterator_next(struct MontyIteratorHandle *iterator,
+                                       struct MontyObjectHandle **out);
+
+/**
+ * Returns a new handle sharing the object's value; each handle is freed separately.
+ */
+struct MontyObjectHandle *monty_object_retain(const struct MontyObjectHandle *object);
+
+void monty_object_free(struct MontyObjectHandle *object);
+
+/**
+ * `monty_object_free`, then sets `*object` to NULL.
+ */
+void monty_object_free_and_clear(struct MontyObjectHandle **object);
+
+void monty_iterator_free(struct MontyIteratorHandle *iterator);
+
+/**
+ * `monty_iterator_free`, then sets `*iterator` to NULL.
+ */
+void monty_iterator_free_and_clear(struct MontyIteratorHandle **iterator);
+
 void monty_snapshot_free(struct SnapshotHandle *snapshot);
 
+/**
+ * `monty_snapshot_free`, then sets `*snapshot` to NULL.
+ */
+void monty_snapshot_free_and_clear(struct SnapshotHandle **snapshot);
+
 void monty_future_snapshot_free(struct FutureSnapshotHandle *snapshot);
 
+/**
+ * `monty_future_snapshot_free`, then sets `*snapshot` to NULL.
+ */
+void monty_future_snapshot_free_and_clear(struct FutureSnapshotHandle **snapshot);
+
 void monty_free_bytes(uint8_t *ptr, size_t len);
 
+/**
+ * Turns strict encoding on (nonzero) or off for the whole process. When on, encoding a
+ * value that has no lossless JSON form (a function, a class, a cycle, or any other
+ * value only its repr describes) fails with an error naming its type and where it is,
+ * instead of writing a `{"$repr": ...}` placeholder. `...` is still written as a
+ * `$repr`, since it decodes back to `Ellipsis`.
+ */
+void monty_set_strict_encoding(int32_t enabled);
+
+/**
+ * Turns float strings on (nonzero) or off for the whole process. When on, floats are
+ * encoded as `{"$float": "0.1"}`, the shortest decimal that parses back to the same
+ * value (`NaN`, `inf`, and `-inf` included), so archived results compare byte for byte
+ * regardless of how a JSON library formats numbers. `$float` is accepted on decode
+ * whether or not this is on.
+ */
+void monty_set_float_strings(int32_t enabled);
+
+/**
+ * Turns strict number decoding on (nonzero) or off for the whole process. When on,
+ * inputs, results, and globals fail to decode if a JSON number would change: an integer
+ * too large for 64 bits (which would become a float) or a decimal with more precision
+ * than a float holds. Such values can be passed exactly as `$bigint` or a string.
+ */
+void monty_set_strict_numbers(int32_t enabled);
+
+/**
+ * Turns canonical encoding on (nonzero) or off for the whole process. When on, values
+ * that compare equal encode to identical bytes, for hashing and caching: dict entries
+ * and set items are sorted by their encoding, object keys are sorted, `-0.0` is
+ * written as `0.0`, big ints that fit in 64 bits as plain ints, and `NaN` and
+ * infinities as `$float` strings. Otherwise dicts keep their insertion order.
+ */
+void monty_set_canonical_encoding(int32_t enabled);
+
 void monty_free_string(char *s);
 
 #endif  /* MONTY_FFI_H */
@@ -0,0 +1,172 @@
+//! Async driver for Monty runs on tokio: `run` takes a compiled program to completion,
+//! awaiting each external and OS call on a `Host`, for Rust services that would rather
+//! not handle snapshots themselves.
+//!
+//! Calls to functions the host marks async are spawned as tasks and the script keeps
+//! running; when it awaits them (e.g. with `asyncio.gather`), the driver hands their
+//! results back as they finish, keeping results of calls the script has not awaited
+//! yet until it does. Other calls are awaited before the script continues.
+
+use std::{borrow::Cow, collections::HashMap, future::Future, sync::Arc};
+
+use monty::{
+    ExcType, ExternalResult, MontyException, MontyObject, MontyRun, PrintWriter,
+    PrintWriterCallback, ResourceTracker, RunProgress,
+};
+use tokio::task::JoinSet;
+
+/// Answers the calls a script makes.
+pub trait Host: Send + Sync + 'static {
+    /// Answers a call to an external function declared when the program was compiled.
+    fn call(
+        &self,
+        function_name: &str,
+        args: Vec<MontyObject>,
+        kwargs: Vec<(MontyObject, MontyObject)>,
+    ) -> impl Future<Output = ExternalResult> + Send;
+
+    /// Answers an OS call such as `Path.read_text`. By default every OS call raises
+    /// `PermissionError` in the script.
+    fn os_call(
+        &self,
+        function: &str,
+        args: Vec<MontyObject>,
+        kwargs: Vec<(MontyObject, MontyObject)>,
+    ) -> impl Future<Output = ExternalResult> + Send {
+        let _ = (args, kwargs);
+        let exc = MontyException::new(
+            ExcType::PermissionError,
+            Some(format!("{function}() is not permitted")),
+        );
+        async move { ExternalResult::Error(exc) }
+    }
+
+    /// Whether the script awaits calls to `function_name` as coroutines. Those calls
+    /// run concurrently as tasks; the rest are awaited one at a time.
+    fn is_async(&self, function_name: &str) -> bool {
+        let _ = function_name;
+        false
+    }
+}
+
+/// A run that completed.
+#[derive(Debug, Clone)]
+pub struct Finished {
+    /// The value of the script's last expression.
+    pub value: MontyObject,
+    /// Everything the script printed.
+    pub output: String,
+}
+
+/// Runs `runner` with `inputs` to completion, answering its calls with `host`.
+///
+/// VM steps run on the calling task between calls; bound them with `tracker` when
+/// scripts are untrusted. Dropping the returned future cancels the run and aborts the
+/// tasks of its outstanding async calls.
+pub async fn run<H, T>(
+    runner: &MontyRun,
+    inputs: Vec<MontyObject>,
+    tracker: T,
+    host: Arc<H>,
+) -> Result<Finished, MontyException>
+where
+    H: Host,
+    T: ResourceTracker + Send,
+{
+    let mut output = Output::default();
+    let mut spawned = JoinSet::new();
+    let mut ready = HashMap::new();
+    let mut progress = runner.start(inputs, tracker, &mut output.writer())?;
+    loop {
+        progress = match progress {
+            RunProgress::Complete(value) => {
+                return Ok(Finished {
+                    value,
+                    output: output.0,
+                })
+            }
+            RunProgress::FunctionCall {
+                function_name,
+                args,
+                kwargs,
+                call_id,
+                state,
+                ..
+            } if host.is_async(&function_name) => {
+                let host = host.clone();
+                spawned.spawn(async move {
+                    let result = host.call(&function_name, args, kwargs).await;
+                    (call_id, result)
+                });
+                state.run(ExternalResult::Future, &mut output.writer())?
+            }
+            RunProgress::FunctionCall {
+                function_name,
+                args,
+                kwargs,
+                state,
+                ..
+            } => {
+                let result = host.call(&function_name, args, kwargs).await;
+                state.run(result, &mut output.writer())?
+            }
+            RunProgress::OsCall {
+                function,
+                args,
+                kwargs,
+                state,
+                ..
+            } => {
+                let result = host.os_call(&function.to_string(), args, kwargs).await;
+                state.run(result, &mut output.writer())?
+            }
+            RunProgress::ResolveFutures(snapshot) => {
+                let awaited = snapshot.pending_call_ids().to_vec();
+                let mut results: Vec<(u32, ExternalResult)> = awaited
+                    .iter()
+                    .filter_map(|call_id| ready.remove(call_id).map(|result| (*call_id, result)))
+                    .collect();
+                while results.is_empty() {
+                    let (call_id, result) = match spawned.join_next().await {
+                        Some(Ok(done)) => done,
+                        Some(Err(err)) => std::panic::resume_unwind(err.into_panic()),
+                        None => {
+                            return Err(MontyException::new(
+                                ExcType::RuntimeError,
+                                Some("the script awaits calls that were never made".into()),
+                            ))
+                        }
+                    };
+                    if awaited.contains(&call_id) {
+                        results.push((call_id, result));
+                    } else {
+                        ready.insert(call_id, result);
+                    }
+                }
+                snapshot.resume(results, &mut output.writer())?
+            }
+        };
+    }
+}
+
+/// Collects what the script prints.
+#[derive(Debug, Default)]
+struct Output(String);
+
+impl Output {
+    fn writer(&mut self) -> PrintWriter<'_> {
+        PrintWriter::Callback(self)
+    }
+}
+
+impl PrintWriterCallback for Output {
+    fn stdout_write(&mut self, output: Cow<'_, str>) -> Result<(), MontyException> {
+        self.0.push_str(&output);
+        Ok(())
+    }
+
+    fn stdout_push(&mut self, end: char) -> Result<(), MontyException> {
+        self.0.push(end);
+        Ok(())
+    }
+}
@@ -0,0 +1,144 @@
+use std::{sync::Arc, time::Duration};
+
+use monty::{ExternalResult, MontyObject, MontyRun, NoLimitTracker};
+use monty_async::{run, Host};
+
+fn compile(code: &str, ext_funcs: &[&str]) -> MontyRun {
+    MontyRun::new(
+        code.to_owned(),
+        "test.py",
+        vec![],
+        ext_funcs.iter().map(|name| name.to_string()).collect(),
+    )
+    .unwrap()
+}
+
+/// `double` doubles its argument, `fetch` returns it after a delay that is shorter
+/// for larger arguments, and `Path.read_text` reads from a fixed file.
+struct TestHost;
+
+impl Host for TestHost {
+    async fn call(
+        &self,
+        function_name: &str,
+        args: Vec<MontyObject>,
+        _kwargs: Vec<(MontyObject, MontyObject)>,
+    ) -> ExternalResult {
+        let Some(MontyObject::Int(n)) = args.first().cloned() else {
+            panic!("{function_name} called with {args:?}");
+        };
+        match function_name {
+            "double" => ExternalResult::Return(MontyObject::Int(n * 2)),
+            "fetch" => {
+                tokio::time::sleep(Duration::from_millis(30 - 10 * n as u64)).await;
+                ExternalResult::Return(MontyObject::Int(n * 10))
+            }
+            name => panic!("unexpected call to {name}"),
+        }
+    }
+
+    async fn os_call(
+        &self,
+        function: &str,
+        args: Vec<MontyObject>,
+        _kwargs: Vec<(MontyObject, MontyObject)>,
+    ) -> ExternalResult {
+        match (function, args.first()) {
+            ("Path.read_text", Some(MontyObject::Path(path))) if path == "/data/a.txt" => {
+                ExternalResult::Return(MontyObject::String("contents".into()))
+            }
+            _ => panic!("unexpected OS call {function}({args:?})"),
+        }
+    }
+
+    fn is_async(&self, function_name: &str) -> bool {
+        function_name == "fetch"
+    }
+}
+
+/// Answers calls but leaves OS calls to the default.
+struct NoOsHost;
+
+impl Host for NoOsHost {
+    async fn call(
+        &self,
+        _function_name: &str,
+        _args: Vec<MontyObject>,
+        _kwargs: Vec<(MontyObject, MontyObject)>,
+    ) -> ExternalResult {
+        ExternalResult::Return(MontyObject::None)
+    }
+}
+
+#[tokio::test]
+async fn sync_call() {
+    let runner = compile("print('hi')\ndouble(20) + 2", &["double"]);
+    let finished = run(&runner, vec![], NoLimitTracker, Arc::new(TestHost))
+        .await
+        .unwrap();
+    assert!(matches!(finished.value, MontyObject::Int(42)));
+    assert_eq!(finished.output, "hi\n");
+}
+
+#[tokio::test]
+async fn gather_keeps_order() {
+    let runner = compile(
+        "import asyncio\nawait asyncio.gather(fetch(1), fetch(2), fetch(3))",
+        &["fetch"],
+    );
+    let finished = run(&runner, vec![], NoLimitTracker, Arc::new(TestHost))
+        .await
+        .unwrap();
+    let MontyObject::List(values) = finished.value else {
+        panic!("gather returned {:?}", finished.value);
+    };
+    assert!(matches!(
+        values.as_slice(),
+        [
+            MontyObject::Int(10),
+            MontyObject::Int(20),
+            MontyObject::Int(30)
+        ]
+    ));
+}
+
+#[tokio::test]
+async fn awaits_in_any_order() {
+    // fetch(2) finishes first but is awaited last, so its result waits in the driver.
+    let runner = compile(
+        "a = fetch(1)\nb = fetch(2)\nx = await a\ny = await b\nx * 100 + y",
+        &["fetch"],
+    );
+    let finished = run(&runner, vec![], NoLimitTracker, Arc::new(TestHost))
+        .await
+        .unwrap();
+    assert!(matches!(finished.value, MontyObject::Int(1020)));
+}
+
+#[tokio::test]
+async fn os_call() {
+    let runner = compile(
+        "from pathlib import Path\nPath('/data/a.txt').read_text()",
+        &[],
+    );
+    let finished = run(&runner, vec![], NoLimitTracker, Arc::new(TestHost))
+        .await
+        .unwrap();
+    assert!(matches!(finished.value, MontyObject::String(s) if s == "contents"));
+}
+
+#[tokio::test]
+async fn os_call_denied_by_default() {
+    let runner = compile(
+        "from pathlib import Path\nPath('/data/a.txt').read_text()",
+        &[],
+    );
+    let err = run(&runner, vec![], NoLimitTracker, Arc::new(NoOsHost))
+        .await
+        .unwrap_err();
+    assert!(
+        err.summary().starts_with("PermissionError"),
+        "{}",
+        err.summary()
+    );
+}
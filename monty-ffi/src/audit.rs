@@ -0,0 +1,129 @@
+//! Audit log of the OS calls a run attempts, for reviewing untrusted scripts.
+//!
+//! With the `audit_os_calls` run option, each OS call is recorded when the script
+//! makes it, with a short repr of its arguments, and marked once the host resolves
+//! it: resuming with a value allows the call, resuming with an exception denies it.
+//! Calls the run's policy rejects are recorded as denied without reaching the host.
+//! Calls still unresolved (pending futures, or a run interrupted at the call) stay
+//! `Pending`. The log is kept in the run's context, so it survives dumps.
+
+use monty::{ExternalResult, MontyException, MontyObject};
+use serde::{Deserialize, Serialize};
+
+use crate::trace::unix_us;
+
+/// Longest argument summary kept per call; longer ones are cut with `...`.
+const MAX_ARGS_LEN: usize = 200;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditLog {
+    started_us: u64,
+    entries: Vec<AuditEntry>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub call_id: u32,
+    pub function: String,
+    pub args: String,
+    /// Microseconds since the run started.
+    pub at_us: u64,
+    pub outcome: Outcome,
+    /// Summary of the exception a denied call was resumed with.
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    Pending,
+    Allowed,
+    Denied,
+}
+
+impl Default for AuditLog {
+    fn default() -> Self {
+        Self {
+            started_us: unix_us(),
+            entries: Vec::new(),
+        }
+    }
+}
+
+impl AuditLog {
+    pub fn attempted(
+        &mut self,
+        call_id: u32,
+        function: &str,
+        args: &[MontyObject],
+        kwargs: &[(MontyObject, MontyObject)],
+    ) {
+        self.entries.push(AuditEntry {
+            call_id,
+            function: function.to_owned(),
+            args: summarize(args, kwargs),
+            at_us: unix_us().saturating_sub(self.started_us),
+            outcome: Outcome::Pending,
+            error: None,
+        });
+    }
+
+    /// Records a call the run's policy rejected before it reached the host.
+    pub fn rejected(
+        &mut self,
+        call_id: u32,
+        function: &str,
+        args: &[MontyObject],
+        kwargs: &[(MontyObject, MontyObject)],
+        exc: &MontyException,
+    ) {
+        self.attempted(call_id, function, args, kwargs);
+        if let Some(entry) = self.entries.last_mut() {
+            entry.outcome = Outcome::Denied;
+            entry.error = Some(exc.summary());
+        }
+    }
+
+    /// Marks the call allowed or denied by how the host resolved it; calls that are
+    /// not OS calls have no entry and are ignored.
+    pub fn resolved(&mut self, call_id: u32, result: &ExternalResult) {
+        let Some(entry) = self
+            .entries
+            .iter_mut()
+            .rev()
+            .find(|entry| entry.call_id == call_id)
+        else {
+            return;
+        };
+        match result {
+            ExternalResult::Return(_) => entry.outcome = Outcome::Allowed,
+            ExternalResult::Error(exc) => {
+                entry.outcome = Outcome::Denied;
+                entry.error = Some(exc.summary());
+            }
+            ExternalResult::Future => {}
+        }
+    }
+
+    pub fn entries(&self) -> &[AuditEntry] {
+        &self.entries
+    }
+}
+
+fn summarize(args: &[MontyObject], kwargs: &[(MontyObject, MontyObject)]) -> String {
+    let mut parts: Vec<String> = args.iter().map(|arg| format!("{arg}")).collect();
+    parts.extend(kwargs.iter().map(|(key, value)| match key {
+        MontyObject::String(name) => format!("{name}={value}"),
+        other => format!("{other}={value}"),
+    }));
+    let mut summary = parts.join(", ");
+    if summary.len() > MAX_ARGS_LEN {
+        let mut end = MAX_ARGS_LEN;
+        while !summary.is_char_boundary(end) {
+            end -= 1;
+        }
+        summary.truncate(end);
+        summary.push_str("...");
+    }
+    summary
+}
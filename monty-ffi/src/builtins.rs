@@ -0,0 +1,106 @@
+//! The builtins a script can use without declaring them, for hosts that publish a
+//! capability matrix to their script authors.
+//!
+//! Monty does not enumerate its builtins, so the Python ones listed here follow the
+//! pinned Monty revision and must be updated with it; the host builtins are the ones
+//! this library declares for every program.
+
+use serde::Serialize;
+
+use crate::compiled::{EMIT_FUNCTION, INPUT_FUNCTION, SLEEP_FUNCTION};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BuiltinKind {
+    Function,
+    Type,
+    /// Provided by this library and answered through progress results (`host_emit`,
+    /// `input`).
+    Host,
+}
+
+const FUNCTIONS: &[&str] = &[
+    "abs",
+    "all",
+    "any",
+    "bin",
+    "callable",
+    "chr",
+    "divmod",
+    "enumerate",
+    "filter",
+    "getattr",
+    "hasattr",
+    "hash",
+    "hex",
+    "id",
+    "isinstance",
+    "iter",
+    "len",
+    "map",
+    "max",
+    "min",
+    "next",
+    "oct",
+    "ord",
+    "pow",
+    "print",
+    "repr",
+    "reversed",
+    "round",
+    "sorted",
+    "sum",
+    "type",
+    "zip",
+];
+
+const TYPES: &[&str] = &[
+    "bool",
+    "bytes",
+    "dict",
+    "float",
+    "frozenset",
+    "int",
+    "list",
+    "range",
+    "set",
+    "str",
+    "tuple",
+];
+
+const HOST: &[&str] = &[EMIT_FUNCTION, SLEEP_FUNCTION, INPUT_FUNCTION];
+
+/// One builtin, as listed by `monty_run_list_builtins`.
+#[derive(Debug, Clone, Serialize)]
+pub struct Listed {
+    pub name: &'static str,
+    pub kind: BuiltinKind,
+    pub enabled: bool,
+}
+
+/// Whether a script can use `name` without declaring it.
+pub fn exists(name: &str) -> bool {
+    [FUNCTIONS, TYPES, HOST]
+        .iter()
+        .any(|names| names.contains(&name))
+}
+
+/// Every builtin, functions first, then types, then host builtins; those in `disabled`
+/// are listed as not enabled.
+pub fn list(disabled: &[String]) -> Vec<Listed> {
+    let kinds = [
+        (FUNCTIONS, BuiltinKind::Function),
+        (TYPES, BuiltinKind::Type),
+        (HOST, BuiltinKind::Host),
+    ];
+    kinds
+        .into_iter()
+        .flat_map(|(names, kind)| {
+            names.iter().map(move |&name| Listed {
+                name,
+                kind,
+                enabled: !disabled.iter().any(|d| d == name),
+            })
+        })
+        .collect()
+}
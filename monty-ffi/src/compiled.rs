@@ -0,0 +1,339 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, RwLock, RwLockReadGuard},
+};
+
+use monty::{MontyObject, MontyRun};
+use postcard::{from_bytes, to_allocvec};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    builtins,
+    error::{FfiError, FfiResult},
+    globals::Globals,
+    namespaces,
+    quota::QuotaGroup,
+    signature::{self, Signature},
+};
+
+/// Built-in function that streams a partial result to the host: `host_emit(value)`
+/// suspends the run with an emit progress carrying `value`, and returns `None` when
+/// the host resumes it. It is declared as an external function of every program.
+pub const EMIT_FUNCTION: &str = "host_emit";
+
+/// Built-in function that hands a delay to the host's scheduler: `host_sleep(seconds)`
+/// suspends the run with a sleep progress carrying the delay, and returns `None` when
+/// the host resumes it, which it does once the delay has passed. It is declared as an
+/// external function of every program, like `host_emit`.
+pub const SLEEP_FUNCTION: &str = "host_sleep";
+
+/// Python's `input(prompt)`: suspends the run with a prompt progress carrying the
+/// prompt, and returns the line the host resumes it with. It is declared as an external
+/// function of every program, like `host_emit`.
+pub const INPUT_FUNCTION: &str = "input";
+
+const BUILTIN_FUNCTIONS: [&str; 3] = [EMIT_FUNCTION, SLEEP_FUNCTION, INPUT_FUNCTION];
+
+/// Prefix of run dumps that carry their `Source`; older dumps are a bare `MontyRun`.
+const RUN_DUMP_MAGIC: &[u8] = b"MONTYRUN\x01";
+
+/// What `monty_run_new` was given, kept so the program can be recompiled with extra
+/// names bound as inputs and so declarations survive a dump. Runs restored from a
+/// dump made before sources were recorded do not have it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Source {
+    pub code: String,
+    pub script_name: String,
+    pub input_names: Vec<String>,
+    /// External function declarations: bare or dotted names, optionally with signatures.
+    pub ext_funcs: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct RunDump {
+    runner: Vec<u8>,
+    source: Option<Source>,
+}
+
+/// What Monty is compiled with, derived from a `Source`'s declarations.
+struct Declarations {
+    input_names: Vec<String>,
+    ext_funcs: Vec<String>,
+    namespaces: Vec<MontyObject>,
+    dotted_funcs: Vec<String>,
+    signatures: Vec<Signature>,
+}
+
+impl Source {
+    fn declarations(&self) -> FfiResult<Declarations> {
+        let mut names = Vec::with_capacity(self.ext_funcs.len());
+        let mut signatures = Vec::new();
+        for decl in &self.ext_funcs {
+            let (name, signature) = signature::parse(decl)?;
+            if BUILTIN_FUNCTIONS.contains(&name.as_str()) {
+                return Err(FfiError::Message(format!(
+                    "{name} is a built-in function and can't be declared"
+                )));
+            }
+            names.push(name);
+            signatures.extend(signature);
+        }
+        names.extend(BUILTIN_FUNCTIONS.map(str::to_owned));
+        let dotted_funcs = names
+            .iter()
+            .filter(|name| name.contains('.'))
+            .cloned()
+            .collect();
+        // Namespaces of dotted function names are bound as extra inputs.
+        let (ext_funcs, namespaces) = namespaces::split(names);
+        let (namespace_names, namespaces): (Vec<_>, Vec<_>) = namespaces.into_iter().unzip();
+        let mut input_names = self.input_names.clone();
+        input_names.extend(namespace_names);
+        Ok(Declarations {
+            input_names,
+            ext_funcs,
+            namespaces,
+            dotted_funcs,
+            signatures,
+        })
+    }
+
+    fn compile(&self, declarations: Declarations) -> FfiResult<MontyRun> {
+        Ok(MontyRun::new(
+            self.code.clone(),
+            &self.script_name,
+            declarations.input_names,
+            declarations.ext_funcs,
+        )?)
+    }
+}
+
+/// The program behind a `MontyRunHandle`, plus what every later start binds.
+pub struct Compiled {
+    pub runner: MontyRun,
+    source: Option<Source>,
+    /// Namespace objects for dotted external functions, bound after the declared inputs.
+    pub namespaces: Vec<MontyObject>,
+    /// The declared dotted external functions; calls to other members of a namespace
+    /// raise `AttributeError`.
+    pub dotted_funcs: Vec<String>,
+    pub signatures: Vec<Signature>,
+    /// Shared values bound by name to `$input_object` markers, kept across starts.
+    /// Locked so a value can be rebound while other threads start the run.
+    input_objects: RwLock<HashMap<String, Arc<MontyObject>>>,
+    pub globals: Option<Arc<Globals>>,
+    /// Quota group every later start draws from.
+    pub quota: Option<Arc<QuotaGroup>>,
+    /// Builtins calls to which raise `PermissionError`; not kept in dumps.
+    pub disabled_builtins: Vec<String>,
+    /// Builtins whose calls are reported to the host as external calls; not kept in
+    /// dumps.
+    pub overridden_builtins: Vec<String>,
+    /// The program recompiled for the most recent set of global names.
+    variant: Mutex<Option<(Vec<String>, MontyRun)>>,
+}
+
+impl Compiled {
+    pub fn compile(source: Source) -> FfiResult<Self> {
+        let declarations = source.declarations()?;
+        let namespaces = declarations.namespaces.clone();
+        let dotted_funcs = declarations.dotted_funcs.clone();
+        let signatures = declarations.signatures.clone();
+        let runner = source.compile(declarations)?;
+        Ok(Self::new(
+            runner,
+            Some(source),
+            namespaces,
+            dotted_funcs,
+            signatures,
+        ))
+    }
+
+    fn new(
+        runner: MontyRun,
+        source: Option<Source>,
+        namespaces: Vec<MontyObject>,
+        dotted_funcs: Vec<String>,
+        signatures: Vec<Signature>,
+    ) -> Self {
+        Self {
+            runner,
+            source,
+            namespaces,
+            dotted_funcs,
+            signatures,
+            input_objects: RwLock::new(HashMap::new()),
+            globals: None,
+            quota: None,
+            disabled_builtins: Vec::new(),
+            overridden_builtins: Vec::new(),
+            variant: Mutex::new(None),
+        }
+    }
+
+    pub fn dump(&self) -> FfiResult<Vec<u8>> {
+        let dump = RunDump {
+            runner: self.runner.dump()?,
+            source: self.source.clone(),
+        };
+        let mut bytes = RUN_DUMP_MAGIC.to_vec();
+        bytes.extend(to_allocvec(&dump)?);
+        Ok(bytes)
+    }
+
+    pub fn load(bytes: &[u8]) -> FfiResult<Self> {
+        let Some(rest) = bytes.strip_prefix(RUN_DUMP_MAGIC) else {
+            return Ok(Self::new(
+                MontyRun::load(bytes)?,
+                None,
+                Vec::new(),
+                Vec::new(),
+                Vec::new(),
+            ));
+        };
+        let dump: RunDump = from_bytes(rest)?;
+        let runner = MontyRun::load(&dump.runner)?;
+        match dump.source {
+            Some(source) => {
+                let declarations = source.declarations()?;
+                Ok(Self::new(
+                    runner,
+                    Some(source),
+                    declarations.namespaces,
+                    declarations.dotted_funcs,
+                    declarations.signatures,
+                ))
+            }
+            None => Ok(Self::new(runner, None, Vec::new(), Vec::new(), Vec::new())),
+        }
+    }
+
+    /// The input names given to `monty_run_new`, in order; `None` for runs restored
+    /// from a dump that predates recording sources.
+    pub fn input_names(&self) -> Option<&[String]> {
+        self.source
+            .as_ref()
+            .map(|source| source.input_names.as_slice())
+    }
+
+    pub fn input_objects(&self) -> RwLockReadGuard<'_, HashMap<String, Arc<MontyObject>>> {
+        self.input_objects
+            .read()
+            .unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// Binds `object` to `name` for later starts, or unbinds the name when it is `None`.
+    pub fn bind_input_object(&self, name: String, object: Option<Arc<MontyObject>>) {
+        let mut objects = self
+            .input_objects
+            .write()
+            .unwrap_or_else(|err| err.into_inner());
+        match object {
+            Some(object) => objects.insert(name, object),
+            None => objects.remove(&name),
+        };
+    }
+
+    /// Returns a runner whose inputs are the declared ones followed by `names`.
+    ///
+    /// Monty only binds module-level names through inputs, so globals are added by
+    /// compiling a variant of the program; it is cached until the names change.
+    pub fn runner_binding(&self, names: &[String]) -> FfiResult<MontyRun> {
+        if names.is_empty() {
+            return Ok(self.runner.clone());
+        }
+        let mut variant = self.variant.lock().unwrap_or_else(|err| err.into_inner());
+        if let Some((cached, runner)) = &*variant {
+            if cached == names {
+                return Ok(runner.clone());
+            }
+        }
+        let source = self.source.as_ref().ok_or_else(|| {
+            FfiError::Message(
+                "globals need the run's source, which dumps from older versions do not keep"
+                    .to_owned(),
+            )
+        })?;
+        let shadowed = self.shadowed_builtins();
+        let mut declarations = Self::declarations(source, &shadowed)?;
+        declarations.input_names.extend(names.iter().cloned());
+        let runner = source.compile(declarations)?;
+        *variant = Some((names.to_vec(), runner.clone()));
+        Ok(runner)
+    }
+
+    /// Makes calls to each builtin in `names` raise `PermissionError` in every later
+    /// start, replacing the previous set; an empty `names` enables them all again.
+    ///
+    /// Disabled builtins are shadowed by external functions of the same name, so the
+    /// program is recompiled and calls to them are rejected before reaching the host.
+    pub fn disable_builtins(&mut self, names: Vec<String>) -> FfiResult<()> {
+        let overridden = self.overridden_builtins.clone();
+        self.shadow_builtins(names, overridden)
+    }
+
+    /// Reports calls to each builtin in `names` to the host as external calls marked
+    /// as builtin overrides in every later start, replacing the previous set. Host
+    /// builtins (`host_emit`, `host_sleep`) are already answered by the host and can't
+    /// be overridden.
+    pub fn override_builtins(&mut self, names: Vec<String>) -> FfiResult<()> {
+        if let Some(name) = names
+            .iter()
+            .find(|name| BUILTIN_FUNCTIONS.contains(&name.as_str()))
+        {
+            return Err(FfiError::Message(format!(
+                "{name} is a host builtin and can't be overridden"
+            )));
+        }
+        let disabled = self.disabled_builtins.clone();
+        self.shadow_builtins(disabled, names)
+    }
+
+    /// Recompiles the program with the disabled and overridden builtins shadowed by
+    /// external functions of the same name.
+    fn shadow_builtins(&mut self, disabled: Vec<String>, overridden: Vec<String>) -> FfiResult<()> {
+        let source = self.source.clone().ok_or_else(|| {
+            FfiError::Message(
+                "disabling or overriding builtins needs the run's source, which dumps from \
+                 older versions do not keep"
+                    .to_owned(),
+            )
+        })?;
+        let declared = source.declarations()?;
+        let shadowed: Vec<String> = disabled.iter().chain(&overridden).cloned().collect();
+        for name in &shadowed {
+            if !builtins::exists(name) {
+                return Err(FfiError::Message(format!("unknown builtin `{name}`")));
+            }
+            if declared.ext_funcs.contains(name) && !BUILTIN_FUNCTIONS.contains(&name.as_str()) {
+                return Err(FfiError::Message(format!(
+                    "`{name}` is declared as an external function"
+                )));
+            }
+        }
+        self.runner = source.compile(Self::declarations(&source, &shadowed)?)?;
+        self.disabled_builtins = disabled;
+        self.overridden_builtins = overridden;
+        self.variant = Mutex::new(None);
+        Ok(())
+    }
+
+    fn shadowed_builtins(&self) -> Vec<String> {
+        self.disabled_builtins
+            .iter()
+            .chain(&self.overridden_builtins)
+            .cloned()
+            .collect()
+    }
+
+    /// The source's declarations, with `shadowed` builtins declared as external functions.
+    fn declarations(source: &Source, shadowed: &[String]) -> FfiResult<Declarations> {
+        let mut declarations = source.declarations()?;
+        for name in shadowed {
+            if !declarations.ext_funcs.contains(name) {
+                declarations.ext_funcs.push(name.clone());
+            }
+        }
+        Ok(declarations)
+    }
+}
@@ -0,0 +1,734 @@
+use std::{collections::HashMap, sync::Arc};
+
+use monty::{ExcType, ExternalResult, MontyException, MontyObject, PrintWriter};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{
+    audit::{AuditEntry, AuditLog},
+    compiled::{EMIT_FUNCTION, INPUT_FUNCTION, SLEEP_FUNCTION},
+    dataclasses::DataclassSchema,
+    error::{FfiError, FfiResult},
+    intern,
+    json::{self, ExceptionData, HostCauses},
+    metrics, namespaces,
+    options::{
+        CallPriorities, CallTimeouts, ErrorFormat, PrintOverflow, ResolutionMode, ReturnMismatch,
+        RunOptions,
+    },
+    policy::{self, Policy},
+    print::Printer,
+    protocol::FutureResolution,
+    quota::{Membership, QuotaGroup},
+    sha256,
+    signature::Signature,
+    trace::Trace,
+    tracker::{self, CostModel, LimitChanges, RemainingBudget, ResourceLimits, RunStats},
+    warnings::{Warning, WarningKind, Warnings},
+};
+
+/// FFI-side state that follows a run from start through every snapshot handle and
+/// is persisted alongside the VM state in snapshot dumps.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RunContext {
+    pub stats: RunStats,
+    pub limits: ResourceLimits,
+    pub call_timeouts: CallTimeouts,
+    pub signatures: Vec<Signature>,
+    pub return_mismatch: ReturnMismatch,
+    pub dataclasses: Vec<DataclassSchema>,
+    pub object_handles: bool,
+    /// Reported or deferred calls with a declared return type, by call id, whose
+    /// results the host has not provided yet.
+    #[serde(serialize_with = "intern::sorted")]
+    awaiting_returns: HashMap<u32, String>,
+    trace: Option<Trace>,
+    pub run_id: String,
+    audit: Option<AuditLog>,
+    pub policy: Policy,
+    /// Policies added to a suspended run with `restrict`; a call must pass every one.
+    restrictions: Vec<Policy>,
+    /// OS calls raise instead of being reported; see the `pure` run option.
+    pub pure: bool,
+    pub cost_model: CostModel,
+    pub print_overflow: PrintOverflow,
+    pub error_format: ErrorFormat,
+    warnings: Warnings,
+    pub print_to_stdout: bool,
+    /// Output collected since the last progress result; always empty when a run is
+    /// dumped.
+    #[serde(skip)]
+    output: Vec<u8>,
+    pub future_resolution: ResolutionMode,
+    /// Future results held back under `ResolutionMode::Gather`, as the host's JSON
+    /// entries by call id.
+    gathered: Vec<(u32, String)>,
+    pub call_priorities: CallPriorities,
+    /// Priorities of unresolved calls by call id, for ordering pending futures; calls
+    /// at priority 0 are left out.
+    #[serde(serialize_with = "intern::sorted")]
+    priorities: HashMap<u32, i32>,
+    /// Builtins the run was started with disabled; calls to them raise.
+    disabled_builtins: Vec<String>,
+    /// Builtins the run was started with overridden; calls to them are reported.
+    overridden_builtins: Vec<String>,
+    cache_calls: Vec<String>,
+    /// Results of calls to `cache_calls` functions, encoded, by cache key.
+    #[serde(serialize_with = "intern::sorted")]
+    call_cache: HashMap<String, String>,
+    /// Cache keys of reported calls to `cache_calls` functions, by call id, until the
+    /// host resolves them.
+    #[serde(serialize_with = "intern::sorted")]
+    cache_pending: HashMap<u32, String>,
+    awaitable: Vec<String>,
+    /// The program's declared dotted functions; empty for runs loaded from unversioned
+    /// dumps, which report every method call on a namespace.
+    dotted_funcs: Vec<String>,
+    /// The quota group the run draws from; not kept in dumps.
+    #[serde(skip)]
+    quota: Option<Membership>,
+    /// Payloads the run is resumed with are decoded lossily; see the `lossy_utf8` option.
+    pub lossy_utf8: bool,
+    /// Args and attributes of the host exceptions raised into the run, one entry per
+    /// type and message, put back when such an exception is reported to the host.
+    exception_data: Vec<ExceptionData>,
+}
+
+impl RunContext {
+    pub fn new(options: RunOptions, signatures: Vec<Signature>) -> Self {
+        metrics::run_started();
+        Self {
+            stats: RunStats::default(),
+            limits: options.limits,
+            call_timeouts: options.call_timeouts,
+            signatures,
+            return_mismatch: options.return_mismatch,
+            dataclasses: options.dataclasses,
+            object_handles: options.object_handles,
+            awaiting_returns: HashMap::new(),
+            trace: options.trace.then(Trace::default),
+            run_id: options.run_id,
+            audit: options.audit_os_calls.then(AuditLog::default),
+            policy: options.policy,
+            restrictions: Vec::new(),
+            pure: options.pure,
+            cost_model: options.cost_model,
+            print_overflow: options.print_overflow,
+            error_format: options.error_format,
+            warnings: Warnings::default(),
+            print_to_stdout: options.print_to_stdout,
+            output: Vec::new(),
+            future_resolution: options.future_resolution,
+            gathered: Vec::new(),
+            call_priorities: options.call_priorities,
+            priorities: HashMap::new(),
+            disabled_builtins: Vec::new(),
+            overridden_builtins: Vec::new(),
+            cache_calls: options.cache_calls,
+            call_cache: HashMap::new(),
+            cache_pending: HashMap::new(),
+            awaitable: options.awaitable,
+            dotted_funcs: Vec::new(),
+            quota: None,
+            lossy_utf8: options.lossy_utf8,
+            exception_data: Vec::new(),
+        }
+    }
+
+    /// A copy for hashing equal states alike: without what the run has recorded about
+    /// its own progress (stats, trace, audit log, warnings) or its run id.
+    pub fn canonical(&self) -> Self {
+        let mut context = self.clone();
+        context.stats = RunStats::default();
+        context.trace = self.trace.as_ref().map(|_| Trace::default());
+        context.audit = self.audit.as_ref().map(|_| AuditLog::default());
+        context.warnings = Warnings::default();
+        context.run_id = String::new();
+        context.quota = None;
+        context
+    }
+
+    /// Makes later steps draw from `group`'s budgets, or from none when it is `None`.
+    pub fn join_quota(&mut self, group: Option<Arc<QuotaGroup>>) {
+        self.quota = group.map(Membership::new);
+    }
+
+    /// Makes calls to the builtins in `names`, which the program declares as external
+    /// functions to shadow them, raise `PermissionError` instead of being reported.
+    pub fn disable_builtins(&mut self, names: Vec<String>) {
+        self.disabled_builtins = names;
+    }
+
+    /// Makes method calls on a namespace that don't name one of the dotted functions
+    /// in `names` raise `AttributeError` instead of being reported.
+    pub fn declare_dotted_funcs(&mut self, names: Vec<String>) {
+        self.dotted_funcs = names;
+    }
+
+    /// Marks calls to the builtins in `names`, which the program declares as external
+    /// functions to shadow them, as builtin overrides when they are reported.
+    pub fn override_builtins(&mut self, names: Vec<String>) {
+        self.overridden_builtins = names;
+    }
+
+    /// Whether the script awaits calls to `function_name`, so they can be deferred.
+    pub fn is_awaitable(&self, function_name: &str) -> bool {
+        self.awaitable.iter().any(|name| name == function_name)
+    }
+
+    pub fn is_builtin_override(&self, function_name: &str) -> bool {
+        self.overridden_builtins
+            .iter()
+            .any(|name| name == function_name)
+    }
+
+    fn signature(&self, name: &str) -> Option<&Signature> {
+        self.signatures.iter().find(|s| s.name == name)
+    }
+
+    /// Checks an external call against the run's policy, its nesting limit, and its
+    /// declared signature, returning the `PermissionError`, `ValueError`, or `TypeError`
+    /// to raise in the script instead of reporting it.
+    pub fn check_call(
+        &self,
+        function_name: &str,
+        args: &[MontyObject],
+        kwargs: &[(MontyObject, MontyObject)],
+        method_call: bool,
+    ) -> FfiResult<Option<MontyException>> {
+        if self
+            .disabled_builtins
+            .iter()
+            .any(|name| name == function_name)
+        {
+            return Ok(Some(MontyException::new(
+                ExcType::PermissionError,
+                Some(format!("{function_name}() is disabled")),
+            )));
+        }
+        if function_name == EMIT_FUNCTION {
+            return Ok(check_emit(args, kwargs));
+        }
+        if function_name == SLEEP_FUNCTION {
+            return Ok(sleep_delay_ms(args, kwargs).err());
+        }
+        if function_name == INPUT_FUNCTION {
+            return Ok(input_prompt(args, kwargs).err());
+        }
+        let (name, args) = match namespaces::dotted_name(function_name, args, method_call) {
+            Some(name) => {
+                if let Some(exc) = self.check_dotted_func(&name) {
+                    return Ok(Some(exc));
+                }
+                (name, &args[1..])
+            }
+            None => (function_name.to_owned(), args),
+        };
+        for policy in self.policies() {
+            if let Some(exc) = policy.check_function(&name)? {
+                return Ok(Some(exc));
+            }
+        }
+        if let Some(exc) = self.check_arg_nesting(&name, args, kwargs) {
+            return Ok(Some(exc));
+        }
+        Ok(self
+            .signature(&name)
+            .and_then(|signature| signature.check(args, kwargs).err()))
+    }
+
+    /// Returns the `AttributeError` for a method call on a namespace that names no
+    /// declared function, like `db.missing()` when only `db.query` is declared.
+    fn check_dotted_func(&self, name: &str) -> Option<MontyException> {
+        if self.dotted_funcs.is_empty() || self.dotted_funcs.iter().any(|f| f == name) {
+            return None;
+        }
+        let (namespace, member) = name.rsplit_once('.')?;
+        Some(MontyException::new(
+            ExcType::AttributeError,
+            Some(format!(
+                "'{namespace}' namespace has no attribute '{member}'"
+            )),
+        ))
+    }
+
+    /// Checks an OS call against pure mode, the run's policy, and its nesting limit,
+    /// returning the exception to raise in the script instead of reporting it. Rejected
+    /// calls are still audited.
+    pub fn check_os_call(
+        &mut self,
+        call_id: u32,
+        function: &str,
+        args: &[MontyObject],
+        kwargs: &[(MontyObject, MontyObject)],
+    ) -> FfiResult<Option<MontyException>> {
+        let mut rejected = None;
+        if self.pure {
+            rejected = Some(policy::os_call_disabled(function)?);
+        }
+        for policy in self.policies() {
+            if rejected.is_some() {
+                break;
+            }
+            rejected = policy.check_os_function(function)?;
+        }
+        if rejected.is_none() {
+            rejected = self.check_arg_nesting(function, args, kwargs);
+        }
+        if let (Some(exc), Some(audit)) = (&rejected, &mut self.audit) {
+            audit.rejected(call_id, function, args, kwargs, exc);
+        }
+        Ok(rejected)
+    }
+
+    /// Returns the `ValueError` to raise when an argument nests deeper than
+    /// `max_nesting_depth`, before the host has to decode it.
+    fn check_arg_nesting(
+        &self,
+        function: &str,
+        args: &[MontyObject],
+        kwargs: &[(MontyObject, MontyObject)],
+    ) -> Option<MontyException> {
+        let limit = self.limits.max_nesting_depth?;
+        let values = args.iter().chain(kwargs.iter().map(|(_, value)| value));
+        let depth = values.map(json::nesting_depth).max()?;
+        (depth > limit).then(|| {
+            MontyException::new(
+                ExcType::ValueError,
+                Some(format!(
+                    "{function}() argument nests {depth} levels deep, over the limit of {limit}"
+                )),
+            )
+        })
+    }
+
+    /// Fails a run whose result nests deeper than `max_nesting_depth`.
+    pub fn check_result_nesting(&self, value: &MontyObject) -> FfiResult<()> {
+        let Some(limit) = self.limits.max_nesting_depth else {
+            return Ok(());
+        };
+        match json::nesting_depth(value) {
+            depth if depth > limit => Err(FfiError::Message(format!(
+                "result nests {depth} levels deep, over the limit of {limit}"
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Remembers a call handed to the host so its result can be checked on resume.
+    pub fn expect_result(&mut self, call_id: u32, function_name: &str) {
+        if let Some(trace) = &mut self.trace {
+            trace.call_started(call_id, function_name);
+        }
+        let priority = self.call_priorities.for_call(function_name);
+        if priority != 0 {
+            self.priorities.insert(call_id, priority);
+        }
+        if self
+            .signature(function_name)
+            .is_some_and(Signature::declares_return)
+        {
+            self.awaiting_returns
+                .insert(call_id, function_name.to_owned());
+        }
+    }
+
+    /// Key a host can use to dedupe an external call's side effects when it retries
+    /// after a crash: the script's own `idempotency_key=` argument when it passes a
+    /// string, otherwise a digest of the run id and call id, which come out the same
+    /// each time the run is resumed or recovered from the same point. Runs without a
+    /// run id have no stable key to derive.
+    pub fn idempotency_key(
+        &self,
+        call_id: u32,
+        kwargs: &[(MontyObject, MontyObject)],
+    ) -> Option<String> {
+        let supplied = kwargs.iter().find_map(|(key, value)| match (key, value) {
+            (MontyObject::String(key), MontyObject::String(value)) if key == "idempotency_key" => {
+                Some(value.clone())
+            }
+            _ => None,
+        });
+        supplied.or_else(|| {
+            (!self.run_id.is_empty())
+                .then(|| sha256::hex_digest(format!("{}\0{call_id}", self.run_id).as_bytes()))
+        })
+    }
+
+    /// Adds an OS call the script made to the audit log, when the run keeps one.
+    pub fn audit_os_call(
+        &mut self,
+        call_id: u32,
+        function: &str,
+        args: &[MontyObject],
+        kwargs: &[(MontyObject, MontyObject)],
+    ) {
+        if let Some(audit) = &mut self.audit {
+            audit.attempted(call_id, function, args, kwargs);
+        }
+    }
+
+    /// Narrows what the run may call from now on: calls must also pass `policy`.
+    /// Restrictions are kept in dumps and can't be lifted.
+    pub fn restrict(&mut self, policy: Policy) {
+        self.restrictions.push(policy);
+    }
+
+    fn policies(&self) -> impl Iterator<Item = &Policy> {
+        std::iter::once(&self.policy).chain(&self.restrictions)
+    }
+
+    pub fn audit_log(&self) -> Option<&[AuditEntry]> {
+        self.audit.as_ref().map(AuditLog::entries)
+    }
+
+    /// A cached result for a call to a `cache_calls` function made before with the same
+    /// arguments. On a miss the call's cache key is kept, and the result the host
+    /// resolves it with is cached.
+    pub fn cached_result(
+        &mut self,
+        call_id: u32,
+        function_name: &str,
+        args: &[MontyObject],
+        kwargs: &[(MontyObject, MontyObject)],
+        method_call: bool,
+    ) -> FfiResult<Option<MontyObject>> {
+        if self.cache_calls.is_empty() {
+            return Ok(None);
+        }
+        let (name, args, _) =
+            namespaces::resolve(function_name.to_owned(), args.to_vec(), method_call);
+        if !self.cache_calls.contains(&name) {
+            return Ok(None);
+        }
+        let key = json::cache_key(&name, &args, kwargs)?;
+        if let Some(cached) = self.call_cache.get(&key) {
+            return json::decode_object(cached).map(Some);
+        }
+        self.cache_pending.insert(call_id, key);
+        Ok(None)
+    }
+
+    /// Cache key of a reported call to a `cache_calls` function, for hosts that keep
+    /// results across runs.
+    pub fn cache_key(&self, call_id: u32) -> Option<&str> {
+        self.cache_pending.get(&call_id).map(String::as_str)
+    }
+
+    /// Checks a host result against the call's declared return type; a mismatch is
+    /// raised as `TypeError` in the script or fails the resume, per `return_mismatch`.
+    pub fn check_result(
+        &mut self,
+        call_id: u32,
+        result: ExternalResult,
+    ) -> FfiResult<ExternalResult> {
+        if matches!(result, ExternalResult::Future) {
+            return Ok(result);
+        }
+        let key = self.cache_pending.remove(&call_id);
+        let result = self.conform_result(call_id, result)?;
+        if let (Some(key), ExternalResult::Return(value)) = (key, &result) {
+            self.call_cache.insert(key, json::encode_internal(value)?);
+        }
+        Ok(result)
+    }
+
+    fn conform_result(
+        &mut self,
+        call_id: u32,
+        result: ExternalResult,
+    ) -> FfiResult<ExternalResult> {
+        if let Some(trace) = &mut self.trace {
+            trace.call_ended(call_id);
+        }
+        self.priorities.remove(&call_id);
+        if let Some(audit) = &mut self.audit {
+            audit.resolved(call_id, &result);
+        }
+        let Some(name) = self.awaiting_returns.remove(&call_id) else {
+            return Ok(result);
+        };
+        let ExternalResult::Return(value) = result else {
+            return Ok(result);
+        };
+        let Some(signature) = self.signature(&name) else {
+            return Ok(ExternalResult::Return(value));
+        };
+        match self.conform_return(signature, value) {
+            Ok(value) => Ok(ExternalResult::Return(value)),
+            Err(message) => match self.return_mismatch {
+                ReturnMismatch::Raise => Ok(ExternalResult::Error(MontyException::new(
+                    ExcType::TypeError,
+                    Some(message),
+                ))),
+                ReturnMismatch::Fail => Err(FfiError::ReturnMismatch(message)),
+            },
+        }
+    }
+
+    /// Fails like `check_result` would under `ReturnMismatch::Fail` when `value` doesn't
+    /// match `call_id`'s declared return type, but without recording anything, so a
+    /// resume can be rejected before it takes the snapshot apart.
+    pub fn precheck_return(&self, call_id: u32, value: &MontyObject) -> FfiResult<()> {
+        match self.awaiting_returns.get(&call_id) {
+            Some(name) => self.precheck_return_of(name, value),
+            None => Ok(()),
+        }
+    }
+
+    /// `precheck_return` for a call to `function_name` that isn't expected yet.
+    pub fn precheck_return_of(&self, function_name: &str, value: &MontyObject) -> FfiResult<()> {
+        if self.return_mismatch != ReturnMismatch::Fail {
+            return Ok(());
+        }
+        match self.signature(function_name) {
+            Some(signature) if signature.declares_return() => self
+                .conform_return(signature, value.clone())
+                .map(drop)
+                .map_err(FfiError::ReturnMismatch),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn precheck_results(&self, results: &[(u32, ExternalResult)]) -> FfiResult<()> {
+        for (call_id, result) in results {
+            if let ExternalResult::Return(value) = result {
+                self.precheck_return(*call_id, value)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks `value` against the declared return type, first building a plain dict
+    /// into a registered dataclass the type names.
+    fn conform_return(
+        &self,
+        signature: &Signature,
+        value: MontyObject,
+    ) -> Result<MontyObject, String> {
+        if signature.check_return(&value).is_ok() {
+            return Ok(value);
+        }
+        let schema = signature
+            .return_classes()
+            .find_map(|class| self.dataclasses.iter().find(|schema| schema.name == class));
+        let value = match (schema, value) {
+            (Some(schema), MontyObject::Dict(pairs)) => {
+                schema.construct(pairs).map_err(|reason| {
+                    format!("{}() must return {}: {reason}", signature.name, schema.name)
+                })?
+            }
+            (_, value) => value,
+        };
+        signature.check_return(&value)?;
+        Ok(value)
+    }
+
+    pub fn check_results(
+        &mut self,
+        results: Vec<(u32, ExternalResult)>,
+    ) -> FfiResult<Vec<(u32, ExternalResult)>> {
+        results
+            .into_iter()
+            .map(|(call_id, result)| Ok((call_id, self.check_result(call_id, result)?)))
+            .collect()
+    }
+
+    /// Holds back future results under `ResolutionMode::Gather`: returns the results
+    /// for the calls in `pending` as one `results_json` array once each has one, or
+    /// `None` while some are still missing. Results for calls not in `pending` stay
+    /// gathered until a later resume lists them.
+    pub fn gather(&mut self, results_json: &str, pending: &[u32]) -> FfiResult<Option<String>> {
+        let entries: Vec<FutureResolution> = serde_json::from_str(results_json)?;
+        for entry in entries {
+            let settled = entry.result.is_some()
+                || entry.error.is_some()
+                || entry.cancelled
+                || entry.timed_out;
+            if !settled {
+                continue;
+            }
+            self.gathered
+                .retain(|(call_id, _)| *call_id != entry.call_id);
+            self.gathered
+                .push((entry.call_id, serde_json::to_string(&entry)?));
+        }
+        if !self.awaiting_futures(pending).is_empty() {
+            return Ok(None);
+        }
+        let (ready, kept): (Vec<_>, Vec<_>) = std::mem::take(&mut self.gathered)
+            .into_iter()
+            .partition(|(call_id, _)| pending.contains(call_id));
+        self.gathered = kept;
+        let entries: Vec<String> = ready.into_iter().map(|(_, entry)| entry).collect();
+        Ok(Some(format!("[{}]", entries.join(","))))
+    }
+
+    /// The calls in `pending` that have no gathered result yet.
+    pub fn awaiting_futures(&self, pending: &[u32]) -> Vec<u32> {
+        pending
+            .iter()
+            .copied()
+            .filter(|call_id| {
+                !self
+                    .gathered
+                    .iter()
+                    .any(|(gathered, _)| gathered == call_id)
+            })
+            .collect()
+    }
+
+    /// `pending` ordered highest priority first, keeping the VM's order among equals.
+    pub fn by_priority(&self, pending: &[u32]) -> Vec<u32> {
+        let mut ordered = pending.to_vec();
+        ordered.sort_by_key(|call_id| {
+            std::cmp::Reverse(self.priorities.get(call_id).copied().unwrap_or(0))
+        });
+        ordered
+    }
+
+    pub fn remaining_budget(&self) -> RemainingBudget {
+        self.limits.remaining(&self.stats)
+    }
+
+    /// Replaces the limits enforced from the next resume on; consumed counters are kept.
+    pub fn set_limits(&mut self, limits: ResourceLimits) -> LimitChanges {
+        let changes = self.limits.changes_to(&limits);
+        self.limits = limits;
+        changes
+    }
+
+    /// Runs one VM step (start or resume) with this run's tracker state and printer
+    /// installed, formatting an uncaught exception with the run's `error_format`.
+    pub fn execute<T>(
+        &mut self,
+        step: impl FnOnce(&mut PrintWriter) -> Result<T, MontyException>,
+    ) -> FfiResult<T> {
+        self.execute_caused(step, &HostCauses::default())
+    }
+
+    /// Like `execute`, printing the cause chain before a failure that is one of the
+    /// host exceptions the step raised into the script.
+    pub fn execute_caused<T>(
+        &mut self,
+        step: impl FnOnce(&mut PrintWriter) -> Result<T, MontyException>,
+        causes: &HostCauses,
+    ) -> FfiResult<T> {
+        for data in causes.data() {
+            self.exception_data
+                .retain(|kept| !kept.same_exception(data));
+            self.exception_data.push(data.clone());
+        }
+        let mut printer = Printer::new(
+            self.limits.max_print_bytes,
+            self.print_overflow,
+            self.print_to_stdout,
+        );
+        let mut print = PrintWriter::Callback(&mut printer);
+        let started = self.trace.as_ref().map(Trace::now);
+        let instructions = self.stats.instructions;
+        let allowance = self.quota.as_ref().map(Membership::allowance);
+        let result = tracker::track(
+            &mut self.stats,
+            &self.limits,
+            &self.cost_model,
+            allowance,
+            || step(&mut print),
+        );
+        let executed = self.stats.instructions.saturating_sub(instructions);
+        metrics::instructions(executed);
+        if let Some(quota) = &mut self.quota {
+            quota.charge(executed, self.stats.memory);
+        }
+        if let (Some(trace), Some(at_us)) = (&mut self.trace, started) {
+            trace.step(at_us);
+        }
+        self.output.append(&mut printer.take_output());
+        if printer.truncated() {
+            let limit = self.limits.max_print_bytes.unwrap_or_default();
+            self.warnings.push(
+                WarningKind::OutputTruncated,
+                format!("print output past {limit} bytes was dropped"),
+            );
+        }
+        self.warnings.check_limits(&self.limits, &self.stats);
+        result.map_err(|exc| FfiError::with_causes(exc, causes, self.error_format))
+    }
+
+    /// Args and attributes of the host exceptions raised into the run; see
+    /// `json::restoring_exception_data`.
+    pub fn exception_data(&self) -> &[ExceptionData] {
+        &self.exception_data
+    }
+
+    /// Output printed since the last progress result was reported.
+    pub fn take_output(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.output)
+    }
+
+    /// Warnings gathered since the last progress result was reported.
+    pub fn take_warnings(&mut self) -> Vec<Warning> {
+        self.warnings.take()
+    }
+
+    /// The run's Chrome trace, when it was started with the `trace` option.
+    pub fn trace_json(&self) -> Option<Value> {
+        self.trace.as_ref().map(Trace::to_json)
+    }
+}
+
+/// The delay a `host_sleep` call asks for, in milliseconds. It takes one non-negative
+/// number of seconds; other calls raise `TypeError` or `ValueError`.
+pub fn sleep_delay_ms(
+    args: &[MontyObject],
+    kwargs: &[(MontyObject, MontyObject)],
+) -> Result<u64, MontyException> {
+    let seconds = match (args, kwargs.is_empty()) {
+        ([MontyObject::Int(seconds)], true) => *seconds as f64,
+        ([MontyObject::Float(seconds)], true) => *seconds,
+        _ => {
+            return Err(MontyException::new(
+                ExcType::TypeError,
+                Some(format!("{SLEEP_FUNCTION}() takes one number of seconds")),
+            ))
+        }
+    };
+    if !seconds.is_finite() || seconds < 0.0 {
+        return Err(MontyException::new(
+            ExcType::ValueError,
+            Some("sleep length must be a non-negative number".into()),
+        ));
+    }
+    Ok((seconds * 1000.0).round() as u64)
+}
+
+/// The prompt of an `input` call: its one optional positional `str`. Unlike Python,
+/// other values raise `TypeError` rather than being converted with `str()`.
+pub fn input_prompt(
+    args: &[MontyObject],
+    kwargs: &[(MontyObject, MontyObject)],
+) -> Result<String, MontyException> {
+    match (args, kwargs.is_empty()) {
+        ([], true) => Ok(String::new()),
+        ([MontyObject::String(prompt)], true) => Ok(prompt.clone()),
+        _ => Err(MontyException::new(
+            ExcType::TypeError,
+            Some(format!("{INPUT_FUNCTION}() takes an optional str prompt")),
+        )),
+    }
+}
+
+/// `host_emit` takes exactly one positional value; other calls raise `TypeError`.
+fn check_emit(
+    args: &[MontyObject],
+    kwargs: &[(MontyObject, MontyObject)],
+) -> Option<MontyException> {
+    (args.len() != 1 || !kwargs.is_empty()).then(|| {
+        MontyException::new(
+            ExcType::TypeError,
+            Some(format!(
+                "{EMIT_FUNCTION}() takes exactly one positional argument"
+            )),
+        )
+    })
+}
@@ -0,0 +1,54 @@
+//! Dataclass schemas registered per run.
+//!
+//! When a call's declared return type names a registered dataclass, the host may
+//! resume it with a plain object keyed by field name; it is built into the
+//! `MontyObject::Dataclass` the schema describes instead of requiring a hand-built
+//! `$dataclass` envelope.
+
+use monty::{DictPairs, MontyObject};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DataclassSchema {
+    pub name: String,
+    pub type_id: u64,
+    /// Field names in declaration order.
+    pub fields: Vec<String>,
+    #[serde(default)]
+    pub frozen: bool,
+}
+
+impl DataclassSchema {
+    /// Builds the dataclass from a dict holding exactly the schema's fields.
+    pub fn construct(&self, pairs: DictPairs) -> Result<MontyObject, String> {
+        let mut values: Vec<Option<MontyObject>> = vec![None; self.fields.len()];
+        for (key, value) in pairs {
+            let MontyObject::String(key) = key else {
+                return Err("field names must be strings".to_owned());
+            };
+            let index = self
+                .fields
+                .iter()
+                .position(|field| *field == key)
+                .ok_or_else(|| format!("unexpected field '{key}'"))?;
+            values[index] = Some(value);
+        }
+        let attrs = self
+            .fields
+            .iter()
+            .zip(values)
+            .map(|(field, value)| {
+                let value = value.ok_or_else(|| format!("missing field '{field}'"))?;
+                Ok((MontyObject::String(field.clone()), value))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        Ok(MontyObject::Dataclass {
+            name: self.name.clone(),
+            type_id: self.type_id,
+            field_names: self.fields.clone(),
+            attrs: DictPairs::from(attrs),
+            frozen: self.frozen,
+        })
+    }
+}
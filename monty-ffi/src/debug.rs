@@ -0,0 +1,102 @@
+//! Opt-in tracking of every handle, string, and byte buffer handed to the host, for
+//! finding leaks while wiring up finalizers in garbage-collected languages.
+//!
+//! Tracking is off by default and costs one atomic load per allocation when off.
+//! Once `monty_debug_track_handles` turns it on, each pointer given to the host is
+//! recorded until the matching free (or the call that consumes it) takes it back;
+//! whatever is still recorded when `monty_debug_report_leaks` runs has not been freed
+//! yet, and `monty_debug_list_handles` lists it with its age and size. Pointers
+//! handed out before tracking started are not recorded, and freeing them is ignored.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex, OnceLock,
+    },
+    time::Instant,
+};
+
+use serde::Serialize;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+struct Entry {
+    kind: &'static str,
+    created: Instant,
+    size: Option<usize>,
+}
+
+/// One pointer the host holds, as listed by `monty_debug_list_handles`.
+#[derive(Debug, Clone, Serialize)]
+pub struct Held {
+    pub kind: &'static str,
+    pub age_ms: u64,
+    /// Approximate bytes behind it, when known: the VM heap of a snapshot, or the
+    /// length of a string or byte buffer.
+    pub size: Option<usize>,
+}
+
+fn live() -> &'static Mutex<HashMap<usize, Entry>> {
+    static LIVE: OnceLock<Mutex<HashMap<usize, Entry>>> = OnceLock::new();
+    LIVE.get_or_init(Default::default)
+}
+
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+    if !enabled {
+        live().lock().unwrap_or_else(|err| err.into_inner()).clear();
+    }
+}
+
+/// Records `ptr` as owned by the host; `kind` names what it is, e.g. `"snapshot"`.
+pub fn handed_out<T>(kind: &'static str, ptr: *const T, size: Option<usize>) {
+    if !ENABLED.load(Ordering::Relaxed) || ptr.is_null() {
+        return;
+    }
+    let entry = Entry {
+        kind,
+        created: Instant::now(),
+        size,
+    };
+    live()
+        .lock()
+        .unwrap_or_else(|err| err.into_inner())
+        .insert(ptr as usize, entry);
+}
+
+/// Forgets `ptr` once the host has freed it or passed it to a call that consumes it.
+pub fn taken_back<T>(ptr: *const T) {
+    if !ENABLED.load(Ordering::Relaxed) || ptr.is_null() {
+        return;
+    }
+    live()
+        .lock()
+        .unwrap_or_else(|err| err.into_inner())
+        .remove(&(ptr as usize));
+}
+
+/// How many pointers of each kind the host holds.
+pub fn outstanding() -> BTreeMap<&'static str, usize> {
+    let live = live().lock().unwrap_or_else(|err| err.into_inner());
+    let mut counts = BTreeMap::new();
+    for entry in live.values() {
+        *counts.entry(entry.kind).or_default() += 1;
+    }
+    counts
+}
+
+/// Everything the host holds, oldest first.
+pub fn held() -> Vec<Held> {
+    let live = live().lock().unwrap_or_else(|err| err.into_inner());
+    let mut entries: Vec<&Entry> = live.values().collect();
+    entries.sort_by_key(|entry| entry.created);
+    entries
+        .into_iter()
+        .map(|entry| Held {
+            kind: entry.kind,
+            age_ms: entry.created.elapsed().as_millis() as u64,
+            size: entry.size,
+        })
+        .collect()
+}
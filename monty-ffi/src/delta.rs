@@ -0,0 +1,150 @@
+//! Byte-level deltas between two snapshot dumps.
+//!
+//! A delta is a list of copy/insert ops against a base dump: stretches of the current
+//! dump that also occur in the base (found with a rolling hash over fixed-size blocks)
+//! become copies, everything else is stored literally. The base's length and hash are
+//! recorded so applying a delta to the wrong base fails instead of producing garbage.
+
+use std::collections::HashMap;
+
+use postcard::{from_bytes, to_allocvec};
+use serde::{Deserialize, Serialize};
+
+use crate::error::{FfiError, FfiResult};
+
+const DELTA_MAGIC: &[u8] = b"MONTYDLT\x01";
+const BLOCK: usize = 32;
+const PRIME: u64 = 0x100_0000_01b3;
+/// Weight of the oldest byte in a block hash, `PRIME^(BLOCK - 1)`.
+const LEADING: u64 = {
+    let mut power = 1u64;
+    let mut i = 1;
+    while i < BLOCK {
+        power = power.wrapping_mul(PRIME);
+        i += 1;
+    }
+    power
+};
+
+#[derive(Serialize, Deserialize)]
+enum Op {
+    Copy { offset: u64, len: u64 },
+    Insert(Vec<u8>),
+}
+
+#[derive(Serialize, Deserialize)]
+struct Delta {
+    base_len: u64,
+    base_hash: u64,
+    ops: Vec<Op>,
+}
+
+/// Encodes `current` as a delta against `base`.
+pub fn diff(base: &[u8], current: &[u8]) -> FfiResult<Vec<u8>> {
+    let mut blocks = HashMap::new();
+    for offset in (0..base.len().saturating_sub(BLOCK - 1)).step_by(BLOCK) {
+        blocks
+            .entry(block_hash(&base[offset..offset + BLOCK]))
+            .or_insert(offset);
+    }
+
+    let mut ops = Vec::new();
+    let mut literal = 0;
+    let mut pos = 0;
+    let mut hash = None;
+    while pos + BLOCK <= current.len() {
+        let window = hash.unwrap_or_else(|| block_hash(&current[pos..pos + BLOCK]));
+        let found = blocks
+            .get(&window)
+            .copied()
+            .filter(|&offset| base[offset..offset + BLOCK] == current[pos..pos + BLOCK]);
+        if let Some(offset) = found {
+            let (mut start, mut from) = (pos, offset);
+            while start > literal && from > 0 && base[from - 1] == current[start - 1] {
+                start -= 1;
+                from -= 1;
+            }
+            let (mut end, mut to) = (pos + BLOCK, offset + BLOCK);
+            while end < current.len() && to < base.len() && base[to] == current[end] {
+                end += 1;
+                to += 1;
+            }
+            if start > literal {
+                ops.push(Op::Insert(current[literal..start].to_vec()));
+            }
+            ops.push(Op::Copy {
+                offset: from as u64,
+                len: (end - start) as u64,
+            });
+            literal = end;
+            pos = end;
+            hash = None;
+            continue;
+        }
+        hash =
+            (pos + BLOCK < current.len()).then(|| roll(window, current[pos], current[pos + BLOCK]));
+        pos += 1;
+    }
+    if literal < current.len() {
+        ops.push(Op::Insert(current[literal..].to_vec()));
+    }
+
+    let delta = Delta {
+        base_len: base.len() as u64,
+        base_hash: fingerprint(base),
+        ops,
+    };
+    let mut bytes = DELTA_MAGIC.to_vec();
+    bytes.extend(to_allocvec(&delta)?);
+    Ok(bytes)
+}
+
+/// Rebuilds the dump a delta was produced from.
+pub fn apply(base: &[u8], delta: &[u8]) -> FfiResult<Vec<u8>> {
+    let rest = delta
+        .strip_prefix(DELTA_MAGIC)
+        .ok_or_else(|| FfiError::Message("not a snapshot delta".into()))?;
+    let delta: Delta = from_bytes(rest)?;
+    if delta.base_len != base.len() as u64 || delta.base_hash != fingerprint(base) {
+        return Err(FfiError::Message(
+            "snapshot delta was produced against a different base".into(),
+        ));
+    }
+
+    let mut bytes = Vec::new();
+    for op in delta.ops {
+        match op {
+            Op::Copy { offset, len } => {
+                let range = usize::try_from(offset)
+                    .ok()
+                    .zip(usize::try_from(len).ok())
+                    .and_then(|(offset, len)| Some(offset..offset.checked_add(len)?))
+                    .and_then(|range| base.get(range))
+                    .ok_or_else(|| FfiError::Message("snapshot delta copy out of range".into()))?;
+                bytes.extend_from_slice(range);
+            }
+            Op::Insert(literal) => bytes.extend(literal),
+        }
+    }
+    Ok(bytes)
+}
+
+fn block_hash(block: &[u8]) -> u64 {
+    block.iter().fold(0u64, |hash, &byte| {
+        hash.wrapping_mul(PRIME).wrapping_add(byte as u64)
+    })
+}
+
+/// Slides a block hash one byte forward, dropping `out` and appending `byte`.
+fn roll(hash: u64, out: u8, byte: u8) -> u64 {
+    hash.wrapping_sub((out as u64).wrapping_mul(LEADING))
+        .wrapping_mul(PRIME)
+        .wrapping_add(byte as u64)
+}
+
+/// FNV-1a over the whole base dump.
+fn fingerprint(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(PRIME)
+    })
+}
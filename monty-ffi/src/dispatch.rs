@@ -0,0 +1,222 @@
+//! Turns a run's progress into the step its driver handles next, so reporting progress
+//! to the host, `monty_snapshot_resume_batch`, `monty_run_drive` and `monty_run_recover`
+//! apply the same checks and bookkeeping to every call.
+//!
+//! `step` answers calls the run rejects or has cached in the script, then counts an
+//! external or OS call in the run's stats, resolves a namespace call to its dotted name,
+//! remembers the call so its result is checked, and audits an OS call. A completed run
+//! has its result checked and is counted. Drivers resolve a call with `Call::run`.
+
+use monty::{ExternalResult, FutureSnapshot, MontyObject, RunProgress, Snapshot};
+
+use crate::{
+    compiled::{EMIT_FUNCTION, INPUT_FUNCTION, SLEEP_FUNCTION},
+    context::{self, RunContext},
+    error::FfiResult,
+    json::HostCauses,
+    metrics, namespaces,
+    tracker::FfiTracker,
+};
+
+/// What a run needs from its driver next.
+pub enum Step {
+    Complete(MontyObject),
+    /// A `host_emit` call; the emitted value is its last argument.
+    Emit {
+        call_id: u32,
+        args: Vec<MontyObject>,
+        kwargs: Vec<(MontyObject, MontyObject)>,
+        state: Snapshot<FfiTracker>,
+    },
+    /// A `host_sleep` call, with its validated delay.
+    Sleep {
+        call_id: u32,
+        delay_ms: u64,
+        state: Snapshot<FfiTracker>,
+    },
+    /// An `input` call, with its validated prompt.
+    Prompt {
+        call_id: u32,
+        prompt: String,
+        args: Vec<MontyObject>,
+        kwargs: Vec<(MontyObject, MontyObject)>,
+        state: Snapshot<FfiTracker>,
+    },
+    /// An external or OS call for the host to resolve.
+    Call(Call),
+    ResolveFutures(FutureSnapshot<FfiTracker>),
+}
+
+/// An external or OS call, already counted and expected by the run's context.
+pub struct Call {
+    pub call_id: u32,
+    /// The called function, with a namespace call resolved to its dotted name; empty
+    /// for an OS call.
+    pub function_name: String,
+    pub os_function: Option<String>,
+    pub args: Vec<MontyObject>,
+    pub kwargs: Vec<(MontyObject, MontyObject)>,
+    pub method_call: bool,
+    pub state: Snapshot<FfiTracker>,
+}
+
+impl Call {
+    /// The name the run's per-function options, like timeouts and `awaitable`, use.
+    pub fn name(&self) -> &str {
+        self.os_function.as_deref().unwrap_or(&self.function_name)
+    }
+
+    /// Checks `resolution` against the call and resumes the run with it.
+    pub fn run(
+        self,
+        resolution: ExternalResult,
+        context: &mut RunContext,
+        causes: &HostCauses,
+    ) -> FfiResult<RunProgress<FfiTracker>> {
+        let resolution = context.check_result(self.call_id, resolution)?;
+        context.execute_caused(|print| self.state.run(resolution, print), causes)
+    }
+}
+
+/// Advances `progress` past what the run answers itself and returns the step its
+/// driver has to handle, with that step's bookkeeping done.
+pub fn step(progress: RunProgress<FfiTracker>, context: &mut RunContext) -> FfiResult<Step> {
+    Ok(match reject_invalid_calls(progress, context)? {
+        RunProgress::Complete(value) => {
+            context.check_result_nesting(&value)?;
+            metrics::run_completed();
+            Step::Complete(value)
+        }
+        RunProgress::FunctionCall {
+            function_name,
+            args,
+            kwargs,
+            call_id,
+            state,
+            ..
+        } if function_name == EMIT_FUNCTION => Step::Emit {
+            call_id,
+            args,
+            kwargs,
+            state,
+        },
+        RunProgress::FunctionCall {
+            function_name,
+            args,
+            kwargs,
+            call_id,
+            state,
+            ..
+        } if function_name == SLEEP_FUNCTION => Step::Sleep {
+            call_id,
+            // `reject_invalid_calls` has raised for any call without a valid delay.
+            delay_ms: context::sleep_delay_ms(&args, &kwargs).unwrap_or(0),
+            state,
+        },
+        RunProgress::FunctionCall {
+            function_name,
+            args,
+            kwargs,
+            call_id,
+            state,
+            ..
+        } if function_name == INPUT_FUNCTION => Step::Prompt {
+            call_id,
+            // `reject_invalid_calls` has raised for any call with an invalid prompt.
+            prompt: context::input_prompt(&args, &kwargs).unwrap_or_default(),
+            args,
+            kwargs,
+            state,
+        },
+        RunProgress::FunctionCall {
+            function_name,
+            args,
+            kwargs,
+            call_id,
+            method_call,
+            state,
+        } => {
+            context.stats.external_calls += 1;
+            let (function_name, args, method_call) =
+                namespaces::resolve(function_name, args, method_call);
+            context.expect_result(call_id, &function_name);
+            Step::Call(Call {
+                call_id,
+                function_name,
+                os_function: None,
+                args,
+                kwargs,
+                method_call,
+                state,
+            })
+        }
+        RunProgress::OsCall {
+            function,
+            args,
+            kwargs,
+            call_id,
+            state,
+        } => {
+            context.stats.os_calls += 1;
+            let function = function.to_string();
+            context.expect_result(call_id, &function);
+            context.audit_os_call(call_id, &function, &args, &kwargs);
+            Step::Call(Call {
+                call_id,
+                function_name: String::new(),
+                os_function: Some(function),
+                args,
+                kwargs,
+                method_call: false,
+                state,
+            })
+        }
+        RunProgress::ResolveFutures(state) => Step::ResolveFutures(state),
+    })
+}
+
+/// Answers calls the run's policy forbids with a `PermissionError`, calls that do not
+/// match their declared signature with a `TypeError`, and repeated calls to
+/// `cache_calls` functions with their cached result, in the script until the run
+/// reaches a progress the host should see.
+fn reject_invalid_calls(
+    mut progress: RunProgress<FfiTracker>,
+    context: &mut RunContext,
+) -> FfiResult<RunProgress<FfiTracker>> {
+    loop {
+        let answer = match &progress {
+            RunProgress::FunctionCall {
+                function_name,
+                args,
+                kwargs,
+                call_id,
+                method_call,
+                ..
+            } => match context.check_call(function_name, args, kwargs, *method_call)? {
+                Some(exc) => Some(ExternalResult::Error(exc)),
+                None => context
+                    .cached_result(*call_id, function_name, args, kwargs, *method_call)?
+                    .map(ExternalResult::Return),
+            },
+            RunProgress::OsCall {
+                function,
+                args,
+                kwargs,
+                call_id,
+                ..
+            } => context
+                .check_os_call(*call_id, &function.to_string(), args, kwargs)?
+                .map(ExternalResult::Error),
+            _ => None,
+        };
+        let Some(answer) = answer else {
+            return Ok(progress);
+        };
+        let (RunProgress::FunctionCall { state, .. } | RunProgress::OsCall { state, .. }) =
+            progress
+        else {
+            unreachable!()
+        };
+        progress = context.execute(|print| state.run(answer, print))?;
+    }
+}
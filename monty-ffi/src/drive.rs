@@ -0,0 +1,191 @@
+//! `monty_run_drive`: runs a program to completion, answering its external and OS calls
+//! through host callbacks, for embedders that don't want to handle snapshots.
+//!
+//! Calls are answered one at a time as they are made, so a run never has futures to
+//! resolve; `host_emit` values are passed to the function callback like a call, and
+//! the script sees `None` whatever it answers. `host_sleep` blocks the driving thread
+//! and counts against `max_duration_ms`; a delay longer than the time the run has left,
+//! or than its call timeout, raises `TimeoutError` instead of waiting. `input` calls go
+//! to the function callback, whose answer is the line read.
+
+use std::{
+    ffi::{c_void, CString},
+    thread,
+    time::Duration,
+};
+
+use monty::{ExternalResult, MontyException, MontyObject, RunProgress};
+
+use crate::{
+    compiled::{EMIT_FUNCTION, INPUT_FUNCTION, SLEEP_FUNCTION},
+    context::RunContext,
+    dispatch::{self, Step},
+    error::{FfiError, FfiResult},
+    inputs::PendingStart,
+    json::{
+        decode_exception, decode_object, encode_kwargs, encode_object, encode_objects,
+        parse_exc_type, HostCauses,
+    },
+    print, reentry,
+    seal::append,
+    tracker::FfiTracker,
+    MontyCallCallback, MontyWriteCallback, TIMEOUT_EXC_TYPE,
+};
+
+/// Callback status: what the callback wrote is the call's return value.
+pub const MONTY_DRIVE_RETURN: i32 = 0;
+/// Callback status: what the callback wrote is a `$exception` object to raise.
+pub const MONTY_DRIVE_RAISE: i32 = 1;
+
+pub struct Callbacks {
+    pub function: MontyCallCallback,
+    pub os: MontyCallCallback,
+    pub print: MontyWriteCallback,
+    pub userdata: *mut c_void,
+}
+
+/// Runs `pending` to completion and returns its encoded result.
+pub fn drive(pending: PendingStart, callbacks: &Callbacks) -> FfiResult<String> {
+    if let Some(name) = pending.next_request() {
+        return Err(FfiError::Message(format!(
+            "monty_run_drive can't provide lazy input {name:?}"
+        )));
+    }
+    let (runner, inputs, mut context) = pending.into_start();
+    print::redirect(callbacks.print, callbacks.userdata, || {
+        let progress = context.execute(|print| runner.start(inputs, FfiTracker, print))?;
+        run_to_completion(progress, &mut context, callbacks)
+    })
+}
+
+fn run_to_completion(
+    mut progress: RunProgress<FfiTracker>,
+    context: &mut RunContext,
+    callbacks: &Callbacks,
+) -> FfiResult<String> {
+    let no_causes = HostCauses::default();
+    loop {
+        progress = match dispatch::step(progress, context)? {
+            Step::Complete(value) => return encode_object(&value),
+            Step::Emit {
+                args,
+                kwargs,
+                state,
+                ..
+            } => {
+                answer(callbacks.function, callbacks, EMIT_FUNCTION, &args, &kwargs)?;
+                let resolution = ExternalResult::Return(MontyObject::None);
+                context.execute(|print| state.run(resolution, print))?
+            }
+            Step::Sleep {
+                delay_ms, state, ..
+            } => {
+                // Driven runs hold their thread anyway, so the delay is waited out here.
+                let resolution = sleep(context, delay_ms)?;
+                context.execute(|print| state.run(resolution, print))?
+            }
+            Step::Prompt {
+                args,
+                kwargs,
+                state,
+                ..
+            } => {
+                let resolution = answer(
+                    callbacks.function,
+                    callbacks,
+                    INPUT_FUNCTION,
+                    &args,
+                    &kwargs,
+                )?;
+                context.execute(|print| state.run(resolution, print))?
+            }
+            Step::Call(call) => {
+                let result = match &call.os_function {
+                    Some(function) => {
+                        answer(callbacks.os, callbacks, function, &call.args, &call.kwargs)?
+                    }
+                    None => answer(
+                        callbacks.function,
+                        callbacks,
+                        &call.function_name,
+                        &call.args,
+                        &call.kwargs,
+                    )?,
+                };
+                call.run(result, context, &no_causes)?
+            }
+            Step::ResolveFutures(_) => {
+                return Err(FfiError::Message(
+                    "monty_run_drive answers calls as they are made and can't resolve futures"
+                        .into(),
+                ))
+            }
+        };
+    }
+}
+
+/// Waits out a `host_sleep` delay, charging it to the run's elapsed time, unless it is
+/// longer than the time the run has left or the call timeout for `host_sleep`.
+fn sleep(context: &mut RunContext, delay_ms: u64) -> FfiResult<ExternalResult> {
+    let left_ms = context.remaining_budget().time_ms;
+    let timeout_ms = context.call_timeouts.for_call(SLEEP_FUNCTION);
+    let limit_ms = match (left_ms, timeout_ms) {
+        (Some(left), Some(timeout)) => Some(left.min(timeout)),
+        (left, timeout) => left.or(timeout),
+    };
+    if let Some(limit_ms) = limit_ms.filter(|&limit_ms| delay_ms > limit_ms) {
+        return Ok(ExternalResult::Error(MontyException::new(
+            parse_exc_type(TIMEOUT_EXC_TYPE)?,
+            Some(format!(
+                "{SLEEP_FUNCTION}() of {delay_ms} ms is longer than the {limit_ms} ms allowed"
+            )),
+        )));
+    }
+    thread::sleep(Duration::from_millis(delay_ms));
+    let stats = &mut context.stats;
+    stats.elapsed_us = stats
+        .elapsed_us
+        .saturating_add(delay_ms.saturating_mul(1000));
+    Ok(ExternalResult::Return(MontyObject::None))
+}
+
+/// Asks `callback` to answer a call, reading its answer from what it writes to `emit`.
+fn answer(
+    callback: MontyCallCallback,
+    callbacks: &Callbacks,
+    function: &str,
+    args: &[MontyObject],
+    kwargs: &[(MontyObject, MontyObject)],
+) -> FfiResult<ExternalResult> {
+    let callback = callback.ok_or(FfiError::NullPointer("callback"))?;
+    let function = c_string(function.to_owned(), "function_name")?;
+    let args = c_string(encode_objects(args)?, "args_json")?;
+    let kwargs = c_string(encode_kwargs(kwargs)?, "kwargs_json")?;
+    let mut written = Vec::new();
+    let sink = &mut written as *mut Vec<u8> as *mut c_void;
+    let status = reentry::call_host(|| unsafe {
+        callback(
+            callbacks.userdata,
+            function.as_ptr(),
+            args.as_ptr(),
+            kwargs.as_ptr(),
+            Some(append),
+            sink,
+        )
+    });
+    let json = std::str::from_utf8(&written)?;
+    match status {
+        MONTY_DRIVE_RETURN => Ok(ExternalResult::Return(decode_object(json)?)),
+        MONTY_DRIVE_RAISE => {
+            let host = decode_exception(serde_json::from_str(json)?)?;
+            Ok(ExternalResult::Error(host.exception))
+        }
+        code => Err(FfiError::Message(format!(
+            "call callback failed with status {code}"
+        ))),
+    }
+}
+
+fn c_string(value: String, field: &'static str) -> FfiResult<CString> {
+    CString::new(value).map_err(|_| FfiError::InteriorNul { field })
+}
@@ -1,4 +1,5 @@
 use std::{
+    cell::Cell,
     ffi::{CStr, CString},
     os::raw::c_char,
     ptr,
@@ -7,11 +8,36 @@ use std::{
 use monty::MontyException;
 use thiserror::Error;
 
+use crate::{debug, json::HostCauses, options::ErrorFormat};
+
+/// Values of `MontyStatus::code` on failure, exported to the header as
+/// `MONTY_ERROR_*`; the code is zero on success.
+#[repr(i32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MontyError {
+    /// A failure without a more specific code.
+    Generic = 1,
+    /// A sealed dump that failed authentication on load.
+    Tampered = 2,
+    /// A resume whose result doesn't match the call's declared return type under the
+    /// `fail` return mismatch; the snapshot (and any object handle) is left untouched.
+    ReturnMismatch = 3,
+}
+
+pub const MONTY_ERROR_GENERIC: i32 = MontyError::Generic as i32;
+pub const MONTY_ERROR_TAMPERED: i32 = MontyError::Tampered as i32;
+pub const MONTY_ERROR_RETURN_MISMATCH: i32 = MontyError::ReturnMismatch as i32;
+
 #[repr(C)]
 #[derive(Debug, Clone, Copy)]
 pub struct MontyStatus {
     pub ok: i32,
     pub error: *mut c_char,
+    /// Zero on success, otherwise one of the `MONTY_ERROR_*` codes.
+    pub code: i32,
+    /// Nonzero when this call decoded code or a JSON payload with lossy UTF-8 and had
+    /// to replace invalid bytes; see the `lossy_utf8` run option.
+    pub lossy: i32,
 }
 
 impl MontyStatus {
@@ -19,16 +45,23 @@ impl MontyStatus {
         Self {
             ok: 1,
             error: ptr::null_mut(),
+            code: 0,
+            lossy: take_replaced() as i32,
         }
     }
 
     pub fn from_error(err: impl Into<FfiError>) -> Self {
         let err = err.into();
-        let c_string = CString::new(err.to_string())
+        let error = CString::new(err.to_string())
             .unwrap_or_else(|_| CString::new("monty-ffi error").unwrap());
+        let size = error.as_bytes().len();
+        let error = error.into_raw();
+        debug::handed_out("string", error, Some(size));
         Self {
             ok: 0,
-            error: c_string.into_raw(),
+            error,
+            code: err.code(),
+            lossy: take_replaced() as i32,
         }
     }
 }
@@ -45,6 +78,34 @@ pub enum FfiError {
     InvalidUtf8 { field: &'static str },
     #[error("string for {field} contains interior NUL bytes")]
     InteriorNul { field: &'static str },
+    #[error("sealed snapshot failed authentication")]
+    Tampered,
+    #[error("{0}")]
+    ReturnMismatch(String),
+}
+
+impl FfiError {
+    pub fn code(&self) -> i32 {
+        match self {
+            Self::Tampered => MONTY_ERROR_TAMPERED,
+            Self::ReturnMismatch(_) => MONTY_ERROR_RETURN_MISMATCH,
+            _ => MONTY_ERROR_GENERIC,
+        }
+    }
+
+    /// Formats a failed run, printing the cause chain of the host exception it failed
+    /// with, if any, oldest first like Python's `__cause__` tracebacks.
+    pub fn with_causes(exc: MontyException, causes: &HostCauses, format: ErrorFormat) -> Self {
+        let mut message = String::new();
+        for cause in causes.of(&exc).iter().rev() {
+            message.push_str(cause);
+            message.push_str(
+                "\n\nThe above exception was the direct cause of the following exception:\n\n",
+            );
+        }
+        message.push_str(&format.format(&exc));
+        Self::Message(message)
+    }
 }
 
 impl From<MontyException> for FfiError {
@@ -81,6 +142,54 @@ pub unsafe fn read_required_str(ptr: *const c_char, field: &'static str) -> FfiR
         .to_owned())
 }
 
+thread_local! {
+    /// Set when a payload read by the current call needed replacement characters.
+    static REPLACED: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Whether a payload was decoded lossily since the last call, clearing the flag.
+pub fn take_replaced() -> bool {
+    REPLACED.with(|replaced| replaced.replace(false))
+}
+
+/// Runs `f` with the current call's replacement flag set aside, so calls a host
+/// callback makes inside it report only their own replacements.
+pub fn keep_replaced<R>(f: impl FnOnce() -> R) -> R {
+    let outer = take_replaced();
+    let result = f();
+    REPLACED.with(|replaced| replaced.set(outer));
+    result
+}
+
+/// Reads code or a JSON payload. With `lossy`, invalid UTF-8 is decoded with U+FFFD
+/// replacement characters instead of failing, and the call's status has `lossy` set.
+pub unsafe fn read_text(ptr: *const c_char, field: &'static str, lossy: bool) -> FfiResult<String> {
+    if ptr.is_null() {
+        return Err(FfiError::NullPointer(field));
+    }
+    let bytes = CStr::from_ptr(ptr).to_bytes();
+    match std::str::from_utf8(bytes) {
+        Ok(text) => Ok(text.to_owned()),
+        Err(_) if lossy => {
+            REPLACED.with(|replaced| replaced.set(true));
+            Ok(String::from_utf8_lossy(bytes).into_owned())
+        }
+        Err(_) => Err(FfiError::InvalidUtf8 { field }),
+    }
+}
+
+pub unsafe fn read_optional_text(
+    ptr: *const c_char,
+    field: &'static str,
+    lossy: bool,
+) -> FfiResult<Option<String>> {
+    if ptr.is_null() {
+        Ok(None)
+    } else {
+        read_text(ptr, field, lossy).map(Some)
+    }
+}
+
 pub unsafe fn read_optional_str(ptr: *const c_char) -> FfiResult<Option<String>> {
     if ptr.is_null() {
         Ok(None)
@@ -99,12 +208,16 @@ pub fn to_c_string(value: impl Into<String>, field: &'static str) -> FfiResult<*
     if value.bytes().any(|b| b == 0) {
         return Err(FfiError::InteriorNul { field });
     }
-    Ok(CString::new(value).unwrap().into_raw())
+    let size = value.len();
+    let ptr = CString::new(value).unwrap().into_raw();
+    debug::handed_out("string", ptr, Some(size));
+    Ok(ptr)
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn monty_free_string(s: *mut c_char) {
     if !s.is_null() {
+        debug::taken_back(s);
         drop(CString::from_raw(s));
     }
 }
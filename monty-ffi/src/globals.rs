@@ -0,0 +1,51 @@
+use monty::MontyObject;
+use serde_json::{Map, Value};
+
+use crate::{
+    error::{FfiError, FfiResult},
+    json::{check_numbers, decode_value},
+};
+
+/// Named module-level variables bound before a run starts, decoded once from a
+/// JSON object of tagged values.
+///
+/// A `Globals` built with `monty_globals_new` is shared by reference between every
+/// run it is attached to, so the host encodes and decodes it only once. Monty has
+/// no shared heap, though: each run still receives its own copy of the values.
+pub struct Globals {
+    entries: Vec<(String, MontyObject)>,
+}
+
+impl Globals {
+    pub fn from_json(json: &str) -> FfiResult<Self> {
+        check_numbers(json)?;
+        match serde_json::from_str(json)? {
+            Value::Object(map) => Self::from_map(map),
+            other => Err(FfiError::Message(format!(
+                "expected JSON object for globals, got {other}"
+            ))),
+        }
+    }
+
+    pub fn from_map(map: Map<String, Value>) -> FfiResult<Self> {
+        Ok(Self {
+            entries: map
+                .into_iter()
+                .map(|(name, value)| Ok((name, decode_value(value)?)))
+                .collect::<FfiResult<_>>()?,
+        })
+    }
+
+    /// Names and values to bind for a run: `shared` first, then `seeded`, whose
+    /// entries win when both define a name.
+    pub fn bindings(shared: Option<&Self>, seeded: Self) -> (Vec<String>, Vec<MontyObject>) {
+        let mut entries: Vec<_> = shared
+            .into_iter()
+            .flat_map(|globals| globals.entries.iter())
+            .filter(|(name, _)| !seeded.entries.iter().any(|(seeded, _)| seeded == name))
+            .cloned()
+            .collect();
+        entries.extend(seeded.entries);
+        entries.into_iter().unzip()
+    }
+}
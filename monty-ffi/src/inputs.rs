@@ -0,0 +1,142 @@
+//! Preparing a run's inputs before it starts.
+//!
+//! Besides ordinary tagged JSON values, the inputs array accepts three markers:
+//!
+//! - `{"$input_bytes": "name"}` binds raw bytes passed to
+//!   `monty_run_start_with_input_bytes`, skipping JSON for large binary data.
+//! - `{"$input_object": "name"}` binds an object handle shared with the run handle
+//!   through `monty_run_set_input_object`, so one value can feed many runs without
+//!   being re-encoded; it is still copied into each run's VM.
+//! - `{"$lazy": "name"}` is only serialized if the script can actually read it.
+//!   Monty cannot suspend on a variable load, so the check is a text scan of the
+//!   source before the run starts: every lazy input whose declared input name
+//!   appears as an identifier is requested from the host under `name` (one
+//!   `INPUT_REQUEST` progress each), and the rest are bound to `None` without ever
+//!   being encoded.
+
+use std::{collections::HashMap, sync::Arc};
+
+use monty::{MontyObject, MontyRun};
+use serde_json::Value;
+
+use crate::{
+    context::RunContext,
+    error::{FfiError, FfiResult},
+    json::{decode_input_values, decode_value},
+};
+
+const LAZY_TAG: &str = "$lazy";
+const INPUT_BYTES_TAG: &str = "$input_bytes";
+const INPUT_OBJECT_TAG: &str = "$input_object";
+
+enum PendingInput {
+    Ready(MontyObject),
+    Requested(String),
+}
+
+/// A run whose start is waiting on the host for lazy input values.
+pub struct PendingStart {
+    runner: MontyRun,
+    inputs: Vec<PendingInput>,
+    context: RunContext,
+}
+
+impl PendingStart {
+    pub fn new(
+        runner: MontyRun,
+        inputs_json: &str,
+        input_names: Option<&[String]>,
+        mut input_bytes: HashMap<String, Vec<u8>>,
+        input_objects: &HashMap<String, Arc<MontyObject>>,
+        bound: Vec<MontyObject>,
+        context: RunContext,
+    ) -> FfiResult<Self> {
+        let mut inputs = Vec::new();
+        for (i, value) in decode_input_values(inputs_json)?.into_iter().enumerate() {
+            let input = if let Some(name) = marker(&value, INPUT_BYTES_TAG) {
+                let bytes = input_bytes.remove(name).ok_or_else(|| {
+                    FfiError::Message(format!("no bytes given for input `{name}`"))
+                })?;
+                PendingInput::Ready(MontyObject::Bytes(bytes))
+            } else if let Some(name) = marker(&value, INPUT_OBJECT_TAG) {
+                let object = input_objects.get(name).ok_or_else(|| {
+                    FfiError::Message(format!("no object bound for input `{name}`"))
+                })?;
+                // The VM owns its inputs, so the shared value is copied here.
+                PendingInput::Ready(MontyObject::clone(object))
+            } else if let Some(name) = marker(&value, LAZY_TAG) {
+                // The variable the script reads is the declared input, whatever key
+                // the host asks for it by; runs without a recorded source only have
+                // the key.
+                let variable = input_names
+                    .and_then(|names| names.get(i))
+                    .map_or(name, String::as_str);
+                if references(runner.code(), variable) {
+                    PendingInput::Requested(name.to_owned())
+                } else {
+                    PendingInput::Ready(MontyObject::None)
+                }
+            } else {
+                PendingInput::Ready(decode_value(value)?)
+            };
+            inputs.push(input);
+        }
+        inputs.extend(bound.into_iter().map(PendingInput::Ready));
+        Ok(Self {
+            runner,
+            inputs,
+            context,
+        })
+    }
+
+    /// Whether the run decodes the values the host provides lossily.
+    pub fn lossy_utf8(&self) -> bool {
+        self.context.lossy_utf8
+    }
+
+    /// Name of the next lazy input the host must provide, if any.
+    pub fn next_request(&self) -> Option<&str> {
+        self.inputs.iter().find_map(|input| match input {
+            PendingInput::Requested(name) => Some(name.as_str()),
+            PendingInput::Ready(_) => None,
+        })
+    }
+
+    /// Fills the input named by `next_request`.
+    pub fn provide(&mut self, value: MontyObject) -> FfiResult<()> {
+        let slot = self
+            .inputs
+            .iter_mut()
+            .find(|input| matches!(input, PendingInput::Requested(_)))
+            .ok_or_else(|| FfiError::Message("no lazy input is pending".to_owned()))?;
+        *slot = PendingInput::Ready(value);
+        Ok(())
+    }
+
+    /// Splits into what `MontyRun::start` needs; only valid once nothing is pending.
+    pub fn into_start(self) -> (MontyRun, Vec<MontyObject>, RunContext) {
+        let inputs = self
+            .inputs
+            .into_iter()
+            .map(|input| match input {
+                PendingInput::Ready(value) => value,
+                PendingInput::Requested(_) => MontyObject::None,
+            })
+            .collect();
+        (self.runner, inputs, self.context)
+    }
+}
+
+fn marker<'a>(value: &'a Value, tag: &str) -> Option<&'a str> {
+    match value {
+        Value::Object(map) if map.len() == 1 => map.get(tag)?.as_str(),
+        _ => None,
+    }
+}
+
+/// Conservative identifier scan: a false positive (e.g. the name inside a string)
+/// only means the value is requested when it did not have to be.
+fn references(code: &str, name: &str) -> bool {
+    code.split(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .any(|word| word == name)
+}
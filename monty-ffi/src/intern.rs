@@ -0,0 +1,700 @@
+//! String interning for snapshot dumps.
+//!
+//! Suspended VM state repeats the same identifiers and literals many times. `to_writer`
+//! serializes a value with postcard, writing each distinct string once at its first use
+//! and by index afterwards; `from_bytes` rebuilds the table in the same order while
+//! deserializing. Everything else is encoded exactly as postcard would, and the output
+//! is produced in a single pass so it can be streamed.
+//!
+//! Interned dumps start with `MAGIC` and a format version byte. Bytes without the magic
+//! are version 0: the bare postcard VM state earlier releases dumped, which `from_bytes`
+//! does not read; see `load_suspended` in the crate root.
+
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, HashMap},
+    fmt,
+};
+
+use postcard::ser_flavors::{Flavor, Size};
+use serde::{
+    de::{self, DeserializeOwned, DeserializeSeed, Error as _, Visitor},
+    ser, Deserialize, Deserializer, Serialize, Serializer,
+};
+
+use crate::{
+    error::{FfiError, FfiResult},
+    metrics,
+};
+
+/// Format version written by `to_writer`.
+pub const FORMAT_VERSION: u8 = 1;
+const MAGIC: &[u8] = b"MONTYSTR";
+/// Size of the chunks handed to a `to_writer` callback.
+const CHUNK: usize = 64 * 1024;
+
+/// How a string is encoded: a reference to an earlier one, or its first occurrence.
+#[derive(Serialize, Deserialize)]
+enum Entry<S> {
+    Seen(u32),
+    New(S),
+}
+
+#[derive(Default)]
+struct Table {
+    indices: HashMap<String, u32>,
+}
+
+impl Table {
+    /// Index of a string already written, or `None` after registering a new one.
+    fn intern(&mut self, value: &str) -> Option<u32> {
+        if let Some(&index) = self.indices.get(value) {
+            return Some(index);
+        }
+        let index = self.indices.len() as u32;
+        self.indices.insert(value.to_owned(), index);
+        None
+    }
+}
+
+pub fn to_bytes<T: Serialize>(value: &T) -> FfiResult<Vec<u8>> {
+    let mut bytes = Vec::new();
+    to_writer(value, |chunk| {
+        bytes.extend_from_slice(chunk);
+        Ok(())
+    })?;
+    Ok(bytes)
+}
+
+/// Serializes `value`, handing the output to `write` in chunks as it is produced.
+pub fn to_writer<T: Serialize>(
+    value: &T,
+    write: impl FnMut(&[u8]) -> FfiResult<()>,
+) -> FfiResult<()> {
+    let table = RefCell::new(Table::default());
+    let mut failure = None;
+    let mut output = Chunked {
+        buffer: Vec::with_capacity(CHUNK),
+        write,
+        failure: &mut failure,
+    };
+    output.buffer.extend_from_slice(MAGIC);
+    output.buffer.push(FORMAT_VERSION);
+    let interned = Interned {
+        value,
+        table: &table,
+    };
+    let result = postcard::serialize_with_flavor(&interned, output);
+    match failure {
+        Some(err) => Err(err),
+        None => Ok(result?),
+    }
+}
+
+/// Length of the `to_writer` output for `value`, counted without producing it.
+pub fn serialized_size<T: Serialize>(value: &T) -> FfiResult<usize> {
+    let table = RefCell::new(Table::default());
+    let interned = Interned {
+        value,
+        table: &table,
+    };
+    let payload = postcard::serialize_with_flavor(&interned, Size::default())?;
+    Ok(MAGIC.len() + 1 + payload)
+}
+
+/// The format version `bytes` were written with.
+pub fn format_version(bytes: &[u8]) -> u8 {
+    match bytes.strip_prefix(MAGIC) {
+        Some([version, ..]) => *version,
+        _ => 0,
+    }
+}
+
+pub fn from_bytes<T: DeserializeOwned>(bytes: &[u8]) -> FfiResult<T> {
+    match format_version(bytes) {
+        FORMAT_VERSION => {}
+        0 => return Err(FfiError::Message("not an interned dump".to_owned())),
+        version => {
+            return Err(FfiError::Message(format!(
+                "unsupported snapshot format version {version}"
+            )))
+        }
+    }
+    let payload = &bytes[MAGIC.len() + 1..];
+    let strings = RefCell::new(Vec::new());
+    let mut deserializer = postcard::Deserializer::from_bytes(payload);
+    let value = T::deserialize(Resolving {
+        inner: &mut deserializer,
+        strings: &strings,
+    })?;
+    Ok(value)
+}
+
+/// `serialize_with` for a `HashMap` in the snapshot state: writes it in key order, so
+/// equal states dump to the same bytes (see `monty_snapshot_hash`). The encoding is
+/// the same as postcard's for the map itself.
+pub fn sorted<K, V, S>(map: &HashMap<K, V>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    K: Ord + Serialize,
+    V: Serialize,
+    S: Serializer,
+{
+    serializer.collect_map(map.iter().collect::<BTreeMap<_, _>>())
+}
+
+/// Postcard output buffered into `CHUNK`-sized writes. A failed write is kept in
+/// `failure`, since postcard's own error type can't carry it.
+struct Chunked<'f, W> {
+    buffer: Vec<u8>,
+    write: W,
+    failure: &'f mut Option<FfiError>,
+}
+
+impl<W: FnMut(&[u8]) -> FfiResult<()>> Chunked<'_, W> {
+    fn flush(&mut self) -> postcard::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        let result = (self.write)(&self.buffer);
+        if result.is_ok() {
+            metrics::serialized(self.buffer.len());
+        }
+        self.buffer.clear();
+        result.map_err(|err| {
+            *self.failure = Some(err);
+            postcard::Error::SerdeSerCustom
+        })
+    }
+}
+
+impl<W: FnMut(&[u8]) -> FfiResult<()>> Flavor for Chunked<'_, W> {
+    type Output = ();
+
+    fn try_extend(&mut self, data: &[u8]) -> postcard::Result<()> {
+        self.buffer.extend_from_slice(data);
+        if self.buffer.len() >= CHUNK {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    fn try_push(&mut self, data: u8) -> postcard::Result<()> {
+        self.try_extend(&[data])
+    }
+
+    fn finalize(mut self) -> postcard::Result<()> {
+        self.flush()
+    }
+}
+
+/// A value serialized with its strings interned into `table`.
+struct Interned<'a, 't, T: ?Sized> {
+    value: &'a T,
+    table: &'t RefCell<Table>,
+}
+
+impl<T: Serialize + ?Sized> Serialize for Interned<'_, '_, T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.value.serialize(Interning {
+            inner: serializer,
+            table: self.table,
+        })
+    }
+}
+
+/// Wraps a serializer (or one of its compound serializers) so nested strings are interned.
+struct Interning<'t, S> {
+    inner: S,
+    table: &'t RefCell<Table>,
+}
+
+impl<'t, S> Interning<'t, S> {
+    fn interned<'a, T: ?Sized>(&self, value: &'a T) -> Interned<'a, 't, T> {
+        Interned {
+            value,
+            table: self.table,
+        }
+    }
+}
+
+macro_rules! forward_serialize {
+    ($($method:ident($ty:ty)),* $(,)?) => {
+        $(
+            fn $method(self, value: $ty) -> Result<S::Ok, S::Error> {
+                self.inner.$method(value)
+            }
+        )*
+    };
+}
+
+impl<'t, S: Serializer> Serializer for Interning<'t, S> {
+    type Ok = S::Ok;
+    type Error = S::Error;
+    type SerializeSeq = Interning<'t, S::SerializeSeq>;
+    type SerializeTuple = Interning<'t, S::SerializeTuple>;
+    type SerializeTupleStruct = Interning<'t, S::SerializeTupleStruct>;
+    type SerializeTupleVariant = Interning<'t, S::SerializeTupleVariant>;
+    type SerializeMap = Interning<'t, S::SerializeMap>;
+    type SerializeStruct = Interning<'t, S::SerializeStruct>;
+    type SerializeStructVariant = Interning<'t, S::SerializeStructVariant>;
+
+    forward_serialize!(
+        serialize_bool(bool),
+        serialize_i8(i8),
+        serialize_i16(i16),
+        serialize_i32(i32),
+        serialize_i64(i64),
+        serialize_i128(i128),
+        serialize_u8(u8),
+        serialize_u16(u16),
+        serialize_u32(u32),
+        serialize_u64(u64),
+        serialize_u128(u128),
+        serialize_f32(f32),
+        serialize_f64(f64),
+        serialize_char(char),
+        serialize_bytes(&[u8]),
+        serialize_unit_struct(&'static str),
+    );
+
+    fn serialize_str(self, value: &str) -> Result<S::Ok, S::Error> {
+        let index = self.table.borrow_mut().intern(value);
+        match index {
+            Some(index) => Entry::<&str>::Seen(index).serialize(self.inner),
+            None => Entry::New(value).serialize(self.inner),
+        }
+    }
+
+    fn serialize_none(self) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_none()
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<S::Ok, S::Error> {
+        let value = self.interned(value);
+        self.inner.serialize_some(&value)
+    }
+
+    fn serialize_unit(self) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_unit()
+    }
+
+    fn serialize_unit_variant(
+        self,
+        name: &'static str,
+        index: u32,
+        variant: &'static str,
+    ) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_unit_variant(name, index, variant)
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        name: &'static str,
+        value: &T,
+    ) -> Result<S::Ok, S::Error> {
+        let value = self.interned(value);
+        self.inner.serialize_newtype_struct(name, &value)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        name: &'static str,
+        index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<S::Ok, S::Error> {
+        let value = self.interned(value);
+        self.inner
+            .serialize_newtype_variant(name, index, variant, &value)
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<Self::SerializeSeq, S::Error> {
+        let inner = self.inner.serialize_seq(len)?;
+        Ok(Interning {
+            inner,
+            table: self.table,
+        })
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<Self::SerializeTuple, S::Error> {
+        let inner = self.inner.serialize_tuple(len)?;
+        Ok(Interning {
+            inner,
+            table: self.table,
+        })
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        name: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeTupleStruct, S::Error> {
+        let inner = self.inner.serialize_tuple_struct(name, len)?;
+        Ok(Interning {
+            inner,
+            table: self.table,
+        })
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        name: &'static str,
+        index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeTupleVariant, S::Error> {
+        let inner = self
+            .inner
+            .serialize_tuple_variant(name, index, variant, len)?;
+        Ok(Interning {
+            inner,
+            table: self.table,
+        })
+    }
+
+    fn serialize_map(self, len: Option<usize>) -> Result<Self::SerializeMap, S::Error> {
+        let inner = self.inner.serialize_map(len)?;
+        Ok(Interning {
+            inner,
+            table: self.table,
+        })
+    }
+
+    fn serialize_struct(
+        self,
+        name: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeStruct, S::Error> {
+        let inner = self.inner.serialize_struct(name, len)?;
+        Ok(Interning {
+            inner,
+            table: self.table,
+        })
+    }
+
+    fn serialize_struct_variant(
+        self,
+        name: &'static str,
+        index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeStructVariant, S::Error> {
+        let inner = self
+            .inner
+            .serialize_struct_variant(name, index, variant, len)?;
+        Ok(Interning {
+            inner,
+            table: self.table,
+        })
+    }
+
+    fn is_human_readable(&self) -> bool {
+        self.inner.is_human_readable()
+    }
+}
+
+macro_rules! forward_compound {
+    ($trait:ident, $method:ident $(, $key:ident: $key_ty:ty)?) => {
+        impl<S: ser::$trait> ser::$trait for Interning<'_, S> {
+            type Ok = S::Ok;
+            type Error = S::Error;
+
+            fn $method<T: Serialize + ?Sized>(
+                &mut self,
+                $($key: $key_ty,)?
+                value: &T,
+            ) -> Result<(), S::Error> {
+                let value = self.interned(value);
+                self.inner.$method($($key,)? &value)
+            }
+
+            fn end(self) -> Result<S::Ok, S::Error> {
+                self.inner.end()
+            }
+        }
+    };
+}
+
+forward_compound!(SerializeSeq, serialize_element);
+forward_compound!(SerializeTuple, serialize_element);
+forward_compound!(SerializeTupleStruct, serialize_field);
+forward_compound!(SerializeTupleVariant, serialize_field);
+forward_compound!(SerializeStruct, serialize_field, key: &'static str);
+forward_compound!(SerializeStructVariant, serialize_field, key: &'static str);
+
+impl<S: ser::SerializeMap> ser::SerializeMap for Interning<'_, S> {
+    type Ok = S::Ok;
+    type Error = S::Error;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), S::Error> {
+        let key = self.interned(key);
+        self.inner.serialize_key(&key)
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), S::Error> {
+        let value = self.interned(value);
+        self.inner.serialize_value(&value)
+    }
+
+    fn end(self) -> Result<S::Ok, S::Error> {
+        self.inner.end()
+    }
+}
+
+/// Wraps a deserializer (or one of its access types, visitors, or seeds) so strings
+/// written by `Interning` are collected into and resolved against `strings`, the
+/// strings seen so far in the order they were first written.
+struct Resolving<'s, D> {
+    inner: D,
+    strings: &'s RefCell<Vec<String>>,
+}
+
+impl<'s, D> Resolving<'s, D> {
+    fn wrap<U>(&self, inner: U) -> Resolving<'s, U> {
+        Resolving {
+            inner,
+            strings: self.strings,
+        }
+    }
+}
+
+macro_rules! forward_deserialize {
+    ($($method:ident($($arg:ident: $ty:ty),*)),* $(,)?) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, $($arg: $ty,)* visitor: V) -> Result<V::Value, D::Error> {
+                let visitor = self.wrap(visitor);
+                self.inner.$method($($arg,)* visitor)
+            }
+        )*
+    };
+}
+
+impl<'de, D: Deserializer<'de>> Deserializer<'de> for Resolving<'_, D> {
+    type Error = D::Error;
+
+    forward_deserialize!(
+        deserialize_any(),
+        deserialize_bool(),
+        deserialize_i8(),
+        deserialize_i16(),
+        deserialize_i32(),
+        deserialize_i64(),
+        deserialize_i128(),
+        deserialize_u8(),
+        deserialize_u16(),
+        deserialize_u32(),
+        deserialize_u64(),
+        deserialize_u128(),
+        deserialize_f32(),
+        deserialize_f64(),
+        deserialize_char(),
+        deserialize_bytes(),
+        deserialize_byte_buf(),
+        deserialize_option(),
+        deserialize_unit(),
+        deserialize_unit_struct(name: &'static str),
+        deserialize_newtype_struct(name: &'static str),
+        deserialize_seq(),
+        deserialize_tuple(len: usize),
+        deserialize_tuple_struct(name: &'static str, len: usize),
+        deserialize_map(),
+        deserialize_struct(name: &'static str, fields: &'static [&'static str]),
+        deserialize_enum(name: &'static str, variants: &'static [&'static str]),
+        deserialize_identifier(),
+        deserialize_ignored_any(),
+    );
+
+    fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, D::Error> {
+        let value = match Entry::<String>::deserialize(self.inner)? {
+            Entry::Seen(index) => self
+                .strings
+                .borrow()
+                .get(index as usize)
+                .cloned()
+                .ok_or_else(|| D::Error::custom(format!("string index {index} out of range")))?,
+            Entry::New(value) => {
+                self.strings.borrow_mut().push(value.clone());
+                value
+            }
+        };
+        visitor.visit_string(value)
+    }
+
+    fn deserialize_string<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, D::Error> {
+        self.deserialize_str(visitor)
+    }
+
+    fn is_human_readable(&self) -> bool {
+        self.inner.is_human_readable()
+    }
+}
+
+macro_rules! forward_visit {
+    ($($method:ident($ty:ty)),* $(,)?) => {
+        $(
+            fn $method<E: de::Error>(self, value: $ty) -> Result<Self::Value, E> {
+                self.inner.$method(value)
+            }
+        )*
+    };
+}
+
+impl<'de, V: Visitor<'de>> Visitor<'de> for Resolving<'_, V> {
+    type Value = V::Value;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        self.inner.expecting(formatter)
+    }
+
+    forward_visit!(
+        visit_bool(bool),
+        visit_i8(i8),
+        visit_i16(i16),
+        visit_i32(i32),
+        visit_i64(i64),
+        visit_i128(i128),
+        visit_u8(u8),
+        visit_u16(u16),
+        visit_u32(u32),
+        visit_u64(u64),
+        visit_u128(u128),
+        visit_f32(f32),
+        visit_f64(f64),
+        visit_char(char),
+        visit_str(&str),
+        visit_borrowed_str(&'de str),
+        visit_string(String),
+        visit_bytes(&[u8]),
+        visit_borrowed_bytes(&'de [u8]),
+        visit_byte_buf(Vec<u8>),
+    );
+
+    fn visit_none<E: de::Error>(self) -> Result<Self::Value, E> {
+        self.inner.visit_none()
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<Self::Value, E> {
+        self.inner.visit_unit()
+    }
+
+    fn visit_some<T: Deserializer<'de>>(self, deserializer: T) -> Result<Self::Value, T::Error> {
+        let deserializer = self.wrap(deserializer);
+        self.inner.visit_some(deserializer)
+    }
+
+    fn visit_newtype_struct<T: Deserializer<'de>>(
+        self,
+        deserializer: T,
+    ) -> Result<Self::Value, T::Error> {
+        let deserializer = self.wrap(deserializer);
+        self.inner.visit_newtype_struct(deserializer)
+    }
+
+    fn visit_seq<A: de::SeqAccess<'de>>(self, seq: A) -> Result<Self::Value, A::Error> {
+        let seq = self.wrap(seq);
+        self.inner.visit_seq(seq)
+    }
+
+    fn visit_map<A: de::MapAccess<'de>>(self, map: A) -> Result<Self::Value, A::Error> {
+        let map = self.wrap(map);
+        self.inner.visit_map(map)
+    }
+
+    fn visit_enum<A: de::EnumAccess<'de>>(self, data: A) -> Result<Self::Value, A::Error> {
+        let data = self.wrap(data);
+        self.inner.visit_enum(data)
+    }
+}
+
+impl<'de, T: DeserializeSeed<'de>> DeserializeSeed<'de> for Resolving<'_, T> {
+    type Value = T::Value;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<T::Value, D::Error> {
+        let deserializer = self.wrap(deserializer);
+        self.inner.deserialize(deserializer)
+    }
+}
+
+impl<'de, A: de::SeqAccess<'de>> de::SeqAccess<'de> for Resolving<'_, A> {
+    type Error = A::Error;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, A::Error> {
+        let seed = self.wrap(seed);
+        self.inner.next_element_seed(seed)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        self.inner.size_hint()
+    }
+}
+
+impl<'de, A: de::MapAccess<'de>> de::MapAccess<'de> for Resolving<'_, A> {
+    type Error = A::Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, A::Error> {
+        let seed = self.wrap(seed);
+        self.inner.next_key_seed(seed)
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, A::Error> {
+        let seed = self.wrap(seed);
+        self.inner.next_value_seed(seed)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        self.inner.size_hint()
+    }
+}
+
+impl<'de, 's, A: de::EnumAccess<'de>> de::EnumAccess<'de> for Resolving<'s, A> {
+    type Error = A::Error;
+    type Variant = Resolving<'s, A::Variant>;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(
+        self,
+        seed: V,
+    ) -> Result<(V::Value, Self::Variant), A::Error> {
+        let seed = self.wrap(seed);
+        let (value, variant) = self.inner.variant_seed(seed)?;
+        Ok((
+            value,
+            Resolving {
+                inner: variant,
+                strings: self.strings,
+            },
+        ))
+    }
+}
+
+impl<'de, A: de::VariantAccess<'de>> de::VariantAccess<'de> for Resolving<'_, A> {
+    type Error = A::Error;
+
+    fn unit_variant(self) -> Result<(), A::Error> {
+        self.inner.unit_variant()
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value, A::Error> {
+        let seed = self.wrap(seed);
+        self.inner.newtype_variant_seed(seed)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, len: usize, visitor: V) -> Result<V::Value, A::Error> {
+        let visitor = self.wrap(visitor);
+        self.inner.tuple_variant(len, visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, A::Error> {
+        let visitor = self.wrap(visitor);
+        self.inner.struct_variant(fields, visitor)
+    }
+}
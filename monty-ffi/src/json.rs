@@ -1,8 +1,17 @@
-use monty::{DictPairs, ExcType, MontyObject};
+use std::{
+    cell::RefCell,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use monty::{DictPairs, ExcType, MontyException, MontyObject};
 use num_bigint::BigInt;
+use serde::{Deserialize, Serialize};
 use serde_json::{json, Map, Value};
 
-use crate::error::{FfiError, FfiResult};
+use crate::{
+    error::{FfiError, FfiResult},
+    objects, repr,
+};
 
 const TUPLE_TAG: &str = "$tuple";
 const BYTES_TAG: &str = "$bytes";
@@ -15,14 +24,74 @@ const PATH_TAG: &str = "$path";
 const BIGINT_TAG: &str = "$bigint";
 const DATACLASS_TAG: &str = "$dataclass";
 const NAMED_TUPLE_TAG: &str = "$named_tuple";
+const FLOAT_TAG: &str = "$float";
+
+/// Whether encoding fails on values that could only be written as `$repr`; set with
+/// `monty_set_strict_encoding`.
+static STRICT_ENCODING: AtomicBool = AtomicBool::new(false);
+
+/// Whether floats are encoded as `$float` strings; set with `monty_set_float_strings`.
+static FLOAT_STRINGS: AtomicBool = AtomicBool::new(false);
+
+/// Turns float strings on (nonzero) or off for the whole process. When on, floats are
+/// encoded as `{"$float": "0.1"}`, the shortest decimal that parses back to the same
+/// value (`NaN`, `inf`, and `-inf` included), so archived results compare byte for byte
+/// regardless of how a JSON library formats numbers. `$float` is accepted on decode
+/// whether or not this is on.
+#[no_mangle]
+pub extern "C" fn monty_set_float_strings(enabled: i32) {
+    FLOAT_STRINGS.store(enabled != 0, Ordering::Relaxed);
+}
+
+/// Whether encoding is canonical; set with `monty_set_canonical_encoding`.
+static CANONICAL_ENCODING: AtomicBool = AtomicBool::new(false);
+
+/// Turns canonical encoding on (nonzero) or off for the whole process. When on, values
+/// that compare equal encode to identical bytes, for hashing and caching: dict entries
+/// and set items are sorted by their encoding, object keys are sorted, `-0.0` is
+/// written as `0.0`, big ints that fit in 64 bits as plain ints, and `NaN` and
+/// infinities as `$float` strings. Otherwise dicts keep their insertion order.
+#[no_mangle]
+pub extern "C" fn monty_set_canonical_encoding(enabled: i32) {
+    CANONICAL_ENCODING.store(enabled != 0, Ordering::Relaxed);
+}
+
+fn canonical() -> bool {
+    CANONICAL_ENCODING.load(Ordering::Relaxed)
+}
 
-pub fn decode_inputs(json: &str) -> FfiResult<Vec<MontyObject>> {
+/// Whether decoding fails on JSON numbers it can't represent exactly; set with
+/// `monty_set_strict_numbers`.
+static STRICT_NUMBERS: AtomicBool = AtomicBool::new(false);
+
+/// Turns strict number decoding on (nonzero) or off for the whole process. When on,
+/// inputs, results, and globals fail to decode if a JSON number would change: an integer
+/// too large for 64 bits (which would become a float) or a decimal with more precision
+/// than a float holds. Such values can be passed exactly as `$bigint` or a string.
+#[no_mangle]
+pub extern "C" fn monty_set_strict_numbers(enabled: i32) {
+    STRICT_NUMBERS.store(enabled != 0, Ordering::Relaxed);
+}
+
+/// Turns strict encoding on (nonzero) or off for the whole process. When on, encoding a
+/// value that has no lossless JSON form (a function, a class, a cycle, or any other
+/// value only its repr describes) fails with an error naming its type and where it is,
+/// instead of writing a `{"$repr": ...}` placeholder. `...` is still written as a
+/// `$repr`, since it decodes back to `Ellipsis`.
+#[no_mangle]
+pub extern "C" fn monty_set_strict_encoding(enabled: i32) {
+    STRICT_ENCODING.store(enabled != 0, Ordering::Relaxed);
+}
+
+/// Splits the inputs array into its still-encoded items; see `decode_value`.
+pub fn decode_input_values(json: &str) -> FfiResult<Vec<Value>> {
     if json.trim().is_empty() {
         return Ok(Vec::new());
     }
+    check_numbers(json)?;
     let value: Value = serde_json::from_str(json)?;
     match value {
-        Value::Array(items) => items.into_iter().map(value_to_object).collect(),
+        Value::Array(items) => Ok(items),
         other => Err(FfiError::Message(format!(
             "expected JSON array for inputs, got {other}"
         ))),
@@ -30,29 +99,96 @@ pub fn decode_inputs(json: &str) -> FfiResult<Vec<MontyObject>> {
 }
 
 pub fn decode_object(json: &str) -> FfiResult<MontyObject> {
+    check_numbers(json)?;
     let value: Value = serde_json::from_str(json)?;
     value_to_object(value)
 }
 
 pub fn encode_object(value: &MontyObject) -> FfiResult<String> {
-    let json_value = object_to_value(value)?;
+    let json_value = encode_value(value)?;
     serde_json::to_string(&json_value).map_err(Into::into)
 }
 
 pub fn encode_objects(values: &[MontyObject]) -> FfiResult<String> {
-    let json_values: FfiResult<Vec<_>> = values.iter().map(object_to_value).collect();
+    for (index, value) in values.iter().enumerate() {
+        check_lossless(value, format!("$[{index}]"))?;
+    }
+    let json_values: FfiResult<Vec<_>> = values.iter().map(encoded).collect();
     serde_json::to_string(&json_values?).map_err(Into::into)
 }
 
 pub fn encode_kwargs(values: &[(MontyObject, MontyObject)]) -> FfiResult<String> {
-    let mut encoded = Vec::with_capacity(values.len());
+    check_lossless_pairs(values)?;
+    let mut pairs = Vec::with_capacity(values.len());
     for (key, value) in values {
-        encoded.push(Value::Array(vec![
-            object_to_value(key)?,
-            object_to_value(value)?,
-        ]));
+        pairs.push(Value::Array(vec![encoded(key)?, encoded(value)?]));
     }
-    serde_json::to_string(&encoded).map_err(Into::into)
+    serde_json::to_string(&pairs).map_err(Into::into)
+}
+
+pub fn encode_value(value: &MontyObject) -> FfiResult<Value> {
+    check_lossless(value, "$".to_owned())?;
+    encoded(value)
+}
+
+/// Encodes a value kept inside the library, such as a cached call result, without the
+/// strict encoding check, which is for what the host receives.
+pub fn encode_internal(value: &MontyObject) -> FfiResult<String> {
+    Ok(object_to_value(value)?.to_string())
+}
+
+/// Key a call's result is cached under: the function name and its arguments, with
+/// keyword arguments in name order, so calls that only order them differently share
+/// an entry.
+pub fn cache_key(
+    function_name: &str,
+    args: &[MontyObject],
+    kwargs: &[(MontyObject, MontyObject)],
+) -> FfiResult<String> {
+    let args = args
+        .iter()
+        .map(object_to_value)
+        .collect::<FfiResult<Vec<_>>>()?;
+    let mut kwargs = kwargs
+        .iter()
+        .map(|(key, value)| Ok((object_to_value(key)?, object_to_value(value)?)))
+        .collect::<FfiResult<Vec<_>>>()?;
+    kwargs.sort_by_cached_key(|(key, _)| key.to_string());
+    Ok(serde_json::json!([function_name, args, kwargs]).to_string())
+}
+
+/// `object_to_value`, with object keys sorted in canonical encoding mode.
+fn encoded(value: &MontyObject) -> FfiResult<Value> {
+    let mut value = object_to_value(value)?;
+    if canonical() {
+        sort_keys(&mut value);
+    }
+    Ok(value)
+}
+
+fn sort_keys(value: &mut Value) {
+    match value {
+        Value::Array(items) => items.iter_mut().for_each(sort_keys),
+        Value::Object(map) => {
+            let mut entries: Vec<_> = std::mem::take(map).into_iter().collect();
+            entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+            for (key, mut value) in entries {
+                sort_keys(&mut value);
+                map.insert(key, value);
+            }
+        }
+        _ => {}
+    }
+}
+
+pub fn encode_kwarg_values(
+    values: &[(MontyObject, MontyObject)],
+) -> FfiResult<Vec<(Value, Value)>> {
+    check_lossless_pairs(values)?;
+    values
+        .iter()
+        .map(|(key, value)| Ok((encoded(key)?, encoded(value)?)))
+        .collect()
 }
 
 pub fn encode_u32_slice(values: &[u32]) -> FfiResult<String> {
@@ -73,6 +209,87 @@ fn value_to_object(value: Value) -> FfiResult<MontyObject> {
     }
 }
 
+/// In strict number mode, fails if a number literal in `json` would not decode to
+/// exactly the value it spells. Scans the text because `serde_json` has already rounded
+/// numbers by the time they reach `number_to_object`.
+pub fn check_numbers(json: &str) -> FfiResult<()> {
+    if !STRICT_NUMBERS.load(Ordering::Relaxed) {
+        return Ok(());
+    }
+    let mut chars = json.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        match c {
+            '"' => {
+                while let Some((_, c)) = chars.next() {
+                    match c {
+                        '\\' => {
+                            chars.next();
+                        }
+                        '"' => break,
+                        _ => {}
+                    }
+                }
+            }
+            '-' | '0'..='9' => {
+                let mut end = start + 1;
+                while let Some(&(i, c)) = chars.peek() {
+                    if !matches!(c, '0'..='9' | '.' | 'e' | 'E' | '+' | '-') {
+                        break;
+                    }
+                    end = i + 1;
+                    chars.next();
+                }
+                check_number(&json[start..end])?;
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn check_number(literal: &str) -> FfiResult<()> {
+    if !literal.contains(['.', 'e', 'E']) {
+        if literal.parse::<i64>().is_ok() || literal.parse::<u64>().is_ok() {
+            return Ok(());
+        }
+        return Err(FfiError::Message(format!(
+            "strict numbers: {literal} does not fit in 64 bits and would become a float; \
+             pass it as {{\"$bigint\": \"{literal}\"}}"
+        )));
+    }
+    let Ok(float) = literal.parse::<f64>() else {
+        return Ok(());
+    };
+    if !float.is_finite() || decimal_digits(literal) != decimal_digits(&format!("{float:e}")) {
+        return Err(FfiError::Message(format!(
+            "strict numbers: {literal} can't be held exactly by a float and would become \
+             {float:?}; pass it as a string"
+        )));
+    }
+    Ok(())
+}
+
+/// The significant digits and power of ten of a decimal literal, so `0.50` and `5e-1`
+/// compare equal.
+fn decimal_digits(literal: &str) -> (bool, String, i64) {
+    let negative = literal.starts_with('-');
+    let literal = literal.trim_start_matches(['-', '+']);
+    let (mantissa, mut exponent) = match literal.split_once(['e', 'E']) {
+        Some((mantissa, exponent)) => (mantissa, exponent.parse::<i64>().unwrap_or(0)),
+        None => (literal, 0),
+    };
+    let (int, frac) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+    exponent -= frac.len() as i64;
+    let digits = format!("{int}{frac}");
+    let digits = digits.trim_start_matches('0');
+    let trimmed = digits.trim_end_matches('0');
+    exponent += (digits.len() - trimmed.len()) as i64;
+    if trimmed.is_empty() {
+        return (false, String::new(), 0);
+    }
+    (negative, trimmed.to_owned(), exponent)
+}
+
 fn number_to_object(num: serde_json::Number) -> FfiResult<MontyObject> {
     if let Some(i) = num.as_i64() {
         Ok(MontyObject::Int(i))
@@ -128,6 +345,15 @@ fn object_map_to_object(mut map: Map<String, Value>) -> FfiResult<MontyObject> {
             _ => Err(FfiError::Message("$bigint must be a string".into())),
         };
     }
+    if let Some(float) = map.remove(FLOAT_TAG) {
+        return match float {
+            Value::String(raw) => raw
+                .parse::<f64>()
+                .map(MontyObject::Float)
+                .map_err(|err| FfiError::Message(format!("invalid float literal {raw:?}: {err}"))),
+            _ => Err(FfiError::Message("$float must be a string".into())),
+        };
+    }
     if let Some(path) = map.remove(PATH_TAG) {
         return match path {
             Value::String(p) => Ok(MontyObject::Path(p)),
@@ -136,7 +362,7 @@ fn object_map_to_object(mut map: Map<String, Value>) -> FfiResult<MontyObject> {
     }
     if let Some(repr) = map.remove(REPR_TAG) {
         return match repr {
-            Value::String(r) => Ok(MontyObject::Repr(r)),
+            Value::String(r) => repr::parse(&r).map_err(FfiError::Message),
             _ => Err(FfiError::Message("$repr must be a string".into())),
         };
     }
@@ -150,7 +376,7 @@ fn object_map_to_object(mut map: Map<String, Value>) -> FfiResult<MontyObject> {
         return parse_named_tuple(raw_named_tuple);
     }
 
-    // Fallback: regular dict with string keys.
+    // Fallback: regular dict with string keys, in the order the object lists them.
     let mut pairs = Vec::with_capacity(map.len());
     for (key, value) in map {
         let val = value_to_object(value)?;
@@ -197,18 +423,206 @@ fn parse_exception(value: Value) -> FfiResult<MontyObject> {
         .remove("type")
         .and_then(|value| value.as_str().map(|s| s.to_owned()))
         .ok_or_else(|| FfiError::Message("$exception.type missing".into()))?;
-    let message = map
+    let mut message = map
         .remove("message")
         .and_then(|value| value.as_str().map(|s| s.to_owned()));
-    let exc_type = exc_type
-        .parse::<ExcType>()
-        .map_err(|_| FfiError::Message("unknown exception type".into()))?;
+    // Monty exceptions carry a single message, so positional args collapse into it the
+    // way `str(exc)` renders them. Attributes have no counterpart; `decode_exception`
+    // keeps both for the run to put back when the exception is reported.
+    if message.is_none() {
+        if let Some(args) = map.remove("args") {
+            let mut args = parse_collection(args)?;
+            message = match args.len() {
+                0 => None,
+                1 => Some(match args.pop().unwrap() {
+                    MontyObject::String(s) => s,
+                    other => other.to_string(),
+                }),
+                _ => Some(MontyObject::Tuple(args).to_string()),
+            };
+        }
+    }
+    let exc_type = parse_exc_type(&exc_type)?;
     Ok(MontyObject::Exception {
         exc_type,
         arg: message,
     })
 }
 
+/// A host-supplied exception together with its `cause` chain, rendered for error output.
+pub struct HostException {
+    pub exception: MontyException,
+    /// Summaries of the chained causes, direct cause first.
+    pub causes: Vec<String>,
+    /// The envelope's `args` and `attributes`, when it has either.
+    pub data: Option<ExceptionData>,
+}
+
+/// Decodes a `{"$exception": {...}}` envelope into an exception to raise in the script.
+/// An optional `cause` (another envelope) is unwound into `HostException::causes`, and
+/// `args` and `attributes` (an object) are kept in `HostException::data`.
+pub fn decode_exception(mut value: Value) -> FfiResult<HostException> {
+    let (cause, args, attributes) = match value.get_mut(EXCEPTION_TAG) {
+        Some(Value::Object(inner)) => (
+            inner.remove("cause"),
+            inner.get("args").cloned(),
+            inner.remove("attributes"),
+        ),
+        _ => (None, None, None),
+    };
+    let (exc_type, message) = match value_to_object(value)? {
+        MontyObject::Exception { exc_type, arg } => (exc_type, arg),
+        _ => return Err(FfiError::Message("expected a $exception object".into())),
+    };
+    let args = args.filter(|args| args.as_array().is_some_and(|args| !args.is_empty()));
+    let attributes = match attributes {
+        None | Some(Value::Null) => None,
+        Some(Value::Object(map)) if map.is_empty() => None,
+        Some(attributes @ Value::Object(_)) => Some(attributes.to_string()),
+        Some(_) => {
+            return Err(FfiError::Message(
+                "$exception.attributes must be an object".into(),
+            ))
+        }
+    };
+    let data = (args.is_some() || attributes.is_some()).then(|| ExceptionData {
+        exc_type: exc_type.to_string(),
+        message: message.clone(),
+        args: args.map(|args| args.to_string()),
+        attributes,
+    });
+    let exception = MontyException::new(exc_type, message);
+    let causes = match cause {
+        Some(Value::Null) | None => Vec::new(),
+        Some(cause) => {
+            let cause = decode_exception(cause)?;
+            let mut causes = vec![cause.exception.summary()];
+            causes.extend(cause.causes);
+            causes
+        }
+    };
+    Ok(HostException {
+        exception,
+        causes,
+        data,
+    })
+}
+
+/// The `args` and `attributes` of a host exception, which Monty's exceptions have no
+/// room for: the script sees only the type and message. The run keeps them, and they
+/// are put back when an exception with the same type and message is reported to the
+/// host again, as a result or a call argument.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ExceptionData {
+    exc_type: String,
+    message: Option<String>,
+    /// Encoded, since dumps can't hold a `Value`.
+    args: Option<String>,
+    attributes: Option<String>,
+}
+
+impl ExceptionData {
+    /// Whether this is the data of exceptions with `other`'s type and message.
+    pub fn same_exception(&self, other: &Self) -> bool {
+        self.exc_type == other.exc_type && self.message == other.message
+    }
+}
+
+thread_local! {
+    /// The exception data of the run whose values are being encoded; see
+    /// `restoring_exception_data`.
+    static EXCEPTION_DATA: RefCell<Vec<ExceptionData>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Puts `data` back on the exceptions encoded on this thread until the returned guard
+/// is dropped.
+pub fn restoring_exception_data(data: &[ExceptionData]) -> RestoringExceptionData {
+    let outer = EXCEPTION_DATA.with(|current| current.replace(data.to_vec()));
+    RestoringExceptionData(outer)
+}
+
+pub struct RestoringExceptionData(Vec<ExceptionData>);
+
+impl Drop for RestoringExceptionData {
+    fn drop(&mut self) {
+        let outer = std::mem::take(&mut self.0);
+        EXCEPTION_DATA.with(|current| *current.borrow_mut() = outer);
+    }
+}
+
+fn restore_exception_data(
+    exc_type: &ExcType,
+    message: &Option<String>,
+    inner: &mut Map<String, Value>,
+) -> FfiResult<()> {
+    EXCEPTION_DATA.with(|current| {
+        let current = current.borrow();
+        let exc_type = exc_type.to_string();
+        let Some(data) = current
+            .iter()
+            .find(|data| data.exc_type == exc_type && data.message == *message)
+        else {
+            return Ok(());
+        };
+        if let Some(args) = &data.args {
+            inner.insert("args".into(), serde_json::from_str(args)?);
+        }
+        if let Some(attributes) = &data.attributes {
+            inner.insert("attributes".into(), serde_json::from_str(attributes)?);
+        }
+        Ok(())
+    })
+}
+
+/// The cause chains and data of the host exceptions raised into a script by one
+/// resume. Chains are kept per exception so a failure is only attributed to the host
+/// error it actually is.
+#[derive(Debug, Default)]
+pub struct HostCauses {
+    causes: Vec<(String, Vec<String>)>,
+    data: Vec<ExceptionData>,
+}
+
+impl HostCauses {
+    pub fn push(&mut self, host: &HostException) {
+        if !host.causes.is_empty() {
+            self.causes
+                .push((host.exception.summary(), host.causes.clone()));
+        }
+        if let Some(data) = &host.data {
+            self.data.push(data.clone());
+        }
+    }
+
+    /// The chain behind `exc` when it is a host exception the script let propagate;
+    /// empty when the script handled it and failed with something else.
+    pub fn of(&self, exc: &MontyException) -> &[String] {
+        let summary = exc.summary();
+        self.causes
+            .iter()
+            .find(|(raised, _)| *raised == summary)
+            .map_or(&[], |(_, causes)| causes.as_slice())
+    }
+
+    /// The args and attributes of the exceptions raised, for the run to keep.
+    pub fn data(&self) -> &[ExceptionData] {
+        &self.data
+    }
+}
+
+impl From<&HostException> for HostCauses {
+    fn from(host: &HostException) -> Self {
+        let mut causes = Self::default();
+        causes.push(host);
+        causes
+    }
+}
+
+pub fn parse_exc_type(name: &str) -> FfiResult<ExcType> {
+    name.parse::<ExcType>()
+        .map_err(|_| FfiError::Message(format!("unknown exception type {name}")))
+}
+
 fn parse_dataclass(value: Value) -> FfiResult<MontyObject> {
     let map = match value {
         Value::Object(m) => m,
@@ -282,7 +696,18 @@ fn object_to_value(obj: &MontyObject) -> FfiResult<Value> {
         MontyObject::None => Value::Null,
         MontyObject::Bool(b) => Value::Bool(*b),
         MontyObject::Int(i) => Value::Number((*i).into()),
-        MontyObject::Float(f) => json!(f),
+        MontyObject::Float(f) => {
+            // `0.0 == -0.0`, so canonical encoding writes both as `0.0`.
+            let f = if canonical() && *f == 0.0 { 0.0 } else { *f };
+            if FLOAT_STRINGS.load(Ordering::Relaxed) || (canonical() && !f.is_finite()) {
+                let mut outer = Map::new();
+                // `Debug` writes the shortest round-trip form and always marks it a float.
+                outer.insert(FLOAT_TAG.into(), Value::String(format!("{f:?}")));
+                Value::Object(outer)
+            } else {
+                json!(f)
+            }
+        }
         MontyObject::String(s) => Value::String(s.clone()),
         MontyObject::Bytes(bytes) => {
             let mut outer = Map::new();
@@ -312,16 +737,15 @@ fn object_to_value(obj: &MontyObject) -> FfiResult<Value> {
             Value::Object(outer)
         }
         MontyObject::Dict(pairs) => {
+            let mut items = pairs
+                .into_iter()
+                .map(|(k, v)| object_to_value_pair(k, v))
+                .collect::<FfiResult<Vec<_>>>()?;
+            if canonical() {
+                sort_canonically(&mut items);
+            }
             let mut outer = Map::new();
-            outer.insert(
-                DICT_TAG.into(),
-                Value::Array(
-                    pairs
-                        .into_iter()
-                        .map(|(k, v)| object_to_value_pair(k, v))
-                        .collect::<FfiResult<Vec<_>>>()?,
-                ),
-            );
+            outer.insert(DICT_TAG.into(), Value::Array(items));
             Value::Object(outer)
         }
         MontyObject::Set(items) => encode_collection(SET_TAG, items)?,
@@ -332,6 +756,7 @@ fn object_to_value(obj: &MontyObject) -> FfiResult<Value> {
             if let Some(message) = arg {
                 inner.insert("message".into(), Value::String(message.clone()));
             }
+            restore_exception_data(exc_type, arg, &mut inner)?;
             let mut outer = Map::new();
             outer.insert(EXCEPTION_TAG.into(), Value::Object(inner));
             Value::Object(outer)
@@ -346,11 +771,14 @@ fn object_to_value(obj: &MontyObject) -> FfiResult<Value> {
             outer.insert(REPR_TAG.into(), Value::String(r.clone()));
             Value::Object(outer)
         }
-        MontyObject::BigInt(value) => {
-            let mut outer = Map::new();
-            outer.insert(BIGINT_TAG.into(), Value::String(value.to_string()));
-            Value::Object(outer)
-        }
+        MontyObject::BigInt(value) => match i64::try_from(value) {
+            Ok(int) if canonical() => Value::Number(int.into()),
+            _ => {
+                let mut outer = Map::new();
+                outer.insert(BIGINT_TAG.into(), Value::String(value.to_string()));
+                Value::Object(outer)
+            }
+        },
         MontyObject::Dataclass {
             name,
             type_id,
@@ -416,19 +844,25 @@ fn object_to_value(obj: &MontyObject) -> FfiResult<Value> {
 }
 
 fn encode_collection(tag: &str, items: &[MontyObject]) -> FfiResult<Value> {
+    let mut items = items
+        .iter()
+        .map(object_to_value)
+        .collect::<FfiResult<Vec<_>>>()?;
+    if canonical() {
+        sort_canonically(&mut items);
+    }
     let mut outer = Map::new();
-    outer.insert(
-        tag.into(),
-        Value::Array(
-            items
-                .iter()
-                .map(object_to_value)
-                .collect::<FfiResult<Vec<_>>>()?,
-        ),
-    );
+    outer.insert(tag.into(), Value::Array(items));
     Ok(Value::Object(outer))
 }
 
+/// Orders set items or dict entries by their key-sorted encoding, so equal sets and
+/// dicts encode the same whatever order they were built in.
+fn sort_canonically(items: &mut [Value]) {
+    items.iter_mut().for_each(sort_keys);
+    items.sort_by_cached_key(|item| item.to_string());
+}
+
 fn object_to_value_pair(key: &MontyObject, value: &MontyObject) -> FfiResult<Value> {
     Ok(Value::Array(vec![
         object_to_value(key)?,
@@ -436,6 +870,102 @@ fn object_to_value_pair(key: &MontyObject, value: &MontyObject) -> FfiResult<Val
     ]))
 }
 
+/// In strict encoding mode, fails if anything in `value` would be written as a `$repr`
+/// it can't be decoded from, naming its type and its path from `root`
+/// (e.g. `$[0]['key']`). Walks with an explicit stack like `nesting_depth`.
+fn check_lossless(value: &MontyObject, root: String) -> FfiResult<()> {
+    if !STRICT_ENCODING.load(Ordering::Relaxed) {
+        return Ok(());
+    }
+    let mut pending = vec![(value, root)];
+    while let Some((value, path)) = pending.pop() {
+        match value {
+            MontyObject::List(items)
+            | MontyObject::Tuple(items)
+            | MontyObject::Set(items)
+            | MontyObject::FrozenSet(items) => pending.extend(
+                items
+                    .iter()
+                    .enumerate()
+                    .map(|(index, item)| (item, format!("{path}[{index}]"))),
+            ),
+            MontyObject::Dict(pairs) => {
+                for (index, (key, value)) in pairs.into_iter().enumerate() {
+                    pending.push((key, format!("{path}.keys()[{index}]")));
+                    pending.push((value, format!("{path}[{key}]")));
+                }
+            }
+            MontyObject::Dataclass { attrs, .. } => {
+                for (key, value) in attrs {
+                    match key {
+                        MontyObject::String(name) => {
+                            pending.push((value, format!("{path}.{name}")))
+                        }
+                        _ => pending.push((value, format!("{path}[{key}]"))),
+                    }
+                }
+            }
+            MontyObject::NamedTuple {
+                field_names,
+                values,
+                ..
+            } => pending.extend(values.iter().enumerate().map(|(index, item)| {
+                match field_names.get(index) {
+                    Some(name) => (item, format!("{path}.{name}")),
+                    None => (item, format!("{path}[{index}]")),
+                }
+            })),
+            MontyObject::Repr(_)
+            | MontyObject::Cycle(..)
+            | MontyObject::Type(_)
+            | MontyObject::BuiltinFunction(_) => {
+                return Err(FfiError::Message(format!(
+                    "strict encoding: {path} ({}) has no lossless JSON form: {value}",
+                    objects::type_name(value)
+                )))
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn check_lossless_pairs(pairs: &[(MontyObject, MontyObject)]) -> FfiResult<()> {
+    for (index, (key, value)) in pairs.iter().enumerate() {
+        check_lossless(key, format!("$.keys()[{index}]"))?;
+        check_lossless(value, format!("$[{key}]"))?;
+    }
+    Ok(())
+}
+
+/// How deeply `value`'s containers nest: 0 for a scalar, 1 for a flat list. Walks
+/// with an explicit stack so arbitrarily deep values can be measured safely.
+pub fn nesting_depth(value: &MontyObject) -> usize {
+    let mut deepest = 0;
+    let mut pending = vec![(value, 0)];
+    while let Some((value, depth)) = pending.pop() {
+        match value {
+            MontyObject::List(items)
+            | MontyObject::Tuple(items)
+            | MontyObject::Set(items)
+            | MontyObject::FrozenSet(items)
+            | MontyObject::NamedTuple { values: items, .. } => {
+                deepest = deepest.max(depth + 1);
+                pending.extend(items.iter().map(|item| (item, depth + 1)));
+            }
+            MontyObject::Dict(pairs) | MontyObject::Dataclass { attrs: pairs, .. } => {
+                deepest = deepest.max(depth + 1);
+                for (key, value) in pairs {
+                    pending.push((key, depth + 1));
+                    pending.push((value, depth + 1));
+                }
+            }
+            _ => {}
+        }
+    }
+    deepest
+}
+
 pub fn decode_value(value: Value) -> FfiResult<MontyObject> {
     value_to_object(value)
 }
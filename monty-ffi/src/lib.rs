@@ -1,23 +1,70 @@
+mod audit;
+mod builtins;
+mod compiled;
+mod context;
+mod dataclasses;
+mod debug;
+mod delta;
+mod dispatch;
+mod drive;
 mod error;
+mod globals;
+mod inputs;
+mod intern;
 mod json;
+mod metrics;
+mod namespaces;
+mod objects;
+mod options;
+mod os_functions;
+mod policy;
+mod print;
+pub mod protocol;
+mod quota;
+mod recover;
+mod reentry;
+mod repr;
+mod seal;
+mod sha256;
+mod signature;
+mod store;
+mod trace;
+mod tracker;
+mod warnings;
 
-use std::{ffi::c_void, os::raw::c_char, ptr, slice};
+use std::{
+    collections::{HashMap, HashSet},
+    ffi::c_void,
+    os::raw::c_char,
+    ptr, slice,
+    sync::Arc,
+};
 
+use compiled::{Compiled, Source, EMIT_FUNCTION, INPUT_FUNCTION, SLEEP_FUNCTION};
+use context::RunContext;
+use dispatch::{Call, Step};
 use error::{
-    monty_free_string, read_optional_str, read_required_str, to_c_string, FfiError, FfiResult,
-    MontyStatus,
+    monty_free_string, read_optional_str, read_optional_text, read_required_str, read_text,
+    take_replaced, to_c_string, FfiError, FfiResult, MontyStatus,
 };
+use globals::Globals;
+use inputs::PendingStart;
 use json::{
-    decode_inputs, decode_object, decode_value, encode_kwargs, encode_object, encode_objects,
-    encode_u32_slice,
+    check_numbers, decode_exception, decode_object, decode_value, encode_kwarg_values,
+    encode_kwargs, encode_object, encode_objects, encode_u32_slice, encode_value, parse_exc_type,
+    HostCauses,
 };
 use monty::{
-    ExcType, ExternalResult, FutureSnapshot, MontyException, MontyRun, NoLimitTracker, PrintWriter,
-    RunProgress, Snapshot,
+    ExcType, ExternalResult, FutureSnapshot, MontyException, MontyObject, RunProgress, Snapshot,
+};
+use options::{ResolutionMode, RunOptions};
+use protocol::{
+    CallDescriptor, FutureResolution, ProgressKind, ProgressMessage, ResumeError, PROTOCOL_VERSION,
 };
-use postcard::{from_bytes, to_allocvec};
-use serde::Deserialize;
-use serde_json::Value;
+use quota::QuotaGroup;
+use serde::{Deserialize, Serialize};
+use store::{DirStore, MemoryStore, SnapshotStore};
+use tracker::FfiTracker;
 
 #[repr(C)]
 pub struct MontyRunHandle {
@@ -25,15 +72,21 @@ pub struct MontyRunHandle {
 }
 
 impl MontyRunHandle {
-    fn as_ref(&self) -> &MontyRun {
-        unsafe { &*(self.inner as *mut MontyRun) }
+    fn compiled(&self) -> &Compiled {
+        unsafe { &*(self.inner as *mut Compiled) }
+    }
+
+    fn compiled_mut(&mut self) -> &mut Compiled {
+        unsafe { &mut *(self.inner as *mut Compiled) }
     }
 
-    fn new(runner: MontyRun) -> *mut Self {
-        let boxed = Box::new(runner);
-        Box::into_raw(Box::new(Self {
+    fn new(compiled: Compiled) -> *mut Self {
+        let boxed = Box::new(compiled);
+        let handle = Box::into_raw(Box::new(Self {
             inner: Box::into_raw(boxed) as *mut c_void,
-        }))
+        }));
+        debug::handed_out("run", handle, None);
+        handle
     }
 }
 
@@ -42,20 +95,51 @@ pub struct SnapshotHandle {
     inner: *mut c_void,
 }
 
+/// Suspended VM state together with the FFI context that travels with it; this is
+/// also the on-disk format of snapshot dumps.
+#[derive(Serialize, Deserialize)]
+struct Suspended<S> {
+    state: S,
+    context: RunContext,
+}
+
 impl SnapshotHandle {
-    fn as_ref(&self) -> &Snapshot<NoLimitTracker> {
-        unsafe { &*(self.inner as *mut Snapshot<NoLimitTracker>) }
+    fn suspended(&self) -> &Suspended<Snapshot<FfiTracker>> {
+        unsafe { &*(self.inner as *mut Suspended<Snapshot<FfiTracker>>) }
+    }
+
+    /// Fails while a callback of a call reading this snapshot is running.
+    fn suspended_mut(&mut self) -> FfiResult<&mut Suspended<Snapshot<FfiTracker>>> {
+        reentry::check_idle(self, "snapshot")?;
+        Ok(unsafe { &mut *(self.inner as *mut Suspended<Snapshot<FfiTracker>>) })
     }
 
-    fn into_inner(self: Box<Self>) -> Snapshot<NoLimitTracker> {
-        unsafe { *Box::from_raw(self.inner as *mut Snapshot<NoLimitTracker>) }
+    /// Takes the snapshot apart to resume it; fails, leaving the handle valid, while a
+    /// callback of a call reading it is running.
+    fn into_inner(self: Box<Self>) -> FfiResult<(Snapshot<FfiTracker>, RunContext)> {
+        if let Err(err) = reentry::check_idle(&*self, "snapshot") {
+            std::mem::forget(self);
+            return Err(err);
+        }
+        debug::taken_back(&*self);
+        metrics::snapshot_held(false);
+        let suspended =
+            unsafe { *Box::from_raw(self.inner as *mut Suspended<Snapshot<FfiTracker>>) };
+        Ok((suspended.state, suspended.context))
     }
 
-    fn new(snapshot: Snapshot<NoLimitTracker>) -> *mut Self {
-        let boxed = Box::new(snapshot);
-        Box::into_raw(Box::new(Self {
+    fn new(snapshot: Snapshot<FfiTracker>, context: RunContext) -> *mut Self {
+        let size = context.stats.memory;
+        metrics::snapshot_held(true);
+        let boxed = Box::new(Suspended {
+            state: snapshot,
+            context,
+        });
+        let handle = Box::into_raw(Box::new(Self {
             inner: Box::into_raw(boxed) as *mut c_void,
-        }))
+        }));
+        debug::handed_out("snapshot", handle, Some(size));
+        handle
     }
 }
 
@@ -69,19 +153,64 @@ impl FutureSnapshotHandle {
         self.as_ref().pending_call_ids()
     }
 
-    fn into_inner(self: Box<Self>) -> FutureSnapshot<NoLimitTracker> {
-        unsafe { *Box::from_raw(self.inner as *mut FutureSnapshot<NoLimitTracker>) }
+    fn into_inner(self: Box<Self>) -> (FutureSnapshot<FfiTracker>, RunContext) {
+        debug::taken_back(&*self);
+        metrics::future_snapshot_held(false);
+        let suspended =
+            unsafe { *Box::from_raw(self.inner as *mut Suspended<FutureSnapshot<FfiTracker>>) };
+        (suspended.state, suspended.context)
     }
 
-    fn new(snapshot: FutureSnapshot<NoLimitTracker>) -> *mut Self {
-        let boxed = Box::new(snapshot);
-        Box::into_raw(Box::new(Self {
+    fn new(snapshot: FutureSnapshot<FfiTracker>, context: RunContext) -> *mut Self {
+        let size = context.stats.memory;
+        metrics::future_snapshot_held(true);
+        let boxed = Box::new(Suspended {
+            state: snapshot,
+            context,
+        });
+        let handle = Box::into_raw(Box::new(Self {
             inner: Box::into_raw(boxed) as *mut c_void,
-        }))
+        }));
+        debug::handed_out("future_snapshot", handle, Some(size));
+        handle
+    }
+
+    fn as_ref(&self) -> &FutureSnapshot<FfiTracker> {
+        &self.suspended().state
+    }
+
+    fn suspended(&self) -> &Suspended<FutureSnapshot<FfiTracker>> {
+        unsafe { &*(self.inner as *mut Suspended<FutureSnapshot<FfiTracker>>) }
+    }
+
+    fn suspended_mut(&mut self) -> &mut Suspended<FutureSnapshot<FfiTracker>> {
+        unsafe { &mut *(self.inner as *mut Suspended<FutureSnapshot<FfiTracker>>) }
+    }
+}
+
+/// A run waiting on the host for a lazy input before it can start.
+#[repr(C)]
+pub struct InputRequestHandle {
+    inner: *mut c_void,
+}
+
+impl InputRequestHandle {
+    fn pending(&self) -> &PendingStart {
+        unsafe { &*(self.inner as *mut PendingStart) }
+    }
+
+    fn into_inner(self: Box<Self>) -> PendingStart {
+        debug::taken_back(&*self);
+        unsafe { *Box::from_raw(self.inner as *mut PendingStart) }
     }
 
-    fn as_ref(&self) -> &FutureSnapshot<NoLimitTracker> {
-        unsafe { &*(self.inner as *mut FutureSnapshot<NoLimitTracker>) }
+    fn new(pending: PendingStart) -> *mut Self {
+        let boxed = Box::new(pending);
+        let handle = Box::into_raw(Box::new(Self {
+            inner: Box::into_raw(boxed) as *mut c_void,
+        }));
+        debug::handed_out("input_request", handle, None);
+        handle
     }
 }
 
@@ -98,6 +227,59 @@ pub struct ProgressResult {
     pub snapshot: *mut SnapshotHandle,
     pub pending_call_ids_json: *mut c_char,
     pub future_snapshot: *mut FutureSnapshotHandle,
+    pub pending_calls_json: *mut c_char,
+    pub stats_json: *mut c_char,
+    /// Timeout configured for the reported call in milliseconds, or 0 for none.
+    pub timeout_ms: u64,
+    pub input_name: *mut c_char,
+    pub input_request: *mut InputRequestHandle,
+    /// Encoded `self` of a method call (the first of `args_json`), or NULL.
+    pub receiver_json: *mut c_char,
+    /// With the `object_handles` run option, these replace `result_json`, `args_json`
+    /// (as a list), and `kwargs_json` (as a dict). The caller owns and frees them.
+    pub result_object: *mut MontyObjectHandle,
+    pub args_object: *mut MontyObjectHandle,
+    pub kwargs_object: *mut MontyObjectHandle,
+    /// Chrome trace of the run on completion, when it was started with `trace`.
+    pub trace_json: *mut c_char,
+    /// Audit log of attempted OS calls on completion, when the run was started with
+    /// `audit_os_calls`.
+    pub audit_json: *mut c_char,
+    /// Byte lengths of `result_json`, `args_json`, and `kwargs_json`, for bindings that
+    /// take strings as (pointer, length). JSON escapes NUL as `\u0000`, so a Python
+    /// string holding one crosses intact; the strings are still freed as before.
+    pub result_len: usize,
+    pub args_len: usize,
+    pub kwargs_len: usize,
+    /// JSON array of non-fatal warnings gathered since the last progress result, such
+    /// as dropped output or a limit nearly used up, or NULL when there are none.
+    pub warnings_json: *mut c_char,
+    /// What the script printed since the last progress result, unless the run was
+    /// started with `print_to_stdout`, or NULL when it printed nothing. Output is kept
+    /// per run, so concurrent runs don't interleave; it is freed with the strings.
+    pub output: *mut u8,
+    pub output_len: usize,
+    /// How long a `MONTY_PROGRESS_SLEEP` asks the host to wait before resuming, in
+    /// milliseconds.
+    pub delay_ms: u64,
+    /// Priority configured for the reported call with `call_priorities`; higher runs
+    /// sooner. Pending call ids of `MONTY_PROGRESS_RESOLVE_FUTURES` are listed in
+    /// priority order, highest first.
+    pub priority: i32,
+    /// Nonzero when a `MONTY_PROGRESS_FUNCTION_CALL` is to a builtin the host overrides
+    /// with `monty_run_override_builtins`, rather than a declared external function.
+    pub builtin_override: i32,
+    /// Prompt of a `MONTY_PROGRESS_PROMPT`, empty when `input()` was called without one.
+    pub prompt: *mut c_char,
+    /// Idempotency key of a `MONTY_PROGRESS_FUNCTION_CALL`: the script's
+    /// `idempotency_key=` string argument, or one derived from the run id and call id
+    /// that stays the same when the call is reported again after a resume from the same
+    /// snapshot. NULL for runs without a run id whose script passes none.
+    pub idempotency_key: *mut c_char,
+    /// Cache key of a `MONTY_PROGRESS_FUNCTION_CALL` to a function listed in the
+    /// `cache_calls` run option, or NULL. Hosts can keep results by it across runs and
+    /// resume later calls with the same key from their own cache.
+    pub cache_key: *mut c_char,
 }
 
 impl Default for ProgressResult {
@@ -114,24 +296,62 @@ impl Default for ProgressResult {
             snapshot: ptr::null_mut(),
             pending_call_ids_json: ptr::null_mut(),
             future_snapshot: ptr::null_mut(),
+            pending_calls_json: ptr::null_mut(),
+            stats_json: ptr::null_mut(),
+            timeout_ms: 0,
+            input_name: ptr::null_mut(),
+            input_request: ptr::null_mut(),
+            receiver_json: ptr::null_mut(),
+            result_object: ptr::null_mut(),
+            args_object: ptr::null_mut(),
+            kwargs_object: ptr::null_mut(),
+            trace_json: ptr::null_mut(),
+            audit_json: ptr::null_mut(),
+            result_len: 0,
+            args_len: 0,
+            kwargs_len: 0,
+            warnings_json: ptr::null_mut(),
+            output: ptr::null_mut(),
+            output_len: 0,
+            delay_ms: 0,
+            priority: 0,
+            builtin_override: 0,
+            prompt: ptr::null_mut(),
+            idempotency_key: ptr::null_mut(),
+            cache_key: ptr::null_mut(),
         }
     }
 }
 
-pub const MONTY_PROGRESS_COMPLETE: i32 = 0;
-pub const MONTY_PROGRESS_FUNCTION_CALL: i32 = 1;
-pub const MONTY_PROGRESS_OS_CALL: i32 = 2;
-pub const MONTY_PROGRESS_RESOLVE_FUTURES: i32 = 3;
-
-#[derive(Debug, Deserialize)]
-struct FutureResultJson {
-    call_id: u32,
-    #[serde(default)]
-    result: Option<Value>,
-    #[serde(default)]
-    error: Option<String>,
+/// Values of `ProgressResult::kind`, exported to the header as `MONTY_PROGRESS_*`. The
+/// field itself stays an `int32_t`, since the size of a C enum is up to the compiler.
+#[repr(i32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MontyProgress {
+    Complete = 0,
+    FunctionCall = 1,
+    OsCall = 2,
+    ResolveFutures = 3,
+    InputRequest = 4,
+    Emit = 5,
+    Sleep = 6,
+    Prompt = 7,
 }
 
+pub const MONTY_PROGRESS_COMPLETE: i32 = MontyProgress::Complete as i32;
+pub const MONTY_PROGRESS_FUNCTION_CALL: i32 = MontyProgress::FunctionCall as i32;
+pub const MONTY_PROGRESS_OS_CALL: i32 = MontyProgress::OsCall as i32;
+pub const MONTY_PROGRESS_RESOLVE_FUTURES: i32 = MontyProgress::ResolveFutures as i32;
+pub const MONTY_PROGRESS_INPUT_REQUEST: i32 = MontyProgress::InputRequest as i32;
+pub const MONTY_PROGRESS_EMIT: i32 = MontyProgress::Emit as i32;
+pub const MONTY_PROGRESS_SLEEP: i32 = MontyProgress::Sleep as i32;
+pub const MONTY_PROGRESS_PROMPT: i32 = MontyProgress::Prompt as i32;
+
+const CANCELLED_EXC_TYPE: &str = "CancelledError";
+const TIMEOUT_EXC_TYPE: &str = "TimeoutError";
+
+type FutureResults = Vec<(u32, ExternalResult)>;
+
 #[no_mangle]
 pub unsafe extern "C" fn monty_run_new(
     code: *const c_char,
@@ -140,33 +360,56 @@ pub unsafe extern "C" fn monty_run_new(
     ext_funcs: *const *const c_char,
     out: *mut *mut MontyRunHandle,
 ) -> MontyStatus {
-    fn inner(
-        code: *const c_char,
-        script_name: *const c_char,
-        input_names: *const *const c_char,
-        ext_funcs: *const *const c_char,
-        out: *mut *mut MontyRunHandle,
-    ) -> FfiResult<()> {
-        if out.is_null() {
-            return Err(FfiError::NullPointer("out"));
-        }
-        let code = unsafe { read_required_str(code, "code") }?;
-        let script_name = unsafe { read_required_str(script_name, "script_name") }?;
-        let input_names = unsafe { read_string_array(input_names, "input_names")? };
-        let ext_funcs = unsafe { read_string_array(ext_funcs, "ext_funcs")? };
-        let runner = MontyRun::new(code, &script_name, input_names, ext_funcs)?;
-        unsafe {
-            *out = MontyRunHandle::new(runner);
-        }
-        Ok(())
+    match run_new(code, script_name, input_names, ext_funcs, false, out) {
+        Ok(()) => MontyStatus::success(),
+        Err(err) => MontyStatus::from_error(err),
     }
+}
 
-    match inner(code, script_name, input_names, ext_funcs, out) {
+/// Like `monty_run_new`, but code that is not valid UTF-8 is decoded with U+FFFD
+/// replacement characters instead of failing, and the status has `lossy` set when it
+/// was. Names stay strict.
+#[no_mangle]
+pub unsafe extern "C" fn monty_run_new_lossy(
+    code: *const c_char,
+    script_name: *const c_char,
+    input_names: *const *const c_char,
+    ext_funcs: *const *const c_char,
+    out: *mut *mut MontyRunHandle,
+) -> MontyStatus {
+    match run_new(code, script_name, input_names, ext_funcs, true, out) {
         Ok(()) => MontyStatus::success(),
         Err(err) => MontyStatus::from_error(err),
     }
 }
 
+fn run_new(
+    code: *const c_char,
+    script_name: *const c_char,
+    input_names: *const *const c_char,
+    ext_funcs: *const *const c_char,
+    lossy: bool,
+    out: *mut *mut MontyRunHandle,
+) -> FfiResult<()> {
+    if out.is_null() {
+        return Err(FfiError::NullPointer("out"));
+    }
+    let code = unsafe { read_text(code, "code", lossy) }?;
+    let script_name = unsafe { read_required_str(script_name, "script_name") }?;
+    let input_names = unsafe { read_string_array(input_names, "input_names")? };
+    let ext_funcs = unsafe { read_string_array(ext_funcs, "ext_funcs")? };
+    let compiled = Compiled::compile(Source {
+        code,
+        script_name,
+        input_names,
+        ext_funcs,
+    })?;
+    unsafe {
+        *out = MontyRunHandle::new(compiled);
+    }
+    Ok(())
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn monty_run_dump(
     run: *mut MontyRunHandle,
@@ -179,7 +422,7 @@ pub unsafe extern "C" fn monty_run_dump(
         out_len: *mut usize,
     ) -> FfiResult<()> {
         let run = unsafe { run.as_ref().ok_or(FfiError::NullPointer("run"))? };
-        let bytes = run.as_ref().dump()?;
+        let bytes = run.compiled().dump()?;
         write_bytes(bytes, out_bytes, out_len)
     }
 
@@ -203,9 +446,9 @@ pub unsafe extern "C" fn monty_run_load(
             return Err(FfiError::NullPointer("bytes"));
         }
         let slice = unsafe { slice::from_raw_parts(bytes, len) };
-        let run = MontyRun::load(slice)?;
+        let compiled = Compiled::load(slice)?;
         unsafe {
-            *out = MontyRunHandle::new(run);
+            *out = MontyRunHandle::new(compiled);
         }
         Ok(())
     }
@@ -219,365 +462,3525 @@ pub unsafe extern "C" fn monty_run_load(
 #[no_mangle]
 pub unsafe extern "C" fn monty_run_free(run: *mut MontyRunHandle) {
     if !run.is_null() {
+        debug::taken_back(run);
         let handle = Box::from_raw(run);
-        drop(Box::from_raw(handle.inner as *mut MontyRun));
+        drop(Box::from_raw(handle.inner as *mut Compiled));
     }
 }
 
+/// Frees `*run` like `monty_run_free` and sets it to NULL, so freeing the same pointer again
+/// (e.g. from a finalizer that runs after an explicit free) does nothing. The other
+/// `_free_and_clear` functions work the same way.
 #[no_mangle]
-pub unsafe extern "C" fn monty_run_start(
-    run: *mut MontyRunHandle,
-    inputs_json: *const c_char,
-    out: *mut ProgressResult,
-) -> MontyStatus {
-    fn inner(
-        run: *mut MontyRunHandle,
-        inputs_json: *const c_char,
-        out: *mut ProgressResult,
-    ) -> FfiResult<()> {
-        if out.is_null() {
-            return Err(FfiError::NullPointer("out"));
-        }
-        let run = unsafe { run.as_ref().ok_or(FfiError::NullPointer("run"))? };
-        let inputs_json = unsafe {
-            if inputs_json.is_null() {
-                String::from("[]")
-            } else {
-                read_required_str(inputs_json, "inputs_json")?
-            }
-        };
-        let inputs = decode_inputs(&inputs_json)?;
-        let mut print = PrintWriter::Stdout;
-        let progress = run
-            .as_ref()
-            .clone()
-            .start(inputs, NoLimitTracker, &mut print)?;
-        unsafe { write_progress_result(out, progress) }
+pub unsafe extern "C" fn monty_run_free_and_clear(run: *mut *mut MontyRunHandle) {
+    if let Some(run) = run.as_mut() {
+        monty_run_free(std::mem::replace(run, ptr::null_mut()));
     }
+}
 
-    match inner(run, inputs_json, out) {
-        Ok(()) => MontyStatus::success(),
-        Err(err) => MontyStatus::from_error(err),
-    }
+/// Opaque, reference-counted set of named globals that can be attached to many runs.
+#[repr(C)]
+pub struct GlobalsHandle {
+    inner: *mut c_void,
 }
 
-#[no_mangle]
-pub unsafe extern "C" fn monty_progress_result_free_strings(result: *mut ProgressResult) {
-    if let Some(result) = result.as_mut() {
-        monty_free_string(result.result_json);
-        monty_free_string(result.function_name);
-        monty_free_string(result.os_function);
-        monty_free_string(result.args_json);
-        monty_free_string(result.kwargs_json);
-        monty_free_string(result.pending_call_ids_json);
-        result.result_json = ptr::null_mut();
-        result.function_name = ptr::null_mut();
-        result.os_function = ptr::null_mut();
-        result.args_json = ptr::null_mut();
-        result.kwargs_json = ptr::null_mut();
-        result.pending_call_ids_json = ptr::null_mut();
+impl GlobalsHandle {
+    fn as_ref(&self) -> &Arc<Globals> {
+        unsafe { &*(self.inner as *mut Arc<Globals>) }
     }
 }
 
+/// Decodes `globals_json`, a JSON object mapping names to tagged values, once into a
+/// handle that can be attached to any number of runs with `monty_run_attach_globals`.
 #[no_mangle]
-pub unsafe extern "C" fn monty_snapshot_resume(
-    snapshot: *mut SnapshotHandle,
-    _call_id: u32,
-    result_json: *const c_char,
-    error_message: *const c_char,
-    out: *mut ProgressResult,
+pub unsafe extern "C" fn monty_globals_new(
+    globals_json: *const c_char,
+    out: *mut *mut GlobalsHandle,
 ) -> MontyStatus {
-    fn inner(
-        snapshot: *mut SnapshotHandle,
-        result_json: *const c_char,
-        error_message: *const c_char,
-        out: *mut ProgressResult,
-    ) -> FfiResult<()> {
+    fn inner(globals_json: *const c_char, out: *mut *mut GlobalsHandle) -> FfiResult<()> {
         if out.is_null() {
             return Err(FfiError::NullPointer("out"));
         }
-        if snapshot.is_null() {
-            return Err(FfiError::NullPointer("snapshot"));
+        let json = unsafe { read_text(globals_json, "globals_json", false)? };
+        let boxed = Box::new(Arc::new(Globals::from_json(&json)?));
+        unsafe {
+            *out = Box::into_raw(Box::new(GlobalsHandle {
+                inner: Box::into_raw(boxed) as *mut c_void,
+            }));
+            debug::handed_out("globals", *out, None);
         }
-        let resolution = if let Some(err) = unsafe { read_optional_str(error_message)? } {
-            ExternalResult::Error(MontyException::new(ExcType::RuntimeError, Some(err)))
-        } else if let Some(json) = unsafe { read_optional_str(result_json)? } {
-            ExternalResult::Return(decode_object(&json)?)
-        } else {
-            ExternalResult::Future
-        };
-        let mut print = PrintWriter::Stdout;
-        let snapshot = unsafe { Box::from_raw(snapshot) };
-        let progress = snapshot.into_inner().run(resolution, &mut print)?;
-        unsafe { write_progress_result(out, progress) }
+        Ok(())
     }
 
-    match inner(snapshot, result_json, error_message, out) {
+    match inner(globals_json, out) {
         Ok(()) => MontyStatus::success(),
         Err(err) => MontyStatus::from_error(err),
     }
 }
 
+/// Releases the caller's reference; runs the globals are attached to keep theirs.
 #[no_mangle]
-pub unsafe extern "C" fn monty_future_snapshot_resume(
-    snapshot: *mut FutureSnapshotHandle,
-    results_json: *const c_char,
-    out: *mut ProgressResult,
+pub unsafe extern "C" fn monty_globals_free(globals: *mut GlobalsHandle) {
+    if !globals.is_null() {
+        debug::taken_back(globals);
+        let handle = Box::from_raw(globals);
+        drop(Box::from_raw(handle.inner as *mut Arc<Globals>));
+    }
+}
+
+/// `monty_globals_free`, then sets `*globals` to NULL.
+#[no_mangle]
+pub unsafe extern "C" fn monty_globals_free_and_clear(globals: *mut *mut GlobalsHandle) {
+    if let Some(globals) = globals.as_mut() {
+        monty_globals_free(std::mem::replace(globals, ptr::null_mut()));
+    }
+}
+
+/// Writes the builtins scripts of `run` can use without declaring them, as a JSON array
+/// of `{"name", "kind", "enabled"}` objects. `kind` is `function`, `type`, or `host`
+/// for the builtins this library answers through progress results (`host_emit`,
+/// `host_sleep`).
+#[no_mangle]
+pub unsafe extern "C" fn monty_run_list_builtins(
+    run: *const MontyRunHandle,
+    out_json: *mut *mut c_char,
 ) -> MontyStatus {
-    fn inner(
-        snapshot: *mut FutureSnapshotHandle,
-        results_json: *const c_char,
-        out: *mut ProgressResult,
-    ) -> FfiResult<()> {
-        if out.is_null() {
-            return Err(FfiError::NullPointer("out"));
-        }
-        if snapshot.is_null() {
-            return Err(FfiError::NullPointer("snapshot"));
-        }
-        let json = unsafe { read_required_str(results_json, "results_json") }?;
-        let results = decode_future_results(&json)?;
-        let mut print = PrintWriter::Stdout;
-        let snapshot = unsafe { Box::from_raw(snapshot) };
-        let progress = snapshot.into_inner().resume(results, &mut print)?;
-        unsafe { write_progress_result(out, progress) }
+    fn inner(run: *const MontyRunHandle, out_json: *mut *mut c_char) -> FfiResult<()> {
+        let run = unsafe { run.as_ref().ok_or(FfiError::NullPointer("run"))? };
+        write_json(&builtins::list(&run.compiled().disabled_builtins), out_json)
     }
 
-    match inner(snapshot, results_json, out) {
+    match inner(run, out_json) {
         Ok(()) => MontyStatus::success(),
         Err(err) => MontyStatus::from_error(err),
     }
 }
 
+/// Makes calls to each builtin named in the NULL-terminated `names` raise
+/// `PermissionError` in every later start of `run`, replacing the previously disabled
+/// set; NULL or an empty array enables them all again. Names must be listed by
+/// `monty_run_list_builtins` and not declared as external functions. The set is not
+/// kept by `monty_run_dump`, and runs restored from older dumps can't disable builtins.
 #[no_mangle]
-pub unsafe extern "C" fn monty_snapshot_dump(
-    snapshot: *mut SnapshotHandle,
-    out_bytes: *mut *mut u8,
-    out_len: *mut usize,
+pub unsafe extern "C" fn monty_run_disable_builtins(
+    run: *mut MontyRunHandle,
+    names: *const *const c_char,
 ) -> MontyStatus {
-    fn inner(
-        snapshot: *mut SnapshotHandle,
-        out_bytes: *mut *mut u8,
-        out_len: *mut usize,
-    ) -> FfiResult<()> {
-        let snapshot = unsafe { snapshot.as_ref().ok_or(FfiError::NullPointer("snapshot"))? };
-        let bytes = to_allocvec(snapshot.as_ref())?;
-        write_bytes(bytes, out_bytes, out_len)
+    fn inner(run: *mut MontyRunHandle, names: *const *const c_char) -> FfiResult<()> {
+        let run = unsafe { run.as_mut().ok_or(FfiError::NullPointer("run"))? };
+        let names = unsafe { read_string_array(names, "names")? };
+        run.compiled_mut().disable_builtins(names)
     }
 
-    match inner(snapshot, out_bytes, out_len) {
+    match inner(run, names) {
         Ok(()) => MontyStatus::success(),
         Err(err) => MontyStatus::from_error(err),
     }
 }
 
+/// Reports calls to each builtin named in the NULL-terminated `names` (e.g. `print`) as
+/// `MONTY_PROGRESS_FUNCTION_CALL` with `builtin_override` set in every later start of
+/// `run`, so the host can virtualize them without changing scripts; the host resumes
+/// them like any external call. Replaces the previously overridden set; NULL or an
+/// empty array restores them all. Host builtins can't be overridden, and a builtin
+/// that is also disabled stays disabled. The set is not kept by `monty_run_dump`.
 #[no_mangle]
-pub unsafe extern "C" fn monty_snapshot_load(
-    bytes: *const u8,
-    len: usize,
-    out: *mut *mut SnapshotHandle,
+pub unsafe extern "C" fn monty_run_override_builtins(
+    run: *mut MontyRunHandle,
+    names: *const *const c_char,
 ) -> MontyStatus {
-    fn inner(bytes: *const u8, len: usize, out: *mut *mut SnapshotHandle) -> FfiResult<()> {
-        if out.is_null() {
-            return Err(FfiError::NullPointer("out"));
-        }
-        if len > 0 && bytes.is_null() {
-            return Err(FfiError::NullPointer("bytes"));
-        }
-        let slice = unsafe { slice::from_raw_parts(bytes, len) };
-        let snapshot: Snapshot<NoLimitTracker> = from_bytes(slice)?;
-        unsafe {
-            *out = SnapshotHandle::new(snapshot);
-        }
-        Ok(())
+    fn inner(run: *mut MontyRunHandle, names: *const *const c_char) -> FfiResult<()> {
+        let run = unsafe { run.as_mut().ok_or(FfiError::NullPointer("run"))? };
+        let names = unsafe { read_string_array(names, "names")? };
+        run.compiled_mut().override_builtins(names)
     }
 
-    match inner(bytes, len, out) {
+    match inner(run, names) {
         Ok(()) => MontyStatus::success(),
         Err(err) => MontyStatus::from_error(err),
     }
 }
 
+/// Binds the shared globals as module-level variables in every later start of `run`,
+/// or detaches them when `globals` is NULL. The handle is held by reference; each start
+/// copies the values into its own VM.
 #[no_mangle]
-pub unsafe extern "C" fn monty_future_snapshot_dump(
-    snapshot: *mut FutureSnapshotHandle,
-    out_bytes: *mut *mut u8,
-    out_len: *mut usize,
+pub unsafe extern "C" fn monty_run_attach_globals(
+    run: *mut MontyRunHandle,
+    globals: *const GlobalsHandle,
 ) -> MontyStatus {
-    fn inner(
-        snapshot: *mut FutureSnapshotHandle,
-        out_bytes: *mut *mut u8,
-        out_len: *mut usize,
-    ) -> FfiResult<()> {
-        let snapshot = unsafe { snapshot.as_ref().ok_or(FfiError::NullPointer("snapshot"))? };
-        let bytes = to_allocvec(snapshot.as_ref())?;
-        write_bytes(bytes, out_bytes, out_len)
+    fn inner(run: *mut MontyRunHandle, globals: *const GlobalsHandle) -> FfiResult<()> {
+        let run = unsafe { run.as_mut().ok_or(FfiError::NullPointer("run"))? };
+        let globals = unsafe { globals.as_ref() }.map(|globals| Arc::clone(globals.as_ref()));
+        run.compiled_mut().globals = globals;
+        Ok(())
     }
 
-    match inner(snapshot, out_bytes, out_len) {
+    match inner(run, globals) {
         Ok(()) => MontyStatus::success(),
         Err(err) => MontyStatus::from_error(err),
     }
 }
 
+/// Opaque, reference-counted budget shared by every run attached to it.
+#[repr(C)]
+pub struct QuotaGroupHandle {
+    inner: *mut c_void,
+}
+
+impl QuotaGroupHandle {
+    fn as_ref(&self) -> &Arc<QuotaGroup> {
+        unsafe { &*(self.inner as *mut Arc<QuotaGroup>) }
+    }
+}
+
+/// Creates a quota group from `limits_json`
+/// (`{"max_instructions": 10000000, "max_memory": 67108864, "window_ms": 60000}`).
+/// Runs attached to it draw from the same instruction and memory budgets.
 #[no_mangle]
-pub unsafe extern "C" fn monty_future_snapshot_load(
-    bytes: *const u8,
-    len: usize,
-    out: *mut *mut FutureSnapshotHandle,
+pub unsafe extern "C" fn monty_quota_group_new(
+    limits_json: *const c_char,
+    out: *mut *mut QuotaGroupHandle,
 ) -> MontyStatus {
-    fn inner(bytes: *const u8, len: usize, out: *mut *mut FutureSnapshotHandle) -> FfiResult<()> {
+    fn inner(limits_json: *const c_char, out: *mut *mut QuotaGroupHandle) -> FfiResult<()> {
         if out.is_null() {
             return Err(FfiError::NullPointer("out"));
         }
-        if len > 0 && bytes.is_null() {
-            return Err(FfiError::NullPointer("bytes"));
-        }
-        let slice = unsafe { slice::from_raw_parts(bytes, len) };
-        let snapshot: FutureSnapshot<NoLimitTracker> = from_bytes(slice)?;
+        let json = unsafe { read_required_str(limits_json, "limits_json")? };
+        let boxed = Box::new(Arc::new(QuotaGroup::new(serde_json::from_str(&json)?)));
         unsafe {
-            *out = FutureSnapshotHandle::new(snapshot);
+            *out = Box::into_raw(Box::new(QuotaGroupHandle {
+                inner: Box::into_raw(boxed) as *mut c_void,
+            }));
+            debug::handed_out("quota_group", *out, None);
         }
         Ok(())
     }
 
-    match inner(bytes, len, out) {
+    match inner(limits_json, out) {
         Ok(()) => MontyStatus::success(),
         Err(err) => MontyStatus::from_error(err),
     }
 }
 
+/// Releases the caller's reference; runs attached to the group keep theirs.
 #[no_mangle]
-pub unsafe extern "C" fn monty_snapshot_free(snapshot: *mut SnapshotHandle) {
-    if !snapshot.is_null() {
-        let handle = Box::from_raw(snapshot);
-        drop(Box::from_raw(handle.inner as *mut Snapshot<NoLimitTracker>));
+pub unsafe extern "C" fn monty_quota_group_free(group: *mut QuotaGroupHandle) {
+    if !group.is_null() {
+        debug::taken_back(group);
+        let handle = Box::from_raw(group);
+        drop(Box::from_raw(handle.inner as *mut Arc<QuotaGroup>));
     }
 }
 
+/// `monty_quota_group_free`, then sets `*group` to NULL.
 #[no_mangle]
-pub unsafe extern "C" fn monty_future_snapshot_free(snapshot: *mut FutureSnapshotHandle) {
-    if !snapshot.is_null() {
-        let handle = Box::from_raw(snapshot);
-        drop(Box::from_raw(
-            handle.inner as *mut FutureSnapshot<NoLimitTracker>,
-        ));
+pub unsafe extern "C" fn monty_quota_group_free_and_clear(group: *mut *mut QuotaGroupHandle) {
+    if let Some(group) = group.as_mut() {
+        monty_quota_group_free(std::mem::replace(group, ptr::null_mut()));
     }
 }
 
+/// Writes what the group's runs have used in the current window, and what is left, as
+/// JSON.
 #[no_mangle]
-pub unsafe extern "C" fn monty_free_bytes(ptr: *mut u8, len: usize) {
-    if !ptr.is_null() {
-        drop(Vec::from_raw_parts(ptr, len, len));
+pub unsafe extern "C" fn monty_quota_group_usage(
+    group: *const QuotaGroupHandle,
+    out_json: *mut *mut c_char,
+) -> MontyStatus {
+    fn inner(group: *const QuotaGroupHandle, out_json: *mut *mut c_char) -> FfiResult<()> {
+        let group = unsafe { group.as_ref().ok_or(FfiError::NullPointer("group"))? };
+        write_json(&group.as_ref().report(), out_json)
     }
-}
 
-fn write_bytes(bytes: Vec<u8>, out_bytes: *mut *mut u8, out_len: *mut usize) -> FfiResult<()> {
-    if out_bytes.is_null() {
-        return Err(FfiError::NullPointer("out_bytes"));
-    }
-    if out_len.is_null() {
-        return Err(FfiError::NullPointer("out_len"));
-    }
-    let mut boxed = bytes.into_boxed_slice();
-    let len = boxed.len();
-    let ptr = boxed.as_mut_ptr();
-    std::mem::forget(boxed);
-    unsafe {
-        *out_bytes = ptr;
-        *out_len = len;
+    match inner(group, out_json) {
+        Ok(()) => MontyStatus::success(),
+        Err(err) => MontyStatus::from_error(err),
     }
-    Ok(())
 }
 
-unsafe fn read_string_array(
-    ptr: *const *const c_char,
-    field: &'static str,
-) -> FfiResult<Vec<String>> {
-    if ptr.is_null() {
-        return Ok(Vec::new());
+/// Makes every later start of `run` draw from `group`, or from no group when it is
+/// NULL. Runs already started keep the group they started with.
+#[no_mangle]
+pub unsafe extern "C" fn monty_run_attach_quota_group(
+    run: *mut MontyRunHandle,
+    group: *const QuotaGroupHandle,
+) -> MontyStatus {
+    fn inner(run: *mut MontyRunHandle, group: *const QuotaGroupHandle) -> FfiResult<()> {
+        let run = unsafe { run.as_mut().ok_or(FfiError::NullPointer("run"))? };
+        let group = unsafe { group.as_ref() }.map(|group| Arc::clone(group.as_ref()));
+        run.compiled_mut().quota = group;
+        Ok(())
     }
-    let mut values = Vec::new();
-    let mut index = 0;
-    loop {
-        let current = unsafe { *ptr.add(index) };
-        if current.is_null() {
-            break;
-        }
-        values.push(unsafe { read_required_str(current, field)? });
-        index += 1;
+
+    match inner(run, group) {
+        Ok(()) => MontyStatus::success(),
+        Err(err) => MontyStatus::from_error(err),
     }
-    Ok(values)
 }
 
-fn decode_future_results(json: &str) -> FfiResult<Vec<(u32, ExternalResult)>> {
-    let raw: Vec<FutureResultJson> = serde_json::from_str(json)?;
-    raw.into_iter()
-        .map(|entry| {
-            if let Some(err) = entry.error.filter(|s| !s.is_empty()) {
+/// Binds `object` to the input referenced as `{"$input_object": "<name>"}` in every
+/// later start's inputs array, or unbinds the name when `object` is NULL. The run
+/// holds a reference to the handle's value rather than a copy, and the caller keeps
+/// its handle, so binding is cheap and skips JSON entirely; each start still copies
+/// the value into its own VM. Safe to call while other threads start the run.
+#[no_mangle]
+pub unsafe extern "C" fn monty_run_set_input_object(
+    run: *const MontyRunHandle,
+    name: *const c_char,
+    object: *const MontyObjectHandle,
+) -> MontyStatus {
+    fn inner(
+        run: *const MontyRunHandle,
+        name: *const c_char,
+        object: *const MontyObjectHandle,
+    ) -> FfiResult<()> {
+        let run = unsafe { run.as_ref().ok_or(FfiError::NullPointer("run"))? };
+        let name = unsafe { read_required_str(name, "name")? };
+        let object = unsafe { object.as_ref() }.map(|object| Arc::clone(object.shared()));
+        run.compiled().bind_input_object(name, object);
+        Ok(())
+    }
+
+    match inner(run, name, object) {
+        Ok(()) => MontyStatus::success(),
+        Err(err) => MontyStatus::from_error(err),
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn monty_run_start(
+    run: *mut MontyRunHandle,
+    inputs_json: *const c_char,
+    out: *mut ProgressResult,
+) -> MontyStatus {
+    match start_run(run, inputs_json, ptr::null(), HashMap::new(), out) {
+        Ok(()) => MontyStatus::success(),
+        Err(err) => MontyStatus::from_error(err),
+    }
+}
+
+/// Like `monty_run_start`, with per-run options such as resource limits given as a
+/// JSON object (`{"limits": {"max_instructions": 100000}}`). NULL or empty options
+/// behave like `monty_run_start`.
+#[no_mangle]
+pub unsafe extern "C" fn monty_run_start_with_options(
+    run: *mut MontyRunHandle,
+    inputs_json: *const c_char,
+    options_json: *const c_char,
+    out: *mut ProgressResult,
+) -> MontyStatus {
+    match start_run(run, inputs_json, options_json, HashMap::new(), out) {
+        Ok(()) => MontyStatus::success(),
+        Err(err) => MontyStatus::from_error(err),
+    }
+}
+
+/// Raw bytes for the input referenced as `{"$input_bytes": "<name>"}` in a start's
+/// inputs array, where they become a `bytes` value without going through JSON.
+#[repr(C)]
+pub struct MontyInputBytes {
+    pub name: *const c_char,
+    pub data: *const u8,
+    pub len: usize,
+}
+
+/// Like `monty_run_start_with_options`, binding the `count` byte inputs in
+/// `input_bytes` to the `$input_bytes` markers that name them. The data is copied and
+/// only used by this start, so concurrent starts of `run` never see each other's bytes.
+#[no_mangle]
+pub unsafe extern "C" fn monty_run_start_with_input_bytes(
+    run: *mut MontyRunHandle,
+    inputs_json: *const c_char,
+    options_json: *const c_char,
+    input_bytes: *const MontyInputBytes,
+    count: usize,
+    out: *mut ProgressResult,
+) -> MontyStatus {
+    fn inner(
+        run: *mut MontyRunHandle,
+        inputs_json: *const c_char,
+        options_json: *const c_char,
+        input_bytes: *const MontyInputBytes,
+        count: usize,
+        out: *mut ProgressResult,
+    ) -> FfiResult<()> {
+        let input_bytes = unsafe { read_input_bytes(input_bytes, count)? };
+        start_run(run, inputs_json, options_json, input_bytes, out)
+    }
+
+    match inner(run, inputs_json, options_json, input_bytes, count, out) {
+        Ok(()) => MontyStatus::success(),
+        Err(err) => MontyStatus::from_error(err),
+    }
+}
+
+fn start_run(
+    run: *mut MontyRunHandle,
+    inputs_json: *const c_char,
+    options_json: *const c_char,
+    input_bytes: HashMap<String, Vec<u8>>,
+    out: *mut ProgressResult,
+) -> FfiResult<()> {
+    if out.is_null() {
+        return Err(FfiError::NullPointer("out"));
+    }
+    let pending = prepare_start(run, inputs_json, options_json, input_bytes)?;
+    unsafe { advance_start(out, pending) }
+}
+
+/// Binds a start's inputs, globals, and options, up to where lazy inputs are requested.
+/// The run handle is only read, so it can be started from several threads at once.
+fn prepare_start(
+    run: *const MontyRunHandle,
+    inputs_json: *const c_char,
+    options_json: *const c_char,
+    input_bytes: HashMap<String, Vec<u8>>,
+) -> FfiResult<PendingStart> {
+    let run = unsafe { run.as_ref().ok_or(FfiError::NullPointer("run"))? };
+    let options_json = unsafe { read_optional_str(options_json)? };
+    let mut options = RunOptions::from_json(options_json.as_deref())?;
+    let inputs_json = unsafe {
+        if inputs_json.is_null() {
+            String::from("[]")
+        } else {
+            read_text(inputs_json, "inputs_json", options.lossy_utf8)?
+        }
+    };
+    let seeded = Globals::from_map(std::mem::take(&mut options.globals))?;
+    let compiled = run.compiled();
+    let (names, globals) = Globals::bindings(compiled.globals.as_deref(), seeded);
+    let runner = compiled.runner_binding(&names)?;
+    let mut bound = compiled.namespaces.clone();
+    bound.extend(globals);
+    let mut context = RunContext::new(options, compiled.signatures.clone());
+    context.join_quota(compiled.quota.clone());
+    context.disable_builtins(compiled.disabled_builtins.clone());
+    context.declare_dotted_funcs(compiled.dotted_funcs.clone());
+    context.override_builtins(compiled.overridden_builtins.clone());
+    PendingStart::new(
+        runner,
+        &inputs_json,
+        compiled.input_names(),
+        input_bytes,
+        &compiled.input_objects(),
+        bound,
+        context,
+    )
+}
+
+/// Answers an external or OS call for `monty_run_drive`. It receives the function name
+/// and the JSON-encoded args and kwargs, writes a JSON answer through `emit(sink, ...)`,
+/// and returns `MONTY_DRIVE_RETURN` when the answer is the call's value or
+/// `MONTY_DRIVE_RAISE` when it is a `$exception` object to raise in the script. Any
+/// other status aborts the run. The callback may call any `monty_*` function, e.g. to
+/// run a nested script, whose output is then its own rather than `print_callback`'s.
+pub type MontyCallCallback = Option<
+    unsafe extern "C" fn(
+        userdata: *mut c_void,
+        function_name: *const c_char,
+        args_json: *const c_char,
+        kwargs_json: *const c_char,
+        emit: MontyWriteCallback,
+        sink: *mut c_void,
+    ) -> i32,
+>;
+
+/// Runs a program to completion in one call, answering its external calls with
+/// `function_callback` and its OS calls with `os_callback`, and writes the encoded
+/// result to `out_result_json`. Options are as in `monty_run_start_with_options`, and
+/// may be NULL. Script output goes to `print_callback`, or stdout when it is NULL.
+/// Runs that need lazy inputs or futures fail.
+#[no_mangle]
+pub unsafe extern "C" fn monty_run_drive(
+    run: *mut MontyRunHandle,
+    inputs_json: *const c_char,
+    options_json: *const c_char,
+    function_callback: MontyCallCallback,
+    os_callback: MontyCallCallback,
+    print_callback: MontyWriteCallback,
+    userdata: *mut c_void,
+    out_result_json: *mut *mut c_char,
+) -> MontyStatus {
+    fn inner(
+        run: *mut MontyRunHandle,
+        inputs_json: *const c_char,
+        options_json: *const c_char,
+        callbacks: drive::Callbacks,
+        out_result_json: *mut *mut c_char,
+    ) -> FfiResult<()> {
+        if out_result_json.is_null() {
+            return Err(FfiError::NullPointer("out_result_json"));
+        }
+        let pending = prepare_start(run, inputs_json, options_json, HashMap::new())?;
+        let result = drive::drive(pending, &callbacks)?;
+        unsafe {
+            *out_result_json = to_c_string(result, "result_json")?;
+        }
+        Ok(())
+    }
+
+    let callbacks = drive::Callbacks {
+        function: function_callback,
+        os: os_callback,
+        print: print_callback,
+        userdata,
+    };
+    match inner(run, inputs_json, options_json, callbacks, out_result_json) {
+        Ok(()) => MontyStatus::success(),
+        Err(err) => MontyStatus::from_error(err),
+    }
+}
+
+/// Rebuilds a run after a crash from `journal`, the append-only journal of its progress
+/// events written by the Go `Recorder` (`NewJournalWriter`): the program is started
+/// with `inputs_json` and `options_json`, as for `monty_run_start_with_options`, and
+/// each call is answered with its journaled resolution, so only the journal needs to be
+/// persisted at each suspension. `out` receives the first progress the journal doesn't
+/// resolve, or the completed result. A call that differs from the journaled one fails
+/// the recovery. Runs that need lazy inputs fail.
+#[no_mangle]
+pub unsafe extern "C" fn monty_run_recover(
+    run: *mut MontyRunHandle,
+    inputs_json: *const c_char,
+    options_json: *const c_char,
+    journal: *const u8,
+    journal_len: usize,
+    out: *mut ProgressResult,
+) -> MontyStatus {
+    fn inner(
+        run: *mut MontyRunHandle,
+        inputs_json: *const c_char,
+        options_json: *const c_char,
+        journal: *const u8,
+        journal_len: usize,
+        out: *mut ProgressResult,
+    ) -> FfiResult<()> {
+        if out.is_null() {
+            return Err(FfiError::NullPointer("out"));
+        }
+        if journal_len > 0 && journal.is_null() {
+            return Err(FfiError::NullPointer("journal"));
+        }
+        let journal = unsafe { slice::from_raw_parts(journal, journal_len) };
+        let pending = prepare_start(run, inputs_json, options_json, HashMap::new())?;
+        let (step, context) = recover::recover(pending, journal)?;
+        unsafe { write_step(out, step, context) }
+    }
+
+    match inner(run, inputs_json, options_json, journal, journal_len, out) {
+        Ok(()) => MontyStatus::success(),
+        Err(err) => MontyStatus::from_error(err),
+    }
+}
+
+/// Compiles and runs `code` to completion in one call, for evaluating expressions that
+/// need nothing from the host, and writes the encoded result to `out_json`.
+/// `inputs_json` is an object of input values by name and `limits_json` a
+/// `ResourceLimits` object; either may be NULL. A script that makes any external or OS
+/// call fails instead of being suspended.
+#[no_mangle]
+pub unsafe extern "C" fn monty_eval(
+    code: *const c_char,
+    inputs_json: *const c_char,
+    limits_json: *const c_char,
+    out_json: *mut *mut c_char,
+) -> MontyStatus {
+    fn inner(
+        code: *const c_char,
+        inputs_json: *const c_char,
+        limits_json: *const c_char,
+        out_json: *mut *mut c_char,
+    ) -> FfiResult<()> {
+        if out_json.is_null() {
+            return Err(FfiError::NullPointer("out_json"));
+        }
+        let code = unsafe { read_text(code, "code", false)? };
+        let inputs: serde_json::Map<String, serde_json::Value> =
+            match unsafe { read_optional_text(inputs_json, "inputs_json", false)? } {
+                Some(json) if !json.trim().is_empty() => {
+                    check_numbers(&json)?;
+                    serde_json::from_str(&json)?
+                }
+                _ => serde_json::Map::new(),
+            };
+        let limits = match unsafe { read_optional_str(limits_json)? } {
+            Some(json) if !json.trim().is_empty() => serde_json::from_str(&json)?,
+            _ => Default::default(),
+        };
+        let (input_names, values): (Vec<_>, Vec<_>) = inputs.into_iter().unzip();
+        let inputs = values
+            .into_iter()
+            .map(decode_value)
+            .collect::<FfiResult<Vec<_>>>()?;
+        let compiled = Compiled::compile(Source {
+            code,
+            script_name: "<eval>".to_owned(),
+            input_names,
+            ext_funcs: Vec::new(),
+        })?;
+        let options = RunOptions {
+            limits,
+            ..RunOptions::default()
+        };
+        let mut context = RunContext::new(options, Vec::new());
+        let progress = context.execute(|print| compiled.runner.start(inputs, FfiTracker, print))?;
+        let value = completed_value("monty_eval", dispatch::step(progress, &mut context)?)?;
+        unsafe {
+            *out_json = to_c_string(encode_object(&value)?, "result_json")?;
+        }
+        Ok(())
+    }
+
+    match inner(code, inputs_json, limits_json, out_json) {
+        Ok(()) => MontyStatus::success(),
+        Err(err) => MontyStatus::from_error(err),
+    }
+}
+
+/// Starts a run and returns `{"result": value}` when it completes without suspending,
+/// or `{"error": message}` when it fails or would suspend, as a string to free with
+/// `monty_free_string`. There is no status struct or out-pointer, for binding
+/// generators that handle neither well. Options are as in `monty_run_start_with_options`.
+#[no_mangle]
+pub unsafe extern "C" fn monty_run_result_json(
+    run: *mut MontyRunHandle,
+    inputs_json: *const c_char,
+    options_json: *const c_char,
+) -> *mut c_char {
+    fn inner(
+        run: *mut MontyRunHandle,
+        inputs_json: *const c_char,
+        options_json: *const c_char,
+    ) -> FfiResult<serde_json::Value> {
+        let pending = prepare_start(run, inputs_json, options_json, HashMap::new())?;
+        if let Some(name) = pending.next_request() {
+            return Err(FfiError::Message(format!(
+                "monty_run_result_json can't provide lazy input {name:?}"
+            )));
+        }
+        let (runner, inputs, mut context) = pending.into_start();
+        let progress = context.execute(|print| runner.start(inputs, FfiTracker, print))?;
+        let step = dispatch::step(progress, &mut context)?;
+        let value = completed_value("monty_run_result_json", step)?;
+        encode_value(&value)
+    }
+
+    let mut envelope = match inner(run, inputs_json, options_json) {
+        Ok(result) => serde_json::json!({ "result": result }),
+        Err(err) => serde_json::json!({ "error": err.to_string() }),
+    };
+    if take_replaced() {
+        envelope["lossy"] = true.into();
+    }
+    // JSON escapes NUL, so this only fails if the allocation does.
+    to_c_string(envelope.to_string(), "result_json").unwrap_or(ptr::null_mut())
+}
+
+/// The result of a run that had to complete without the host's help; `caller` names
+/// the entry point in the error for any other step.
+fn completed_value(caller: &str, step: Step) -> FfiResult<MontyObject> {
+    let function = match step {
+        Step::Complete(value) => return Ok(value),
+        Step::Emit { .. } => EMIT_FUNCTION,
+        Step::Sleep { .. } => SLEEP_FUNCTION,
+        Step::Prompt { .. } => INPUT_FUNCTION,
+        Step::Call(Call {
+            os_function: Some(function),
+            ..
+        }) => {
+            return Err(FfiError::Message(format!(
+                "{caller} can't answer the OS call to {function}()"
+            )))
+        }
+        Step::Call(call) => {
+            return Err(FfiError::Message(format!(
+                "{caller} can't answer the call to {}()",
+                call.function_name
+            )))
+        }
+        Step::ResolveFutures(_) => {
+            return Err(FfiError::Message(format!("{caller} can't resolve futures")))
+        }
+    };
+    Err(FfiError::Message(format!(
+        "{caller} can't answer the call to {function}()"
+    )))
+}
+
+/// Provides the value of the lazy input named in an `INPUT_REQUEST` progress and
+/// continues: either the next lazy input is requested or the run starts. Consumes
+/// the request handle.
+#[no_mangle]
+pub unsafe extern "C" fn monty_input_request_resume(
+    request: *mut InputRequestHandle,
+    value_json: *const c_char,
+    out: *mut ProgressResult,
+) -> MontyStatus {
+    fn inner(
+        request: *mut InputRequestHandle,
+        value_json: *const c_char,
+        out: *mut ProgressResult,
+    ) -> FfiResult<()> {
+        if out.is_null() {
+            return Err(FfiError::NullPointer("out"));
+        }
+        if request.is_null() {
+            return Err(FfiError::NullPointer("request"));
+        }
+        let lossy = unsafe { &*request }.pending().lossy_utf8();
+        let json = unsafe { read_text(value_json, "value_json", lossy)? };
+        let mut pending = unsafe { Box::from_raw(request) }.into_inner();
+        pending.provide(decode_object(&json)?)?;
+        unsafe { advance_start(out, pending) }
+    }
+
+    match inner(request, value_json, out) {
+        Ok(()) => MontyStatus::success(),
+        Err(err) => MontyStatus::from_error(err),
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn monty_input_request_free(request: *mut InputRequestHandle) {
+    if !request.is_null() {
+        debug::taken_back(request);
+        let handle = Box::from_raw(request);
+        drop(Box::from_raw(handle.inner as *mut PendingStart));
+    }
+}
+
+/// `monty_input_request_free`, then sets `*request` to NULL.
+#[no_mangle]
+pub unsafe extern "C" fn monty_input_request_free_and_clear(request: *mut *mut InputRequestHandle) {
+    if let Some(request) = request.as_mut() {
+        monty_input_request_free(std::mem::replace(request, ptr::null_mut()));
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn monty_progress_result_free_strings(result: *mut ProgressResult) {
+    if let Some(result) = result.as_mut() {
+        monty_free_string(result.result_json);
+        monty_free_string(result.function_name);
+        monty_free_string(result.os_function);
+        monty_free_string(result.args_json);
+        monty_free_string(result.kwargs_json);
+        monty_free_string(result.pending_call_ids_json);
+        monty_free_string(result.pending_calls_json);
+        monty_free_string(result.stats_json);
+        monty_free_string(result.input_name);
+        monty_free_string(result.receiver_json);
+        monty_free_string(result.trace_json);
+        monty_free_string(result.audit_json);
+        monty_free_string(result.warnings_json);
+        monty_free_bytes(result.output, result.output_len);
+        monty_free_string(result.prompt);
+        monty_free_string(result.idempotency_key);
+        monty_free_string(result.cache_key);
+        result.result_json = ptr::null_mut();
+        result.function_name = ptr::null_mut();
+        result.os_function = ptr::null_mut();
+        result.args_json = ptr::null_mut();
+        result.kwargs_json = ptr::null_mut();
+        result.pending_call_ids_json = ptr::null_mut();
+        result.pending_calls_json = ptr::null_mut();
+        result.stats_json = ptr::null_mut();
+        result.input_name = ptr::null_mut();
+        result.receiver_json = ptr::null_mut();
+        result.trace_json = ptr::null_mut();
+        result.audit_json = ptr::null_mut();
+        result.warnings_json = ptr::null_mut();
+        result.output = ptr::null_mut();
+        result.output_len = 0;
+        result.prompt = ptr::null_mut();
+        result.idempotency_key = ptr::null_mut();
+        result.cache_key = ptr::null_mut();
+        result.result_len = 0;
+        result.args_len = 0;
+        result.kwargs_len = 0;
+    }
+}
+
+/// Frees everything a progress result still owns: its strings, and any snapshot,
+/// future snapshot, input request, or object handle the caller has not taken. Set a
+/// handle field to NULL to keep it. The struct itself is left zeroed.
+#[no_mangle]
+pub unsafe extern "C" fn monty_progress_result_free(result: *mut ProgressResult) {
+    monty_progress_result_free_strings(result);
+    if let Some(result) = result.as_mut() {
+        monty_snapshot_free(result.snapshot);
+        monty_future_snapshot_free(result.future_snapshot);
+        monty_input_request_free(result.input_request);
+        monty_object_free(result.result_object);
+        monty_object_free(result.args_object);
+        monty_object_free(result.kwargs_object);
+        *result = ProgressResult::default();
+    }
+}
+
+/// Moves the snapshot out of a progress result into `*out` and clears the field, so
+/// the caller owns it and `monty_progress_result_free` leaves it alone. `*out` is NULL
+/// when the result has no snapshot.
+#[no_mangle]
+pub unsafe extern "C" fn monty_progress_result_take_snapshot(
+    result: *mut ProgressResult,
+    out: *mut *mut SnapshotHandle,
+) -> MontyStatus {
+    fn inner(result: *mut ProgressResult, out: *mut *mut SnapshotHandle) -> FfiResult<()> {
+        let result = unsafe { result.as_mut() }.ok_or(FfiError::NullPointer("result"))?;
+        let out = unsafe { out.as_mut() }.ok_or(FfiError::NullPointer("out"))?;
+        *out = std::mem::replace(&mut result.snapshot, ptr::null_mut());
+        Ok(())
+    }
+
+    match inner(result, out) {
+        Ok(()) => MontyStatus::success(),
+        Err(err) => MontyStatus::from_error(err),
+    }
+}
+
+/// Moves the future snapshot out of a progress result, like
+/// `monty_progress_result_take_snapshot`.
+#[no_mangle]
+pub unsafe extern "C" fn monty_progress_result_take_future_snapshot(
+    result: *mut ProgressResult,
+    out: *mut *mut FutureSnapshotHandle,
+) -> MontyStatus {
+    fn inner(result: *mut ProgressResult, out: *mut *mut FutureSnapshotHandle) -> FfiResult<()> {
+        let result = unsafe { result.as_mut() }.ok_or(FfiError::NullPointer("result"))?;
+        let out = unsafe { out.as_mut() }.ok_or(FfiError::NullPointer("out"))?;
+        *out = std::mem::replace(&mut result.future_snapshot, ptr::null_mut());
+        Ok(())
+    }
+
+    match inner(result, out) {
+        Ok(()) => MontyStatus::success(),
+        Err(err) => MontyStatus::from_error(err),
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn monty_protocol_version() -> u32 {
+    PROTOCOL_VERSION
+}
+
+/// The C header this library was built with, so bindings can check the exact ABI of
+/// the library they loaded. The string is static and must not be freed.
+#[no_mangle]
+pub extern "C" fn monty_ffi_header() -> *const c_char {
+    concat!(include_str!("../../include/monty_ffi.h"), "\0")
+        .as_ptr()
+        .cast()
+}
+
+/// Writes process-wide counters (runs started and completed, snapshot handles held,
+/// instructions executed, bytes serialized) as JSON. Per-run counters are reported by
+/// `monty_snapshot_stats` and on completion.
+#[no_mangle]
+pub unsafe extern "C" fn monty_metrics_json(out_json: *mut *mut c_char) -> MontyStatus {
+    match write_json(&metrics::current(), out_json) {
+        Ok(()) => MontyStatus::success(),
+        Err(err) => MontyStatus::from_error(err),
+    }
+}
+
+/// Turns tracking of handles, strings, and byte buffers handed to the host on
+/// (nonzero) or off for the whole process, for finding leaks while debugging.
+/// Turning it off forgets everything recorded so far.
+#[no_mangle]
+pub extern "C" fn monty_debug_track_handles(enabled: i32) {
+    debug::set_enabled(enabled != 0);
+}
+
+/// Writes how many handles, strings, and byte buffers of each kind the host has been
+/// given and not freed since tracking was turned on, as a JSON object keyed by kind
+/// (`run`, `snapshot`, `string`, ...); `{}` means nothing has leaked.
+#[no_mangle]
+pub unsafe extern "C" fn monty_debug_report_leaks(out_json: *mut *mut c_char) -> MontyStatus {
+    match write_json(&debug::outstanding(), out_json) {
+        Ok(()) => MontyStatus::success(),
+        Err(err) => MontyStatus::from_error(err),
+    }
+}
+
+/// Writes everything the host holds since tracking was turned on, oldest first, as a
+/// JSON array of `{"kind", "age_ms", "size"}` objects. `size` is approximate and only
+/// known for snapshots (their VM heap), strings, and byte buffers.
+#[no_mangle]
+pub unsafe extern "C" fn monty_debug_list_handles(out_json: *mut *mut c_char) -> MontyStatus {
+    match write_json(&debug::held(), out_json) {
+        Ok(()) => MontyStatus::success(),
+        Err(err) => MontyStatus::from_error(err),
+    }
+}
+
+/// Writes every OS function Monty can report as `MONTY_PROGRESS_OS_CALL`, as a JSON
+/// array of `{"name", "category"}` objects; categories are `filesystem` and
+/// `environment`. Hosts can check their dispatcher covers each one at startup and
+/// treat any other name as unknown.
+#[no_mangle]
+pub unsafe extern "C" fn monty_list_os_functions(out_json: *mut *mut c_char) -> MontyStatus {
+    match write_json(&os_functions::list(), out_json) {
+        Ok(()) => MontyStatus::success(),
+        Err(err) => MontyStatus::from_error(err),
+    }
+}
+
+/// Writes the expected arguments of the OS function called `name` (e.g.
+/// `Path.read_text`) as a JSON object `{"name", "category", "args", "kwargs",
+/// "returns"}`, where each argument is `{"name", "type", "required"}` and types are
+/// Python annotations. The first argument of a `Path` method is the path it was called on. Fails for a
+/// name Monty has no OS function by.
+#[no_mangle]
+pub unsafe extern "C" fn monty_os_call_schema(
+    name: *const c_char,
+    out_json: *mut *mut c_char,
+) -> MontyStatus {
+    fn inner(name: *const c_char, out_json: *mut *mut c_char) -> FfiResult<()> {
+        let name = unsafe { read_required_str(name, "name")? };
+        let schema = os_functions::schema(&name)
+            .ok_or_else(|| FfiError::Message(format!("unknown OS function `{name}`")))?;
+        write_json(schema, out_json)
+    }
+
+    match inner(name, out_json) {
+        Ok(()) => MontyStatus::success(),
+        Err(err) => MontyStatus::from_error(err),
+    }
+}
+
+/// Encodes a filled `ProgressResult` as a `protocol::ProgressMessage` JSON document.
+/// Snapshot handles are not part of the message and stay owned by `result`.
+#[no_mangle]
+pub unsafe extern "C" fn monty_progress_result_to_json(
+    result: *const ProgressResult,
+    out_json: *mut *mut c_char,
+) -> MontyStatus {
+    fn inner(result: *const ProgressResult, out_json: *mut *mut c_char) -> FfiResult<()> {
+        let result = unsafe { result.as_ref().ok_or(FfiError::NullPointer("result"))? };
+        let message = unsafe { progress_message(result)? };
+        write_json(&message, out_json)
+    }
+
+    match inner(result, out_json) {
+        Ok(()) => MontyStatus::success(),
+        Err(err) => MontyStatus::from_error(err),
+    }
+}
+
+/// Resumes a function or OS call. `error_message` raises a `RuntimeError` at the call
+/// site instead of returning `result_json`; both NULL leave the call pending as a future.
+#[no_mangle]
+pub unsafe extern "C" fn monty_snapshot_resume(
+    snapshot: *mut SnapshotHandle,
+    call_id: u32,
+    result_json: *const c_char,
+    error_message: *const c_char,
+    out: *mut ProgressResult,
+) -> MontyStatus {
+    match resume_call(
+        snapshot,
+        call_id,
+        result_json,
+        error_message,
+        ptr::null(),
+        out,
+    ) {
+        Ok(()) => MontyStatus::success(),
+        Err(err) => MontyStatus::from_error(err),
+    }
+}
+
+/// Resumes a function or OS call by raising `error_type(message)` at the call site.
+/// `error_type` names a builtin exception class and defaults to `RuntimeError` when
+/// NULL or empty.
+#[no_mangle]
+pub unsafe extern "C" fn monty_snapshot_resume_error(
+    snapshot: *mut SnapshotHandle,
+    call_id: u32,
+    error_type: *const c_char,
+    message: *const c_char,
+    out: *mut ProgressResult,
+) -> MontyStatus {
+    if message.is_null() {
+        return MontyStatus::from_error(FfiError::NullPointer("message"));
+    }
+    match resume_call(snapshot, call_id, ptr::null(), message, error_type, out) {
+        Ok(()) => MontyStatus::success(),
+        Err(err) => MontyStatus::from_error(err),
+    }
+}
+
+/// Shared body of `monty_snapshot_resume` and `monty_snapshot_resume_error`.
+fn resume_call(
+    snapshot: *mut SnapshotHandle,
+    call_id: u32,
+    result_json: *const c_char,
+    error_message: *const c_char,
+    error_type: *const c_char,
+    out: *mut ProgressResult,
+) -> FfiResult<()> {
+    if out.is_null() {
+        return Err(FfiError::NullPointer("out"));
+    }
+    if snapshot.is_null() {
+        return Err(FfiError::NullPointer("snapshot"));
+    }
+    let lossy = unsafe { &*snapshot }.suspended().context.lossy_utf8;
+    let resolution = if let Some(err) = unsafe { read_optional_str(error_message)? } {
+        let error_type = unsafe { read_optional_str(error_type)? };
+        external_error(error_type.as_deref(), err)?
+    } else if let Some(json) = unsafe { read_optional_text(result_json, "result_json", lossy)? } {
+        ExternalResult::Return(decode_object(&json)?)
+    } else {
+        ExternalResult::Future
+    };
+    if let ExternalResult::Return(value) = &resolution {
+        let suspended = unsafe { &*snapshot }.suspended();
+        suspended.context.precheck_return(call_id, value)?;
+    }
+    let (snapshot, mut context) = unsafe { Box::from_raw(snapshot) }.into_inner()?;
+    let resolution = context.check_result(call_id, resolution)?;
+    let progress = context.execute(|print| snapshot.run(resolution, print))?;
+    unsafe { write_progress_result(out, progress, context) }
+}
+
+/// Resumes a function or OS call by raising a structured exception.
+/// `exception_json` is a `{"$exception": {"type", "message", "args", "attributes"}}`
+/// object; an optional `cause` envelope is printed before the error when the run fails
+/// with it. The script sees the type and message; `args` and `attributes` are put back
+/// when the exception is reported to the host again.
+#[no_mangle]
+pub unsafe extern "C" fn monty_snapshot_resume_exception(
+    snapshot: *mut SnapshotHandle,
+    call_id: u32,
+    exception_json: *const c_char,
+    out: *mut ProgressResult,
+) -> MontyStatus {
+    fn inner(
+        snapshot: *mut SnapshotHandle,
+        call_id: u32,
+        exception_json: *const c_char,
+        out: *mut ProgressResult,
+    ) -> FfiResult<()> {
+        if out.is_null() {
+            return Err(FfiError::NullPointer("out"));
+        }
+        if snapshot.is_null() {
+            return Err(FfiError::NullPointer("snapshot"));
+        }
+        let lossy = unsafe { &*snapshot }.suspended().context.lossy_utf8;
+        let json = unsafe { read_text(exception_json, "exception_json", lossy) }?;
+        let host = decode_exception(serde_json::from_str(&json)?)?;
+        let (snapshot, mut context) = unsafe { Box::from_raw(snapshot) }.into_inner()?;
+        let causes = HostCauses::from(&host);
+        let resolution = context.check_result(call_id, ExternalResult::Error(host.exception))?;
+        let progress = context.execute_caused(|print| snapshot.run(resolution, print), &causes)?;
+        unsafe { write_progress_result(out, progress, context) }
+    }
+
+    match inner(snapshot, call_id, exception_json, out) {
+        Ok(()) => MontyStatus::success(),
+        Err(err) => MontyStatus::from_error(err),
+    }
+}
+
+/// Resumes a function or OS call with a value passed by handle, consuming `object`.
+#[no_mangle]
+pub unsafe extern "C" fn monty_snapshot_resume_object(
+    snapshot: *mut SnapshotHandle,
+    call_id: u32,
+    object: *mut MontyObjectHandle,
+    out: *mut ProgressResult,
+) -> MontyStatus {
+    fn inner(
+        snapshot: *mut SnapshotHandle,
+        call_id: u32,
+        object: *mut MontyObjectHandle,
+        out: *mut ProgressResult,
+    ) -> FfiResult<()> {
+        if out.is_null() {
+            return Err(FfiError::NullPointer("out"));
+        }
+        if snapshot.is_null() {
+            return Err(FfiError::NullPointer("snapshot"));
+        }
+        if object.is_null() {
+            return Err(FfiError::NullPointer("object"));
+        }
+        let value = unsafe { &*object }.as_ref();
+        let suspended = unsafe { &*snapshot }.suspended();
+        suspended.context.precheck_return(call_id, value)?;
+        let (snapshot, mut context) = unsafe { Box::from_raw(snapshot) }.into_inner()?;
+        let value = unsafe { Box::from_raw(object) }.into_inner();
+        let resolution = context.check_result(call_id, ExternalResult::Return(value))?;
+        let progress = context.execute(|print| snapshot.run(resolution, print))?;
+        unsafe { write_progress_result(out, progress, context) }
+    }
+
+    match inner(snapshot, call_id, object, out) {
+        Ok(()) => MontyStatus::success(),
+        Err(err) => MontyStatus::from_error(err),
+    }
+}
+
+/// Resumes a run waiting on futures with `results_json`, an array of resolutions.
+/// Under the `gather` future resolution, results are held back until every pending
+/// call has one; until then the progress is `MONTY_PROGRESS_RESOLVE_FUTURES` again,
+/// listing the calls still without a result.
+#[no_mangle]
+pub unsafe extern "C" fn monty_future_snapshot_resume(
+    snapshot: *mut FutureSnapshotHandle,
+    results_json: *const c_char,
+    out: *mut ProgressResult,
+) -> MontyStatus {
+    fn inner(
+        snapshot: *mut FutureSnapshotHandle,
+        results_json: *const c_char,
+        out: *mut ProgressResult,
+    ) -> FfiResult<()> {
+        if out.is_null() {
+            return Err(FfiError::NullPointer("out"));
+        }
+        if snapshot.is_null() {
+            return Err(FfiError::NullPointer("snapshot"));
+        }
+        let handle = unsafe { &mut *snapshot };
+        let lossy = handle.suspended().context.lossy_utf8;
+        let mut json = unsafe { read_text(results_json, "results_json", lossy) }?;
+        // Reject a bad batch before anything changes, including before it is gathered.
+        let (results, _) = decode_future_results(&json)?;
+        handle.suspended().context.precheck_results(&results)?;
+        if handle.suspended().context.future_resolution == ResolutionMode::Gather {
+            let pending = handle.pending_ids().to_vec();
+            match handle.suspended_mut().context.gather(&json, &pending)? {
+                Some(gathered) => json = gathered,
+                None => {
+                    let (snapshot, context) = unsafe { Box::from_raw(snapshot) }.into_inner();
+                    return unsafe { write_gathering(out, snapshot, context) };
+                }
+            }
+        }
+        let (results, causes) = decode_future_results(&json)?;
+        let (snapshot, mut context) = unsafe { Box::from_raw(snapshot) }.into_inner();
+        let results = context.check_results(results)?;
+        let progress = context.execute_caused(|print| snapshot.resume(results, print), &causes)?;
+        unsafe { write_progress_result(out, progress, context) }
+    }
+
+    match inner(snapshot, results_json, out) {
+        Ok(()) => MontyStatus::success(),
+        Err(err) => MontyStatus::from_error(err),
+    }
+}
+
+/// Tears down a paused run by raising `exc_type(message)` at its suspension point, so
+/// `finally:` blocks and context managers run. Consumes the snapshot like a resume.
+#[no_mangle]
+pub unsafe extern "C" fn monty_snapshot_interrupt(
+    snapshot: *mut SnapshotHandle,
+    exc_type: *const c_char,
+    message: *const c_char,
+    out: *mut ProgressResult,
+) -> MontyStatus {
+    fn inner(
+        snapshot: *mut SnapshotHandle,
+        exc_type: *const c_char,
+        message: *const c_char,
+        out: *mut ProgressResult,
+    ) -> FfiResult<()> {
+        if out.is_null() {
+            return Err(FfiError::NullPointer("out"));
+        }
+        if snapshot.is_null() {
+            return Err(FfiError::NullPointer("snapshot"));
+        }
+        let exc_type = parse_exc_type(&unsafe { read_required_str(exc_type, "exc_type") }?)?;
+        let message = unsafe { read_optional_str(message)? };
+        let (snapshot, mut context) = unsafe { Box::from_raw(snapshot) }.into_inner()?;
+        let resolution = ExternalResult::Error(MontyException::new(exc_type, message));
+        let progress = context.execute(|print| snapshot.run(resolution, print))?;
+        unsafe { write_progress_result(out, progress, context) }
+    }
+
+    match inner(snapshot, exc_type, message, out) {
+        Ok(()) => MontyStatus::success(),
+        Err(err) => MontyStatus::from_error(err),
+    }
+}
+
+/// Future-snapshot counterpart of `monty_snapshot_interrupt`: every pending call is
+/// resolved by raising `exc_type(message)`.
+#[no_mangle]
+pub unsafe extern "C" fn monty_future_snapshot_interrupt(
+    snapshot: *mut FutureSnapshotHandle,
+    exc_type: *const c_char,
+    message: *const c_char,
+    out: *mut ProgressResult,
+) -> MontyStatus {
+    fn inner(
+        snapshot: *mut FutureSnapshotHandle,
+        exc_type: *const c_char,
+        message: *const c_char,
+        out: *mut ProgressResult,
+    ) -> FfiResult<()> {
+        if out.is_null() {
+            return Err(FfiError::NullPointer("out"));
+        }
+        if snapshot.is_null() {
+            return Err(FfiError::NullPointer("snapshot"));
+        }
+        let exc_type = parse_exc_type(&unsafe { read_required_str(exc_type, "exc_type") }?)?;
+        let message = unsafe { read_optional_str(message)? };
+        let snapshot = unsafe { Box::from_raw(snapshot) };
+        let results = snapshot
+            .pending_ids()
+            .iter()
+            .map(|&call_id| {
+                let exc = MontyException::new(exc_type, message.clone());
+                (call_id, ExternalResult::Error(exc))
+            })
+            .collect();
+        let (snapshot, mut context) = snapshot.into_inner();
+        let progress = context.execute(|print| snapshot.resume(results, print))?;
+        unsafe { write_progress_result(out, progress, context) }
+    }
+
+    match inner(snapshot, exc_type, message, out) {
+        Ok(()) => MontyStatus::success(),
+        Err(err) => MontyStatus::from_error(err),
+    }
+}
+
+/// Resumes a function call and keeps going through consecutive function calls in one hop.
+///
+/// `results_json` uses the same shape as `monty_future_snapshot_resume`. The suspended call
+/// (`call_id`) and every following function call with a matching entry are resolved
+/// in-process. A call without an entry to one of the run's `awaitable` functions is
+/// deferred as a future and described in `pending_calls_json`, so such calls are reported
+/// together on the next progress result; any other call without an entry ends the batch
+/// and is reported as usual, since the script uses its value directly. The suspended
+/// call itself is deferred when it has no entry, as with `monty_snapshot_resume`. A later
+/// result the `fail` return mismatch would reject also ends the batch at its call. When
+/// `include_os_calls` is non-zero, OS calls are batched the same way.
+#[no_mangle]
+pub unsafe extern "C" fn monty_snapshot_resume_batch(
+    snapshot: *mut SnapshotHandle,
+    call_id: u32,
+    results_json: *const c_char,
+    include_os_calls: i32,
+    out: *mut ProgressResult,
+) -> MontyStatus {
+    fn inner(
+        snapshot: *mut SnapshotHandle,
+        call_id: u32,
+        results_json: *const c_char,
+        include_os_calls: bool,
+        out: *mut ProgressResult,
+    ) -> FfiResult<()> {
+        if out.is_null() {
+            return Err(FfiError::NullPointer("out"));
+        }
+        if snapshot.is_null() {
+            return Err(FfiError::NullPointer("snapshot"));
+        }
+        let lossy = unsafe { &*snapshot }.suspended().context.lossy_utf8;
+        let json = unsafe { read_text(results_json, "results_json", lossy) }?;
+        let (results, causes) = decode_future_results(&json)?;
+        let mut results: HashMap<u32, ExternalResult> = results.into_iter().collect();
+        let mut deferred = Vec::new();
+        let first = results.remove(&call_id).unwrap_or(ExternalResult::Future);
+        if let ExternalResult::Return(value) = &first {
+            let suspended = unsafe { &*snapshot }.suspended();
+            suspended.context.precheck_return(call_id, value)?;
+        }
+        let (snapshot, mut context) = unsafe { Box::from_raw(snapshot) }.into_inner()?;
+        let first = context.check_result(call_id, first)?;
+        let mut progress = context.execute_caused(|print| snapshot.run(first, print), &causes)?;
+        let step = loop {
+            // Rejected calls and cache hits are answered without using up a result.
+            let call = match dispatch::step(progress, &mut context)? {
+                Step::Call(call) if include_os_calls || call.os_function.is_none() => call,
+                step => break step,
+            };
+            let resolution = match results.remove(&call.call_id) {
+                // A result `check_result` would fail on is left for the host to resume
+                // the call with itself, so the failure leaves it the snapshot.
+                Some(ExternalResult::Return(value))
+                    if context.precheck_return_of(call.name(), &value).is_err() =>
+                {
+                    break Step::Call(call)
+                }
+                Some(resolution) => resolution,
+                None if context.is_awaitable(call.name()) => ExternalResult::Future,
+                None => break Step::Call(call),
+            };
+            if matches!(resolution, ExternalResult::Future) {
+                deferred.push(call_descriptor(
+                    call.call_id,
+                    call.function_name.clone(),
+                    call.os_function.clone(),
+                    &call.args,
+                    &call.kwargs,
+                    call.method_call,
+                    &context,
+                )?);
+            }
+            progress = call.run(resolution, &mut context, &causes)?;
+        };
+        unsafe { write_step(out, step, context)? };
+        if !deferred.is_empty() {
+            let result = unsafe { &mut *out };
+            result.pending_calls_json =
+                to_c_string(serde_json::to_string(&deferred)?, "pending_calls_json")?;
+        }
+        Ok(())
+    }
+
+    match inner(snapshot, call_id, results_json, include_os_calls != 0, out) {
+        Ok(()) => MontyStatus::success(),
+        Err(err) => MontyStatus::from_error(err),
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn monty_snapshot_dump(
+    snapshot: *mut SnapshotHandle,
+    out_bytes: *mut *mut u8,
+    out_len: *mut usize,
+) -> MontyStatus {
+    fn inner(
+        snapshot: *mut SnapshotHandle,
+        out_bytes: *mut *mut u8,
+        out_len: *mut usize,
+    ) -> FfiResult<()> {
+        let snapshot = unsafe { snapshot.as_ref().ok_or(FfiError::NullPointer("snapshot"))? };
+        let bytes = intern::to_bytes(snapshot.suspended())?;
+        write_bytes(bytes, out_bytes, out_len)
+    }
+
+    match inner(snapshot, out_bytes, out_len) {
+        Ok(()) => MontyStatus::success(),
+        Err(err) => MontyStatus::from_error(err),
+    }
+}
+
+/// Reports the size `monty_snapshot_dump` would produce, without building the dump.
+#[no_mangle]
+pub unsafe extern "C" fn monty_snapshot_estimated_size(
+    snapshot: *mut SnapshotHandle,
+    out_size: *mut usize,
+) -> MontyStatus {
+    fn inner(snapshot: *mut SnapshotHandle, out_size: *mut usize) -> FfiResult<()> {
+        let snapshot = unsafe { snapshot.as_ref().ok_or(FfiError::NullPointer("snapshot"))? };
+        if out_size.is_null() {
+            return Err(FfiError::NullPointer("out_size"));
+        }
+        let size = intern::serialized_size(snapshot.suspended())?;
+        unsafe {
+            *out_size = size;
+        }
+        Ok(())
+    }
+
+    match inner(snapshot, out_size) {
+        Ok(()) => MontyStatus::success(),
+        Err(err) => MontyStatus::from_error(err),
+    }
+}
+
+/// Writes a SHA-256 hex digest of the suspended state: the VM state and run options,
+/// but not the run id or what the run has recorded so far (stats, trace, audit log,
+/// warnings). States whose dumps are identical apart from those hash equal. Only the
+/// FFI-side maps are sorted first; the VM state is hashed as Monty serializes it, so
+/// equivalent states reached by different paths may hash differently. For
+/// deduplicating stored states and spotting resumes that led back to a state already
+/// seen.
+#[no_mangle]
+pub unsafe extern "C" fn monty_snapshot_hash(
+    snapshot: *mut SnapshotHandle,
+    out_hash: *mut *mut c_char,
+) -> MontyStatus {
+    fn inner(snapshot: *mut SnapshotHandle, out_hash: *mut *mut c_char) -> FfiResult<()> {
+        let snapshot = unsafe { snapshot.as_ref().ok_or(FfiError::NullPointer("snapshot"))? };
+        if out_hash.is_null() {
+            return Err(FfiError::NullPointer("out_hash"));
+        }
+        let hash = state_hash(snapshot.suspended())?;
+        unsafe {
+            *out_hash = to_c_string(hash, "out_hash")?;
+        }
+        Ok(())
+    }
+
+    match inner(snapshot, out_hash) {
+        Ok(()) => MontyStatus::success(),
+        Err(err) => MontyStatus::from_error(err),
+    }
+}
+
+/// Loads a snapshot from a dump. The buffer is only read during the call and is not
+/// kept after it returns, so the caller may free or reuse it straight away.
+#[no_mangle]
+pub unsafe extern "C" fn monty_snapshot_load(
+    bytes: *const u8,
+    len: usize,
+    out: *mut *mut SnapshotHandle,
+) -> MontyStatus {
+    fn inner(bytes: *const u8, len: usize, out: *mut *mut SnapshotHandle) -> FfiResult<()> {
+        if out.is_null() {
+            return Err(FfiError::NullPointer("out"));
+        }
+        if len > 0 && bytes.is_null() {
+            return Err(FfiError::NullPointer("bytes"));
+        }
+        let slice = unsafe { slice::from_raw_parts(bytes, len) };
+        let Suspended { state, context }: Suspended<Snapshot<FfiTracker>> = load_suspended(slice)?;
+        unsafe {
+            *out = SnapshotHandle::new(state, context);
+        }
+        Ok(())
+    }
+
+    match inner(bytes, len, out) {
+        Ok(()) => MontyStatus::success(),
+        Err(err) => MontyStatus::from_error(err),
+    }
+}
+
+/// Format version a snapshot or future snapshot dump was written with; 0 for the bare
+/// VM state dumped by releases before dumps were versioned. Those still load, with a
+/// default run context, and the migrate functions rewrite them in the current version.
+/// Any other version than 0 or the current one is rejected.
+#[no_mangle]
+pub unsafe extern "C" fn monty_dump_format_version(bytes: *const u8, len: usize) -> u32 {
+    if bytes.is_null() {
+        return 0;
+    }
+    intern::format_version(slice::from_raw_parts(bytes, len)) as u32
+}
+
+/// Rewrites a snapshot dump from an earlier format version in the current one; see
+/// `monty_dump_format_version`.
+#[no_mangle]
+pub unsafe extern "C" fn monty_snapshot_migrate(
+    bytes: *const u8,
+    len: usize,
+    out_bytes: *mut *mut u8,
+    out_len: *mut usize,
+) -> MontyStatus {
+    fn inner(
+        bytes: *const u8,
+        len: usize,
+        out_bytes: *mut *mut u8,
+        out_len: *mut usize,
+    ) -> FfiResult<()> {
+        let dump = unsafe { read_bytes(bytes, len, "bytes")? };
+        write_bytes(migrate::<Snapshot<FfiTracker>>(&dump)?, out_bytes, out_len)
+    }
+
+    match inner(bytes, len, out_bytes, out_len) {
+        Ok(()) => MontyStatus::success(),
+        Err(err) => MontyStatus::from_error(err),
+    }
+}
+
+/// Receives the next chunk of a streamed dump. Returning non-zero aborts the dump.
+pub type MontyWriteCallback =
+    Option<unsafe extern "C" fn(userdata: *mut c_void, data: *const u8, len: usize) -> i32>;
+
+/// Streams the same bytes as `monty_snapshot_dump` to `write` in chunks, without
+/// building the whole dump in memory first. While `write` runs, resuming or changing
+/// `snapshot` fails, and freeing it takes effect once the dump ends.
+#[no_mangle]
+pub unsafe extern "C" fn monty_snapshot_dump_stream(
+    snapshot: *mut SnapshotHandle,
+    write: MontyWriteCallback,
+    userdata: *mut c_void,
+) -> MontyStatus {
+    fn inner(
+        snapshot: *mut SnapshotHandle,
+        write: MontyWriteCallback,
+        userdata: *mut c_void,
+    ) -> FfiResult<()> {
+        let handle = unsafe { snapshot.as_ref().ok_or(FfiError::NullPointer("snapshot"))? };
+        let write = write.ok_or(FfiError::NullPointer("write"))?;
+        let (result, freed) = reentry::lend(snapshot, || {
+            intern::to_writer(handle.suspended(), |chunk| {
+                let status =
+                    reentry::call_host(|| unsafe { write(userdata, chunk.as_ptr(), chunk.len()) });
+                match status {
+                    0 => Ok(()),
+                    code => Err(FfiError::Message(format!(
+                        "snapshot write callback failed with status {code}"
+                    ))),
+                }
+            })
+        });
+        if freed {
+            unsafe { monty_snapshot_free(snapshot) };
+        }
+        result
+    }
+
+    match inner(snapshot, write, userdata) {
+        Ok(()) => MontyStatus::success(),
+        Err(err) => MontyStatus::from_error(err),
+    }
+}
+
+/// Transforms `data` (seals or opens a dump), passing the result to `emit(sink, ...)` in
+/// one or more pieces. Returning non-zero fails the call.
+pub type MontyTransformCallback = Option<
+    unsafe extern "C" fn(
+        userdata: *mut c_void,
+        data: *const u8,
+        len: usize,
+        emit: MontyWriteCallback,
+        sink: *mut c_void,
+    ) -> i32,
+>;
+
+/// Dumps `snapshot` and passes the bytes through the host's `seal` callback, e.g. to
+/// encrypt and authenticate them.
+#[no_mangle]
+pub unsafe extern "C" fn monty_snapshot_dump_sealed(
+    snapshot: *mut SnapshotHandle,
+    seal: MontyTransformCallback,
+    userdata: *mut c_void,
+    out_bytes: *mut *mut u8,
+    out_len: *mut usize,
+) -> MontyStatus {
+    fn inner(
+        snapshot: *mut SnapshotHandle,
+        seal: MontyTransformCallback,
+        userdata: *mut c_void,
+        out_bytes: *mut *mut u8,
+        out_len: *mut usize,
+    ) -> FfiResult<()> {
+        let snapshot = unsafe { snapshot.as_ref().ok_or(FfiError::NullPointer("snapshot"))? };
+        let bytes = intern::to_bytes(snapshot.suspended())?;
+        write_bytes(seal::seal(seal, userdata, &bytes)?, out_bytes, out_len)
+    }
+
+    match inner(snapshot, seal, userdata, out_bytes, out_len) {
+        Ok(()) => MontyStatus::success(),
+        Err(err) => MontyStatus::from_error(err),
+    }
+}
+
+/// Loads a dump made by `monty_snapshot_dump_sealed`. A failing `open` callback is
+/// reported with code `MONTY_ERROR_TAMPERED`.
+#[no_mangle]
+pub unsafe extern "C" fn monty_snapshot_load_sealed(
+    bytes: *const u8,
+    len: usize,
+    open: MontyTransformCallback,
+    userdata: *mut c_void,
+    out: *mut *mut SnapshotHandle,
+) -> MontyStatus {
+    fn inner(
+        bytes: *const u8,
+        len: usize,
+        open: MontyTransformCallback,
+        userdata: *mut c_void,
+        out: *mut *mut SnapshotHandle,
+    ) -> FfiResult<()> {
+        if out.is_null() {
+            return Err(FfiError::NullPointer("out"));
+        }
+        let sealed = unsafe { read_bytes(bytes, len, "bytes")? };
+        let dump = seal::open(open, userdata, &sealed)?;
+        let Suspended { state, context }: Suspended<Snapshot<FfiTracker>> = load_suspended(&dump)?;
+        unsafe {
+            *out = SnapshotHandle::new(state, context);
+        }
+        Ok(())
+    }
+
+    match inner(bytes, len, open, userdata, out) {
+        Ok(()) => MontyStatus::success(),
+        Err(err) => MontyStatus::from_error(err),
+    }
+}
+
+/// Dumps `snapshot` as a delta against `base_bytes`, an earlier dump of the same run
+/// (typically the one already persisted). Load it with `monty_snapshot_load_delta` and
+/// the same base bytes.
+#[no_mangle]
+pub unsafe extern "C" fn monty_snapshot_dump_delta(
+    base_bytes: *const u8,
+    base_len: usize,
+    snapshot: *mut SnapshotHandle,
+    out_bytes: *mut *mut u8,
+    out_len: *mut usize,
+) -> MontyStatus {
+    fn inner(
+        base_bytes: *const u8,
+        base_len: usize,
+        snapshot: *mut SnapshotHandle,
+        out_bytes: *mut *mut u8,
+        out_len: *mut usize,
+    ) -> FfiResult<()> {
+        let base = unsafe { read_bytes(base_bytes, base_len, "base_bytes")? };
+        let snapshot = unsafe { snapshot.as_ref().ok_or(FfiError::NullPointer("snapshot"))? };
+        let bytes = intern::to_bytes(snapshot.suspended())?;
+        write_bytes(delta::diff(&base, &bytes)?, out_bytes, out_len)
+    }
+
+    match inner(base_bytes, base_len, snapshot, out_bytes, out_len) {
+        Ok(()) => MontyStatus::success(),
+        Err(err) => MontyStatus::from_error(err),
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn monty_snapshot_load_delta(
+    base_bytes: *const u8,
+    base_len: usize,
+    delta_bytes: *const u8,
+    delta_len: usize,
+    out: *mut *mut SnapshotHandle,
+) -> MontyStatus {
+    fn inner(
+        base_bytes: *const u8,
+        base_len: usize,
+        delta_bytes: *const u8,
+        delta_len: usize,
+        out: *mut *mut SnapshotHandle,
+    ) -> FfiResult<()> {
+        if out.is_null() {
+            return Err(FfiError::NullPointer("out"));
+        }
+        let base = unsafe { read_bytes(base_bytes, base_len, "base_bytes")? };
+        let delta = unsafe { read_bytes(delta_bytes, delta_len, "delta_bytes")? };
+        let bytes = delta::apply(&base, &delta)?;
+        let Suspended { state, context }: Suspended<Snapshot<FfiTracker>> = load_suspended(&bytes)?;
+        unsafe {
+            *out = SnapshotHandle::new(state, context);
+        }
+        Ok(())
+    }
+
+    match inner(base_bytes, base_len, delta_bytes, delta_len, out) {
+        Ok(()) => MontyStatus::success(),
+        Err(err) => MontyStatus::from_error(err),
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn monty_future_snapshot_dump(
+    snapshot: *mut FutureSnapshotHandle,
+    out_bytes: *mut *mut u8,
+    out_len: *mut usize,
+) -> MontyStatus {
+    fn inner(
+        snapshot: *mut FutureSnapshotHandle,
+        out_bytes: *mut *mut u8,
+        out_len: *mut usize,
+    ) -> FfiResult<()> {
+        let snapshot = unsafe { snapshot.as_ref().ok_or(FfiError::NullPointer("snapshot"))? };
+        let bytes = intern::to_bytes(snapshot.suspended())?;
+        write_bytes(bytes, out_bytes, out_len)
+    }
+
+    match inner(snapshot, out_bytes, out_len) {
+        Ok(()) => MontyStatus::success(),
+        Err(err) => MontyStatus::from_error(err),
+    }
+}
+
+/// `monty_snapshot_hash` for a future snapshot.
+#[no_mangle]
+pub unsafe extern "C" fn monty_future_snapshot_hash(
+    snapshot: *mut FutureSnapshotHandle,
+    out_hash: *mut *mut c_char,
+) -> MontyStatus {
+    fn inner(snapshot: *mut FutureSnapshotHandle, out_hash: *mut *mut c_char) -> FfiResult<()> {
+        let snapshot = unsafe { snapshot.as_ref().ok_or(FfiError::NullPointer("snapshot"))? };
+        if out_hash.is_null() {
+            return Err(FfiError::NullPointer("out_hash"));
+        }
+        let hash = state_hash(snapshot.suspended())?;
+        unsafe {
+            *out_hash = to_c_string(hash, "out_hash")?;
+        }
+        Ok(())
+    }
+
+    match inner(snapshot, out_hash) {
+        Ok(()) => MontyStatus::success(),
+        Err(err) => MontyStatus::from_error(err),
+    }
+}
+
+/// A content-addressed store of snapshot dumps, created with `monty_store_new`.
+#[repr(C)]
+pub struct StoreHandle {
+    inner: *mut c_void,
+}
+
+impl StoreHandle {
+    fn new(store: Box<dyn SnapshotStore>) -> *mut Self {
+        let handle = Box::into_raw(Box::new(Self {
+            inner: Box::into_raw(Box::new(store)) as *mut c_void,
+        }));
+        debug::handed_out("store", handle, None);
+        handle
+    }
+
+    fn store(&self) -> &dyn SnapshotStore {
+        unsafe { &**(self.inner as *const Box<dyn SnapshotStore>) }
+    }
+}
+
+/// Creates an empty in-memory snapshot store. Snapshots are put and got by the
+/// SHA-256 hex digest of their dump, so identical dumps are kept once.
+#[no_mangle]
+pub unsafe extern "C" fn monty_store_new(out: *mut *mut StoreHandle) -> MontyStatus {
+    fn inner(out: *mut *mut StoreHandle) -> FfiResult<()> {
+        if out.is_null() {
+            return Err(FfiError::NullPointer("out"));
+        }
+        unsafe {
+            *out = StoreHandle::new(Box::<MemoryStore>::default());
+        }
+        Ok(())
+    }
+
+    match inner(out) {
+        Ok(()) => MontyStatus::success(),
+        Err(err) => MontyStatus::from_error(err),
+    }
+}
+
+/// Opens a snapshot store kept as files under the directory `path`, creating it if
+/// needed, so stored snapshots survive restarts. Dumps are written atomically, and
+/// several stores (in one process or many) may share a directory.
+#[no_mangle]
+pub unsafe extern "C" fn monty_store_open_dir(
+    path: *const c_char,
+    out: *mut *mut StoreHandle,
+) -> MontyStatus {
+    fn inner(path: *const c_char, out: *mut *mut StoreHandle) -> FfiResult<()> {
+        if out.is_null() {
+            return Err(FfiError::NullPointer("out"));
+        }
+        let path = unsafe { read_required_str(path, "path")? };
+        let store = DirStore::open(path)?;
+        unsafe {
+            *out = StoreHandle::new(Box::new(store));
+        }
+        Ok(())
+    }
+
+    match inner(path, out) {
+        Ok(()) => MontyStatus::success(),
+        Err(err) => MontyStatus::from_error(err),
+    }
+}
+
+/// Closes the store; for a directory store, the files stay.
+#[no_mangle]
+pub unsafe extern "C" fn monty_store_free(store: *mut StoreHandle) {
+    if !store.is_null() {
+        debug::taken_back(store);
+        let handle = Box::from_raw(store);
+        drop(Box::from_raw(handle.inner as *mut Box<dyn SnapshotStore>));
+    }
+}
+
+/// `monty_store_free`, then sets `*store` to NULL.
+#[no_mangle]
+pub unsafe extern "C" fn monty_store_free_and_clear(store: *mut *mut StoreHandle) {
+    if let Some(store) = store.as_mut() {
+        monty_store_free(std::mem::replace(store, ptr::null_mut()));
+    }
+}
+
+/// Dumps `snapshot` into `store` without consuming it and writes the dump's hash, the
+/// key to get it back with; the hash is also listed under the snapshot's run id.
+/// Putting a snapshot whose dump is already stored keeps one copy and writes the same
+/// hash.
+#[no_mangle]
+pub unsafe extern "C" fn monty_store_put(
+    store: *const StoreHandle,
+    snapshot: *mut SnapshotHandle,
+    out_hash: *mut *mut c_char,
+) -> MontyStatus {
+    fn inner(
+        store: *const StoreHandle,
+        snapshot: *mut SnapshotHandle,
+        out_hash: *mut *mut c_char,
+    ) -> FfiResult<()> {
+        let store = unsafe { store.as_ref().ok_or(FfiError::NullPointer("store"))? };
+        let snapshot = unsafe { snapshot.as_ref().ok_or(FfiError::NullPointer("snapshot"))? };
+        if out_hash.is_null() {
+            return Err(FfiError::NullPointer("out_hash"));
+        }
+        let suspended = snapshot.suspended();
+        let hash = store
+            .store()
+            .put(&suspended.context.run_id, &intern::to_bytes(suspended)?)?;
+        unsafe {
+            *out_hash = to_c_string(hash, "out_hash")?;
+        }
+        Ok(())
+    }
+
+    match inner(store, snapshot, out_hash) {
+        Ok(()) => MontyStatus::success(),
+        Err(err) => MontyStatus::from_error(err),
+    }
+}
+
+/// Loads a new snapshot from the dump stored under `hash`; the store keeps the dump.
+#[no_mangle]
+pub unsafe extern "C" fn monty_store_get(
+    store: *const StoreHandle,
+    hash: *const c_char,
+    out: *mut *mut SnapshotHandle,
+) -> MontyStatus {
+    fn inner(
+        store: *const StoreHandle,
+        hash: *const c_char,
+        out: *mut *mut SnapshotHandle,
+    ) -> FfiResult<()> {
+        let store = unsafe { store.as_ref().ok_or(FfiError::NullPointer("store"))? };
+        let hash = unsafe { read_required_str(hash, "hash")? };
+        if out.is_null() {
+            return Err(FfiError::NullPointer("out"));
+        }
+        let dump = store.store().get(&hash)?;
+        let Suspended { state, context }: Suspended<Snapshot<FfiTracker>> = load_suspended(&dump)?;
+        unsafe {
+            *out = SnapshotHandle::new(state, context);
+        }
+        Ok(())
+    }
+
+    match inner(store, hash, out) {
+        Ok(()) => MontyStatus::success(),
+        Err(err) => MontyStatus::from_error(err),
+    }
+}
+
+/// Removes the dump stored under `hash`; does nothing if there is none.
+#[no_mangle]
+pub unsafe extern "C" fn monty_store_delete(
+    store: *const StoreHandle,
+    hash: *const c_char,
+) -> MontyStatus {
+    fn inner(store: *const StoreHandle, hash: *const c_char) -> FfiResult<()> {
+        let store = unsafe { store.as_ref().ok_or(FfiError::NullPointer("store"))? };
+        let hash = unsafe { read_required_str(hash, "hash")? };
+        store.store().delete(&hash)
+    }
+
+    match inner(store, hash) {
+        Ok(()) => MontyStatus::success(),
+        Err(err) => MontyStatus::from_error(err),
+    }
+}
+
+/// Writes the hashes of the stored snapshots of the run `run_id` (empty for runs
+/// started without one) as a JSON array, in the order they were put.
+#[no_mangle]
+pub unsafe extern "C" fn monty_store_list(
+    store: *const StoreHandle,
+    run_id: *const c_char,
+    out_json: *mut *mut c_char,
+) -> MontyStatus {
+    fn inner(
+        store: *const StoreHandle,
+        run_id: *const c_char,
+        out_json: *mut *mut c_char,
+    ) -> FfiResult<()> {
+        let store = unsafe { store.as_ref().ok_or(FfiError::NullPointer("store"))? };
+        let run_id = unsafe { read_required_str(run_id, "run_id")? };
+        write_json(&store.store().list(&run_id)?, out_json)
+    }
+
+    match inner(store, run_id, out_json) {
+        Ok(()) => MontyStatus::success(),
+        Err(err) => MontyStatus::from_error(err),
+    }
+}
+
+/// Number of distinct dumps in `store`; 0 if it can't be read.
+#[no_mangle]
+pub unsafe extern "C" fn monty_store_count(store: *const StoreHandle) -> usize {
+    store
+        .as_ref()
+        .and_then(|store| store.store().count().ok())
+        .unwrap_or(0)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn monty_future_snapshot_load(
+    bytes: *const u8,
+    len: usize,
+    out: *mut *mut FutureSnapshotHandle,
+) -> MontyStatus {
+    fn inner(bytes: *const u8, len: usize, out: *mut *mut FutureSnapshotHandle) -> FfiResult<()> {
+        if out.is_null() {
+            return Err(FfiError::NullPointer("out"));
+        }
+        if len > 0 && bytes.is_null() {
+            return Err(FfiError::NullPointer("bytes"));
+        }
+        let slice = unsafe { slice::from_raw_parts(bytes, len) };
+        let Suspended { state, context }: Suspended<FutureSnapshot<FfiTracker>> =
+            load_suspended(slice)?;
+        unsafe {
+            *out = FutureSnapshotHandle::new(state, context);
+        }
+        Ok(())
+    }
+
+    match inner(bytes, len, out) {
+        Ok(()) => MontyStatus::success(),
+        Err(err) => MontyStatus::from_error(err),
+    }
+}
+
+/// Rewrites a future snapshot dump from an earlier format version in the current one;
+/// see `monty_dump_format_version`.
+#[no_mangle]
+pub unsafe extern "C" fn monty_future_snapshot_migrate(
+    bytes: *const u8,
+    len: usize,
+    out_bytes: *mut *mut u8,
+    out_len: *mut usize,
+) -> MontyStatus {
+    fn inner(
+        bytes: *const u8,
+        len: usize,
+        out_bytes: *mut *mut u8,
+        out_len: *mut usize,
+    ) -> FfiResult<()> {
+        let dump = unsafe { read_bytes(bytes, len, "bytes")? };
+        write_bytes(
+            migrate::<FutureSnapshot<FfiTracker>>(&dump)?,
+            out_bytes,
+            out_len,
+        )
+    }
+
+    match inner(bytes, len, out_bytes, out_len) {
+        Ok(()) => MontyStatus::success(),
+        Err(err) => MontyStatus::from_error(err),
+    }
+}
+
+/// Writes the run's tracker counters so far (instructions, memory high-water mark,
+/// calls, printed bytes) as JSON, without consuming the snapshot.
+#[no_mangle]
+pub unsafe extern "C" fn monty_snapshot_stats(
+    snapshot: *mut SnapshotHandle,
+    out_json: *mut *mut c_char,
+) -> MontyStatus {
+    fn inner(snapshot: *mut SnapshotHandle, out_json: *mut *mut c_char) -> FfiResult<()> {
+        let snapshot = unsafe { snapshot.as_ref().ok_or(FfiError::NullPointer("snapshot"))? };
+        write_json(&snapshot.suspended().context.stats, out_json)
+    }
+
+    match inner(snapshot, out_json) {
+        Ok(()) => MontyStatus::success(),
+        Err(err) => MontyStatus::from_error(err),
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn monty_future_snapshot_stats(
+    snapshot: *mut FutureSnapshotHandle,
+    out_json: *mut *mut c_char,
+) -> MontyStatus {
+    fn inner(snapshot: *mut FutureSnapshotHandle, out_json: *mut *mut c_char) -> FfiResult<()> {
+        let snapshot = unsafe { snapshot.as_ref().ok_or(FfiError::NullPointer("snapshot"))? };
+        write_json(&snapshot.suspended().context.stats, out_json)
+    }
+
+    match inner(snapshot, out_json) {
+        Ok(()) => MontyStatus::success(),
+        Err(err) => MontyStatus::from_error(err),
+    }
+}
+
+/// Adds a policy (`{"functions": {...}, "os_functions": {...}}`, as in the run
+/// options) that every later call of the run must also pass. Restrictions travel with
+/// dumps and can't be lifted, so a snapshot handed to a less-trusted worker keeps them.
+#[no_mangle]
+pub unsafe extern "C" fn monty_snapshot_restrict(
+    snapshot: *mut SnapshotHandle,
+    policy_json: *const c_char,
+) -> MontyStatus {
+    fn inner(snapshot: *mut SnapshotHandle, policy_json: *const c_char) -> FfiResult<()> {
+        let snapshot = unsafe { snapshot.as_mut().ok_or(FfiError::NullPointer("snapshot"))? };
+        let policy_json = unsafe { read_required_str(policy_json, "policy_json")? };
+        let policy = serde_json::from_str(&policy_json)?;
+        snapshot.suspended_mut()?.context.restrict(policy);
+        Ok(())
+    }
+
+    match inner(snapshot, policy_json) {
+        Ok(()) => MontyStatus::success(),
+        Err(err) => MontyStatus::from_error(err),
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn monty_future_snapshot_restrict(
+    snapshot: *mut FutureSnapshotHandle,
+    policy_json: *const c_char,
+) -> MontyStatus {
+    fn inner(snapshot: *mut FutureSnapshotHandle, policy_json: *const c_char) -> FfiResult<()> {
+        let snapshot = unsafe { snapshot.as_mut().ok_or(FfiError::NullPointer("snapshot"))? };
+        let policy_json = unsafe { read_required_str(policy_json, "policy_json")? };
+        let policy = serde_json::from_str(&policy_json)?;
+        snapshot.suspended_mut().context.restrict(policy);
+        Ok(())
+    }
+
+    match inner(snapshot, policy_json) {
+        Ok(()) => MontyStatus::success(),
+        Err(err) => MontyStatus::from_error(err),
+    }
+}
+
+/// Moves a suspended run into `group`, or out of any group when it is NULL. Group
+/// membership is not kept in dumps, so loaded snapshots must be attached again.
+#[no_mangle]
+pub unsafe extern "C" fn monty_snapshot_attach_quota_group(
+    snapshot: *mut SnapshotHandle,
+    group: *const QuotaGroupHandle,
+) -> MontyStatus {
+    fn inner(snapshot: *mut SnapshotHandle, group: *const QuotaGroupHandle) -> FfiResult<()> {
+        let snapshot = unsafe { snapshot.as_mut().ok_or(FfiError::NullPointer("snapshot"))? };
+        let group = unsafe { group.as_ref() }.map(|group| Arc::clone(group.as_ref()));
+        snapshot.suspended_mut()?.context.join_quota(group);
+        Ok(())
+    }
+
+    match inner(snapshot, group) {
+        Ok(()) => MontyStatus::success(),
+        Err(err) => MontyStatus::from_error(err),
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn monty_future_snapshot_attach_quota_group(
+    snapshot: *mut FutureSnapshotHandle,
+    group: *const QuotaGroupHandle,
+) -> MontyStatus {
+    fn inner(snapshot: *mut FutureSnapshotHandle, group: *const QuotaGroupHandle) -> FfiResult<()> {
+        let snapshot = unsafe { snapshot.as_mut().ok_or(FfiError::NullPointer("snapshot"))? };
+        let group = unsafe { group.as_ref() }.map(|group| Arc::clone(group.as_ref()));
+        snapshot.suspended_mut().context.join_quota(group);
+        Ok(())
+    }
+
+    match inner(snapshot, group) {
+        Ok(()) => MontyStatus::success(),
+        Err(err) => MontyStatus::from_error(err),
+    }
+}
+
+/// Writes the run id given in the run options (empty when none was set).
+#[no_mangle]
+pub unsafe extern "C" fn monty_snapshot_run_id(
+    snapshot: *mut SnapshotHandle,
+    out_id: *mut *mut c_char,
+) -> MontyStatus {
+    fn inner(snapshot: *mut SnapshotHandle, out_id: *mut *mut c_char) -> FfiResult<()> {
+        let snapshot = unsafe { snapshot.as_ref().ok_or(FfiError::NullPointer("snapshot"))? };
+        if out_id.is_null() {
+            return Err(FfiError::NullPointer("out_id"));
+        }
+        let run_id = snapshot.suspended().context.run_id.clone();
+        unsafe {
+            *out_id = to_c_string(run_id, "out_id")?;
+        }
+        Ok(())
+    }
+
+    match inner(snapshot, out_id) {
+        Ok(()) => MontyStatus::success(),
+        Err(err) => MontyStatus::from_error(err),
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn monty_future_snapshot_run_id(
+    snapshot: *mut FutureSnapshotHandle,
+    out_id: *mut *mut c_char,
+) -> MontyStatus {
+    fn inner(snapshot: *mut FutureSnapshotHandle, out_id: *mut *mut c_char) -> FfiResult<()> {
+        let snapshot = unsafe { snapshot.as_ref().ok_or(FfiError::NullPointer("snapshot"))? };
+        if out_id.is_null() {
+            return Err(FfiError::NullPointer("out_id"));
+        }
+        let run_id = snapshot.suspended().context.run_id.clone();
+        unsafe {
+            *out_id = to_c_string(run_id, "out_id")?;
+        }
+        Ok(())
+    }
+
+    match inner(snapshot, out_id) {
+        Ok(()) => MontyStatus::success(),
+        Err(err) => MontyStatus::from_error(err),
+    }
+}
+
+/// Writes the run's Chrome trace so far, or `null` when it was started without the
+/// `trace` option, without consuming the snapshot.
+#[no_mangle]
+pub unsafe extern "C" fn monty_snapshot_trace(
+    snapshot: *mut SnapshotHandle,
+    out_json: *mut *mut c_char,
+) -> MontyStatus {
+    fn inner(snapshot: *mut SnapshotHandle, out_json: *mut *mut c_char) -> FfiResult<()> {
+        let snapshot = unsafe { snapshot.as_ref().ok_or(FfiError::NullPointer("snapshot"))? };
+        write_json(&snapshot.suspended().context.trace_json(), out_json)
+    }
+
+    match inner(snapshot, out_json) {
+        Ok(()) => MontyStatus::success(),
+        Err(err) => MontyStatus::from_error(err),
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn monty_future_snapshot_trace(
+    snapshot: *mut FutureSnapshotHandle,
+    out_json: *mut *mut c_char,
+) -> MontyStatus {
+    fn inner(snapshot: *mut FutureSnapshotHandle, out_json: *mut *mut c_char) -> FfiResult<()> {
+        let snapshot = unsafe { snapshot.as_ref().ok_or(FfiError::NullPointer("snapshot"))? };
+        write_json(&snapshot.suspended().context.trace_json(), out_json)
+    }
+
+    match inner(snapshot, out_json) {
+        Ok(()) => MontyStatus::success(),
+        Err(err) => MontyStatus::from_error(err),
+    }
+}
+
+/// Writes the audit log of OS calls attempted so far, or `null` when the run was
+/// started without the `audit_os_calls` option, without consuming the snapshot.
+#[no_mangle]
+pub unsafe extern "C" fn monty_snapshot_audit_log(
+    snapshot: *mut SnapshotHandle,
+    out_json: *mut *mut c_char,
+) -> MontyStatus {
+    fn inner(snapshot: *mut SnapshotHandle, out_json: *mut *mut c_char) -> FfiResult<()> {
+        let snapshot = unsafe { snapshot.as_ref().ok_or(FfiError::NullPointer("snapshot"))? };
+        write_json(&snapshot.suspended().context.audit_log(), out_json)
+    }
+
+    match inner(snapshot, out_json) {
+        Ok(()) => MontyStatus::success(),
+        Err(err) => MontyStatus::from_error(err),
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn monty_future_snapshot_audit_log(
+    snapshot: *mut FutureSnapshotHandle,
+    out_json: *mut *mut c_char,
+) -> MontyStatus {
+    fn inner(snapshot: *mut FutureSnapshotHandle, out_json: *mut *mut c_char) -> FfiResult<()> {
+        let snapshot = unsafe { snapshot.as_ref().ok_or(FfiError::NullPointer("snapshot"))? };
+        write_json(&snapshot.suspended().context.audit_log(), out_json)
+    }
+
+    match inner(snapshot, out_json) {
+        Ok(()) => MontyStatus::success(),
+        Err(err) => MontyStatus::from_error(err),
+    }
+}
+
+/// Writes how much of each configured limit the run has left as JSON; resources
+/// without a limit are reported as `null`.
+#[no_mangle]
+pub unsafe extern "C" fn monty_snapshot_remaining_budget(
+    snapshot: *mut SnapshotHandle,
+    out_json: *mut *mut c_char,
+) -> MontyStatus {
+    fn inner(snapshot: *mut SnapshotHandle, out_json: *mut *mut c_char) -> FfiResult<()> {
+        let snapshot = unsafe { snapshot.as_ref().ok_or(FfiError::NullPointer("snapshot"))? };
+        write_json(&snapshot.suspended().context.remaining_budget(), out_json)
+    }
+
+    match inner(snapshot, out_json) {
+        Ok(()) => MontyStatus::success(),
+        Err(err) => MontyStatus::from_error(err),
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn monty_future_snapshot_remaining_budget(
+    snapshot: *mut FutureSnapshotHandle,
+    out_json: *mut *mut c_char,
+) -> MontyStatus {
+    fn inner(snapshot: *mut FutureSnapshotHandle, out_json: *mut *mut c_char) -> FfiResult<()> {
+        let snapshot = unsafe { snapshot.as_ref().ok_or(FfiError::NullPointer("snapshot"))? };
+        write_json(&snapshot.suspended().context.remaining_budget(), out_json)
+    }
+
+    match inner(snapshot, out_json) {
+        Ok(()) => MontyStatus::success(),
+        Err(err) => MontyStatus::from_error(err),
+    }
+}
+
+/// Replaces the run's resource limits (same shape as the `limits` run option) before
+/// it is resumed; budget already consumed still counts. Writes, per limit, whether it
+/// was `raised`, `lowered`, or left `unchanged` to `out_json` when non-NULL.
+#[no_mangle]
+pub unsafe extern "C" fn monty_snapshot_set_limits(
+    snapshot: *mut SnapshotHandle,
+    limits_json: *const c_char,
+    out_json: *mut *mut c_char,
+) -> MontyStatus {
+    fn inner(
+        snapshot: *mut SnapshotHandle,
+        limits_json: *const c_char,
+        out_json: *mut *mut c_char,
+    ) -> FfiResult<()> {
+        let snapshot = unsafe { snapshot.as_mut().ok_or(FfiError::NullPointer("snapshot"))? };
+        let limits_json = unsafe { read_required_str(limits_json, "limits_json")? };
+        let limits = serde_json::from_str(&limits_json)?;
+        let changes = snapshot.suspended_mut()?.context.set_limits(limits);
+        if out_json.is_null() {
+            return Ok(());
+        }
+        write_json(&changes, out_json)
+    }
+
+    match inner(snapshot, limits_json, out_json) {
+        Ok(()) => MontyStatus::success(),
+        Err(err) => MontyStatus::from_error(err),
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn monty_future_snapshot_set_limits(
+    snapshot: *mut FutureSnapshotHandle,
+    limits_json: *const c_char,
+    out_json: *mut *mut c_char,
+) -> MontyStatus {
+    fn inner(
+        snapshot: *mut FutureSnapshotHandle,
+        limits_json: *const c_char,
+        out_json: *mut *mut c_char,
+    ) -> FfiResult<()> {
+        let snapshot = unsafe { snapshot.as_mut().ok_or(FfiError::NullPointer("snapshot"))? };
+        let limits_json = unsafe { read_required_str(limits_json, "limits_json")? };
+        let limits = serde_json::from_str(&limits_json)?;
+        let changes = snapshot.suspended_mut().context.set_limits(limits);
+        if out_json.is_null() {
+            return Ok(());
+        }
+        write_json(&changes, out_json)
+    }
+
+    match inner(snapshot, limits_json, out_json) {
+        Ok(()) => MontyStatus::success(),
+        Err(err) => MontyStatus::from_error(err),
+    }
+}
+
+/// A Monty value owned by the host and passed by handle, so large values skip the
+/// JSON round-trip. Handles are reference-counted: `monty_object_retain` and staged
+/// run inputs share the value, and mutating a shared value copies it first.
+#[repr(C)]
+pub struct MontyObjectHandle {
+    inner: *mut c_void,
+}
+
+impl MontyObjectHandle {
+    fn as_ref(&self) -> &MontyObject {
+        self.shared()
+    }
+
+    fn as_mut(&mut self) -> &mut MontyObject {
+        Arc::make_mut(unsafe { &mut *(self.inner as *mut Arc<MontyObject>) })
+    }
+
+    fn shared(&self) -> &Arc<MontyObject> {
+        unsafe { &*(self.inner as *mut Arc<MontyObject>) }
+    }
+
+    fn into_inner(self: Box<Self>) -> MontyObject {
+        debug::taken_back(&*self);
+        let shared = unsafe { *Box::from_raw(self.inner as *mut Arc<MontyObject>) };
+        Arc::try_unwrap(shared).unwrap_or_else(|shared| (*shared).clone())
+    }
+
+    fn new(object: MontyObject) -> *mut Self {
+        Self::from_shared(Arc::new(object))
+    }
+
+    fn from_shared(shared: Arc<MontyObject>) -> *mut Self {
+        let boxed = Box::new(shared);
+        let handle = Box::into_raw(Box::new(Self {
+            inner: Box::into_raw(boxed) as *mut c_void,
+        }));
+        debug::handed_out("object", handle, None);
+        handle
+    }
+}
+
+pub const MONTY_OBJECT_OTHER: i32 = 0;
+pub const MONTY_OBJECT_NONE: i32 = 1;
+pub const MONTY_OBJECT_BOOL: i32 = 2;
+pub const MONTY_OBJECT_INT: i32 = 3;
+pub const MONTY_OBJECT_FLOAT: i32 = 4;
+pub const MONTY_OBJECT_STRING: i32 = 5;
+pub const MONTY_OBJECT_BYTES: i32 = 6;
+pub const MONTY_OBJECT_LIST: i32 = 7;
+pub const MONTY_OBJECT_TUPLE: i32 = 8;
+pub const MONTY_OBJECT_DICT: i32 = 9;
+
+#[no_mangle]
+pub unsafe extern "C" fn monty_object_from_json(
+    json: *const c_char,
+    out: *mut *mut MontyObjectHandle,
+) -> MontyStatus {
+    fn inner(json: *const c_char, out: *mut *mut MontyObjectHandle) -> FfiResult<()> {
+        if out.is_null() {
+            return Err(FfiError::NullPointer("out"));
+        }
+        let json = unsafe { read_text(json, "json", false) }?;
+        let object = decode_object(&json)?;
+        unsafe {
+            *out = MontyObjectHandle::new(object);
+        }
+        Ok(())
+    }
+
+    match inner(json, out) {
+        Ok(()) => MontyStatus::success(),
+        Err(err) => MontyStatus::from_error(err),
+    }
+}
+
+/// Encodes the object with the tagged JSON encoding, without consuming it.
+#[no_mangle]
+pub unsafe extern "C" fn monty_object_to_json(
+    object: *const MontyObjectHandle,
+    out_json: *mut *mut c_char,
+) -> MontyStatus {
+    fn inner(object: *const MontyObjectHandle, out_json: *mut *mut c_char) -> FfiResult<()> {
+        let object = unsafe { object.as_ref().ok_or(FfiError::NullPointer("object"))? };
+        if out_json.is_null() {
+            return Err(FfiError::NullPointer("out_json"));
+        }
+        let json = encode_object(object.as_ref())?;
+        unsafe {
+            *out_json = to_c_string(json, "out_json")?;
+        }
+        Ok(())
+    }
+
+    match inner(object, out_json) {
+        Ok(()) => MontyStatus::success(),
+        Err(err) => MontyStatus::from_error(err),
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn monty_object_none() -> *mut MontyObjectHandle {
+    MontyObjectHandle::new(MontyObject::None)
+}
+
+#[no_mangle]
+pub extern "C" fn monty_object_bool(value: i32) -> *mut MontyObjectHandle {
+    MontyObjectHandle::new(MontyObject::Bool(value != 0))
+}
+
+#[no_mangle]
+pub extern "C" fn monty_object_int(value: i64) -> *mut MontyObjectHandle {
+    MontyObjectHandle::new(MontyObject::Int(value))
+}
+
+#[no_mangle]
+pub extern "C" fn monty_object_float(value: f64) -> *mut MontyObjectHandle {
+    MontyObjectHandle::new(MontyObject::Float(value))
+}
+
+/// Copies `len` bytes of UTF-8 text into a `str` object.
+#[no_mangle]
+pub unsafe extern "C" fn monty_object_string(
+    data: *const u8,
+    len: usize,
+    out: *mut *mut MontyObjectHandle,
+) -> MontyStatus {
+    fn inner(data: *const u8, len: usize, out: *mut *mut MontyObjectHandle) -> FfiResult<()> {
+        if out.is_null() {
+            return Err(FfiError::NullPointer("out"));
+        }
+        let bytes = unsafe { read_bytes(data, len, "data") }?;
+        let text = String::from_utf8(bytes).map_err(|_| FfiError::InvalidUtf8 { field: "data" })?;
+        unsafe {
+            *out = MontyObjectHandle::new(MontyObject::String(text));
+        }
+        Ok(())
+    }
+
+    match inner(data, len, out) {
+        Ok(()) => MontyStatus::success(),
+        Err(err) => MontyStatus::from_error(err),
+    }
+}
+
+/// Copies `len` bytes into a `bytes` object.
+#[no_mangle]
+pub unsafe extern "C" fn monty_object_bytes(
+    data: *const u8,
+    len: usize,
+    out: *mut *mut MontyObjectHandle,
+) -> MontyStatus {
+    fn inner(data: *const u8, len: usize, out: *mut *mut MontyObjectHandle) -> FfiResult<()> {
+        if out.is_null() {
+            return Err(FfiError::NullPointer("out"));
+        }
+        let bytes = unsafe { read_bytes(data, len, "data") }?;
+        unsafe {
+            *out = MontyObjectHandle::new(MontyObject::Bytes(bytes));
+        }
+        Ok(())
+    }
+
+    match inner(data, len, out) {
+        Ok(()) => MontyStatus::success(),
+        Err(err) => MontyStatus::from_error(err),
+    }
+}
+
+/// Builds a `list` from `len` item handles, consuming them. On failure none of them
+/// are consumed; passing the same handle twice fails.
+#[no_mangle]
+pub unsafe extern "C" fn monty_object_list(
+    items: *const *mut MontyObjectHandle,
+    len: usize,
+    out: *mut *mut MontyObjectHandle,
+) -> MontyStatus {
+    fn inner(
+        items: *const *mut MontyObjectHandle,
+        len: usize,
+        out: *mut *mut MontyObjectHandle,
+    ) -> FfiResult<()> {
+        if out.is_null() {
+            return Err(FfiError::NullPointer("out"));
+        }
+        let items = unsafe { object_handles(items, len, "items")? };
+        distinct_handles(&[items])?;
+        let items = unsafe { take_objects(items) };
+        unsafe {
+            *out = MontyObjectHandle::new(MontyObject::List(items));
+        }
+        Ok(())
+    }
+
+    match inner(items, len, out) {
+        Ok(()) => MontyStatus::success(),
+        Err(err) => MontyStatus::from_error(err),
+    }
+}
+
+/// Builds a `dict` from `len` key and value handles, consuming them. On failure none
+/// of them are consumed; passing the same handle twice, as a key or a value, fails.
+#[no_mangle]
+pub unsafe extern "C" fn monty_object_dict(
+    keys: *const *mut MontyObjectHandle,
+    values: *const *mut MontyObjectHandle,
+    len: usize,
+    out: *mut *mut MontyObjectHandle,
+) -> MontyStatus {
+    fn inner(
+        keys: *const *mut MontyObjectHandle,
+        values: *const *mut MontyObjectHandle,
+        len: usize,
+        out: *mut *mut MontyObjectHandle,
+    ) -> FfiResult<()> {
+        if out.is_null() {
+            return Err(FfiError::NullPointer("out"));
+        }
+        // Both arrays are checked before either is taken, so a failure consumes nothing.
+        let keys = unsafe { object_handles(keys, len, "keys")? };
+        let values = unsafe { object_handles(values, len, "values")? };
+        distinct_handles(&[keys, values])?;
+        let keys = unsafe { take_objects(keys) };
+        let values = unsafe { take_objects(values) };
+        let pairs: Vec<_> = keys.into_iter().zip(values).collect();
+        unsafe {
+            *out = MontyObjectHandle::new(MontyObject::Dict(pairs.into()));
+        }
+        Ok(())
+    }
+
+    match inner(keys, values, len, out) {
+        Ok(()) => MontyStatus::success(),
+        Err(err) => MontyStatus::from_error(err),
+    }
+}
+
+/// Returns an empty `list` to fill with `monty_list_push`.
+#[no_mangle]
+pub extern "C" fn monty_list_new() -> *mut MontyObjectHandle {
+    MontyObjectHandle::new(MontyObject::List(Vec::new()))
+}
+
+/// Appends `item` to a list, consuming the item handle.
+#[no_mangle]
+pub unsafe extern "C" fn monty_list_push(
+    list: *mut MontyObjectHandle,
+    item: *mut MontyObjectHandle,
+) -> MontyStatus {
+    fn inner(list: *mut MontyObjectHandle, item: *mut MontyObjectHandle) -> FfiResult<()> {
+        let list = unsafe { list.as_mut().ok_or(FfiError::NullPointer("list"))? };
+        if item.is_null() {
+            return Err(FfiError::NullPointer("item"));
+        }
+        let MontyObject::List(items) = list.as_mut() else {
+            return Err(FfiError::Message("object is not a list".to_owned()));
+        };
+        items.push(unsafe { Box::from_raw(item) }.into_inner());
+        Ok(())
+    }
+
+    match inner(list, item) {
+        Ok(()) => MontyStatus::success(),
+        Err(err) => MontyStatus::from_error(err),
+    }
+}
+
+/// Returns an empty `dict` to fill with `monty_dict_set`.
+#[no_mangle]
+pub extern "C" fn monty_dict_new() -> *mut MontyObjectHandle {
+    MontyObjectHandle::new(MontyObject::Dict(Vec::new().into()))
+}
+
+/// Sets `dict[key] = value`, replacing an equal key's value, and consumes both
+/// handles. Keys are compared linearly; build large dicts with `monty_object_dict`.
+#[no_mangle]
+pub unsafe extern "C" fn monty_dict_set(
+    dict: *mut MontyObjectHandle,
+    key: *mut MontyObjectHandle,
+    value: *mut MontyObjectHandle,
+) -> MontyStatus {
+    fn inner(
+        dict: *mut MontyObjectHandle,
+        key: *mut MontyObjectHandle,
+        value: *mut MontyObjectHandle,
+    ) -> FfiResult<()> {
+        let dict = unsafe { dict.as_mut().ok_or(FfiError::NullPointer("dict"))? };
+        if key.is_null() {
+            return Err(FfiError::NullPointer("key"));
+        }
+        if value.is_null() {
+            return Err(FfiError::NullPointer("value"));
+        }
+        let MontyObject::Dict(pairs) = dict.as_mut() else {
+            return Err(FfiError::Message("object is not a dict".to_owned()));
+        };
+        let key = unsafe { Box::from_raw(key) }.into_inner();
+        let value = unsafe { Box::from_raw(value) }.into_inner();
+        let mut entries: Vec<_> = std::mem::replace(pairs, Vec::new().into())
+            .into_iter()
+            .collect();
+        match entries.iter_mut().find(|(existing, _)| *existing == key) {
+            Some((_, slot)) => *slot = value,
+            None => entries.push((key, value)),
+        }
+        *pairs = entries.into();
+        Ok(())
+    }
+
+    match inner(dict, key, value) {
+        Ok(()) => MontyStatus::success(),
+        Err(err) => MontyStatus::from_error(err),
+    }
+}
+
+/// Returns the object's `MONTY_OBJECT_*` kind; values without a dedicated accessor
+/// are `MONTY_OBJECT_OTHER` and can be read with `monty_object_to_json`.
+#[no_mangle]
+pub unsafe extern "C" fn monty_object_kind(object: *const MontyObjectHandle) -> i32 {
+    match object.as_ref().map(MontyObjectHandle::as_ref) {
+        Some(MontyObject::None) => MONTY_OBJECT_NONE,
+        Some(MontyObject::Bool(_)) => MONTY_OBJECT_BOOL,
+        Some(MontyObject::Int(_) | MontyObject::BigInt(_)) => MONTY_OBJECT_INT,
+        Some(MontyObject::Float(_)) => MONTY_OBJECT_FLOAT,
+        Some(MontyObject::String(_)) => MONTY_OBJECT_STRING,
+        Some(MontyObject::Bytes(_)) => MONTY_OBJECT_BYTES,
+        Some(MontyObject::List(_)) => MONTY_OBJECT_LIST,
+        Some(MontyObject::Tuple(_)) => MONTY_OBJECT_TUPLE,
+        Some(MontyObject::Dict(_)) => MONTY_OBJECT_DICT,
+        _ => MONTY_OBJECT_OTHER,
+    }
+}
+
+/// Number of items in a container, or of bytes in a string or bytes object; 0 for
+/// anything else.
+#[no_mangle]
+pub unsafe extern "C" fn monty_object_len(object: *const MontyObjectHandle) -> usize {
+    object
+        .as_ref()
+        .and_then(|object| objects::len(object.as_ref()))
+        .unwrap_or(0)
+}
+
+/// Writes the Python class name of the object (`list`, `NoneType`, a dataclass name).
+#[no_mangle]
+pub unsafe extern "C" fn monty_object_type(
+    object: *const MontyObjectHandle,
+    out_name: *mut *mut c_char,
+) -> MontyStatus {
+    fn inner(object: *const MontyObjectHandle, out_name: *mut *mut c_char) -> FfiResult<()> {
+        let object = unsafe { object.as_ref().ok_or(FfiError::NullPointer("object"))? };
+        if out_name.is_null() {
+            return Err(FfiError::NullPointer("out_name"));
+        }
+        let name = objects::type_name(object.as_ref()).to_owned();
+        unsafe {
+            *out_name = to_c_string(name, "out_name")?;
+        }
+        Ok(())
+    }
+
+    match inner(object, out_name) {
+        Ok(()) => MontyStatus::success(),
+        Err(err) => MontyStatus::from_error(err),
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn monty_object_as_int(
+    object: *const MontyObjectHandle,
+    out: *mut i64,
+) -> MontyStatus {
+    fn inner(object: *const MontyObjectHandle, out: *mut i64) -> FfiResult<()> {
+        let object = unsafe { object.as_ref().ok_or(FfiError::NullPointer("object"))? };
+        let out = unsafe { out.as_mut().ok_or(FfiError::NullPointer("out"))? };
+        *out = match object.as_ref() {
+            MontyObject::Int(value) => *value,
+            MontyObject::Bool(value) => *value as i64,
+            MontyObject::BigInt(_) => {
+                return Err(FfiError::Message("int does not fit in 64 bits".to_owned()))
+            }
+            _ => return Err(FfiError::Message("object is not an int".to_owned())),
+        };
+        Ok(())
+    }
+
+    match inner(object, out) {
+        Ok(()) => MontyStatus::success(),
+        Err(err) => MontyStatus::from_error(err),
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn monty_object_as_float(
+    object: *const MontyObjectHandle,
+    out: *mut f64,
+) -> MontyStatus {
+    fn inner(object: *const MontyObjectHandle, out: *mut f64) -> FfiResult<()> {
+        let object = unsafe { object.as_ref().ok_or(FfiError::NullPointer("object"))? };
+        let out = unsafe { out.as_mut().ok_or(FfiError::NullPointer("out"))? };
+        *out = match object.as_ref() {
+            MontyObject::Float(value) => *value,
+            MontyObject::Int(value) => *value as f64,
+            _ => return Err(FfiError::Message("object is not a float".to_owned())),
+        };
+        Ok(())
+    }
+
+    match inner(object, out) {
+        Ok(()) => MontyStatus::success(),
+        Err(err) => MontyStatus::from_error(err),
+    }
+}
+
+/// Points `out_data` at the UTF-8 contents of a string or the contents of a bytes
+/// object. The data is borrowed and stays valid until the handle is freed or consumed.
+#[no_mangle]
+pub unsafe extern "C" fn monty_object_as_bytes(
+    object: *const MontyObjectHandle,
+    out_data: *mut *const u8,
+    out_len: *mut usize,
+) -> MontyStatus {
+    fn inner(
+        object: *const MontyObjectHandle,
+        out_data: *mut *const u8,
+        out_len: *mut usize,
+    ) -> FfiResult<()> {
+        let object = unsafe { object.as_ref().ok_or(FfiError::NullPointer("object"))? };
+        if out_data.is_null() {
+            return Err(FfiError::NullPointer("out_data"));
+        }
+        if out_len.is_null() {
+            return Err(FfiError::NullPointer("out_len"));
+        }
+        let data: &[u8] = match object.as_ref() {
+            MontyObject::String(text) => text.as_bytes(),
+            MontyObject::Bytes(bytes) => bytes,
+            _ => return Err(FfiError::Message("object is not a str or bytes".to_owned())),
+        };
+        unsafe {
+            *out_data = data.as_ptr();
+            *out_len = data.len();
+        }
+        Ok(())
+    }
+
+    match inner(object, out_data, out_len) {
+        Ok(()) => MontyStatus::success(),
+        Err(err) => MontyStatus::from_error(err),
+    }
+}
+
+/// Copies item `index` of a list or tuple into a new handle.
+#[no_mangle]
+pub unsafe extern "C" fn monty_object_item(
+    object: *const MontyObjectHandle,
+    index: usize,
+    out: *mut *mut MontyObjectHandle,
+) -> MontyStatus {
+    fn inner(
+        object: *const MontyObjectHandle,
+        index: usize,
+        out: *mut *mut MontyObjectHandle,
+    ) -> FfiResult<()> {
+        let object = unsafe { object.as_ref().ok_or(FfiError::NullPointer("object"))? };
+        if out.is_null() {
+            return Err(FfiError::NullPointer("out"));
+        }
+        let (MontyObject::List(items) | MontyObject::Tuple(items)) = object.as_ref() else {
+            return Err(FfiError::Message(
+                "object is not a list or tuple".to_owned(),
+            ));
+        };
+        let item = items
+            .get(index)
+            .ok_or_else(|| FfiError::Message(format!("index {index} out of range")))?;
+        unsafe {
+            *out = MontyObjectHandle::new(item.clone());
+        }
+        Ok(())
+    }
+
+    match inner(object, index, out) {
+        Ok(()) => MontyStatus::success(),
+        Err(err) => MontyStatus::from_error(err),
+    }
+}
+
+/// Copies entry `index` of a dict, in insertion order, into new key and value handles.
+#[no_mangle]
+pub unsafe extern "C" fn monty_object_dict_item(
+    object: *const MontyObjectHandle,
+    index: usize,
+    out_key: *mut *mut MontyObjectHandle,
+    out_value: *mut *mut MontyObjectHandle,
+) -> MontyStatus {
+    fn inner(
+        object: *const MontyObjectHandle,
+        index: usize,
+        out_key: *mut *mut MontyObjectHandle,
+        out_value: *mut *mut MontyObjectHandle,
+    ) -> FfiResult<()> {
+        let object = unsafe { object.as_ref().ok_or(FfiError::NullPointer("object"))? };
+        if out_key.is_null() {
+            return Err(FfiError::NullPointer("out_key"));
+        }
+        if out_value.is_null() {
+            return Err(FfiError::NullPointer("out_value"));
+        }
+        let MontyObject::Dict(pairs) = object.as_ref() else {
+            return Err(FfiError::Message("object is not a dict".to_owned()));
+        };
+        let (key, value) = pairs
+            .into_iter()
+            .nth(index)
+            .ok_or_else(|| FfiError::Message(format!("index {index} out of range")))?;
+        unsafe {
+            *out_key = MontyObjectHandle::new(key.clone());
+            *out_value = MontyObjectHandle::new(value.clone());
+        }
+        Ok(())
+    }
+
+    match inner(object, index, out_key, out_value) {
+        Ok(()) => MontyStatus::success(),
+        Err(err) => MontyStatus::from_error(err),
+    }
+}
+
+/// Copies `object[key]` into a new handle: sequences take int keys (negative counts
+/// from the end), dicts any key. `key` is not consumed.
+#[no_mangle]
+pub unsafe extern "C" fn monty_object_getitem(
+    object: *const MontyObjectHandle,
+    key: *const MontyObjectHandle,
+    out: *mut *mut MontyObjectHandle,
+) -> MontyStatus {
+    fn inner(
+        object: *const MontyObjectHandle,
+        key: *const MontyObjectHandle,
+        out: *mut *mut MontyObjectHandle,
+    ) -> FfiResult<()> {
+        let object = unsafe { object.as_ref().ok_or(FfiError::NullPointer("object"))? };
+        let key = unsafe { key.as_ref().ok_or(FfiError::NullPointer("key"))? };
+        if out.is_null() {
+            return Err(FfiError::NullPointer("out"));
+        }
+        let item = objects::get_item(object.as_ref(), key.as_ref())?;
+        unsafe {
+            *out = MontyObjectHandle::new(item.clone());
+        }
+        Ok(())
+    }
+
+    match inner(object, key, out) {
+        Ok(()) => MontyStatus::success(),
+        Err(err) => MontyStatus::from_error(err),
+    }
+}
+
+/// Copies attribute `name` of a dataclass or named tuple into a new handle.
+#[no_mangle]
+pub unsafe extern "C" fn monty_object_getattr(
+    object: *const MontyObjectHandle,
+    name: *const c_char,
+    out: *mut *mut MontyObjectHandle,
+) -> MontyStatus {
+    fn inner(
+        object: *const MontyObjectHandle,
+        name: *const c_char,
+        out: *mut *mut MontyObjectHandle,
+    ) -> FfiResult<()> {
+        let object = unsafe { object.as_ref().ok_or(FfiError::NullPointer("object"))? };
+        let name = unsafe { read_required_str(name, "name") }?;
+        if out.is_null() {
+            return Err(FfiError::NullPointer("out"));
+        }
+        let attr = objects::get_attr(object.as_ref(), &name)?;
+        unsafe {
+            *out = MontyObjectHandle::new(attr.clone());
+        }
+        Ok(())
+    }
+
+    match inner(object, name, out) {
+        Ok(()) => MontyStatus::success(),
+        Err(err) => MontyStatus::from_error(err),
+    }
+}
+
+/// A cursor over an object handle's items, as Python iterates them (dict keys for
+/// dicts). It borrows the object, which must outlive it.
+#[repr(C)]
+pub struct MontyIteratorHandle {
+    inner: *mut c_void,
+}
+
+struct Cursor {
+    object: *const MontyObject,
+    next: usize,
+}
+
+impl MontyIteratorHandle {
+    fn cursor_mut(&mut self) -> &mut Cursor {
+        unsafe { &mut *(self.inner as *mut Cursor) }
+    }
+
+    fn new(cursor: Cursor) -> *mut Self {
+        let boxed = Box::new(cursor);
+        let handle = Box::into_raw(Box::new(Self {
+            inner: Box::into_raw(boxed) as *mut c_void,
+        }));
+        debug::handed_out("iterator", handle, None);
+        handle
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn monty_object_iter(
+    object: *const MontyObjectHandle,
+    out: *mut *mut MontyIteratorHandle,
+) -> MontyStatus {
+    fn inner(
+        object: *const MontyObjectHandle,
+        out: *mut *mut MontyIteratorHandle,
+    ) -> FfiResult<()> {
+        let object = unsafe { object.as_ref().ok_or(FfiError::NullPointer("object"))? };
+        if out.is_null() {
+            return Err(FfiError::NullPointer("out"));
+        }
+        // Fail here rather than on the first step for values that cannot be iterated.
+        objects::iter_item(object.as_ref(), 0)?;
+        unsafe {
+            *out = MontyIteratorHandle::new(Cursor {
+                object: object.as_ref(),
+                next: 0,
+            });
+        }
+        Ok(())
+    }
+
+    match inner(object, out) {
+        Ok(()) => MontyStatus::success(),
+        Err(err) => MontyStatus::from_error(err),
+    }
+}
+
+/// Copies the next item into a new handle, or writes NULL once the iterator is exhausted.
+#[no_mangle]
+pub unsafe extern "C" fn monty_iterator_next(
+    iterator: *mut MontyIteratorHandle,
+    out: *mut *mut MontyObjectHandle,
+) -> MontyStatus {
+    fn inner(
+        iterator: *mut MontyIteratorHandle,
+        out: *mut *mut MontyObjectHandle,
+    ) -> FfiResult<()> {
+        let iterator = unsafe { iterator.as_mut().ok_or(FfiError::NullPointer("iterator"))? };
+        if out.is_null() {
+            return Err(FfiError::NullPointer("out"));
+        }
+        let cursor = iterator.cursor_mut();
+        let object = unsafe { &*cursor.object };
+        let item = match objects::iter_item(object, cursor.next)? {
+            Some(item) => {
+                cursor.next += 1;
+                MontyObjectHandle::new(item.clone())
+            }
+            None => ptr::null_mut(),
+        };
+        unsafe {
+            *out = item;
+        }
+        Ok(())
+    }
+
+    match inner(iterator, out) {
+        Ok(()) => MontyStatus::success(),
+        Err(err) => MontyStatus::from_error(err),
+    }
+}
+
+/// Returns a new handle sharing the object's value; each handle is freed separately.
+#[no_mangle]
+pub unsafe extern "C" fn monty_object_retain(
+    object: *const MontyObjectHandle,
+) -> *mut MontyObjectHandle {
+    match object.as_ref() {
+        Some(object) => MontyObjectHandle::from_shared(Arc::clone(object.shared())),
+        None => ptr::null_mut(),
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn monty_object_free(object: *mut MontyObjectHandle) {
+    if !object.is_null() {
+        debug::taken_back(object);
+        let handle = Box::from_raw(object);
+        drop(Box::from_raw(handle.inner as *mut Arc<MontyObject>));
+    }
+}
+
+/// `monty_object_free`, then sets `*object` to NULL.
+#[no_mangle]
+pub unsafe extern "C" fn monty_object_free_and_clear(object: *mut *mut MontyObjectHandle) {
+    if let Some(object) = object.as_mut() {
+        monty_object_free(std::mem::replace(object, ptr::null_mut()));
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn monty_iterator_free(iterator: *mut MontyIteratorHandle) {
+    if !iterator.is_null() {
+        debug::taken_back(iterator);
+        let handle = Box::from_raw(iterator);
+        drop(Box::from_raw(handle.inner as *mut Cursor));
+    }
+}
+
+/// `monty_iterator_free`, then sets `*iterator` to NULL.
+#[no_mangle]
+pub unsafe extern "C" fn monty_iterator_free_and_clear(iterator: *mut *mut MontyIteratorHandle) {
+    if let Some(iterator) = iterator.as_mut() {
+        monty_iterator_free(std::mem::replace(iterator, ptr::null_mut()));
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn monty_snapshot_free(snapshot: *mut SnapshotHandle) {
+    if !snapshot.is_null() && !reentry::defer_free(snapshot) {
+        debug::taken_back(snapshot);
+        let handle = Box::from_raw(snapshot);
+        drop(Box::from_raw(
+            handle.inner as *mut Suspended<Snapshot<FfiTracker>>,
+        ));
+        metrics::snapshot_held(false);
+    }
+}
+
+/// `monty_snapshot_free`, then sets `*snapshot` to NULL.
+#[no_mangle]
+pub unsafe extern "C" fn monty_snapshot_free_and_clear(snapshot: *mut *mut SnapshotHandle) {
+    if let Some(snapshot) = snapshot.as_mut() {
+        monty_snapshot_free(std::mem::replace(snapshot, ptr::null_mut()));
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn monty_future_snapshot_free(snapshot: *mut FutureSnapshotHandle) {
+    if !snapshot.is_null() {
+        debug::taken_back(snapshot);
+        let handle = Box::from_raw(snapshot);
+        drop(Box::from_raw(
+            handle.inner as *mut Suspended<FutureSnapshot<FfiTracker>>,
+        ));
+        metrics::future_snapshot_held(false);
+    }
+}
+
+/// `monty_future_snapshot_free`, then sets `*snapshot` to NULL.
+#[no_mangle]
+pub unsafe extern "C" fn monty_future_snapshot_free_and_clear(
+    snapshot: *mut *mut FutureSnapshotHandle,
+) {
+    if let Some(snapshot) = snapshot.as_mut() {
+        monty_future_snapshot_free(std::mem::replace(snapshot, ptr::null_mut()));
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn monty_free_bytes(ptr: *mut u8, len: usize) {
+    if !ptr.is_null() {
+        debug::taken_back(ptr);
+        drop(Vec::from_raw_parts(ptr, len, len));
+    }
+}
+
+/// Loads a snapshot or future snapshot dump of any supported format version.
+///
+/// Version 0 dumps, from releases before dumps were versioned, hold only the bare VM
+/// state, encoded as it is now: trackers are fieldless, so a `NoLimitTracker` state
+/// reads as an `FfiTracker` one. They load with a default context, as a run started
+/// without options.
+fn load_suspended<S: serde::de::DeserializeOwned>(bytes: &[u8]) -> FfiResult<Suspended<S>> {
+    if intern::format_version(bytes) == 0 {
+        return Ok(Suspended {
+            state: postcard::from_bytes(bytes)?,
+            context: RunContext::default(),
+        });
+    }
+    intern::from_bytes(bytes)
+}
+
+/// Loads a dump of any supported format version and re-dumps it in the current one.
+fn migrate<S: Serialize + serde::de::DeserializeOwned>(dump: &[u8]) -> FfiResult<Vec<u8>> {
+    let suspended: Suspended<S> = load_suspended(dump)?;
+    intern::to_bytes(&suspended)
+}
+
+fn write_bytes(bytes: Vec<u8>, out_bytes: *mut *mut u8, out_len: *mut usize) -> FfiResult<()> {
+    if out_bytes.is_null() {
+        return Err(FfiError::NullPointer("out_bytes"));
+    }
+    if out_len.is_null() {
+        return Err(FfiError::NullPointer("out_len"));
+    }
+    let mut boxed = bytes.into_boxed_slice();
+    let len = boxed.len();
+    let ptr = boxed.as_mut_ptr();
+    std::mem::forget(boxed);
+    debug::handed_out("bytes", ptr, Some(len));
+    unsafe {
+        *out_bytes = ptr;
+        *out_len = len;
+    }
+    Ok(())
+}
+
+/// Digest of a dump of `suspended` with its context made canonical; see
+/// `monty_snapshot_hash`.
+fn state_hash<S: Serialize>(suspended: &Suspended<S>) -> FfiResult<String> {
+    let canonical = Suspended {
+        state: &suspended.state,
+        context: suspended.context.canonical(),
+    };
+    Ok(sha256::hex_digest(&intern::to_bytes(&canonical)?))
+}
+
+fn write_json(value: &impl Serialize, out_json: *mut *mut c_char) -> FfiResult<()> {
+    if out_json.is_null() {
+        return Err(FfiError::NullPointer("out_json"));
+    }
+    let json = serde_json::to_string(value)?;
+    unsafe {
+        *out_json = to_c_string(json, "out_json")?;
+    }
+    Ok(())
+}
+
+/// Copies `len` bytes, allowing NULL only when `len` is 0.
+unsafe fn read_bytes(data: *const u8, len: usize, field: &'static str) -> FfiResult<Vec<u8>> {
+    if len == 0 {
+        return Ok(Vec::new());
+    }
+    if data.is_null() {
+        return Err(FfiError::NullPointer(field));
+    }
+    Ok(unsafe { slice::from_raw_parts(data, len) }.to_vec())
+}
+
+/// Copies `count` byte inputs into a map by name.
+unsafe fn read_input_bytes(
+    input_bytes: *const MontyInputBytes,
+    count: usize,
+) -> FfiResult<HashMap<String, Vec<u8>>> {
+    if count == 0 {
+        return Ok(HashMap::new());
+    }
+    if input_bytes.is_null() {
+        return Err(FfiError::NullPointer("input_bytes"));
+    }
+    let mut map = HashMap::new();
+    for input in unsafe { slice::from_raw_parts(input_bytes, count) } {
+        let name = unsafe { read_required_str(input.name, "name")? };
+        let data = unsafe { read_bytes(input.data, input.len, "data")? };
+        map.insert(name, data);
+    }
+    Ok(map)
+}
+
+/// The `len` handles at `handles`, checked to all be non-NULL so they can be taken.
+unsafe fn object_handles<'a>(
+    handles: *const *mut MontyObjectHandle,
+    len: usize,
+    field: &'static str,
+) -> FfiResult<&'a [*mut MontyObjectHandle]> {
+    if len == 0 {
+        return Ok(&[]);
+    }
+    if handles.is_null() {
+        return Err(FfiError::NullPointer(field));
+    }
+    let handles = unsafe { slice::from_raw_parts(handles, len) };
+    if handles.iter().any(|handle| handle.is_null()) {
+        return Err(FfiError::NullPointer(field));
+    }
+    Ok(handles)
+}
+
+/// Fails if a handle appears more than once across `groups`, which taking them all
+/// would free twice.
+fn distinct_handles(groups: &[&[*mut MontyObjectHandle]]) -> FfiResult<()> {
+    let mut seen = HashSet::new();
+    for &handle in groups.iter().flat_map(|group| group.iter()) {
+        if !seen.insert(handle) {
+            return Err(FfiError::Message(
+                "the same object handle is passed more than once".to_string(),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Consumes handles checked by `object_handles` and `distinct_handles`.
+unsafe fn take_objects(handles: &[*mut MontyObjectHandle]) -> Vec<MontyObject> {
+    handles
+        .iter()
+        .map(|&handle| unsafe { Box::from_raw(handle) }.into_inner())
+        .collect()
+}
+
+unsafe fn read_string_array(
+    ptr: *const *const c_char,
+    field: &'static str,
+) -> FfiResult<Vec<String>> {
+    if ptr.is_null() {
+        return Ok(Vec::new());
+    }
+    let mut values = Vec::new();
+    let mut index = 0;
+    loop {
+        let current = unsafe { *ptr.add(index) };
+        if current.is_null() {
+            break;
+        }
+        values.push(unsafe { read_required_str(current, field)? });
+        index += 1;
+    }
+    Ok(values)
+}
+
+fn call_descriptor(
+    call_id: u32,
+    function_name: String,
+    os_function: Option<String>,
+    args: &[MontyObject],
+    kwargs: &[(MontyObject, MontyObject)],
+    method_call: bool,
+    context: &RunContext,
+) -> FfiResult<CallDescriptor> {
+    let name = os_function.as_deref().unwrap_or(&function_name);
+    let timeout_ms = context.call_timeouts.for_call(name);
+    let priority = Some(context.call_priorities.for_call(name)).filter(|p| *p != 0);
+    let builtin_override = os_function.is_none() && context.is_builtin_override(name);
+    let idempotency_key = if os_function.is_none() {
+        context.idempotency_key(call_id, kwargs)
+    } else {
+        None
+    };
+    Ok(CallDescriptor {
+        call_id,
+        function_name,
+        os_function,
+        args: args.iter().map(encode_value).collect::<FfiResult<_>>()?,
+        kwargs: encode_kwarg_values(kwargs)?,
+        method_call,
+        receiver: match args.first() {
+            Some(receiver) if method_call => Some(encode_value(receiver)?),
+            _ => None,
+        },
+        timeout_ms,
+        priority,
+        builtin_override,
+        idempotency_key,
+    })
+}
+
+fn external_error(error_type: Option<&str>, message: String) -> FfiResult<ExternalResult> {
+    let exc_type = match error_type.filter(|name| !name.is_empty()) {
+        Some(name) => parse_exc_type(name)?,
+        None => ExcType::RuntimeError,
+    };
+    Ok(ExternalResult::Error(MontyException::new(
+        exc_type,
+        Some(message),
+    )))
+}
+
+/// Decodes future resolutions, also returning the host cause chains of any
+/// structured exceptions so a failed resume can report them.
+fn decode_future_results(json: &str) -> FfiResult<(FutureResults, HostCauses)> {
+    check_numbers(json)?;
+    let raw: Vec<FutureResolution> = serde_json::from_str(json)?;
+    let mut causes = HostCauses::default();
+    let results = raw
+        .into_iter()
+        .map(|entry| {
+            if entry.cancelled || entry.timed_out {
+                let message = match entry.error {
+                    Some(ResumeError::Message(err)) if !err.is_empty() => Some(err),
+                    _ => None,
+                };
+                let exc_type = if entry.cancelled {
+                    CANCELLED_EXC_TYPE
+                } else {
+                    TIMEOUT_EXC_TYPE
+                };
                 return Ok((
                     entry.call_id,
-                    ExternalResult::Error(MontyException::new(ExcType::RuntimeError, Some(err))),
+                    ExternalResult::Error(MontyException::new(parse_exc_type(exc_type)?, message)),
                 ));
             }
+            match entry.error {
+                Some(ResumeError::Exception(value)) => {
+                    let host = decode_exception(value)?;
+                    causes.push(&host);
+                    return Ok((entry.call_id, ExternalResult::Error(host.exception)));
+                }
+                Some(ResumeError::Message(err)) if !err.is_empty() => {
+                    return Ok((
+                        entry.call_id,
+                        external_error(entry.error_type.as_deref(), err)?,
+                    ));
+                }
+                _ => {}
+            }
             if let Some(value) = entry.result {
                 let object = decode_value(value)?;
                 return Ok((entry.call_id, ExternalResult::Return(object)));
             }
             Ok((entry.call_id, ExternalResult::Future))
         })
-        .collect()
+        .collect::<FfiResult<_>>()?;
+    Ok((results, causes))
+}
+
+unsafe fn progress_message(result: &ProgressResult) -> FfiResult<ProgressMessage> {
+    let kind = ProgressKind::from_i32(result.kind)
+        .ok_or_else(|| FfiError::Message(format!("unknown progress kind {}", result.kind)))?;
+    let mut message = ProgressMessage::new(kind);
+    if let Some(json) = read_optional_str(result.result_json)? {
+        message.result = Some(serde_json::from_str(&json)?);
+    }
+    message.function_name = read_optional_str(result.function_name)?;
+    message.os_function = read_optional_str(result.os_function)?;
+    if let Some(json) = read_optional_str(result.args_json)? {
+        message.args = serde_json::from_str(&json)?;
+    }
+    if let Some(json) = read_optional_str(result.kwargs_json)? {
+        message.kwargs = serde_json::from_str(&json)?;
+    }
+    if matches!(
+        kind,
+        ProgressKind::FunctionCall
+            | ProgressKind::OsCall
+            | ProgressKind::Emit
+            | ProgressKind::Sleep
+            | ProgressKind::Prompt
+    ) {
+        message.call_id = Some(result.call_id);
+    }
+    if kind == ProgressKind::Sleep {
+        message.delay_ms = Some(result.delay_ms);
+    }
+    message.prompt = read_optional_str(result.prompt)?;
+    message.idempotency_key = read_optional_str(result.idempotency_key)?;
+    message.cache_key = read_optional_str(result.cache_key)?;
+    message.method_call = result.method_call != 0;
+    message.builtin_override = result.builtin_override != 0;
+    if let Some(json) = read_optional_str(result.pending_call_ids_json)? {
+        message.pending_call_ids = serde_json::from_str(&json)?;
+    }
+    if let Some(json) = read_optional_str(result.pending_calls_json)? {
+        message.pending_calls = serde_json::from_str(&json)?;
+    }
+    if result.timeout_ms > 0 {
+        message.timeout_ms = Some(result.timeout_ms);
+    }
+    if result.priority != 0 {
+        message.priority = Some(result.priority);
+    }
+    message.input_name = read_optional_str(result.input_name)?;
+    if let Some(json) = read_optional_str(result.receiver_json)? {
+        message.receiver = Some(serde_json::from_str(&json)?);
+    }
+    if let Some(json) = read_optional_str(result.stats_json)? {
+        message.stats = Some(serde_json::from_str(&json)?);
+    }
+    if let Some(json) = read_optional_str(result.trace_json)? {
+        message.trace = Some(serde_json::from_str(&json)?);
+    }
+    if let Some(json) = read_optional_str(result.audit_json)? {
+        message.audit = Some(serde_json::from_str(&json)?);
+    }
+    if let Some(json) = read_optional_str(result.warnings_json)? {
+        message.warnings = serde_json::from_str(&json)?;
+    }
+    if !result.output.is_null() {
+        let output = std::slice::from_raw_parts(result.output, result.output_len);
+        message.output = Some(String::from_utf8_lossy(output).into_owned());
+    }
+    Ok(message)
+}
+
+/// Requests the next lazy input, or starts the run once every input is available.
+unsafe fn advance_start(out: *mut ProgressResult, pending: PendingStart) -> FfiResult<()> {
+    if let Some(name) = pending.next_request() {
+        let result = out.as_mut().ok_or(FfiError::NullPointer("out"))?;
+        *result = ProgressResult::default();
+        result.kind = MONTY_PROGRESS_INPUT_REQUEST;
+        result.input_name = to_c_string(name.to_owned(), "input_name")?;
+        result.input_request = InputRequestHandle::new(pending);
+        return Ok(());
+    }
+    let (runner, inputs, mut context) = pending.into_start();
+    let progress = context.execute(|print| runner.start(inputs, FfiTracker, print))?;
+    write_progress_result(out, progress, context)
+}
+
+/// Hands a call's arguments to the host as JSON or, with the `object_handles` option,
+/// as a list handle and a dict handle.
+fn write_call_args(
+    result: &mut ProgressResult,
+    args: Vec<MontyObject>,
+    kwargs: Vec<(MontyObject, MontyObject)>,
+    object_handles: bool,
+) -> FfiResult<()> {
+    if object_handles {
+        result.args_object = MontyObjectHandle::new(MontyObject::List(args));
+        result.kwargs_object = MontyObjectHandle::new(MontyObject::Dict(kwargs.into()));
+    } else {
+        let args = encode_objects(&args)?;
+        let kwargs = encode_kwargs(&kwargs)?;
+        result.args_len = args.len();
+        result.kwargs_len = kwargs.len();
+        result.args_json = to_c_string(args, "args_json")?;
+        result.kwargs_json = to_c_string(kwargs, "kwargs_json")?;
+    }
+    Ok(())
+}
+
+/// Sets `result_json` and its length to the encoded `value`.
+fn write_result_json(result: &mut ProgressResult, value: &MontyObject) -> FfiResult<()> {
+    let json = encode_object(value)?;
+    result.result_len = json.len();
+    result.result_json = to_c_string(json, "result_json")?;
+    Ok(())
+}
+
+/// Reports a future snapshot that is still gathering results, listing the pending
+/// calls without one.
+unsafe fn write_gathering(
+    out: *mut ProgressResult,
+    snapshot: FutureSnapshot<FfiTracker>,
+    context: RunContext,
+) -> FfiResult<()> {
+    let result = out.as_mut().ok_or(FfiError::NullPointer("out"))?;
+    *result = ProgressResult::default();
+    result.kind = MONTY_PROGRESS_RESOLVE_FUTURES;
+    let awaiting = context.by_priority(&context.awaiting_futures(snapshot.pending_call_ids()));
+    result.pending_call_ids_json = to_c_string(encode_u32_slice(&awaiting)?, "pending_call_ids")?;
+    result.future_snapshot = FutureSnapshotHandle::new(snapshot, context);
+    Ok(())
 }
 
 unsafe fn write_progress_result(
     out: *mut ProgressResult,
-    progress: RunProgress<NoLimitTracker>,
+    progress: RunProgress<FfiTracker>,
+    mut context: RunContext,
+) -> FfiResult<()> {
+    let step = dispatch::step(progress, &mut context)?;
+    write_step(out, step, context)
+}
+
+/// Reports `step` to the host, handing it the run's context in the step's snapshot.
+unsafe fn write_step(
+    out: *mut ProgressResult,
+    step: Step,
+    mut context: RunContext,
 ) -> FfiResult<()> {
     let result = out.as_mut().ok_or(FfiError::NullPointer("out"))?;
     *result = ProgressResult::default();
-    match progress {
-        RunProgress::Complete(value) => {
+    let _restoring = json::restoring_exception_data(context.exception_data());
+    let warnings = context.take_warnings();
+    if !warnings.is_empty() {
+        result.warnings_json = to_c_string(serde_json::to_string(&warnings)?, "warnings_json")?;
+    }
+    let output = context.take_output();
+    if !output.is_empty() {
+        write_bytes(output, &mut result.output, &mut result.output_len)?;
+    }
+    match step {
+        Step::Complete(value) => {
             result.kind = MONTY_PROGRESS_COMPLETE;
-            let json = encode_object(&value)?;
-            result.result_json = to_c_string(json, "result_json")?;
+            if context.object_handles {
+                result.result_object = MontyObjectHandle::new(value);
+            } else {
+                write_result_json(result, &value)?;
+            }
+            let stats = serde_json::to_string(&context.stats)?;
+            result.stats_json = to_c_string(stats, "stats_json")?;
+            if let Some(trace) = context.trace_json() {
+                result.trace_json = to_c_string(serde_json::to_string(&trace)?, "trace_json")?;
+            }
+            if let Some(audit) = context.audit_log() {
+                result.audit_json = to_c_string(serde_json::to_string(audit)?, "audit_json")?;
+            }
         }
-        RunProgress::FunctionCall {
-            function_name,
+        Step::Emit {
+            call_id,
+            mut args,
+            state,
+            ..
+        } => {
+            result.kind = MONTY_PROGRESS_EMIT;
+            result.call_id = call_id;
+            let value = args.pop().unwrap_or(MontyObject::None);
+            if context.object_handles {
+                result.result_object = MontyObjectHandle::new(value);
+            } else {
+                write_result_json(result, &value)?;
+            }
+            result.snapshot = SnapshotHandle::new(state, context);
+        }
+        Step::Sleep {
+            call_id,
+            delay_ms,
+            state,
+        } => {
+            result.kind = MONTY_PROGRESS_SLEEP;
+            result.call_id = call_id;
+            result.delay_ms = delay_ms;
+            result.snapshot = SnapshotHandle::new(state, context);
+        }
+        Step::Prompt {
+            call_id,
+            prompt,
             args,
             kwargs,
+            state,
+        } => {
+            result.kind = MONTY_PROGRESS_PROMPT;
+            result.call_id = call_id;
+            result.prompt = to_c_string(prompt, "prompt")?;
+            result.function_name = to_c_string(INPUT_FUNCTION.to_owned(), "function_name")?;
+            write_call_args(result, args, kwargs, context.object_handles)?;
+            result.snapshot = SnapshotHandle::new(state, context);
+        }
+        Step::Call(Call {
             call_id,
+            function_name,
+            os_function: None,
+            args,
+            kwargs,
             method_call,
             state,
-        } => {
+        }) => {
             result.kind = MONTY_PROGRESS_FUNCTION_CALL;
+            result.timeout_ms = context.call_timeouts.for_call(&function_name).unwrap_or(0);
+            result.priority = context.call_priorities.for_call(&function_name);
+            result.builtin_override = context.is_builtin_override(&function_name) as i32;
             result.function_name = to_c_string(function_name, "function_name")?;
-            result.args_json = to_c_string(encode_objects(&args)?, "args_json")?;
-            result.kwargs_json = to_c_string(encode_kwargs(&kwargs)?, "kwargs_json")?;
             result.call_id = call_id;
             result.method_call = method_call as i32;
-            result.snapshot = SnapshotHandle::new(state);
+            if let Some(receiver) = args.first().filter(|_| method_call) {
+                result.receiver_json = to_c_string(encode_object(receiver)?, "receiver_json")?;
+            }
+            if let Some(key) = context.idempotency_key(call_id, &kwargs) {
+                result.idempotency_key = to_c_string(key, "idempotency_key")?;
+            }
+            if let Some(key) = context.cache_key(call_id) {
+                result.cache_key = to_c_string(key.to_owned(), "cache_key")?;
+            }
+            write_call_args(result, args, kwargs, context.object_handles)?;
+            result.snapshot = SnapshotHandle::new(state, context);
         }
-        RunProgress::OsCall {
-            function,
+        Step::Call(Call {
+            call_id,
+            os_function: Some(function),
             args,
             kwargs,
-            call_id,
             state,
-        } => {
+            ..
+        }) => {
             result.kind = MONTY_PROGRESS_OS_CALL;
-            result.os_function = to_c_string(function.to_string(), "os_function")?;
-            result.args_json = to_c_string(encode_objects(&args)?, "args_json")?;
-            result.kwargs_json = to_c_string(encode_kwargs(&kwargs)?, "kwargs_json")?;
+            result.timeout_ms = context.call_timeouts.for_call(&function).unwrap_or(0);
+            result.priority = context.call_priorities.for_call(&function);
+            result.os_function = to_c_string(function, "os_function")?;
+            write_call_args(result, args, kwargs, context.object_handles)?;
             result.call_id = call_id;
-            result.snapshot = SnapshotHandle::new(state);
+            result.snapshot = SnapshotHandle::new(state, context);
         }
-        RunProgress::ResolveFutures(state) => {
+        Step::ResolveFutures(state) => {
             result.kind = MONTY_PROGRESS_RESOLVE_FUTURES;
             result.pending_call_ids_json = to_c_string(
-                encode_u32_slice(state.pending_call_ids())?,
+                encode_u32_slice(&context.by_priority(state.pending_call_ids()))?,
                 "pending_call_ids",
             )?;
-            result.future_snapshot = FutureSnapshotHandle::new(state);
+            result.future_snapshot = FutureSnapshotHandle::new(state, context);
         }
     }
     Ok(())
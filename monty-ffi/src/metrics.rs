@@ -0,0 +1,73 @@
+//! Process-wide counters for operators, reported by `monty_metrics_json`.
+//!
+//! Per-run counters live in each run's `RunStats` and travel with its snapshots;
+//! these aggregate over every run in the process since it started and are not
+//! persisted anywhere.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use serde::Serialize;
+
+static RUNS_STARTED: AtomicU64 = AtomicU64::new(0);
+static RUNS_COMPLETED: AtomicU64 = AtomicU64::new(0);
+static ACTIVE_SNAPSHOTS: AtomicU64 = AtomicU64::new(0);
+static ACTIVE_FUTURE_SNAPSHOTS: AtomicU64 = AtomicU64::new(0);
+static INSTRUCTIONS: AtomicU64 = AtomicU64::new(0);
+static BYTES_SERIALIZED: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Metrics {
+    pub runs_started: u64,
+    pub runs_completed: u64,
+    /// Snapshot handles currently held by the host.
+    pub active_snapshots: u64,
+    pub active_future_snapshots: u64,
+    /// VM steps executed by every run, as counted in `RunStats::instructions`.
+    pub instructions_executed: u64,
+    /// Size of every snapshot and future snapshot dump produced.
+    pub bytes_serialized: u64,
+}
+
+pub fn current() -> Metrics {
+    Metrics {
+        runs_started: RUNS_STARTED.load(Ordering::Relaxed),
+        runs_completed: RUNS_COMPLETED.load(Ordering::Relaxed),
+        active_snapshots: ACTIVE_SNAPSHOTS.load(Ordering::Relaxed),
+        active_future_snapshots: ACTIVE_FUTURE_SNAPSHOTS.load(Ordering::Relaxed),
+        instructions_executed: INSTRUCTIONS.load(Ordering::Relaxed),
+        bytes_serialized: BYTES_SERIALIZED.load(Ordering::Relaxed),
+    }
+}
+
+pub fn run_started() {
+    RUNS_STARTED.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn run_completed() {
+    RUNS_COMPLETED.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Tracks a snapshot handle being handed out (`held`) or freed or consumed.
+pub fn snapshot_held(held: bool) {
+    adjust(&ACTIVE_SNAPSHOTS, held);
+}
+
+pub fn future_snapshot_held(held: bool) {
+    adjust(&ACTIVE_FUTURE_SNAPSHOTS, held);
+}
+
+pub fn instructions(count: u64) {
+    INSTRUCTIONS.fetch_add(count, Ordering::Relaxed);
+}
+
+pub fn serialized(bytes: usize) {
+    BYTES_SERIALIZED.fetch_add(bytes as u64, Ordering::Relaxed);
+}
+
+fn adjust(gauge: &AtomicU64, up: bool) {
+    if up {
+        gauge.fetch_add(1, Ordering::Relaxed);
+    } else {
+        gauge.fetch_sub(1, Ordering::Relaxed);
+    }
+}
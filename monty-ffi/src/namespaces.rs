@@ -0,0 +1,93 @@
+//! Dotted external function names such as `db.query`.
+//!
+//! Monty only calls bare names, so each top-level namespace is bound as an input
+//! holding a frozen marker dataclass (nested namespaces become its attributes).
+//! Calling `db.query(x)` on it is a method call, which Monty reports as an external
+//! call of `query` with the marker as `self`; `resolve` turns that back into the
+//! function `db.query` called with `x`.
+
+use monty::{DictPairs, MontyObject};
+
+/// Type id of namespace marker objects, distinguishing them from host dataclasses.
+const NAMESPACE_TYPE_ID: u64 = u64::MAX;
+
+/// Splits registered external functions into the bare names Monty resolves and the
+/// top-level namespace objects to bind, by name, for the dotted ones.
+pub fn split(ext_funcs: Vec<String>) -> (Vec<String>, Vec<(String, MontyObject)>) {
+    let (dotted, bare): (Vec<_>, Vec<_>) = ext_funcs.into_iter().partition(|f| f.contains('.'));
+    let mut roots: Vec<String> = Vec::new();
+    for name in &dotted {
+        let root = name.split('.').next().unwrap_or_default().to_owned();
+        if !roots.contains(&root) {
+            roots.push(root);
+        }
+    }
+    let namespaces = roots
+        .into_iter()
+        .map(|root| {
+            let object = namespace(&root, &dotted);
+            (root, object)
+        })
+        .collect();
+    (bare, namespaces)
+}
+
+/// Builds the marker for `path`, with a nested marker for every deeper namespace.
+fn namespace(path: &str, dotted: &[String]) -> MontyObject {
+    let prefix = format!("{path}.");
+    let mut children: Vec<String> = Vec::new();
+    for name in dotted {
+        if let Some(rest) = name.strip_prefix(&prefix) {
+            if let Some((child, _)) = rest.split_once('.') {
+                if !children.iter().any(|c| c == child) {
+                    children.push(child.to_owned());
+                }
+            }
+        }
+    }
+    let attrs: Vec<_> = children
+        .iter()
+        .map(|child| {
+            (
+                MontyObject::String(child.clone()),
+                namespace(&format!("{path}.{child}"), dotted),
+            )
+        })
+        .collect();
+    MontyObject::Dataclass {
+        name: path.to_owned(),
+        type_id: NAMESPACE_TYPE_ID,
+        field_names: children,
+        attrs: DictPairs::from(attrs),
+        frozen: true,
+    }
+}
+
+/// The dotted function a method call on a namespace marker names, or `None` for any
+/// other call.
+pub fn dotted_name(function_name: &str, args: &[MontyObject], method_call: bool) -> Option<String> {
+    match args.first() {
+        Some(MontyObject::Dataclass {
+            name,
+            type_id: NAMESPACE_TYPE_ID,
+            ..
+        }) if method_call => Some(format!("{name}.{function_name}")),
+        _ => None,
+    }
+}
+
+/// Rewrites a method call on a namespace marker into the dotted function it names;
+/// any other call is returned unchanged.
+pub fn resolve(
+    function_name: String,
+    mut args: Vec<MontyObject>,
+    method_call: bool,
+) -> (String, Vec<MontyObject>, bool) {
+    match dotted_name(&function_name, &args, method_call) {
+        Some(function_name) => {
+            args.remove(0);
+            (function_name, args, false)
+        }
+        None => (function_name, args, method_call),
+    }
+}
@@ -0,0 +1,129 @@
+//! Inspection of values held by object handles, following Python semantics where
+//! the host would expect them (`len`, `obj[key]`, `getattr`, iteration).
+
+use monty::MontyObject;
+
+use crate::error::{FfiError, FfiResult};
+
+/// The Python class name of a value.
+pub fn type_name(value: &MontyObject) -> &str {
+    match value {
+        MontyObject::Ellipsis => "ellipsis",
+        MontyObject::None => "NoneType",
+        MontyObject::Bool(_) => "bool",
+        MontyObject::Int(_) | MontyObject::BigInt(_) => "int",
+        MontyObject::Float(_) => "float",
+        MontyObject::String(_) => "str",
+        MontyObject::Bytes(_) => "bytes",
+        MontyObject::List(_) => "list",
+        MontyObject::Tuple(_) => "tuple",
+        MontyObject::NamedTuple { type_name, .. } => type_name,
+        MontyObject::Dict(_) => "dict",
+        MontyObject::Set(_) => "set",
+        MontyObject::FrozenSet(_) => "frozenset",
+        MontyObject::Exception { .. } => "BaseException",
+        MontyObject::Type(_) => "type",
+        MontyObject::BuiltinFunction(_) => "builtin_function_or_method",
+        MontyObject::Path(_) => "PosixPath",
+        MontyObject::Dataclass { name, .. } => name,
+        MontyObject::Repr(_) | MontyObject::Cycle(..) => "object",
+    }
+}
+
+/// Number of items in a container, or of bytes in a `str` or `bytes` value.
+pub fn len(value: &MontyObject) -> Option<usize> {
+    match value {
+        MontyObject::String(text) => Some(text.len()),
+        MontyObject::Bytes(bytes) => Some(bytes.len()),
+        MontyObject::List(items)
+        | MontyObject::Tuple(items)
+        | MontyObject::Set(items)
+        | MontyObject::FrozenSet(items)
+        | MontyObject::NamedTuple { values: items, .. } => Some(items.len()),
+        MontyObject::Dict(pairs) => Some(pairs.len()),
+        _ => None,
+    }
+}
+
+/// `value[key]` for sequences (int keys, negative from the end) and dicts.
+pub fn get_item<'a>(value: &'a MontyObject, key: &MontyObject) -> FfiResult<&'a MontyObject> {
+    match value {
+        MontyObject::List(items)
+        | MontyObject::Tuple(items)
+        | MontyObject::NamedTuple { values: items, .. } => {
+            let index = match key {
+                MontyObject::Int(index) => *index,
+                MontyObject::Bool(index) => *index as i64,
+                _ => {
+                    return Err(FfiError::Message(format!(
+                        "{} indices must be integers, not {}",
+                        type_name(value),
+                        type_name(key)
+                    )))
+                }
+            };
+            let resolved = if index < 0 {
+                index + items.len() as i64
+            } else {
+                index
+            };
+            usize::try_from(resolved)
+                .ok()
+                .and_then(|index| items.get(index))
+                .ok_or_else(|| {
+                    FfiError::Message(format!("{} index out of range", type_name(value)))
+                })
+        }
+        MontyObject::Dict(pairs) => pairs
+            .into_iter()
+            .find(|(candidate, _)| candidate == key)
+            .map(|(_, value)| value)
+            .ok_or_else(|| FfiError::Message("key not found in dict".to_owned())),
+        _ => Err(FfiError::Message(format!(
+            "'{}' object is not subscriptable",
+            type_name(value)
+        ))),
+    }
+}
+
+/// Attribute `name` of a dataclass or named tuple.
+pub fn get_attr<'a>(value: &'a MontyObject, name: &str) -> FfiResult<&'a MontyObject> {
+    let found = match value {
+        MontyObject::Dataclass { attrs, .. } => attrs
+            .into_iter()
+            .find(|(key, _)| matches!(key, MontyObject::String(key) if key == name))
+            .map(|(_, value)| value),
+        MontyObject::NamedTuple {
+            field_names,
+            values,
+            ..
+        } => field_names
+            .iter()
+            .position(|field| field == name)
+            .and_then(|index| values.get(index)),
+        _ => None,
+    };
+    found.ok_or_else(|| {
+        FfiError::Message(format!(
+            "'{}' object has no attribute '{name}'",
+            type_name(value)
+        ))
+    })
+}
+
+/// Item `index` of iterating `value`: sequence and set items, or dict keys. `None`
+/// once exhausted.
+pub fn iter_item(value: &MontyObject, index: usize) -> FfiResult<Option<&MontyObject>> {
+    match value {
+        MontyObject::List(items)
+        | MontyObject::Tuple(items)
+        | MontyObject::Set(items)
+        | MontyObject::FrozenSet(items)
+        | MontyObject::NamedTuple { values: items, .. } => Ok(items.get(index)),
+        MontyObject::Dict(pairs) => Ok(pairs.into_iter().nth(index).map(|(key, _)| key)),
+        _ => Err(FfiError::Message(format!(
+            "'{}' object is not iterable",
+            type_name(value)
+        ))),
+    }
+}
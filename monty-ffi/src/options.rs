@@ -0,0 +1,162 @@
+use std::collections::HashMap;
+
+use monty::MontyException;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::{
+    dataclasses::DataclassSchema,
+    error::FfiResult,
+    intern,
+    policy::Policy,
+    tracker::{CostModel, ResourceLimits},
+};
+
+/// Per-run configuration accepted as `options_json` when starting a run.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RunOptions {
+    pub limits: ResourceLimits,
+    pub call_timeouts: CallTimeouts,
+    /// Module-level variables seeded for this run only, as tagged values by name.
+    pub globals: Map<String, Value>,
+    pub return_mismatch: ReturnMismatch,
+    /// Dataclasses that results of calls declared to return them may be built into.
+    pub dataclasses: Vec<DataclassSchema>,
+    /// Report results and call arguments as object handles instead of JSON.
+    pub object_handles: bool,
+    /// Record a Chrome trace of VM steps and external calls.
+    pub trace: bool,
+    /// Host-chosen identifier for the run, kept in snapshot dumps for telemetry.
+    pub run_id: String,
+    /// Keep an audit log of the OS calls the script attempts.
+    pub audit_os_calls: bool,
+    /// External and OS functions the script may call.
+    pub policy: Policy,
+    /// Make every OS call raise `OSError` in the script instead of reaching the host.
+    pub pure: bool,
+    /// Weights of the `cost` counter that `limits.max_cost` applies to.
+    pub cost_model: CostModel,
+    /// What happens to output past `limits.max_print_bytes`.
+    pub print_overflow: PrintOverflow,
+    /// How an uncaught exception is written to the failing call's error.
+    pub error_format: ErrorFormat,
+    /// Write output straight to the process stdout, where concurrent runs interleave,
+    /// instead of collecting it for each run's progress results.
+    pub print_to_stdout: bool,
+    /// When a future snapshot's resume lets the script continue.
+    pub future_resolution: ResolutionMode,
+    pub call_priorities: CallPriorities,
+    /// External functions whose results answer later calls with the same arguments in
+    /// the same run, without reporting them to the host.
+    pub cache_calls: Vec<String>,
+    /// External and OS functions the script awaits, which `monty_snapshot_resume_batch`
+    /// defers as futures when it has no result for them.
+    pub awaitable: Vec<String>,
+    /// Decode `inputs_json` and the JSON payloads the run is resumed with using U+FFFD
+    /// replacement characters where they are not valid UTF-8, instead of failing.
+    pub lossy_utf8: bool,
+}
+
+/// What happens when the host resumes a call with a value that does not match the
+/// function's declared return type.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReturnMismatch {
+    /// Raise `TypeError` at the call site in the script.
+    #[default]
+    Raise,
+    /// Fail the resume with a `MONTY_ERROR_RETURN_MISMATCH` error, leaving the snapshot
+    /// untouched so it can be resumed again.
+    Fail,
+}
+
+/// What happens when a script prints more than `max_print_bytes`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PrintOverflow {
+    /// Print up to the limit, then a truncation marker, and drop the rest.
+    #[default]
+    Truncate,
+    /// Raise `RuntimeError` from the `print` call that crosses the limit.
+    Raise,
+}
+
+/// When results passed to `monty_future_snapshot_resume` reach the script.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ResolutionMode {
+    /// Resume the script with whatever results each call provides, so it sees every
+    /// future as soon as the host does.
+    #[default]
+    AsCompleted,
+    /// Hold results back until every pending future has one, then resume the script
+    /// once with all of them.
+    Gather,
+}
+
+/// How an exception that ends a run is written to `MontyStatus.error`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorFormat {
+    /// One line: `Type: message`.
+    #[default]
+    Summary,
+    /// The full multi-line traceback, as Python prints it.
+    Traceback,
+}
+
+impl ErrorFormat {
+    pub fn format(self, exc: &MontyException) -> String {
+        match self {
+            Self::Summary => exc.summary(),
+            Self::Traceback => exc.to_string(),
+        }
+    }
+}
+
+/// How long the host may take to resolve an external or OS call before it should
+/// resume it with a timeout error. Reported on each call; Monty does not enforce it.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CallTimeouts {
+    /// Applies to calls without an entry in `functions`.
+    pub default_ms: Option<u64>,
+    /// Keyed by external function name or OS function name (e.g. `Path.read_text`).
+    #[serde(serialize_with = "intern::sorted")]
+    pub functions: HashMap<String, u64>,
+}
+
+impl CallTimeouts {
+    pub fn for_call(&self, name: &str) -> Option<u64> {
+        self.functions.get(name).copied().or(self.default_ms)
+    }
+}
+
+/// Scheduling hints for external and OS calls, reported on each call and used to order
+/// the pending calls of a future snapshot, so hosts dispatching to worker pools can run
+/// important calls first. Higher runs sooner; Monty does not act on it.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CallPriorities {
+    /// Applies to calls without an entry in `functions`.
+    pub default: i32,
+    /// Keyed by external function name or OS function name (e.g. `Path.read_text`).
+    #[serde(serialize_with = "intern::sorted")]
+    pub functions: HashMap<String, i32>,
+}
+
+impl CallPriorities {
+    pub fn for_call(&self, name: &str) -> i32 {
+        self.functions.get(name).copied().unwrap_or(self.default)
+    }
+}
+
+impl RunOptions {
+    pub fn from_json(json: Option<&str>) -> FfiResult<Self> {
+        match json.map(str::trim) {
+            None | Some("") => Ok(Self::default()),
+            Some(json) => Ok(serde_json::from_str(json)?),
+        }
+    }
+}
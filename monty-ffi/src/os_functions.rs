@@ -0,0 +1,148 @@
+//! Machine-readable shapes of the OS functions Monty reports as `OsCall` progress, so
+//! hosts can validate and dispatch them generically instead of hard-coding each one.
+//!
+//! Monty does not describe its OS functions itself, so this table follows the
+//! `OsFunction` variants of the pinned Monty revision and must be updated with it.
+//! Arguments arrive in the tagged JSON form; the first argument of a `Path` method is
+//! the path it was called on.
+
+use serde::Serialize;
+
+/// Expected arguments and result of one OS function.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct OsFunctionSchema {
+    pub name: &'static str,
+    /// What the function reaches: `filesystem` or `environment`.
+    pub category: &'static str,
+    pub args: &'static [Arg],
+    pub kwargs: &'static [Arg],
+    /// Python type of the value the call should be resumed with.
+    pub returns: &'static str,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Arg {
+    pub name: &'static str,
+    /// Python type annotation, e.g. `str | None`.
+    #[serde(rename = "type")]
+    pub type_name: &'static str,
+    pub required: bool,
+}
+
+const fn arg(name: &'static str, type_name: &'static str) -> Arg {
+    Arg {
+        name,
+        type_name,
+        required: true,
+    }
+}
+
+const fn optional(name: &'static str, type_name: &'static str) -> Arg {
+    Arg {
+        name,
+        type_name,
+        required: false,
+    }
+}
+
+const FILESYSTEM: &str = "filesystem";
+const ENVIRONMENT: &str = "environment";
+
+const PATH: Arg = arg("self", "Path");
+
+const fn path_method(name: &'static str, returns: &'static str) -> OsFunctionSchema {
+    OsFunctionSchema {
+        name,
+        category: FILESYSTEM,
+        args: &[PATH],
+        kwargs: &[],
+        returns,
+    }
+}
+
+static SCHEMAS: &[OsFunctionSchema] = &[
+    path_method("Path.exists", "bool"),
+    path_method("Path.is_file", "bool"),
+    path_method("Path.is_dir", "bool"),
+    path_method("Path.is_symlink", "bool"),
+    path_method("Path.read_text", "str"),
+    path_method("Path.read_bytes", "bytes"),
+    OsFunctionSchema {
+        name: "Path.write_text",
+        category: FILESYSTEM,
+        args: &[PATH, arg("data", "str")],
+        kwargs: &[],
+        returns: "int",
+    },
+    OsFunctionSchema {
+        name: "Path.write_bytes",
+        category: FILESYSTEM,
+        args: &[PATH, arg("data", "bytes")],
+        kwargs: &[],
+        returns: "int",
+    },
+    OsFunctionSchema {
+        name: "Path.mkdir",
+        category: FILESYSTEM,
+        args: &[PATH],
+        kwargs: &[optional("parents", "bool"), optional("exist_ok", "bool")],
+        returns: "None",
+    },
+    OsFunctionSchema {
+        name: "Path.unlink",
+        category: FILESYSTEM,
+        args: &[PATH],
+        kwargs: &[optional("missing_ok", "bool")],
+        returns: "None",
+    },
+    path_method("Path.rmdir", "None"),
+    path_method("Path.iterdir", "list[Path]"),
+    path_method("Path.stat", "stat_result"),
+    OsFunctionSchema {
+        name: "Path.rename",
+        category: FILESYSTEM,
+        args: &[PATH, arg("target", "Path | str")],
+        kwargs: &[],
+        returns: "Path",
+    },
+    path_method("Path.resolve", "Path"),
+    path_method("Path.absolute", "Path"),
+    OsFunctionSchema {
+        name: "os.getenv",
+        category: ENVIRONMENT,
+        args: &[arg("key", "str"), optional("default", "str | None")],
+        kwargs: &[],
+        returns: "str | None",
+    },
+    OsFunctionSchema {
+        name: "os.environ",
+        category: ENVIRONMENT,
+        args: &[],
+        kwargs: &[],
+        returns: "dict[str, str]",
+    },
+];
+
+/// The schema of the OS function called `name` (e.g. `Path.read_text`), if Monty has
+/// one by that name.
+pub fn schema(name: &str) -> Option<&'static OsFunctionSchema> {
+    SCHEMAS.iter().find(|schema| schema.name == name)
+}
+
+/// Name and category of one OS function, as listed by `monty_list_os_functions`.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Listed {
+    pub name: &'static str,
+    pub category: &'static str,
+}
+
+/// Every OS function Monty can report, in a stable order.
+pub fn list() -> Vec<Listed> {
+    SCHEMAS
+        .iter()
+        .map(|schema| Listed {
+            name: schema.name,
+            category: schema.category,
+        })
+        .collect()
+}
@@ -0,0 +1,101 @@
+//! Per-run allow/deny lists for external and OS functions.
+//!
+//! A call the policy does not permit never reaches the host: it raises
+//! `PermissionError` at the call site, or fails the run, per `on_violation`. The
+//! policy is part of the run's context, so it still applies after a reload.
+//!
+//! Runs started with the `pure` option go further: every OS call raises `OSError`
+//! without reaching the host, whatever the policy says.
+
+use monty::{ExcType, MontyException};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::{FfiError, FfiResult},
+    json::parse_exc_type,
+};
+
+/// Exception class OS calls raise in pure mode, distinct from a policy's
+/// `PermissionError` so scripts and hosts can tell the two apart.
+const PURE_EXC_TYPE: &str = "OSError";
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Policy {
+    /// External functions, by (dotted) name as declared.
+    pub functions: Rule,
+    /// OS functions, by name such as `Path.read_text`.
+    pub os_functions: Rule,
+    pub on_violation: Violation,
+}
+
+/// Names a call may use. Entries match exactly, or by prefix when they end in `*`
+/// (`Path.*`). A name is permitted when no `deny` entry matches and either there is
+/// no `allow` list or one of its entries matches.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Rule {
+    pub allow: Option<Vec<String>>,
+    pub deny: Vec<String>,
+}
+
+/// What happens when a script makes a call its policy does not permit.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Violation {
+    /// Raise `PermissionError` at the call site in the script.
+    #[default]
+    Raise,
+    /// Fail the run with an FFI error.
+    Fail,
+}
+
+impl Rule {
+    pub fn permits(&self, name: &str) -> bool {
+        let matches = |pattern: &String| match pattern.strip_suffix('*') {
+            Some(prefix) => name.starts_with(prefix),
+            None => pattern == name,
+        };
+        !self.deny.iter().any(matches)
+            && self
+                .allow
+                .as_ref()
+                .is_none_or(|allow| allow.iter().any(matches))
+    }
+}
+
+impl Policy {
+    /// The exception to raise for a call to external function `name`, if it is not
+    /// permitted; an error when the policy fails the run instead.
+    pub fn check_function(&self, name: &str) -> FfiResult<Option<MontyException>> {
+        self.enforce(&self.functions, name)
+    }
+
+    pub fn check_os_function(&self, name: &str) -> FfiResult<Option<MontyException>> {
+        self.enforce(&self.os_functions, name)
+    }
+
+    fn enforce(&self, rule: &Rule, name: &str) -> FfiResult<Option<MontyException>> {
+        if rule.permits(name) {
+            return Ok(None);
+        }
+        let message = format!("{name}() is not permitted by the run's policy");
+        match self.on_violation {
+            Violation::Raise => Ok(Some(MontyException::new(
+                ExcType::PermissionError,
+                Some(message),
+            ))),
+            Violation::Fail => Err(FfiError::Message(message)),
+        }
+    }
+}
+
+/// The exception an OS call raises in a run started with the `pure` option.
+pub fn os_call_disabled(function: &str) -> FfiResult<MontyException> {
+    Ok(MontyException::new(
+        parse_exc_type(PURE_EXC_TYPE)?,
+        Some(format!(
+            "{function}() is unavailable: OS calls are disabled in pure mode"
+        )),
+    ))
+}
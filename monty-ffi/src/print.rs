@@ -0,0 +1,133 @@
+use std::{
+    borrow::Cow,
+    cell::Cell,
+    ffi::c_void,
+    io::{self, Write},
+};
+
+use monty::{ExcType, MontyException, PrintWriterCallback};
+
+use crate::{options::PrintOverflow, reentry, tracker, MontyWriteCallback};
+
+/// Appended once when `PrintOverflow::Truncate` starts dropping output.
+const TRUNCATED_MARKER: &str = "\n[output truncated]\n";
+
+type Sink = (
+    unsafe extern "C" fn(userdata: *mut c_void, data: *const u8, len: usize) -> i32,
+    *mut c_void,
+);
+
+thread_local! {
+    /// Host callback receiving output instead of stdout while `redirect` runs.
+    static SINK: Cell<Option<Sink>> = const { Cell::new(None) };
+}
+
+/// Sends script output on this thread to `callback` while `f` runs, in place of the
+/// run's own buffer or stdout; those are used again afterwards, and whenever
+/// `callback` is NULL.
+pub fn redirect<R>(
+    callback: MontyWriteCallback,
+    userdata: *mut c_void,
+    f: impl FnOnce() -> R,
+) -> R {
+    let previous = SINK.with(|sink| sink.replace(callback.map(|callback| (callback, userdata))));
+    let result = f();
+    SINK.with(|sink| sink.set(previous));
+    result
+}
+
+/// Collects script output in a buffer of its own, or forwards it to the process
+/// stdout or the `redirect` callback, counting printed bytes and applying the run's
+/// `max_print_bytes` limit.
+#[derive(Debug, Default)]
+pub struct Printer {
+    limit: Option<u64>,
+    overflow: PrintOverflow,
+    /// Set once output has been dropped under `PrintOverflow::Truncate`.
+    truncated: bool,
+    /// Output collected for the run; `None` when it goes to stdout.
+    buffer: Option<Vec<u8>>,
+}
+
+impl Printer {
+    pub fn new(limit: Option<u64>, overflow: PrintOverflow, stdout: bool) -> Self {
+        Self {
+            limit,
+            overflow,
+            truncated: false,
+            buffer: (!stdout).then(Vec::new),
+        }
+    }
+
+    /// Whether this printer started dropping output.
+    pub fn truncated(&self) -> bool {
+        self.truncated
+    }
+
+    /// Output collected since the printer was created or last taken from.
+    pub fn take_output(&mut self) -> Vec<u8> {
+        self.buffer.as_mut().map(std::mem::take).unwrap_or_default()
+    }
+
+    fn output(&mut self, bytes: &[u8]) {
+        if let Some((callback, userdata)) = SINK.with(Cell::get) {
+            reentry::call_host(|| unsafe { callback(userdata, bytes.as_ptr(), bytes.len()) });
+            return;
+        }
+        match &mut self.buffer {
+            Some(buffer) => buffer.extend_from_slice(bytes),
+            None => {
+                let _ = io::stdout().write_all(bytes);
+            }
+        }
+    }
+
+    fn write(&mut self, output: &str) -> Result<(), MontyException> {
+        let mut printed = 0;
+        tracker::record(|stats| {
+            printed = stats.bytes_printed;
+            stats.bytes_printed += output.len() as u64;
+        });
+        let allowed = match self.limit {
+            Some(limit) if printed + output.len() as u64 > limit => limit.saturating_sub(printed),
+            _ => {
+                self.output(output.as_bytes());
+                return Ok(());
+            }
+        };
+        match self.overflow {
+            PrintOverflow::Raise => Err(MontyException::new(
+                ExcType::RuntimeError,
+                Some(format!(
+                    "print output limit of {} bytes exceeded",
+                    self.limit.unwrap_or_default()
+                )),
+            )),
+            PrintOverflow::Truncate => {
+                let mut end = allowed as usize;
+                while !output.is_char_boundary(end) {
+                    end -= 1;
+                }
+                self.output(output[..end].as_bytes());
+                // Only the write that crosses the limit starts at or below it, so
+                // the marker is written once.
+                if self.limit.is_some_and(|limit| printed <= limit) {
+                    self.output(TRUNCATED_MARKER.as_bytes());
+                    self.truncated = true;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl PrintWriterCallback for Printer {
+    fn stdout_write(&mut self, output: Cow<'_, str>) -> Result<(), MontyException> {
+        self.write(&output)
+    }
+
+    fn stdout_push(&mut self, end: char) -> Result<(), MontyException> {
+        let mut buf = [0; 4];
+        self.write(end.encode_utf8(&mut buf))
+    }
+}
@@ -0,0 +1,201 @@
+//! Versioned wire protocol for progress events and resume requests.
+//!
+//! Every frontend (the C ABI, CLIs, HTTP/gRPC servers, third-party hosts) exchanges
+//! the same JSON shapes defined here. Objects inside the messages use the tagged
+//! encoding from `json.rs`.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// Bumped whenever a message shape changes incompatibly.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProgressKind {
+    Complete,
+    FunctionCall,
+    OsCall,
+    ResolveFutures,
+    InputRequest,
+    /// The script called `host_emit`; `result` is the emitted value.
+    Emit,
+    /// The script called `host_sleep`; resume it once `delay_ms` has passed.
+    Sleep,
+    /// The script called `input`; resume it with the line read for `prompt`.
+    Prompt,
+}
+
+impl ProgressKind {
+    pub fn from_i32(kind: i32) -> Option<Self> {
+        match kind {
+            crate::MONTY_PROGRESS_COMPLETE => Some(Self::Complete),
+            crate::MONTY_PROGRESS_FUNCTION_CALL => Some(Self::FunctionCall),
+            crate::MONTY_PROGRESS_OS_CALL => Some(Self::OsCall),
+            crate::MONTY_PROGRESS_RESOLVE_FUTURES => Some(Self::ResolveFutures),
+            crate::MONTY_PROGRESS_INPUT_REQUEST => Some(Self::InputRequest),
+            crate::MONTY_PROGRESS_EMIT => Some(Self::Emit),
+            crate::MONTY_PROGRESS_SLEEP => Some(Self::Sleep),
+            crate::MONTY_PROGRESS_PROMPT => Some(Self::Prompt),
+            _ => None,
+        }
+    }
+}
+
+/// A single progress event reported to the host after start/resume.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProgressMessage {
+    pub version: u32,
+    pub kind: ProgressKind,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub function_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub os_function: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub args: Vec<Value>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub kwargs: Vec<(Value, Value)>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub call_id: Option<u32>,
+    #[serde(default)]
+    pub method_call: bool,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub pending_call_ids: Vec<u32>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub pending_calls: Vec<CallDescriptor>,
+    /// Deadline the host should give the reported call, when one is configured.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout_ms: Option<u64>,
+    /// Lazy input the host must provide before the run can start.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub input_name: Option<String>,
+    /// `self` of a method call; also still the first of `args`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub receiver: Option<Value>,
+    /// Run statistics, reported on completion.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stats: Option<Value>,
+    /// Chrome trace of a run started with `trace`, reported on completion.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trace: Option<Value>,
+    /// Audit log of attempted OS calls for a run started with `audit_os_calls`,
+    /// reported on completion.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub audit: Option<Value>,
+    /// Non-fatal warnings gathered since the last progress.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<Value>,
+    /// What the script printed since the last progress.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output: Option<String>,
+    /// How long a sleep asks the host to wait before resuming.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub delay_ms: Option<u64>,
+    /// Scheduling hint for the reported call; higher runs sooner.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub priority: Option<i32>,
+    /// The reported call is to a builtin the host overrides, such as `print`.
+    #[serde(default)]
+    pub builtin_override: bool,
+    /// Prompt shown for the line an `input` call reads.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prompt: Option<String>,
+    /// Key for deduping the reported call's side effects across retries.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub idempotency_key: Option<String>,
+    /// Key for keeping the reported call's result across runs; see `cache_calls`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache_key: Option<String>,
+}
+
+impl ProgressMessage {
+    pub fn new(kind: ProgressKind) -> Self {
+        Self {
+            version: PROTOCOL_VERSION,
+            kind,
+            result: None,
+            function_name: None,
+            os_function: None,
+            args: Vec::new(),
+            kwargs: Vec::new(),
+            call_id: None,
+            method_call: false,
+            pending_call_ids: Vec::new(),
+            pending_calls: Vec::new(),
+            timeout_ms: None,
+            input_name: None,
+            receiver: None,
+            stats: None,
+            trace: None,
+            audit: None,
+            warnings: Vec::new(),
+            output: None,
+            delay_ms: None,
+            priority: None,
+            builtin_override: false,
+            prompt: None,
+            idempotency_key: None,
+            cache_key: None,
+        }
+    }
+}
+
+/// An external or OS call that was deferred as a future instead of being reported on its own.
+/// Exactly one of `function_name` and `os_function` is set.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CallDescriptor {
+    pub call_id: u32,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub function_name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub os_function: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub args: Vec<Value>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub kwargs: Vec<(Value, Value)>,
+    #[serde(default)]
+    pub method_call: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub receiver: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout_ms: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub priority: Option<i32>,
+    #[serde(default)]
+    pub builtin_override: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub idempotency_key: Option<String>,
+}
+
+/// Resolution of a single suspended function or OS call.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ResumeRequest {
+    pub call_id: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<ResumeError>,
+    /// Exception class raised for a plain-string `error`; defaults to `RuntimeError`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error_type: Option<String>,
+    /// Marks a pending future as cancelled; the script's `await` raises `CancelledError`.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub cancelled: bool,
+    /// Marks a pending future the host gave up waiting for; the script's `await`
+    /// raises `TimeoutError`, so it can retry or fall back.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub timed_out: bool,
+}
+
+/// The error side of a resolution: a bare message or a full `$exception` object.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ResumeError {
+    Message(String),
+    Exception(Value),
+}
+
+/// Resolution of one pending future; a batch of these resumes a future snapshot.
+pub type FutureResolution = ResumeRequest;
@@ -0,0 +1,151 @@
+//! Quota groups: instruction and memory budgets shared by every run attached to them.
+//!
+//! A group caps the instructions its runs execute, optionally per time window (e.g.
+//! 10M per minute), and the memory they hold at once. Each VM step may use whatever
+//! the group has left when the step starts, and is charged when it returns, so runs
+//! executing on other threads at the same time can overshoot by up to one step each.
+//! Membership is not persisted: a snapshot loaded from a dump runs outside any group
+//! until one is attached again.
+
+use std::{
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+
+/// Budgets of a quota group; `None` means unlimited.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct QuotaLimits {
+    pub max_instructions: Option<u64>,
+    pub max_memory: Option<usize>,
+    /// Length of the window `max_instructions` applies to; the instruction count starts
+    /// over when a window ends. Without one the budget is for the group's lifetime.
+    pub window_ms: Option<u64>,
+}
+
+#[derive(Debug)]
+pub struct QuotaGroup {
+    limits: QuotaLimits,
+    usage: Mutex<Usage>,
+}
+
+#[derive(Debug)]
+struct Usage {
+    instructions: u64,
+    memory: usize,
+    window_started: Instant,
+}
+
+/// What the group's runs have used, reported by `monty_quota_group_usage`.
+#[derive(Debug, Clone, Serialize)]
+pub struct QuotaUsage {
+    /// Instructions executed in the current window.
+    pub instructions: u64,
+    /// Memory currently held by the group's unfinished runs.
+    pub memory: usize,
+    pub remaining_instructions: Option<u64>,
+    pub remaining_memory: Option<usize>,
+}
+
+/// What a single VM step may use from its run's group.
+#[derive(Debug, Clone, Copy)]
+pub struct Allowance {
+    pub instructions: Option<u64>,
+    /// Total memory the run may hold by the end of the step.
+    pub memory: Option<usize>,
+}
+
+impl QuotaGroup {
+    pub fn new(limits: QuotaLimits) -> Self {
+        Self {
+            limits,
+            usage: Mutex::new(Usage {
+                instructions: 0,
+                memory: 0,
+                window_started: Instant::now(),
+            }),
+        }
+    }
+
+    /// Locks the usage counters, starting a new window first when the current one ended.
+    fn usage(&self) -> MutexGuard<'_, Usage> {
+        let mut usage = self
+            .usage
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if let Some(window_ms) = self.limits.window_ms {
+            let window = Duration::from_millis(window_ms);
+            if usage.window_started.elapsed() >= window {
+                usage.instructions = 0;
+                usage.window_started = Instant::now();
+            }
+        }
+        usage
+    }
+
+    pub fn report(&self) -> QuotaUsage {
+        let usage = self.usage();
+        QuotaUsage {
+            instructions: usage.instructions,
+            memory: usage.memory,
+            remaining_instructions: self
+                .limits
+                .max_instructions
+                .map(|limit| limit.saturating_sub(usage.instructions)),
+            remaining_memory: self
+                .limits
+                .max_memory
+                .map(|limit| limit.saturating_sub(usage.memory)),
+        }
+    }
+}
+
+/// A run's place in a quota group, holding the run's share of the group's memory until
+/// the run finishes or is dropped.
+#[derive(Debug)]
+pub struct Membership {
+    group: Arc<QuotaGroup>,
+    memory: usize,
+}
+
+impl Membership {
+    pub fn new(group: Arc<QuotaGroup>) -> Self {
+        Self { group, memory: 0 }
+    }
+
+    pub fn allowance(&self) -> Allowance {
+        let report = self.group.report();
+        Allowance {
+            instructions: report.remaining_instructions,
+            memory: report.remaining_memory.map(|left| left + self.memory),
+        }
+    }
+
+    /// Charges a finished step: the instructions it executed, and the memory the run
+    /// now holds in place of what it held before.
+    pub fn charge(&mut self, instructions: u64, memory: usize) {
+        let mut usage = self.group.usage();
+        usage.instructions += instructions;
+        usage.memory = (usage.memory + memory).saturating_sub(self.memory);
+        self.memory = memory;
+    }
+}
+
+impl Clone for Membership {
+    fn clone(&self) -> Self {
+        self.group.usage().memory += self.memory;
+        Self {
+            group: Arc::clone(&self.group),
+            memory: self.memory,
+        }
+    }
+}
+
+impl Drop for Membership {
+    fn drop(&mut self) {
+        let mut usage = self.group.usage();
+        usage.memory = usage.memory.saturating_sub(self.memory);
+    }
+}
@@ -0,0 +1,294 @@
+//! `monty_run_recover`: rebuilds a run from its append-only journal after a crash, so
+//! a workflow only has to journal each suspension instead of snapshotting it.
+//!
+//! The journal is what the Go `Recorder` made by `NewJournalWriter` appends, one JSON
+//! event per line: `call` (a call the run reported), `defer` (a call resumed as a
+//! future), `resolve` (the resolution the host supplied), and `complete` or `error`.
+//! The program is started again and every call is answered from the journal, checking
+//! that it is the call the journal recorded, until the run reaches a call the journal
+//! has no resolution for; that progress is returned for the host to handle live.
+//! Emits and sleeps are not journaled and are resumed at once.
+
+use std::collections::HashMap;
+
+use monty::{ExternalResult, MontyException, MontyObject};
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::{
+    compiled::INPUT_FUNCTION,
+    context::RunContext,
+    dispatch::{self, Step},
+    error::{FfiError, FfiResult},
+    external_error,
+    inputs::PendingStart,
+    json::{decode_value, encode_kwarg_values, encode_value, parse_exc_type, HostCauses},
+    tracker::FfiTracker,
+    CANCELLED_EXC_TYPE, TIMEOUT_EXC_TYPE,
+};
+
+#[derive(Debug, Deserialize)]
+struct Event {
+    event: String,
+    #[serde(default)]
+    entry: Option<Entry>,
+}
+
+/// A journaled call, with the same fields as the Go `JournalEntry`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct Entry {
+    call_id: u32,
+    function_name: String,
+    os_function: String,
+    args: Vec<Value>,
+    kwargs: Vec<(Value, Value)>,
+    deferred: bool,
+    result: Option<Value>,
+    error: String,
+    error_type: String,
+    cancelled: bool,
+    timed_out: bool,
+    /// Whether a `resolve` event was seen; a deferred call can be journaled without one.
+    #[serde(skip)]
+    resolved: bool,
+}
+
+/// Starts `pending` and answers its calls from `journal`, returning the step the
+/// journal doesn't cover along with the run's context.
+pub fn recover(pending: PendingStart, journal: &[u8]) -> FfiResult<(Step, RunContext)> {
+    if let Some(name) = pending.next_request() {
+        return Err(FfiError::Message(format!(
+            "monty_run_recover can't provide lazy input {name:?}"
+        )));
+    }
+    let mut journal = Journal::read(journal)?;
+    let (runner, inputs, mut context) = pending.into_start();
+    let no_causes = HostCauses::default();
+    let mut progress = context.execute(|print| runner.start(inputs, FfiTracker, print))?;
+    loop {
+        let step = dispatch::step(progress, &mut context)?;
+        let covered = match &step {
+            Step::Complete(_) => false,
+            Step::Emit { .. } | Step::Sleep { .. } => true,
+            Step::Prompt { .. } | Step::Call(_) => journal
+                .calls
+                .get(journal.next)
+                .is_some_and(|entry| entry.resolved || entry.deferred),
+            Step::ResolveFutures(state) => state
+                .pending_call_ids()
+                .iter()
+                .any(|id| journal.deferred.get(id).is_some_and(|entry| entry.resolved)),
+        };
+        if !covered {
+            return Ok((step, context));
+        }
+        progress = match step {
+            Step::Emit { state, .. } | Step::Sleep { state, .. } => {
+                let resolution = ExternalResult::Return(MontyObject::None);
+                context.execute(|print| state.run(resolution, print))?
+            }
+            Step::Prompt {
+                call_id,
+                args,
+                kwargs,
+                state,
+                ..
+            } => {
+                let resolution = journal.answer(call_id, INPUT_FUNCTION, "", &args, &kwargs)?;
+                context.execute(|print| state.run(resolution, print))?
+            }
+            Step::Call(call) => {
+                let resolution = journal.answer(
+                    call.call_id,
+                    &call.function_name,
+                    call.os_function.as_deref().unwrap_or_default(),
+                    &call.args,
+                    &call.kwargs,
+                )?;
+                call.run(resolution, &mut context, &no_causes)?
+            }
+            Step::ResolveFutures(state) => {
+                let mut results = Vec::new();
+                for id in state.pending_call_ids() {
+                    if let Some(entry) = journal.deferred.remove(id) {
+                        if entry.resolved {
+                            results.push((*id, resolution(&entry)?));
+                        } else {
+                            journal.deferred.insert(*id, entry);
+                        }
+                    }
+                }
+                let results = context.check_results(results)?;
+                context.execute(|print| state.resume(results, print))?
+            }
+            Step::Complete(_) => unreachable!(),
+        };
+    }
+}
+
+struct Journal {
+    calls: Vec<Entry>,
+    /// Index of the next call to answer.
+    next: usize,
+    /// Calls answered with a future, by call ID, until their result is used.
+    deferred: HashMap<u32, Entry>,
+}
+
+impl Journal {
+    /// Folds the journal's events into its calls. A final line without a newline that
+    /// doesn't parse was cut short by a crash and is ignored.
+    fn read(journal: &[u8]) -> FfiResult<Self> {
+        let mut calls: Vec<Entry> = Vec::new();
+        let mut index = HashMap::new();
+        let lines: Vec<&[u8]> = journal.split(|&b| b == b'\n').collect();
+        for (number, line) in lines.iter().enumerate() {
+            if line.iter().all(u8::is_ascii_whitespace) {
+                continue;
+            }
+            let event: Event = match serde_json::from_slice(line) {
+                Ok(event) => event,
+                Err(_) if number == lines.len() - 1 => break,
+                Err(err) => {
+                    return Err(FfiError::Message(format!(
+                        "journal line {}: {err}",
+                        number + 1
+                    )))
+                }
+            };
+            let entry = match (event.event.as_str(), event.entry) {
+                ("complete" | "error", _) => continue,
+                (_, Some(entry)) => entry,
+                (kind, None) => {
+                    return Err(FfiError::Message(format!(
+                        "journal line {}: {kind} event has no entry",
+                        number + 1
+                    )))
+                }
+            };
+            if event.event == "call" {
+                index.insert(entry.call_id, calls.len());
+                calls.push(entry);
+                continue;
+            }
+            let Some(&i) = index.get(&entry.call_id) else {
+                return Err(FfiError::Message(format!(
+                    "journal line {}: {} event for unrecorded call {}",
+                    number + 1,
+                    event.event,
+                    entry.call_id
+                )));
+            };
+            let call = &mut calls[i];
+            match event.event.as_str() {
+                "defer" => call.deferred = true,
+                "resolve" => {
+                    call.result = entry.result;
+                    call.error = entry.error;
+                    call.error_type = entry.error_type;
+                    call.cancelled = entry.cancelled;
+                    call.timed_out = entry.timed_out;
+                    call.resolved = true;
+                }
+                kind => {
+                    return Err(FfiError::Message(format!(
+                        "journal line {}: unknown event {kind:?}",
+                        number + 1
+                    )))
+                }
+            }
+        }
+        Ok(Self {
+            calls,
+            next: 0,
+            deferred: HashMap::new(),
+        })
+    }
+
+    /// Checks a call against the next journaled one and returns how it was resolved,
+    /// or a future for a deferred call.
+    fn answer(
+        &mut self,
+        call_id: u32,
+        function_name: &str,
+        os_function: &str,
+        args: &[MontyObject],
+        kwargs: &[(MontyObject, MontyObject)],
+    ) -> FfiResult<ExternalResult> {
+        let args = args
+            .iter()
+            .map(encode_value)
+            .collect::<FfiResult<Vec<_>>>()?;
+        let kwargs = encode_kwarg_values(kwargs)?;
+        let index = self.next;
+        let entry = std::mem::take(&mut self.calls[index]);
+        let same = entry.call_id == call_id
+            && entry.function_name == function_name
+            && entry.os_function == os_function
+            && entry.args == args
+            && entry.kwargs == kwargs;
+        if !same {
+            let name = if os_function.is_empty() {
+                function_name.to_owned()
+            } else {
+                format!("os:{os_function}")
+            };
+            return Err(FfiError::Message(format!(
+                "journal diverged at call {index}: expected {}, got {}",
+                describe(&entry),
+                describe_call(call_id, &name, &args, &kwargs)
+            )));
+        }
+        self.next += 1;
+        if entry.deferred {
+            self.deferred.insert(call_id, entry);
+            return Ok(ExternalResult::Future);
+        }
+        resolution(&entry)
+    }
+}
+
+/// How the host resolved a journaled call; the same rules as future results.
+fn resolution(entry: &Entry) -> FfiResult<ExternalResult> {
+    if entry.cancelled || entry.timed_out {
+        let exc_type = if entry.cancelled {
+            CANCELLED_EXC_TYPE
+        } else {
+            TIMEOUT_EXC_TYPE
+        };
+        let message = Some(entry.error.clone()).filter(|err| !err.is_empty());
+        return Ok(ExternalResult::Error(MontyException::new(
+            parse_exc_type(exc_type)?,
+            message,
+        )));
+    }
+    if !entry.error.is_empty() {
+        return external_error(Some(entry.error_type.as_str()), entry.error.clone());
+    }
+    match &entry.result {
+        Some(value) => Ok(ExternalResult::Return(decode_value(value.clone())?)),
+        None => Ok(ExternalResult::Return(MontyObject::None)),
+    }
+}
+
+fn describe(entry: &Entry) -> String {
+    let name = if entry.os_function.is_empty() {
+        entry.function_name.clone()
+    } else {
+        format!("os:{}", entry.os_function)
+    };
+    describe_call(entry.call_id, &name, &entry.args, &entry.kwargs)
+}
+
+/// Renders a call as `name(args, key=value) #id`, like the Go replayer's divergences.
+fn describe_call(call_id: u32, name: &str, args: &[Value], kwargs: &[(Value, Value)]) -> String {
+    let mut parts: Vec<String> = args.iter().map(Value::to_string).collect();
+    for (key, value) in kwargs {
+        let key = match key {
+            Value::String(key) => key.clone(),
+            key => key.to_string(),
+        };
+        parts.push(format!("{key}={value}"));
+    }
+    format!("{name}({}) #{call_id}", parts.join(", "))
+}
@@ -0,0 +1,76 @@
+//! Keeps the FFI usable from inside its own host callbacks, so a call handler, print
+//! callback, or dump writer may start or drive a nested run, dump and load snapshots,
+//! and free handles.
+//!
+//! Handles carry all run state and errors are returned in each call's `MontyStatus`,
+//! so the only per-thread state is the print redirect and the lossy UTF-8 flag of the
+//! call in progress; `call_host` sets both aside while a callback runs, so nested calls
+//! neither inherit nor clobber them. The one thing a callback may not do is use up the
+//! snapshot its own call is reading: while `monty_snapshot_dump_stream` runs, resuming
+//! or changing that snapshot fails, and freeing it is deferred until the dump ends.
+
+use std::{cell::RefCell, ptr};
+
+use crate::{
+    error::{keep_replaced, FfiError, FfiResult},
+    print,
+};
+
+struct Lent {
+    ptr: usize,
+    freed: bool,
+}
+
+thread_local! {
+    /// Handles a call on this thread is reading while it runs host callbacks.
+    static LENT: RefCell<Vec<Lent>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Runs a host callback with the calling thread's FFI state set aside.
+pub fn call_host<R>(f: impl FnOnce() -> R) -> R {
+    keep_replaced(|| print::redirect(None, ptr::null_mut(), f))
+}
+
+/// Runs `f` with `handle` marked as in use by the current call; returns whether a
+/// callback freed it meanwhile, in which case the caller must free it now.
+pub fn lend<T, R>(handle: *const T, f: impl FnOnce() -> R) -> (R, bool) {
+    LENT.with(|lent| {
+        lent.borrow_mut().push(Lent {
+            ptr: handle as usize,
+            freed: false,
+        })
+    });
+    let result = f();
+    let entry = LENT.with(|lent| lent.borrow_mut().pop());
+    (result, entry.is_some_and(|entry| entry.freed))
+}
+
+/// Fails when `handle` is in use by a call further up this thread's stack.
+pub fn check_idle<T>(handle: *const T, what: &'static str) -> FfiResult<()> {
+    let lent = LENT.with(|lent| {
+        lent.borrow()
+            .iter()
+            .any(|entry| entry.ptr == handle as usize)
+    });
+    if lent {
+        return Err(FfiError::Message(format!(
+            "{what} is in use by the call whose callback is running"
+        )));
+    }
+    Ok(())
+}
+
+/// Records a free of a lent `handle` for `lend` to carry out; returns false when the
+/// handle is not lent and should be freed now.
+pub fn defer_free<T>(handle: *const T) -> bool {
+    LENT.with(|lent| {
+        let mut lent = lent.borrow_mut();
+        match lent.iter_mut().find(|entry| entry.ptr == handle as usize) {
+            Some(entry) => {
+                entry.freed = true;
+                true
+            }
+            None => false,
+        }
+    })
+}
@@ -0,0 +1,280 @@
+//! Best-effort parsing of `{"$repr": "..."}` values back into objects.
+//!
+//! Values with no tagged JSON form are encoded as their Python `repr`, so a host that
+//! feeds one back as an input or result would otherwise hand the script an opaque
+//! blob. Reprs made of literals (`None`, numbers, strings, bytes), containers of them,
+//! `...`, and `Path`s are parsed into the objects they describe; anything else, such as
+//! `<function f at 0x...>` or a cycle placeholder, is an error naming the repr.
+
+use monty::{DictPairs, MontyObject};
+use num_bigint::BigInt;
+
+/// Parses `repr` into the object it describes.
+pub fn parse(repr: &str) -> Result<MontyObject, String> {
+    let mut parser = Parser { src: repr, pos: 0 };
+    parser
+        .value()
+        .and_then(|value| {
+            parser.skip_whitespace();
+            match parser.rest() {
+                "" => Ok(value),
+                rest => Err(format!("unexpected `{}`", truncate(rest))),
+            }
+        })
+        .map_err(|reason| format!("$repr {repr:?} can't be reconstructed: {reason}"))
+}
+
+struct Parser<'a> {
+    src: &'a str,
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn rest(&self) -> &str {
+        &self.src[self.pos..]
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += c.len_utf8();
+        Some(c)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.bump();
+        }
+    }
+
+    fn eat(&mut self, token: &str) -> bool {
+        self.skip_whitespace();
+        if self.rest().starts_with(token) {
+            self.pos += token.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, token: &str) -> Result<(), String> {
+        if self.eat(token) {
+            Ok(())
+        } else {
+            Err(format!("expected `{token}` at `{}`", truncate(self.rest())))
+        }
+    }
+
+    fn value(&mut self) -> Result<MontyObject, String> {
+        self.skip_whitespace();
+        match self.peek() {
+            None => Err("unexpected end".to_owned()),
+            Some('[') => {
+                self.pos += 1;
+                Ok(MontyObject::List(self.items("]")?.0))
+            }
+            Some('(') => {
+                self.pos += 1;
+                match self.items(")")? {
+                    (mut items, false) if items.len() == 1 => Ok(items.remove(0)),
+                    (items, _) => Ok(MontyObject::Tuple(items)),
+                }
+            }
+            Some('{') => {
+                self.pos += 1;
+                self.dict_or_set()
+            }
+            Some('\'' | '"') => self.string().map(MontyObject::String),
+            Some('b' | 'B') if matches!(self.rest()[1..].chars().next(), Some('\'' | '"')) => {
+                self.pos += 1;
+                self.bytes().map(MontyObject::Bytes)
+            }
+            Some('<') => Err("it describes an object without its state".to_owned()),
+            Some('.') if self.eat("...") => Ok(MontyObject::Ellipsis),
+            Some(c) if c.is_ascii_digit() || c == '-' || c == '+' || c == '.' => self.number(),
+            Some(c) if c.is_alphabetic() || c == '_' => self.name(),
+            Some(c) => Err(format!("unexpected `{c}`")),
+        }
+    }
+
+    /// Comma-separated values up to `close`; also returns whether a trailing comma
+    /// followed the last one, which makes `(x,)` a tuple.
+    fn items(&mut self, close: &str) -> Result<(Vec<MontyObject>, bool), String> {
+        let mut items = Vec::new();
+        let mut trailing_comma = false;
+        while !self.eat(close) {
+            if !items.is_empty() && !trailing_comma {
+                return Err(format!(
+                    "expected `,` or `{close}` at `{}`",
+                    truncate(self.rest())
+                ));
+            }
+            items.push(self.value()?);
+            trailing_comma = self.eat(",");
+        }
+        Ok((items, trailing_comma))
+    }
+
+    /// The rest of `{...}`: a dict if its first item has a `:`, otherwise a set.
+    fn dict_or_set(&mut self) -> Result<MontyObject, String> {
+        if self.eat("}") {
+            return Ok(MontyObject::Dict(DictPairs::from(Vec::new())));
+        }
+        let first = self.value()?;
+        if !self.eat(":") {
+            let mut items = vec![first];
+            if self.eat(",") {
+                items.extend(self.items("}")?.0);
+            } else {
+                self.expect("}")?;
+            }
+            return Ok(MontyObject::Set(items));
+        }
+        let mut pairs = vec![(first, self.value()?)];
+        while self.eat(",") {
+            if self.eat("}") {
+                return Ok(MontyObject::Dict(DictPairs::from(pairs)));
+            }
+            let key = self.value()?;
+            self.expect(":")?;
+            pairs.push((key, self.value()?));
+        }
+        self.expect("}")?;
+        Ok(MontyObject::Dict(DictPairs::from(pairs)))
+    }
+
+    fn number(&mut self) -> Result<MontyObject, String> {
+        let start = self.pos;
+        if matches!(self.peek(), Some('-' | '+')) {
+            self.pos += 1;
+        }
+        if self.rest().starts_with("inf") {
+            self.pos += 3;
+            let negative = self.src[start..].starts_with('-');
+            return Ok(MontyObject::Float(if negative {
+                f64::NEG_INFINITY
+            } else {
+                f64::INFINITY
+            }));
+        }
+        while self
+            .peek()
+            .is_some_and(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_'))
+            || (self.src[..self.pos].ends_with(['e', 'E'])
+                && matches!(self.peek(), Some('-' | '+')))
+        {
+            self.pos += 1;
+        }
+        let literal = self.src[start..self.pos].replace('_', "");
+        let is_float = literal.contains(['.', 'e', 'E']);
+        if !is_float {
+            if let Ok(int) = literal.parse::<i64>() {
+                return Ok(MontyObject::Int(int));
+            }
+            if let Ok(int) = literal.parse::<BigInt>() {
+                return Ok(MontyObject::BigInt(int));
+            }
+        } else if let Ok(float) = literal.parse::<f64>() {
+            return Ok(MontyObject::Float(float));
+        }
+        Err(format!("invalid number `{literal}`"))
+    }
+
+    /// A bare name: a constant, or a call building an empty set, a frozenset, or a path.
+    fn name(&mut self) -> Result<MontyObject, String> {
+        let start = self.pos;
+        while self
+            .peek()
+            .is_some_and(|c| c.is_alphanumeric() || c == '_' || c == '.')
+        {
+            self.bump();
+        }
+        let name = &self.src[start..self.pos];
+        match name {
+            "None" => return Ok(MontyObject::None),
+            "True" => return Ok(MontyObject::Bool(true)),
+            "False" => return Ok(MontyObject::Bool(false)),
+            "Ellipsis" => return Ok(MontyObject::Ellipsis),
+            "nan" => return Ok(MontyObject::Float(f64::NAN)),
+            "inf" => return Ok(MontyObject::Float(f64::INFINITY)),
+            _ => {}
+        }
+        let name = name.to_owned();
+        self.expect("(")?;
+        let (mut args, _) = self.items(")")?;
+        match (name.as_str(), args.len()) {
+            ("set", 0) => Ok(MontyObject::Set(Vec::new())),
+            ("frozenset", 0) => Ok(MontyObject::FrozenSet(Vec::new())),
+            ("frozenset", 1) => match args.remove(0) {
+                MontyObject::Set(items) => Ok(MontyObject::FrozenSet(items)),
+                _ => Err("frozenset() takes a set literal".to_owned()),
+            },
+            ("Path" | "PosixPath" | "PurePosixPath" | "pathlib.Path", 1) => match args.remove(0) {
+                MontyObject::String(path) => Ok(MontyObject::Path(path)),
+                _ => Err(format!("{name}() takes a string")),
+            },
+            _ => Err(format!("`{name}(...)` is not a known constructor")),
+        }
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        let quote = self.bump().unwrap_or('\'');
+        let mut out = String::new();
+        loop {
+            match self.bump().ok_or("unterminated string")? {
+                c if c == quote => return Ok(out),
+                '\\' => out.push(self.escape(false)?),
+                c => out.push(c),
+            }
+        }
+    }
+
+    fn bytes(&mut self) -> Result<Vec<u8>, String> {
+        let quote = self.bump().unwrap_or('\'');
+        let mut out = Vec::new();
+        loop {
+            match self.bump().ok_or("unterminated bytes")? {
+                c if c == quote => return Ok(out),
+                '\\' => out.push(self.escape(true)? as u8),
+                c if c.is_ascii() => out.push(c as u8),
+                c => return Err(format!("non-ASCII `{c}` in bytes")),
+            }
+        }
+    }
+
+    fn escape(&mut self, bytes: bool) -> Result<char, String> {
+        let c = self.bump().ok_or("unterminated escape")?;
+        Ok(match c {
+            '\\' | '\'' | '"' => c,
+            'n' => '\n',
+            'r' => '\r',
+            't' => '\t',
+            'a' => '\x07',
+            'b' => '\x08',
+            'f' => '\x0c',
+            'v' => '\x0b',
+            '0' => '\0',
+            'x' => self.hex_escape(2)?,
+            'u' if !bytes => self.hex_escape(4)?,
+            'U' if !bytes => self.hex_escape(8)?,
+            other => return Err(format!("unknown escape `\\{other}`")),
+        })
+    }
+
+    fn hex_escape(&mut self, digits: usize) -> Result<char, String> {
+        let hex = self.rest().get(..digits).ok_or("truncated escape")?;
+        let code = u32::from_str_radix(hex, 16).map_err(|_| format!("invalid escape `{hex}`"))?;
+        self.pos += digits;
+        char::from_u32(code).ok_or_else(|| format!("invalid code point {code:#x}"))
+    }
+}
+
+fn truncate(s: &str) -> &str {
+    match s.char_indices().nth(20) {
+        Some((end, _)) => &s[..end],
+        None => s,
+    }
+}
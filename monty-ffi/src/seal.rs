@@ -0,0 +1,69 @@
+//! Host-supplied sealing of snapshot dumps.
+//!
+//! The host's seal callback turns a dump into whatever it stores (typically AEAD
+//! ciphertext); its open callback reverses that and fails when authentication does.
+//! Sealed dumps are framed with `SEALED_MAGIC` so they are never mistaken for plain ones.
+
+use std::{ffi::c_void, slice};
+
+use crate::{
+    error::{FfiError, FfiResult},
+    reentry, MontyTransformCallback,
+};
+
+const SEALED_MAGIC: &[u8] = b"MONTYSEAL\x01";
+
+pub fn seal(
+    callback: MontyTransformCallback,
+    userdata: *mut c_void,
+    dump: &[u8],
+) -> FfiResult<Vec<u8>> {
+    let callback = callback.ok_or(FfiError::NullPointer("seal"))?;
+    let mut sealed = SEALED_MAGIC.to_vec();
+    let sink = &mut sealed as *mut Vec<u8> as *mut c_void;
+    match reentry::call_host(|| unsafe {
+        callback(userdata, dump.as_ptr(), dump.len(), Some(append), sink)
+    }) {
+        0 => Ok(sealed),
+        code => Err(FfiError::Message(format!(
+            "seal callback failed with status {code}"
+        ))),
+    }
+}
+
+/// Recovers the dump inside `sealed`; any failure of the open callback is reported as
+/// tampering.
+pub fn open(
+    callback: MontyTransformCallback,
+    userdata: *mut c_void,
+    sealed: &[u8],
+) -> FfiResult<Vec<u8>> {
+    let callback = callback.ok_or(FfiError::NullPointer("open"))?;
+    let payload = sealed
+        .strip_prefix(SEALED_MAGIC)
+        .ok_or_else(|| FfiError::Message("not a sealed snapshot".into()))?;
+    let mut dump = Vec::new();
+    let sink = &mut dump as *mut Vec<u8> as *mut c_void;
+    match reentry::call_host(|| unsafe {
+        callback(
+            userdata,
+            payload.as_ptr(),
+            payload.len(),
+            Some(append),
+            sink,
+        )
+    }) {
+        0 => Ok(dump),
+        _ => Err(FfiError::Tampered),
+    }
+}
+
+/// The `emit` handed to transform and call callbacks: appends to the `Vec<u8>` behind
+/// `sink`.
+pub unsafe extern "C" fn append(sink: *mut c_void, data: *const u8, len: usize) -> i32 {
+    if len > 0 {
+        let bytes = unsafe { &mut *(sink as *mut Vec<u8>) };
+        bytes.extend_from_slice(unsafe { slice::from_raw_parts(data, len) });
+    }
+    0
+}
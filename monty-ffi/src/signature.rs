@@ -0,0 +1,411 @@
+//! Declared signatures for external functions.
+//!
+//! An `ext_funcs` entry may be a bare name (`fetch`) or a Python-style signature
+//! (`fetch(url, /, timeout=None, *, retries=3, **headers)`). Calls that cannot bind
+//! to a declared signature raise `TypeError` inside the script, with CPython's
+//! wording, instead of reaching the host. Parameter annotations and default values
+//! are accepted but only their presence matters. A return annotation (`-> str`) is
+//! checked against the value the host resumes the call with.
+
+use monty::{ExcType, MontyException, MontyObject};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::{FfiError, FfiResult},
+    objects::type_name,
+};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Signature {
+    pub name: String,
+    params: Vec<Param>,
+    var_positional: bool,
+    var_keyword: bool,
+    returns: Option<ReturnType>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Param {
+    name: String,
+    kind: ParamKind,
+    required: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+enum ParamKind {
+    PositionalOnly,
+    Positional,
+    KeywordOnly,
+}
+
+/// A declared return annotation: the value must match one of its alternatives.
+/// Only the outer type is checked; `list[int]` accepts any list.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ReturnType {
+    annotation: String,
+    accepts: Vec<TypeName>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+enum TypeName {
+    Any,
+    None,
+    Bool,
+    Int,
+    Float,
+    Str,
+    Bytes,
+    List,
+    Tuple,
+    Dict,
+    Set,
+    FrozenSet,
+    /// A dataclass or named tuple, matched by its class name.
+    Class(String),
+}
+
+/// Splits an `ext_funcs` entry into the function name and its signature, if declared.
+pub fn parse(decl: &str) -> FfiResult<(String, Option<Signature>)> {
+    let decl = decl.trim();
+    let Some((name, rest)) = decl.split_once('(') else {
+        return Ok((decl.to_owned(), None));
+    };
+    let invalid = |reason: &str| FfiError::Message(format!("invalid signature `{decl}`: {reason}"));
+    let name = name.trim().to_owned();
+    let close = closing_paren(rest).ok_or_else(|| invalid("missing closing parenthesis"))?;
+    let (body, tail) = (&rest[..close], rest[close + 1..].trim());
+    let returns = match tail.strip_prefix("->") {
+        Some(annotation) => Some(ReturnType::parse(annotation.trim()).map_err(|r| invalid(&r))?),
+        None if tail.is_empty() => None,
+        None => return Err(invalid(&format!("unexpected `{tail}` after parameters"))),
+    };
+    let mut signature = Signature {
+        name: name.clone(),
+        params: Vec::new(),
+        var_positional: false,
+        var_keyword: false,
+        returns,
+    };
+    let mut kind = ParamKind::Positional;
+    for part in split_params(body) {
+        let part = part.trim();
+        if part.is_empty() {
+            continue;
+        }
+        if part == "/" {
+            for param in &mut signature.params {
+                param.kind = ParamKind::PositionalOnly;
+            }
+        } else if part == "*" {
+            kind = ParamKind::KeywordOnly;
+        } else if part.starts_with("**") {
+            signature.var_keyword = true;
+        } else if part.starts_with('*') {
+            signature.var_positional = true;
+            kind = ParamKind::KeywordOnly;
+        } else {
+            let (head, required) = match part.split_once('=') {
+                Some((head, _)) => (head, false),
+                None => (part, true),
+            };
+            let param = head.split(':').next().unwrap_or_default().trim();
+            if !is_identifier(param) {
+                return Err(invalid(&format!("bad parameter `{part}`")));
+            }
+            signature.params.push(Param {
+                name: param.to_owned(),
+                kind,
+                required,
+            });
+        }
+    }
+    Ok((name, Some(signature)))
+}
+
+/// Splits on `separator` outside brackets and string literals.
+fn split_top_level(body: &str, separator: char) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut start = 0;
+    scan(body, |index, c, depth| {
+        if c == separator && depth == 0 {
+            parts.push(&body[start..index]);
+            start = index + c.len_utf8();
+        }
+        false
+    });
+    parts.push(&body[start..]);
+    parts
+}
+
+fn split_params(body: &str) -> Vec<&str> {
+    split_top_level(body, ',')
+}
+
+/// Index of the `)` closing the parameter list, which has already been opened.
+fn closing_paren(rest: &str) -> Option<usize> {
+    let mut found = None;
+    scan(rest, |index, c, depth| {
+        if c == ')' && depth == 0 {
+            found = Some(index);
+        }
+        found.is_some()
+    });
+    found
+}
+
+/// Walks `text` outside string literals, passing each character with the bracket depth
+/// around it, until `visit` returns true.
+fn scan(text: &str, mut visit: impl FnMut(usize, char, usize) -> bool) {
+    let mut depth = 0usize;
+    let mut quote = None;
+    for (index, c) in text.char_indices() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '\'' | '"') => quote = Some(c),
+            (None, '(' | '[' | '{') => depth += 1,
+            (None, ')' | ']' | '}') if depth > 0 => depth -= 1,
+            (None, c) => {
+                if visit(index, c, depth) {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+impl ReturnType {
+    fn parse(annotation: &str) -> Result<Self, String> {
+        if annotation.is_empty() {
+            return Err("empty return annotation".to_owned());
+        }
+        let mut accepts = Vec::new();
+        collect_alternatives(annotation, &mut accepts)?;
+        Ok(Self {
+            annotation: annotation.to_owned(),
+            accepts,
+        })
+    }
+
+    fn matches(&self, value: &MontyObject) -> bool {
+        self.accepts.iter().any(|name| name.matches(value))
+    }
+}
+
+/// Expands `A | B`, `Optional[A]` and `Union[A, B]` into their alternatives.
+fn collect_alternatives(annotation: &str, accepts: &mut Vec<TypeName>) -> Result<(), String> {
+    let alternatives = split_top_level(annotation, '|');
+    if alternatives.len() > 1 {
+        for alternative in alternatives {
+            collect_alternatives(alternative.trim(), accepts)?;
+        }
+        return Ok(());
+    }
+    let annotation = annotation.trim();
+    let (base, params) = match annotation.split_once('[') {
+        Some((base, params)) => (base.trim(), params.strip_suffix(']')),
+        None => (annotation, None),
+    };
+    let base = base.strip_prefix("typing.").unwrap_or(base);
+    match (base, params) {
+        ("Optional", Some(inner)) => {
+            accepts.push(TypeName::None);
+            collect_alternatives(inner, accepts)
+        }
+        ("Union", Some(inner)) => split_params(inner)
+            .into_iter()
+            .try_for_each(|alternative| collect_alternatives(alternative, accepts)),
+        _ => {
+            accepts.push(TypeName::parse(base)?);
+            Ok(())
+        }
+    }
+}
+
+impl TypeName {
+    fn parse(name: &str) -> Result<Self, String> {
+        Ok(match name {
+            "Any" | "object" => Self::Any,
+            "None" => Self::None,
+            "bool" => Self::Bool,
+            "int" => Self::Int,
+            "float" => Self::Float,
+            "str" => Self::Str,
+            "bytes" => Self::Bytes,
+            "list" | "List" => Self::List,
+            "tuple" | "Tuple" => Self::Tuple,
+            "dict" | "Dict" => Self::Dict,
+            "set" | "Set" => Self::Set,
+            "frozenset" | "FrozenSet" => Self::FrozenSet,
+            _ if is_identifier(name) => Self::Class(name.to_owned()),
+            _ => return Err(format!("unsupported return annotation `{name}`")),
+        })
+    }
+
+    /// Follows `isinstance`, plus the usual numeric promotion of `int` to `float`.
+    fn matches(&self, value: &MontyObject) -> bool {
+        match (self, value) {
+            (Self::Any, _) => true,
+            (Self::None, MontyObject::None) => true,
+            (Self::Bool, MontyObject::Bool(_)) => true,
+            (Self::Int, MontyObject::Bool(_) | MontyObject::Int(_) | MontyObject::BigInt(_)) => {
+                true
+            }
+            (
+                Self::Float,
+                MontyObject::Float(_)
+                | MontyObject::Int(_)
+                | MontyObject::BigInt(_)
+                | MontyObject::Bool(_),
+            ) => true,
+            (Self::Str, MontyObject::String(_)) => true,
+            (Self::Bytes, MontyObject::Bytes(_)) => true,
+            (Self::List, MontyObject::List(_)) => true,
+            (Self::Tuple, MontyObject::Tuple(_) | MontyObject::NamedTuple { .. }) => true,
+            (Self::Dict, MontyObject::Dict(_)) => true,
+            (Self::Set, MontyObject::Set(_)) => true,
+            (Self::FrozenSet, MontyObject::FrozenSet(_)) => true,
+            (Self::Class(class), MontyObject::Dataclass { name, .. }) => class == name,
+            (Self::Class(class), MontyObject::NamedTuple { type_name, .. }) => class == type_name,
+            _ => false,
+        }
+    }
+}
+
+fn is_identifier(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_alphanumeric() || c == '_')
+}
+
+impl Signature {
+    /// Binds a call's arguments the way CPython does, returning the `TypeError` it
+    /// would raise.
+    pub fn check(
+        &self,
+        args: &[MontyObject],
+        kwargs: &[(MontyObject, MontyObject)],
+    ) -> Result<(), MontyException> {
+        self.bind(args.len(), kwargs).map_err(|message| {
+            MontyException::new(
+                ExcType::TypeError,
+                Some(format!("{}() {message}", self.name)),
+            )
+        })
+    }
+
+    pub fn declares_return(&self) -> bool {
+        self.returns.is_some()
+    }
+
+    /// Class names among the declared return type's alternatives.
+    pub fn return_classes(&self) -> impl Iterator<Item = &str> {
+        self.returns
+            .iter()
+            .flat_map(|returns| &returns.accepts)
+            .filter_map(|accepted| match accepted {
+                TypeName::Class(class) => Some(class.as_str()),
+                _ => None,
+            })
+    }
+
+    /// Describes how `value` violates the declared return type, if it does.
+    pub fn check_return(&self, value: &MontyObject) -> Result<(), String> {
+        match &self.returns {
+            Some(returns) if !returns.matches(value) => Err(format!(
+                "{}() must return {}, not {}",
+                self.name,
+                returns.annotation,
+                type_name(value)
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    fn bind(&self, positional: usize, kwargs: &[(MontyObject, MontyObject)]) -> Result<(), String> {
+        let accepts = self
+            .params
+            .iter()
+            .filter(|param| param.kind != ParamKind::KeywordOnly)
+            .count();
+        if positional > accepts && !self.var_positional {
+            let required = self
+                .params
+                .iter()
+                .filter(|param| param.kind != ParamKind::KeywordOnly && param.required)
+                .count();
+            let takes = if required == accepts {
+                accepts.to_string()
+            } else {
+                format!("from {required} to {accepts}")
+            };
+            return Err(format!(
+                "takes {takes} positional argument{} but {positional} {} given",
+                plural(accepts),
+                if positional == 1 { "was" } else { "were" }
+            ));
+        }
+        let mut bound: Vec<bool> = (0..self.params.len())
+            .map(|index| index < positional.min(accepts))
+            .collect();
+        for (key, _) in kwargs {
+            let MontyObject::String(key) = key else {
+                return Err("keywords must be strings".to_owned());
+            };
+            match self.params.iter().position(|param| &param.name == key) {
+                Some(index) if self.params[index].kind == ParamKind::PositionalOnly => {
+                    if !self.var_keyword {
+                        return Err(format!(
+                            "got some positional-only arguments passed as keyword arguments: '{key}'"
+                        ));
+                    }
+                }
+                Some(index) if bound[index] => {
+                    return Err(format!("got multiple values for argument '{key}'"));
+                }
+                Some(index) => bound[index] = true,
+                None if self.var_keyword => {}
+                None => return Err(format!("got an unexpected keyword argument '{key}'")),
+            }
+        }
+        for (label, keyword_only) in [("positional", false), ("keyword-only", true)] {
+            let missing: Vec<&str> = self
+                .params
+                .iter()
+                .zip(&bound)
+                .filter(|(param, bound)| {
+                    param.required
+                        && !**bound
+                        && (param.kind == ParamKind::KeywordOnly) == keyword_only
+                })
+                .map(|(param, _)| param.name.as_str())
+                .collect();
+            if !missing.is_empty() {
+                return Err(format!(
+                    "missing {} required {label} argument{}: {}",
+                    missing.len(),
+                    plural(missing.len()),
+                    join_names(&missing)
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn plural(count: usize) -> &'static str {
+    if count == 1 {
+        ""
+    } else {
+        "s"
+    }
+}
+
+/// Formats names like CPython: `'a'`, `'a' and 'b'`, `'a', 'b', and 'c'`.
+fn join_names(names: &[&str]) -> String {
+    let quoted: Vec<String> = names.iter().map(|name| format!("'{name}'")).collect();
+    match quoted.as_slice() {
+        [] => String::new(),
+        [one] => one.clone(),
+        [first, second] => format!("{first} and {second}"),
+        [rest @ .., last] => format!("{}, and {last}", rest.join(", ")),
+    }
+}
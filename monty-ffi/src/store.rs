@@ -0,0 +1,218 @@
+//! Snapshot dumps kept by content hash, a building block for durable execution
+//! backends: a workflow persists the hash of each state it reaches, and identical
+//! dumps (a retried step, a run that returns to an earlier state) are stored once.
+//!
+//! `SnapshotStore` is the backend interface; `MemoryStore` keeps dumps in the process
+//! and `DirStore` in a directory, so hosts get persistence without their own blob
+//! plumbing.
+
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::{Mutex, MutexGuard},
+};
+
+use crate::{
+    error::{FfiError, FfiResult},
+    sha256,
+};
+
+/// Where snapshot dumps are kept, by the SHA-256 hex digest of their bytes.
+pub trait SnapshotStore: Send + Sync {
+    /// Keeps `dump`, a snapshot of the run `run_id`, unless an identical one is
+    /// already stored, and returns its hash.
+    fn put(&self, run_id: &str, dump: &[u8]) -> FfiResult<String>;
+
+    fn get(&self, hash: &str) -> FfiResult<Vec<u8>>;
+
+    /// Removes the dump stored under `hash`; does nothing if there is none.
+    fn delete(&self, hash: &str) -> FfiResult<()>;
+
+    /// Hashes of the stored dumps of the run `run_id`, in the order they were put.
+    fn list(&self, run_id: &str) -> FfiResult<Vec<String>>;
+
+    /// Number of distinct dumps stored.
+    fn count(&self) -> FfiResult<usize>;
+}
+
+fn not_found(hash: &str) -> FfiError {
+    FfiError::Message(format!("no snapshot stored under {hash}"))
+}
+
+#[derive(Debug, Default)]
+struct Contents {
+    blobs: HashMap<String, Vec<u8>>,
+    runs: HashMap<String, Vec<String>>,
+}
+
+/// Dumps held in memory for the life of the store.
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    contents: Mutex<Contents>,
+}
+
+impl MemoryStore {
+    fn contents(&self) -> MutexGuard<'_, Contents> {
+        self.contents.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+impl SnapshotStore for MemoryStore {
+    fn put(&self, run_id: &str, dump: &[u8]) -> FfiResult<String> {
+        let hash = sha256::hex_digest(dump);
+        let mut contents = self.contents();
+        contents
+            .blobs
+            .entry(hash.clone())
+            .or_insert_with(|| dump.to_vec());
+        let hashes = contents.runs.entry(run_id.to_owned()).or_default();
+        if !hashes.contains(&hash) {
+            hashes.push(hash.clone());
+        }
+        Ok(hash)
+    }
+
+    fn get(&self, hash: &str) -> FfiResult<Vec<u8>> {
+        self.contents()
+            .blobs
+            .get(hash)
+            .cloned()
+            .ok_or_else(|| not_found(hash))
+    }
+
+    fn delete(&self, hash: &str) -> FfiResult<()> {
+        let mut contents = self.contents();
+        contents.blobs.remove(hash);
+        for hashes in contents.runs.values_mut() {
+            hashes.retain(|stored| stored != hash);
+        }
+        Ok(())
+    }
+
+    fn list(&self, run_id: &str) -> FfiResult<Vec<String>> {
+        Ok(self
+            .contents()
+            .runs
+            .get(run_id)
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    fn count(&self) -> FfiResult<usize> {
+        Ok(self.contents().blobs.len())
+    }
+}
+
+/// Dumps kept as files under a directory: `blobs/<hash>` holds each dump, and
+/// `runs/<hash of the run id>` lists the hashes put for a run, one per line. Dumps are
+/// written to a temporary file and renamed into place, so a crash never leaves a
+/// partial dump under a hash.
+#[derive(Debug)]
+pub struct DirStore {
+    root: PathBuf,
+    /// Serializes updates to run lists from this process.
+    lock: Mutex<()>,
+}
+
+impl DirStore {
+    /// Opens the store in `root`, creating the directory if needed.
+    pub fn open(root: impl Into<PathBuf>) -> FfiResult<Self> {
+        let root = root.into();
+        for dir in ["blobs", "runs"] {
+            fs::create_dir_all(root.join(dir)).map_err(|err| io_error(&root, err))?;
+        }
+        Ok(Self {
+            root,
+            lock: Mutex::new(()),
+        })
+    }
+
+    fn blob(&self, hash: &str) -> FfiResult<PathBuf> {
+        // Hashes become file names, so only accept what `put` hands out.
+        if hash.len() != 64 || !hash.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(not_found(hash));
+        }
+        Ok(self.root.join("blobs").join(hash))
+    }
+
+    fn run(&self, run_id: &str) -> PathBuf {
+        self.root
+            .join("runs")
+            .join(sha256::hex_digest(run_id.as_bytes()))
+    }
+
+    fn read_run(&self, run_id: &str) -> FfiResult<Vec<String>> {
+        let path = self.run(run_id);
+        match fs::read_to_string(&path) {
+            Ok(text) => Ok(text.lines().map(str::to_owned).collect()),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+            Err(err) => Err(io_error(&path, err)),
+        }
+    }
+}
+
+impl SnapshotStore for DirStore {
+    fn put(&self, run_id: &str, dump: &[u8]) -> FfiResult<String> {
+        let hash = sha256::hex_digest(dump);
+        let path = self.blob(&hash)?;
+        if !path.exists() {
+            let partial = path.with_extension(format!("partial-{}", std::process::id()));
+            fs::write(&partial, dump)
+                .and_then(|()| fs::rename(&partial, &path))
+                .map_err(|err| io_error(&path, err))?;
+        }
+        let _guard = self.lock.lock().unwrap_or_else(|err| err.into_inner());
+        if !self.read_run(run_id)?.contains(&hash) {
+            let index = self.run(run_id);
+            fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&index)
+                .and_then(|mut file| writeln!(file, "{hash}"))
+                .map_err(|err| io_error(&index, err))?;
+        }
+        Ok(hash)
+    }
+
+    fn get(&self, hash: &str) -> FfiResult<Vec<u8>> {
+        let path = self.blob(hash)?;
+        fs::read(&path).map_err(|err| match err.kind() {
+            io::ErrorKind::NotFound => not_found(hash),
+            _ => io_error(&path, err),
+        })
+    }
+
+    fn delete(&self, hash: &str) -> FfiResult<()> {
+        let Ok(path) = self.blob(hash) else {
+            return Ok(());
+        };
+        match fs::remove_file(&path) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => Err(io_error(&path, err)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Run lists are not rewritten on delete; hashes whose dump is gone are skipped.
+    fn list(&self, run_id: &str) -> FfiResult<Vec<String>> {
+        let hashes = self.read_run(run_id)?;
+        Ok(hashes
+            .into_iter()
+            .filter(|hash| self.blob(hash).is_ok_and(|path| path.exists()))
+            .collect())
+    }
+
+    fn count(&self) -> FfiResult<usize> {
+        let dir = self.root.join("blobs");
+        let entries = fs::read_dir(&dir).map_err(|err| io_error(&dir, err))?;
+        Ok(entries
+            .filter_map(Result::ok)
+            .filter(|entry| entry.file_name().len() == 64)
+            .count())
+    }
+}
+
+fn io_error(path: &Path, err: io::Error) -> FfiError {
+    FfiError::Message(format!("snapshot store {}: {err}", path.display()))
+}
@@ -0,0 +1,127 @@
+//! Execution timelines in Chrome's trace event format.
+//!
+//! A run started with the `trace` option records a slice for every VM step (the start
+//! or a resume, up to the next suspension) and an async span for every external or OS
+//! call from the moment the script makes it until the host resolves it. Timestamps are
+//! wall-clock microseconds since the run started, so a trace keeps growing across dumps
+//! and processes. Monty has no hooks for the script's own function calls, so those are
+//! not part of the timeline.
+
+use std::{
+    collections::HashMap,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Trace {
+    started_us: u64,
+    events: Vec<Event>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+enum Event {
+    Step {
+        at_us: u64,
+        dur_us: u64,
+    },
+    CallStart {
+        call_id: u32,
+        name: String,
+        at_us: u64,
+    },
+    CallEnd {
+        call_id: u32,
+        at_us: u64,
+    },
+}
+
+impl Default for Trace {
+    fn default() -> Self {
+        Self {
+            started_us: unix_us(),
+            events: Vec::new(),
+        }
+    }
+}
+
+impl Trace {
+    /// Microseconds since the run started.
+    pub fn now(&self) -> u64 {
+        unix_us().saturating_sub(self.started_us)
+    }
+
+    /// Records a VM step that began at `at_us` and has just ended.
+    pub fn step(&mut self, at_us: u64) {
+        let dur_us = self.now().saturating_sub(at_us);
+        self.events.push(Event::Step { at_us, dur_us });
+    }
+
+    pub fn call_started(&mut self, call_id: u32, name: &str) {
+        let at_us = self.now();
+        self.events.push(Event::CallStart {
+            call_id,
+            name: name.to_owned(),
+            at_us,
+        });
+    }
+
+    pub fn call_ended(&mut self, call_id: u32) {
+        let at_us = self.now();
+        self.events.push(Event::CallEnd { call_id, at_us });
+    }
+
+    /// The trace as a `{"traceEvents": [...]}` document for `chrome://tracing` or
+    /// Perfetto. Calls still outstanding have a begin event only.
+    pub fn to_json(&self) -> Value {
+        let mut open = HashMap::new();
+        let mut events = Vec::with_capacity(self.events.len());
+        for event in &self.events {
+            match event {
+                Event::Step { at_us, dur_us } => events.push(json!({
+                    "name": "step",
+                    "cat": "vm",
+                    "ph": "X",
+                    "ts": at_us,
+                    "dur": dur_us,
+                    "pid": 1,
+                    "tid": 1,
+                })),
+                Event::CallStart {
+                    call_id,
+                    name,
+                    at_us,
+                } => {
+                    open.insert(*call_id, name.as_str());
+                    events.push(call_event("b", *call_id, name, *at_us));
+                }
+                Event::CallEnd { call_id, at_us } => {
+                    if let Some(name) = open.remove(call_id) {
+                        events.push(call_event("e", *call_id, name, *at_us));
+                    }
+                }
+            }
+        }
+        json!({ "traceEvents": events, "displayTimeUnit": "ms" })
+    }
+}
+
+fn call_event(phase: &str, call_id: u32, name: &str, at_us: u64) -> Value {
+    json!({
+        "name": name,
+        "cat": "call",
+        "ph": phase,
+        "id": call_id,
+        "ts": at_us,
+        "pid": 1,
+        "tid": 1,
+    })
+}
+
+pub fn unix_us() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_micros() as u64)
+}
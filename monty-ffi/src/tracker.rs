@@ -0,0 +1,387 @@
+//! Resource tracking for runs driven through the FFI.
+//!
+//! Monty owns the tracker inside its snapshots, so `FfiTracker` itself is stateless:
+//! its hooks update the `RunStats` and enforce the `ResourceLimits` of whichever run
+//! is currently executing on this thread, installed by `track` for the duration of
+//! each start/resume. Both live in the run's context and are dumped with it, so a
+//! reloaded snapshot keeps its consumed budget. Runs in a quota group are also held
+//! to the `Allowance` the group had left when the step started.
+//!
+//! Monty only calls `check_time` between opcodes, so a single builtin (`sorted` of a
+//! huge list, big-int exponentiation) could run far past `max_duration_ms`. The
+//! deadline is also checked on every allocation, along with the memory, allocation and
+//! cost budgets that allocations move; that bounds builtins that allocate as they go,
+//! but not ones that only compare or compute (the comparisons inside `sorted`, one
+//! big-int multiplication), which are caught when they return. The `instructions`
+//! counter only moves in `check_time` (it counts those calls, not opcodes), and there
+//! is no cancellation inside a step: a run is interrupted while it is suspended.
+//!
+//! Budgets can also be set on a weighted `cost` instead of raw counts, using a run's
+//! `CostModel`. Monty doesn't tell the tracker which opcode a step ran, so weights are
+//! per category (steps, allocations, allocated bytes, calls) rather than per opcode.
+
+use std::{cell::RefCell, time::Instant};
+
+use monty::{ExcType, MontyException, ResourceError, ResourceTracker};
+use serde::{Deserialize, Serialize};
+
+use crate::quota::Allowance;
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct FfiTracker;
+
+/// Counters accumulated over the lifetime of a run, across every suspension.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RunStats {
+    /// Times Monty called `check_time`. It does so between opcodes, but not
+    /// necessarily after every one, so this counts VM steps rather than exact
+    /// bytecode instructions; `max_instructions`, the cost model's `instruction`
+    /// weight and the process metrics all count the same steps.
+    pub instructions: u64,
+    pub allocations: u64,
+    pub memory: usize,
+    pub peak_memory: usize,
+    pub external_calls: u64,
+    pub os_calls: u64,
+    pub bytes_printed: u64,
+    /// Time spent executing inside the VM, and sleeping in driven runs, excluding time
+    /// suspended on the host.
+    pub elapsed_us: u64,
+    /// Bytes allocated over the run, including memory since freed.
+    pub allocated_bytes: u64,
+    /// Weighted total of the counters above, per the run's `CostModel`.
+    pub cost: u64,
+}
+
+/// Weights the `cost` counter gives each tracked resource.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CostModel {
+    pub instruction: u64,
+    pub allocation: u64,
+    /// Per KiB allocated.
+    pub allocated_kib: u64,
+    pub external_call: u64,
+    pub os_call: u64,
+}
+
+impl Default for CostModel {
+    /// One unit per VM step and nothing else, so `cost` equals `instructions`.
+    fn default() -> Self {
+        Self {
+            instruction: 1,
+            allocation: 0,
+            allocated_kib: 0,
+            external_call: 0,
+            os_call: 0,
+        }
+    }
+}
+
+impl CostModel {
+    pub fn cost(&self, stats: &RunStats) -> u64 {
+        let weighted = [
+            (self.instruction, stats.instructions),
+            (self.allocation, stats.allocations),
+            (self.allocated_kib, stats.allocated_bytes / 1024),
+            (self.external_call, stats.external_calls),
+            (self.os_call, stats.os_calls),
+        ];
+        weighted.iter().fold(0, |total: u64, &(weight, count)| {
+            total.saturating_add(weight.saturating_mul(count))
+        })
+    }
+}
+
+/// Per-run budgets; `None` means unlimited.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ResourceLimits {
+    pub max_instructions: Option<u64>,
+    pub max_allocations: Option<u64>,
+    pub max_memory: Option<usize>,
+    pub max_duration_ms: Option<u64>,
+    pub max_recursion_depth: Option<usize>,
+    /// Limit on `RunStats::cost`, as weighted by the run's cost model.
+    pub max_cost: Option<u64>,
+    /// Largest single allocation, in bytes, so one string, bytes object, or collection
+    /// can't take the whole memory budget; larger ones raise `MemoryError`.
+    pub max_object_size: Option<usize>,
+    /// How deeply lists, dicts, tuples, and other containers may nest in values the
+    /// script passes out of the VM: call arguments and its result.
+    pub max_nesting_depth: Option<usize>,
+    /// Bytes the script may print; what happens past it is the run's `print_overflow`.
+    pub max_print_bytes: Option<u64>,
+}
+
+/// Budget left under each configured limit; `None` means that resource is unlimited.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RemainingBudget {
+    pub instructions: Option<u64>,
+    pub allocations: Option<u64>,
+    pub memory: Option<usize>,
+    pub time_ms: Option<u64>,
+    pub cost: Option<u64>,
+}
+
+impl ResourceLimits {
+    pub fn remaining(&self, stats: &RunStats) -> RemainingBudget {
+        RemainingBudget {
+            instructions: self
+                .max_instructions
+                .map(|limit| limit.saturating_sub(stats.instructions)),
+            allocations: self
+                .max_allocations
+                .map(|limit| limit.saturating_sub(stats.allocations)),
+            memory: self
+                .max_memory
+                .map(|limit| limit.saturating_sub(stats.memory)),
+            time_ms: self
+                .max_duration_ms
+                .map(|limit| limit.saturating_sub(stats.elapsed_us / 1000)),
+            cost: self.max_cost.map(|limit| limit.saturating_sub(stats.cost)),
+        }
+    }
+}
+
+/// How a single limit moved when a run's limits were replaced. Removing a limit
+/// counts as raising it, and adding one to an unlimited resource as lowering it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LimitChange {
+    Unchanged,
+    Raised,
+    Lowered,
+}
+
+impl LimitChange {
+    fn between<T: Ord>(old: Option<T>, new: Option<T>) -> Self {
+        match (old, new) {
+            (None, None) => Self::Unchanged,
+            (Some(_), None) => Self::Raised,
+            (None, Some(_)) => Self::Lowered,
+            (Some(old), Some(new)) => match new.cmp(&old) {
+                std::cmp::Ordering::Equal => Self::Unchanged,
+                std::cmp::Ordering::Greater => Self::Raised,
+                std::cmp::Ordering::Less => Self::Lowered,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LimitChanges {
+    pub max_instructions: LimitChange,
+    pub max_allocations: LimitChange,
+    pub max_memory: LimitChange,
+    pub max_duration_ms: LimitChange,
+    pub max_recursion_depth: LimitChange,
+    pub max_cost: LimitChange,
+    pub max_object_size: LimitChange,
+    pub max_nesting_depth: LimitChange,
+    pub max_print_bytes: LimitChange,
+}
+
+impl ResourceLimits {
+    pub fn changes_to(&self, new: &Self) -> LimitChanges {
+        LimitChanges {
+            max_instructions: LimitChange::between(self.max_instructions, new.max_instructions),
+            max_allocations: LimitChange::between(self.max_allocations, new.max_allocations),
+            max_memory: LimitChange::between(self.max_memory, new.max_memory),
+            max_duration_ms: LimitChange::between(self.max_duration_ms, new.max_duration_ms),
+            max_recursion_depth: LimitChange::between(
+                self.max_recursion_depth,
+                new.max_recursion_depth,
+            ),
+            max_cost: LimitChange::between(self.max_cost, new.max_cost),
+            max_object_size: LimitChange::between(self.max_object_size, new.max_object_size),
+            max_nesting_depth: LimitChange::between(self.max_nesting_depth, new.max_nesting_depth),
+            max_print_bytes: LimitChange::between(self.max_print_bytes, new.max_print_bytes),
+        }
+    }
+}
+
+struct Tracking {
+    stats: RunStats,
+    limits: ResourceLimits,
+    cost_model: CostModel,
+    allowance: Option<Allowance>,
+    /// `stats.instructions` when the step started, to measure it against `allowance`.
+    step_start: u64,
+    resumed_at: Instant,
+}
+
+impl Tracking {
+    fn elapsed_us(&self) -> u64 {
+        self.stats.elapsed_us + self.resumed_at.elapsed().as_micros() as u64
+    }
+}
+
+thread_local! {
+    static ACTIVE: RefCell<Vec<Tracking>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Installs `stats`, `limits`, the cost model, and the quota group `allowance` as the
+/// active run while `f` runs, then writes the updated counters back.
+pub fn track<R>(
+    stats: &mut RunStats,
+    limits: &ResourceLimits,
+    cost_model: &CostModel,
+    allowance: Option<Allowance>,
+    f: impl FnOnce() -> R,
+) -> R {
+    // Calls counted since the last step are only weighted in now.
+    stats.cost = cost_model.cost(stats);
+    ACTIVE.with(|active| {
+        active.borrow_mut().push(Tracking {
+            step_start: stats.instructions,
+            stats: std::mem::take(stats),
+            limits: limits.clone(),
+            cost_model: cost_model.clone(),
+            allowance,
+            resumed_at: Instant::now(),
+        })
+    });
+    let result = f();
+    if let Some(tracking) = ACTIVE.with(|active| active.borrow_mut().pop()) {
+        let elapsed_us = tracking.elapsed_us();
+        *stats = tracking.stats;
+        stats.elapsed_us = elapsed_us;
+        stats.cost = cost_model.cost(stats);
+    }
+    result
+}
+
+/// Updates the active run's counters; a no-op outside `track`.
+pub fn record(f: impl FnOnce(&mut RunStats)) {
+    ACTIVE.with(|active| {
+        if let Some(tracking) = active.borrow_mut().last_mut() {
+            f(&mut tracking.stats);
+        }
+    });
+}
+
+fn check(f: impl FnOnce(&mut Tracking) -> Result<(), ResourceError>) -> Result<(), ResourceError> {
+    ACTIVE.with(|active| match active.borrow_mut().last_mut() {
+        Some(tracking) => f(tracking),
+        None => Ok(()),
+    })
+}
+
+/// Recomputes the run's cost after a counter changed and enforces `max_cost`.
+fn check_cost(tracking: &mut Tracking) -> Result<(), ResourceError> {
+    tracking.stats.cost = tracking.cost_model.cost(&tracking.stats);
+    match tracking.limits.max_cost {
+        Some(limit) if tracking.stats.cost > limit => {
+            Err(ResourceError::Exception(MontyException::new(
+                ExcType::RuntimeError,
+                Some(format!("cost limit of {limit} exceeded")),
+            )))
+        }
+        _ => Ok(()),
+    }
+}
+
+fn check_deadline(tracking: &Tracking) -> Result<(), ResourceError> {
+    if let Some(limit_ms) = tracking.limits.max_duration_ms {
+        let elapsed_us = tracking.elapsed_us();
+        if elapsed_us > limit_ms.saturating_mul(1000) {
+            return Err(ResourceError::Time {
+                limit: std::time::Duration::from_millis(limit_ms),
+                elapsed: std::time::Duration::from_micros(elapsed_us),
+            });
+        }
+    }
+    Ok(())
+}
+
+impl ResourceTracker for FfiTracker {
+    fn on_allocate(&mut self, get_size: impl FnOnce() -> usize) -> Result<(), ResourceError> {
+        let size = get_size();
+        check(|tracking| {
+            if let Some(limit) = tracking.limits.max_object_size {
+                if size > limit {
+                    return Err(ResourceError::Exception(MontyException::new(
+                        ExcType::MemoryError,
+                        Some(format!(
+                            "object of {size} bytes exceeds the size limit of {limit} bytes"
+                        )),
+                    )));
+                }
+            }
+            let stats = &mut tracking.stats;
+            stats.allocations += 1;
+            stats.allocated_bytes += size as u64;
+            stats.memory += size;
+            stats.peak_memory = stats.peak_memory.max(stats.memory);
+            if let Some(limit) = tracking.limits.max_allocations {
+                if stats.allocations > limit {
+                    return Err(ResourceError::Allocation {
+                        limit: limit as usize,
+                        count: stats.allocations as usize,
+                    });
+                }
+            }
+            if let Some(limit) = tracking.limits.max_memory {
+                if stats.memory > limit {
+                    return Err(ResourceError::Memory {
+                        limit,
+                        used: stats.memory,
+                    });
+                }
+            }
+            if let Some(limit) = tracking.allowance.and_then(|allowance| allowance.memory) {
+                if stats.memory > limit {
+                    return Err(ResourceError::Memory {
+                        limit,
+                        used: stats.memory,
+                    });
+                }
+            }
+            check_cost(tracking)?;
+            check_deadline(tracking)
+        })
+    }
+
+    fn on_free(&mut self, get_size: impl FnOnce() -> usize) {
+        let size = get_size();
+        record(|stats| stats.memory = stats.memory.saturating_sub(size));
+    }
+
+    fn check_time(&self) -> Result<(), ResourceError> {
+        check(|tracking| {
+            tracking.stats.instructions += 1;
+            if let Some(limit) = tracking.limits.max_instructions {
+                if tracking.stats.instructions > limit {
+                    return Err(ResourceError::Exception(MontyException::new(
+                        ExcType::RuntimeError,
+                        Some(format!("instruction limit of {limit} exceeded")),
+                    )));
+                }
+            }
+            if let Some(limit) = tracking
+                .allowance
+                .and_then(|allowance| allowance.instructions)
+            {
+                if tracking.stats.instructions - tracking.step_start > limit {
+                    return Err(ResourceError::Exception(MontyException::new(
+                        ExcType::RuntimeError,
+                        Some("quota group instruction budget exhausted".to_owned()),
+                    )));
+                }
+            }
+            check_cost(tracking)?;
+            check_deadline(tracking)
+        })
+    }
+
+    fn check_recursion_depth(&self, current_depth: usize) -> Result<(), ResourceError> {
+        check(|tracking| match tracking.limits.max_recursion_depth {
+            Some(limit) if current_depth > limit => Err(ResourceError::Recursion {
+                limit,
+                depth: current_depth,
+            }),
+            _ => Ok(()),
+        })
+    }
+}
@@ -0,0 +1,86 @@
+//! Non-fatal warnings reported with a progress result, such as dropped output or a
+//! budget nearly used up, for the host to surface without the run failing.
+//!
+//! Warnings are gathered while the VM runs and handed to the host with the next
+//! progress result, so each one is reported once.
+
+use serde::{Deserialize, Serialize};
+
+use crate::tracker::{ResourceLimits, RunStats};
+
+/// Share of a limit, in percent, a run may use before it is warned about.
+const NEAR_LIMIT_PERCENT: u64 = 90;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Warning {
+    pub kind: WarningKind,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WarningKind {
+    /// Output past `max_print_bytes` was dropped.
+    OutputTruncated,
+    /// The run has used most of one of its limits.
+    NearLimit,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Warnings {
+    /// Gathered since the last progress result; always empty when a run is dumped.
+    #[serde(skip)]
+    pending: Vec<Warning>,
+    /// Limits already reported as nearly used, so each is reported once per run.
+    near_limits: Vec<String>,
+}
+
+impl Warnings {
+    pub fn push(&mut self, kind: WarningKind, message: String) {
+        self.pending.push(Warning { kind, message });
+    }
+
+    pub fn take(&mut self) -> Vec<Warning> {
+        std::mem::take(&mut self.pending)
+    }
+
+    /// Warns about each limit `stats` has used at least `NEAR_LIMIT_PERCENT` of.
+    pub fn check_limits(&mut self, limits: &ResourceLimits, stats: &RunStats) {
+        let usage = [
+            (
+                "max_instructions",
+                stats.instructions,
+                limits.max_instructions,
+            ),
+            ("max_allocations", stats.allocations, limits.max_allocations),
+            (
+                "max_memory",
+                stats.memory as u64,
+                limits.max_memory.map(|limit| limit as u64),
+            ),
+            (
+                "max_duration_ms",
+                stats.elapsed_us / 1000,
+                limits.max_duration_ms,
+            ),
+            ("max_cost", stats.cost, limits.max_cost),
+            (
+                "max_print_bytes",
+                stats.bytes_printed,
+                limits.max_print_bytes,
+            ),
+        ];
+        for (name, used, limit) in usage {
+            let Some(limit) = limit else { continue };
+            let near = used.saturating_mul(100) >= limit.saturating_mul(NEAR_LIMIT_PERCENT);
+            if !near || self.near_limits.iter().any(|warned| warned == name) {
+                continue;
+            }
+            self.near_limits.push(name.to_owned());
+            self.push(
+                WarningKind::NearLimit,
+                format!("{name}: {used} of {limit} used"),
+            );
+        }
+    }
+}
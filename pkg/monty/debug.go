@@ -0,0 +1,57 @@
+package monty
+
+/*
+#include "monty_ffi.h"
+*/
+import "C"
+
+import "encoding/json"
+
+// TrackHandles turns leak tracking on or off for the whole process. While it is on,
+// the library records every handle, string, and byte buffer it hands out until it is
+// freed; turning it off forgets the records. It is meant for tests and debugging.
+func TrackHandles(enabled bool) {
+	var flag C.int32_t
+	if enabled {
+		flag = 1
+	}
+	C.monty_debug_track_handles(flag)
+}
+
+// ReportLeaks counts, by kind ("run", "snapshot", "string", ...), what has been handed
+// out and not freed since TrackHandles(true). An empty map means nothing leaked.
+func ReportLeaks() (map[string]int, error) {
+	var out *C.char
+	if err := statusError(C.monty_debug_report_leaks(&out)); err != nil {
+		return nil, err
+	}
+	var leaks map[string]int
+	if err := json.Unmarshal([]byte(takeString(out)), &leaks); err != nil {
+		return nil, err
+	}
+	return leaks, nil
+}
+
+// HeldHandle is one handle, string, or byte buffer the library has handed out and
+// not had back.
+type HeldHandle struct {
+	Kind  string `json:"kind"`
+	AgeMs uint64 `json:"age_ms"`
+	// Size is the approximate bytes behind it when known: a snapshot's VM heap, or a
+	// string's or buffer's length.
+	Size  *int   `json:"size"`
+}
+
+// ListHandles lists what the library has handed out and not had back since
+// TrackHandles(true), oldest first, for seeing what a long-lived process holds onto.
+func ListHandles() ([]HeldHandle, error) {
+	var out *C.char
+	if err := statusError(C.monty_debug_list_handles(&out)); err != nil {
+		return nil, err
+	}
+	var held []HeldHandle
+	if err := json.Unmarshal([]byte(takeString(out)), &held); err != nil {
+		return nil, err
+	}
+	return held, nil
+}
@@ -0,0 +1,162 @@
+package monty
+
+/*
+#include "monty_ffi.h"
+
+int32_t montyDriveCall(void *userdata, char *function_name, char *args_json, char *kwargs_json, MontyWriteCallback emit, void *sink);
+int32_t montyDriveOsCall(void *userdata, char *function_name, char *args_json, char *kwargs_json, MontyWriteCallback emit, void *sink);
+int32_t montyDrivePrint(void *userdata, uint8_t *data, size_t len);
+
+static inline int32_t montyDriveEmit(MontyWriteCallback emit, void *sink, const uint8_t *data, size_t len) {
+	return emit(sink, data, len);
+}
+*/
+import "C"
+
+import (
+	"encoding/json"
+	"errors"
+	"fmt"
+	"runtime/cgo"
+	"unsafe"
+)
+
+// Handler answers one call of a driven run. Returning an Exception as the value raises
+// it in the script; a non-nil error raises RuntimeError with the error's message.
+type Handler func(name string, args []Object, kwargs []KV) (any, error)
+
+// Handlers answer the calls of a run started with Monty.Drive.
+type Handlers struct {
+	// Function answers external calls. host_emit values are passed to it too; what it
+	// returns for them is ignored. input(prompt) calls are passed to it as "input",
+	// and the string it returns is the line read.
+	Function Handler
+	// OsCall answers OS calls such as "Path.read_text".
+	OsCall Handler
+	// Print receives the script's output; nil discards it.
+	Print func(text string)
+}
+
+// Statuses returned to monty_run_drive by the call callbacks.
+const (
+	driveReturn = 0
+	driveRaise  = 1
+	driveFail   = 2
+)
+
+// driver is the Go side of monty_run_drive's callbacks; err keeps a failure to answer
+// a call so it can be returned instead of the generic callback status.
+type driver struct {
+	handlers Handlers
+	err      error
+}
+
+//export montyDriveCall
+func montyDriveCall(userdata unsafe.Pointer, name, argsJSON, kwargsJSON *C.char, emit C.MontyWriteCallback, sink unsafe.Pointer) C.int32_t {
+	d := (*(*cgo.Handle)(userdata)).Value().(*driver)
+	return d.answer(d.handlers.Function, name, argsJSON, kwargsJSON, emit, sink)
+}
+
+//export montyDriveOsCall
+func montyDriveOsCall(userdata unsafe.Pointer, name, argsJSON, kwargsJSON *C.char, emit C.MontyWriteCallback, sink unsafe.Pointer) C.int32_t {
+	d := (*(*cgo.Handle)(userdata)).Value().(*driver)
+	return d.answer(d.handlers.OsCall, name, argsJSON, kwargsJSON, emit, sink)
+}
+
+//export montyDrivePrint
+func montyDrivePrint(userdata unsafe.Pointer, data *C.uint8_t, length C.size_t) C.int32_t {
+	d := (*(*cgo.Handle)(userdata)).Value().(*driver)
+	d.handlers.Print(C.GoStringN((*C.char)(unsafe.Pointer(data)), C.int(length)))
+	return 0
+}
+
+func (d *driver) answer(handler Handler, name, argsJSON, kwargsJSON *C.char, emit C.MontyWriteCallback, sink unsafe.Pointer) C.int32_t {
+	function := C.GoString(name)
+	if handler == nil {
+		d.err = fmt.Errorf("monty: no handler for %s()", function)
+		return driveFail
+	}
+	args, err := decodeObjectArrayString(C.GoString(argsJSON))
+	if err != nil {
+		d.err = err
+		return driveFail
+	}
+	kwargs, err := decodeKwargsString(C.GoString(kwargsJSON))
+	if err != nil {
+		d.err = err
+		return driveFail
+	}
+	value, err := handler(function, args, kwargs)
+	status := C.int32_t(driveReturn)
+	if err != nil {
+		value = Exception{Type: "RuntimeError", Message: err.Error()}
+	}
+	if exc, ok := value.(Exception); ok {
+		if exc.Type == "" {
+			exc.Type = "RuntimeError"
+		}
+		value, status = exc, driveRaise
+	}
+	normalized, err := normalizeValue(value)
+	if err != nil {
+		d.err = err
+		return driveFail
+	}
+	data, err := json.Marshal(normalized)
+	if err != nil {
+		d.err = err
+		return driveFail
+	}
+	if C.montyDriveEmit(emit, sink, bytesPtr(data), C.size_t(len(data))) != 0 {
+		return driveFail
+	}
+	return status
+}
+
+// Drive runs the script to completion in one call, answering each external and OS
+// call with handlers as it is made, for hosts that never pause runs or keep snapshots.
+// Scripts that gather several calls as futures, and Lazy inputs, are not supported.
+func (m *Monty) Drive(handlers Handlers, inputs ...any) (Object, error) {
+	return m.DriveWithOptions(Options{}, handlers, inputs...)
+}
+
+// DriveWithOptions drives the script like Drive, applying per-run options such as
+// resource limits. host_sleep delays count against ResourceLimits.MaxDurationMs, and
+// one longer than the time left or the "host_sleep" call timeout raises TimeoutError.
+func (m *Monty) DriveWithOptions(opts Options, handlers Handlers, inputs ...any) (Object, error) {
+	if m == nil || m.handle == nil {
+		return nil, errors.New("monty: nil handle")
+	}
+	payload, freePayload, err := marshalInputs(inputs)
+	if err != nil {
+		return nil, err
+	}
+	defer freePayload()
+	if opts.Globals, err = normalizeGlobals(opts.Globals); err != nil {
+		return nil, err
+	}
+	data, err := json.Marshal(opts)
+	if err != nil {
+		return nil, err
+	}
+	optsC, freeOpts := cBytes(data)
+	defer freeOpts()
+
+	d := &driver{handlers: handlers}
+	handle := cgo.NewHandle(d)
+	defer handle.Delete()
+	var printCallback C.MontyWriteCallback
+	if handlers.Print != nil {
+		printCallback = C.MontyWriteCallback(C.montyDrivePrint)
+	}
+	var out *C.char
+	status := C.monty_run_drive(m.handle, payload, optsC, C.MontyCallCallback(C.montyDriveCall), C.MontyCallCallback(C.montyDriveOsCall), printCallback, unsafe.Pointer(&handle), &out)
+	err = statusError(status)
+	if d.err != nil {
+		return nil, d.err
+	}
+	if err != nil {
+		return nil, err
+	}
+	return decodeObjectString(takeString(out))
+}
@@ -0,0 +1,449 @@
+package monty
+
+/*
+#include "monty_ffi.h"
+*/
+import "C"
+
+import (
+	"errors"
+	"runtime"
+	"unsafe"
+)
+
+// ObjectKind classifies the value behind an ObjectHandle.
+type ObjectKind int
+
+const (
+	// KindOther covers values without a dedicated accessor; read them with Object.
+	KindOther ObjectKind = iota
+	KindNone
+	KindBool
+	KindInt
+	KindFloat
+	KindString
+	KindBytes
+	KindList
+	KindTuple
+	KindDict
+)
+
+// ObjectHandle is a Monty value held by the FFI layer. Passing handles instead of
+// Object skips encoding large values as JSON. Building a list or dict, or resuming
+// a snapshot with a handle, consumes the handles passed in. Values are
+// reference-counted, so Retain and SetInputObject share rather than copy them.
+type ObjectHandle struct {
+	handle *C.MontyObjectHandle
+}
+
+// NewObjectHandle converts value once through JSON, like a Resume result.
+func NewObjectHandle(value any) (*ObjectHandle, error) {
+	payload, free, err := marshalValue(value)
+	if err != nil {
+		return nil, err
+	}
+	defer free()
+	var handle *C.MontyObjectHandle
+	if err := statusError(C.monty_object_from_json(payload, &handle)); err != nil {
+		return nil, err
+	}
+	return newObjectHandle(handle), nil
+}
+
+// NoneHandle returns a handle to None.
+func NoneHandle() *ObjectHandle {
+	return newObjectHandle(C.monty_object_none())
+}
+
+// BoolHandle returns a handle to a bool.
+func BoolHandle(value bool) *ObjectHandle {
+	return newObjectHandle(C.monty_object_bool(cBool(value)))
+}
+
+// IntHandle returns a handle to an int.
+func IntHandle(value int64) *ObjectHandle {
+	return newObjectHandle(C.monty_object_int(C.int64_t(value)))
+}
+
+// FloatHandle returns a handle to a float.
+func FloatHandle(value float64) *ObjectHandle {
+	return newObjectHandle(C.monty_object_float(C.double(value)))
+}
+
+// StringHandle copies value into a str object.
+func StringHandle(value string) (*ObjectHandle, error) {
+	data := []byte(value)
+	var handle *C.MontyObjectHandle
+	if err := statusError(C.monty_object_string(bytesPtr(data), C.size_t(len(data)), &handle)); err != nil {
+		return nil, err
+	}
+	return newObjectHandle(handle), nil
+}
+
+// BytesHandle copies value into a bytes object.
+func BytesHandle(value []byte) (*ObjectHandle, error) {
+	var handle *C.MontyObjectHandle
+	if err := statusError(C.monty_object_bytes(bytesPtr(value), C.size_t(len(value)), &handle)); err != nil {
+		return nil, err
+	}
+	return newObjectHandle(handle), nil
+}
+
+// ListHandle builds a list from items, consuming them.
+func ListHandle(items ...*ObjectHandle) (*ObjectHandle, error) {
+	raw, err := rawHandles(items)
+	if err != nil {
+		return nil, err
+	}
+	var handle *C.MontyObjectHandle
+	if err := statusError(C.monty_object_list(handlesPtr(raw), C.size_t(len(raw)), &handle)); err != nil {
+		return nil, err
+	}
+	consumeHandles(items)
+	return newObjectHandle(handle), nil
+}
+
+// DictHandle builds a dict from parallel keys and values, consuming them.
+func DictHandle(keys, values []*ObjectHandle) (*ObjectHandle, error) {
+	if len(keys) != len(values) {
+		return nil, errors.New("monty: dict keys and values differ in length")
+	}
+	rawKeys, err := rawHandles(keys)
+	if err != nil {
+		return nil, err
+	}
+	rawValues, err := rawHandles(values)
+	if err != nil {
+		return nil, err
+	}
+	var handle *C.MontyObjectHandle
+	status := C.monty_object_dict(handlesPtr(rawKeys), handlesPtr(rawValues), C.size_t(len(rawKeys)), &handle)
+	if err := statusError(status); err != nil {
+		return nil, err
+	}
+	consumeHandles(keys)
+	consumeHandles(values)
+	return newObjectHandle(handle), nil
+}
+
+// EmptyListHandle returns an empty list to fill with Append.
+func EmptyListHandle() *ObjectHandle {
+	return newObjectHandle(C.monty_list_new())
+}
+
+// EmptyDictHandle returns an empty dict to fill with SetItem.
+func EmptyDictHandle() *ObjectHandle {
+	return newObjectHandle(C.monty_dict_new())
+}
+
+// Append adds item to the end of a list, consuming item.
+func (h *ObjectHandle) Append(item *ObjectHandle) error {
+	if h == nil || h.handle == nil || item == nil || item.handle == nil {
+		return errors.New("monty: object handle closed")
+	}
+	if err := statusError(C.monty_list_push(h.handle, item.handle)); err != nil {
+		return err
+	}
+	item.handle = nil
+	return nil
+}
+
+// SetItem sets h[key] = value on a dict, consuming key and value. Keys are compared
+// one by one; use DictHandle to build large dicts at once.
+func (h *ObjectHandle) SetItem(key, value *ObjectHandle) error {
+	if h == nil || h.handle == nil || key == nil || key.handle == nil || value == nil || value.handle == nil {
+		return errors.New("monty: object handle closed")
+	}
+	if err := statusError(C.monty_dict_set(h.handle, key.handle, value.handle)); err != nil {
+		return err
+	}
+	key.handle = nil
+	value.handle = nil
+	return nil
+}
+
+// Kind reports what kind of value the handle holds.
+func (h *ObjectHandle) Kind() ObjectKind {
+	if h == nil || h.handle == nil {
+		return KindOther
+	}
+	return ObjectKind(C.monty_object_kind(h.handle))
+}
+
+// Len returns the number of items in a list, tuple, or dict, or of bytes in a
+// str or bytes value; 0 for anything else.
+func (h *ObjectHandle) Len() int {
+	if h == nil || h.handle == nil {
+		return 0
+	}
+	return int(C.monty_object_len(h.handle))
+}
+
+// Int returns the value of an int (or bool) that fits in 64 bits.
+func (h *ObjectHandle) Int() (int64, error) {
+	if h == nil || h.handle == nil {
+		return 0, errors.New("monty: object handle closed")
+	}
+	var out C.int64_t
+	if err := statusError(C.monty_object_as_int(h.handle, &out)); err != nil {
+		return 0, err
+	}
+	return int64(out), nil
+}
+
+// Float returns the value of a float or int.
+func (h *ObjectHandle) Float() (float64, error) {
+	if h == nil || h.handle == nil {
+		return 0, errors.New("monty: object handle closed")
+	}
+	var out C.double
+	if err := statusError(C.monty_object_as_float(h.handle, &out)); err != nil {
+		return 0, err
+	}
+	return float64(out), nil
+}
+
+// Bytes copies the contents of a bytes value, or the UTF-8 encoding of a str.
+func (h *ObjectHandle) Bytes() ([]byte, error) {
+	if h == nil || h.handle == nil {
+		return nil, errors.New("monty: object handle closed")
+	}
+	var data *C.uint8_t
+	var length C.size_t
+	if err := statusError(C.monty_object_as_bytes(h.handle, &data, &length)); err != nil {
+		return nil, err
+	}
+	if length == 0 {
+		return []byte{}, nil
+	}
+	return C.GoBytes(unsafe.Pointer(data), C.int(length)), nil
+}
+
+// Text returns the contents of a str value.
+func (h *ObjectHandle) Text() (string, error) {
+	if h.Kind() != KindString {
+		return "", errors.New("monty: object is not a str")
+	}
+	data, err := h.Bytes()
+	return string(data), err
+}
+
+// Index copies item i of a list or tuple into a new handle.
+func (h *ObjectHandle) Index(i int) (*ObjectHandle, error) {
+	if h == nil || h.handle == nil {
+		return nil, errors.New("monty: object handle closed")
+	}
+	var handle *C.MontyObjectHandle
+	if err := statusError(C.monty_object_item(h.handle, C.size_t(i), &handle)); err != nil {
+		return nil, err
+	}
+	return newObjectHandle(handle), nil
+}
+
+// Entry copies entry i of a dict, in insertion order, into new key and value handles.
+func (h *ObjectHandle) Entry(i int) (*ObjectHandle, *ObjectHandle, error) {
+	if h == nil || h.handle == nil {
+		return nil, nil, errors.New("monty: object handle closed")
+	}
+	var key, value *C.MontyObjectHandle
+	if err := statusError(C.monty_object_dict_item(h.handle, C.size_t(i), &key, &value)); err != nil {
+		return nil, nil, err
+	}
+	return newObjectHandle(key), newObjectHandle(value), nil
+}
+
+// Type returns the Python class name of the value.
+func (h *ObjectHandle) Type() (string, error) {
+	if h == nil || h.handle == nil {
+		return "", errors.New("monty: object handle closed")
+	}
+	var out *C.char
+	if err := statusError(C.monty_object_type(h.handle, &out)); err != nil {
+		return "", err
+	}
+	defer C.monty_free_string(out)
+	return C.GoString(out), nil
+}
+
+// GetItem copies h[key] into a new handle; key is not consumed.
+func (h *ObjectHandle) GetItem(key *ObjectHandle) (*ObjectHandle, error) {
+	if h == nil || h.handle == nil || key == nil || key.handle == nil {
+		return nil, errors.New("monty: object handle closed")
+	}
+	var handle *C.MontyObjectHandle
+	if err := statusError(C.monty_object_getitem(h.handle, key.handle, &handle)); err != nil {
+		return nil, err
+	}
+	return newObjectHandle(handle), nil
+}
+
+// GetAttr copies an attribute of a dataclass or named tuple into a new handle.
+func (h *ObjectHandle) GetAttr(name string) (*ObjectHandle, error) {
+	if h == nil || h.handle == nil {
+		return nil, errors.New("monty: object handle closed")
+	}
+	cName, freeName := cString(name)
+	defer freeName()
+	var handle *C.MontyObjectHandle
+	if err := statusError(C.monty_object_getattr(h.handle, cName, &handle)); err != nil {
+		return nil, err
+	}
+	return newObjectHandle(handle), nil
+}
+
+// ObjectIterator walks the items of an ObjectHandle one at a time.
+type ObjectIterator struct {
+	handle *C.MontyIteratorHandle
+	// parent keeps the iterated object alive while the iterator borrows it.
+	parent *ObjectHandle
+}
+
+// Iter iterates the value as Python would: items of a list, tuple, or set, keys of
+// a dict. Closing h while iterating ends the iteration with an error.
+func (h *ObjectHandle) Iter() (*ObjectIterator, error) {
+	if h == nil || h.handle == nil {
+		return nil, errors.New("monty: object handle closed")
+	}
+	var handle *C.MontyIteratorHandle
+	if err := statusError(C.monty_object_iter(h.handle, &handle)); err != nil {
+		return nil, err
+	}
+	it := &ObjectIterator{handle: handle, parent: h}
+	runtime.SetFinalizer(it, func(it *ObjectIterator) { it.Close() })
+	return it, nil
+}
+
+// Next returns the next item, or nil once the iterator is exhausted.
+func (it *ObjectIterator) Next() (*ObjectHandle, error) {
+	if it == nil || it.handle == nil {
+		return nil, errors.New("monty: iterator closed")
+	}
+	if it.parent.handle == nil {
+		return nil, errors.New("monty: iterated object handle closed")
+	}
+	var handle *C.MontyObjectHandle
+	if err := statusError(C.monty_iterator_next(it.handle, &handle)); err != nil {
+		return nil, err
+	}
+	if handle == nil {
+		return nil, nil
+	}
+	return newObjectHandle(handle), nil
+}
+
+// Close frees the iterator.
+func (it *ObjectIterator) Close() {
+	if it != nil && it.handle != nil {
+		C.monty_iterator_free_and_clear(&it.handle)
+		it.parent = nil
+	}
+}
+
+// Object encodes the value as JSON, the same form Progress uses without handles.
+func (h *ObjectHandle) Object() (Object, error) {
+	if h == nil || h.handle == nil {
+		return nil, errors.New("monty: object handle closed")
+	}
+	var out *C.char
+	if err := statusError(C.monty_object_to_json(h.handle, &out)); err != nil {
+		return nil, err
+	}
+	defer C.monty_free_string(out)
+	return decodeObjectString(C.GoString(out))
+}
+
+// Retain returns a new handle sharing the same value; each is closed separately.
+func (h *ObjectHandle) Retain() *ObjectHandle {
+	if h == nil || h.handle == nil {
+		return nil
+	}
+	return newObjectHandle(C.monty_object_retain(h.handle))
+}
+
+// SetInputObject binds value to InputObject(name) in every later Start of m. m
+// holds a reference to the value rather than a copy, and value stays owned by the
+// caller; each Start copies it into the run without going through JSON. A nil
+// value unbinds the name. It may be called while other goroutines start m.
+func (m *Monty) SetInputObject(name string, value *ObjectHandle) error {
+	if m == nil || m.handle == nil {
+		return errors.New("monty: nil handle")
+	}
+	var raw *C.MontyObjectHandle
+	if value != nil {
+		if value.handle == nil {
+			return errors.New("monty: object handle closed")
+		}
+		raw = value.handle
+	}
+	cName, freeName := cString(name)
+	defer freeName()
+	return statusError(C.monty_run_set_input_object(m.handle, cName, raw))
+}
+
+// Close frees the value.
+func (h *ObjectHandle) Close() {
+	if h != nil && h.handle != nil {
+		C.monty_object_free_and_clear(&h.handle)
+	}
+}
+
+// ResumeHandle resumes a call with a value passed by handle, consuming it.
+func (s *Snapshot) ResumeHandle(callID uint32, value *ObjectHandle) (Progress, error) {
+	if s == nil || s.handle == nil {
+		return Progress{}, errors.New("monty: snapshot closed")
+	}
+	if value == nil || value.handle == nil {
+		return Progress{}, errors.New("monty: object handle closed")
+	}
+	var raw C.ProgressResult
+	status := C.monty_snapshot_resume_object(s.handle, C.uint32_t(callID), value.handle, &raw)
+	if consumed(status) {
+		s.handle = nil
+		value.handle = nil
+	}
+	defer C.monty_progress_result_free(&raw)
+	if err := statusError(status); err != nil {
+		return Progress{}, err
+	}
+	return convertProgress(&raw)
+}
+
+func newObjectHandle(handle *C.MontyObjectHandle) *ObjectHandle {
+	h := &ObjectHandle{handle: handle}
+	runtime.SetFinalizer(h, func(h *ObjectHandle) { h.Close() })
+	return h
+}
+
+// takeObjectHandle wraps a handle from a ProgressResult, taking ownership of it.
+func takeObjectHandle(handle **C.MontyObjectHandle) *ObjectHandle {
+	if *handle == nil {
+		return nil
+	}
+	h := newObjectHandle(*handle)
+	*handle = nil
+	return h
+}
+
+func rawHandles(items []*ObjectHandle) ([]*C.MontyObjectHandle, error) {
+	raw := make([]*C.MontyObjectHandle, len(items))
+	for i, item := range items {
+		if item == nil || item.handle == nil {
+			return nil, errors.New("monty: object handle closed")
+		}
+		raw[i] = item.handle
+	}
+	return raw, nil
+}
+
+func handlesPtr(raw []*C.MontyObjectHandle) **C.MontyObjectHandle {
+	if len(raw) == 0 {
+		return nil
+	}
+	return &raw[0]
+}
+
+func consumeHandles(items []*ObjectHandle) {
+	for _, item := range items {
+		item.handle = nil
+	}
+}
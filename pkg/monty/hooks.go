@@ -0,0 +1,80 @@
+package monty
+
+/*
+#include "monty_ffi.h"
+*/
+import "C"
+
+import "errors"
+
+// Helpers for monty_test.go, which can't call the C API itself since cgo isn't
+// available in test files. They reach the parts of ProgressResult the package's own
+// API hides.
+
+// startMessage starts m and returns its first progress as a protocol message, then
+// frees the result without taking its handles.
+func startMessage(m *Monty, inputs ...any) (string, error) {
+	payload, freePayload, err := marshalInputs(inputs)
+	if err != nil {
+		return "", err
+	}
+	defer freePayload()
+
+	var raw C.ProgressResult
+	status := C.monty_run_start(m.handle, payload, &raw)
+	defer C.monty_progress_result_free(&raw)
+	if err := statusError(status); err != nil {
+		return "", err
+	}
+	var out *C.char
+	if err := statusError(C.monty_progress_result_to_json(&raw, &out)); err != nil {
+		return "", err
+	}
+	return takeString(out), nil
+}
+
+// startAndFree starts m and frees the progress result with its snapshot untaken.
+func startAndFree(m *Monty) error {
+	var raw C.ProgressResult
+	status := C.monty_run_start(m.handle, nil, &raw)
+	C.monty_progress_result_free(&raw)
+	if raw.snapshot != nil || raw.function_name != nil {
+		return errors.New("monty: progress result not cleared")
+	}
+	return statusError(status)
+}
+
+// startAndTake starts m and takes its snapshot out of the progress result, checking
+// that the field is cleared, a second take finds nothing, and freeing the result
+// leaves the taken snapshot alone.
+func startAndTake(m *Monty) (*Snapshot, uint32, error) {
+	var raw C.ProgressResult
+	status := C.monty_run_start(m.handle, nil, &raw)
+	defer C.monty_progress_result_free(&raw)
+	if err := statusError(status); err != nil {
+		return nil, 0, err
+	}
+	var taken, again *C.SnapshotHandle
+	var future *C.FutureSnapshotHandle
+	if err := statusError(C.monty_progress_result_take_snapshot(&raw, &taken)); err != nil {
+		return nil, 0, err
+	}
+	if err := statusError(C.monty_progress_result_take_snapshot(&raw, &again)); err != nil {
+		return nil, 0, err
+	}
+	if err := statusError(C.monty_progress_result_take_future_snapshot(&raw, &future)); err != nil {
+		return nil, 0, err
+	}
+	switch {
+	case taken == nil:
+		return nil, 0, errors.New("monty: no snapshot taken")
+	case raw.snapshot != nil || again != nil:
+		C.monty_snapshot_free(taken)
+		return nil, 0, errors.New("monty: snapshot left in the progress result")
+	case future != nil:
+		C.monty_snapshot_free(taken)
+		C.monty_future_snapshot_free(future)
+		return nil, 0, errors.New("monty: unexpected future snapshot")
+	}
+	return newSnapshot(taken), uint32(raw.call_id), nil
+}
@@ -13,10 +13,13 @@ package monty
 import "C"
 
 import (
+	"context"
 	"encoding/json"
 	"errors"
 	"fmt"
 	"runtime"
+	"strconv"
+	"time"
 	"unsafe"
 )
 
@@ -24,10 +27,22 @@ import (
 type ProgressKind int
 
 const (
-	Complete ProgressKind = iota
-	FunctionCall
-	OsCall
-	ResolveFutures
+	Complete       ProgressKind = C.MONTY_PROGRESS_COMPLETE
+	FunctionCall   ProgressKind = C.MONTY_PROGRESS_FUNCTION_CALL
+	OsCall         ProgressKind = C.MONTY_PROGRESS_OS_CALL
+	ResolveFutures ProgressKind = C.MONTY_PROGRESS_RESOLVE_FUTURES
+	// LazyInput asks for the value of an input passed as Lazy before the run starts.
+	LazyInput      ProgressKind = C.MONTY_PROGRESS_INPUT_REQUEST
+	// Emit carries a partial result the script passed to host_emit(value) as Result.
+	// Continue the run with Snapshot.Resume(CallID, nil).
+	Emit           ProgressKind = C.MONTY_PROGRESS_EMIT
+	// Sleep asks the host to wait Delay, e.g. by parking the snapshot on a timer, and
+	// then continue the run with Snapshot.Resume(CallID, nil).
+	Sleep          ProgressKind = C.MONTY_PROGRESS_SLEEP
+	// Prompt asks for a line of input for the script's input(prompt) call; continue
+	// the run with Snapshot.Resume(CallID, line). FunctionName is "input" and Args
+	// holds the prompt, so journals record it like a call.
+	Prompt         ProgressKind = C.MONTY_PROGRESS_PROMPT
 )
 
 // Progress represents the result of a start/resume call.
@@ -43,13 +58,389 @@ type Progress struct {
 	Snapshot       *Snapshot
 	PendingIDs     []uint32
 	FutureSnapshot *FutureSnapshot
+	PendingCalls   []PendingCall
+	Stats          *RunStats
+	Timeout        time.Duration
+	InputName      string
+	InputRequest   *InputRequest
+	Receiver       Object
+	// ResultHandle, ArgsHandle (a list), and KwargsHandle (a dict) replace Result,
+	// Args, and Kwargs when the run was started with Options.ObjectHandles.
+	ResultHandle   *ObjectHandle
+	ArgsHandle     *ObjectHandle
+	KwargsHandle   *ObjectHandle
+	// Trace is the run's Chrome trace_event JSON on completion, when it was started
+	// with Options.Trace.
+	Trace          json.RawMessage
+	// AuditLog lists the OS calls the script attempted, on completion of a run
+	// started with Options.AuditOsCalls.
+	AuditLog       []AuditEntry
+	// Warnings are non-fatal problems since the previous progress, such as dropped
+	// output or a limit nearly used up.
+	Warnings       []Warning
+	// Output is what the script printed since the previous progress, kept per run so
+	// concurrent runs don't interleave. It is empty with Options.PrintToStdout.
+	Output         string
+	// Delay is how long a Sleep progress asks the host to wait.
+	Delay          time.Duration
+	// Priority is the call's Options.CallPriorities entry; PendingIDs are listed
+	// highest priority first.
+	Priority       int
+	// Builtin is set when a FunctionCall is to a builtin overridden with
+	// Monty.OverrideBuiltins rather than a declared external function.
+	Builtin        bool
+	// Prompt is what a Prompt progress asks the user, empty when input() was called
+	// without one.
+	Prompt         string
+	// IdempotencyKey identifies a FunctionCall across retries, for deduping side
+	// effects such as payments: the script's idempotency_key= string argument, or a
+	// key derived from Options.RunID and the call ID, which is the same whenever the
+	// call is reported again from the same snapshot or journal. It is empty for runs
+	// without a run ID unless the script passes one.
+	IdempotencyKey string
+	// CacheKey is set on a FunctionCall to one of Options.CacheCalls: the host can
+	// keep the result under it to answer the same call in later runs.
+	CacheKey       string
+}
+
+// RunStats summarizes a run's resource usage; it is reported on completion.
+type RunStats struct {
+	// Instructions counts VM steps: the times Monty checked the run's deadline
+	// between opcodes, which need not be every opcode. MaxInstructions, the
+	// CostModel's Instruction weight and Metrics count the same steps.
+	Instructions   uint64 `json:"instructions"`
+	Allocations    uint64 `json:"allocations"`
+	Memory         uint64 `json:"memory"`
+	PeakMemory     uint64 `json:"peak_memory"`
+	ExternalCalls  uint64 `json:"external_calls"`
+	OsCalls        uint64 `json:"os_calls"`
+	BytesPrinted   uint64 `json:"bytes_printed"`
+	ElapsedUs      uint64 `json:"elapsed_us"`
+	// AllocatedBytes counts every allocation, including memory since freed.
+	AllocatedBytes uint64 `json:"allocated_bytes"`
+	// Cost weighs the counters above by the run's CostModel.
+	Cost           uint64 `json:"cost"`
+}
+
+// Metrics are process-wide counters summed over every run since the library was
+// loaded, for health dashboards. Per-run counters are RunStats.
+type Metrics struct {
+	RunsStarted           uint64 `json:"runs_started"`
+	RunsCompleted         uint64 `json:"runs_completed"`
+	// ActiveSnapshots and ActiveFutureSnapshots count handles not yet resumed or closed.
+	ActiveSnapshots       uint64 `json:"active_snapshots"`
+	ActiveFutureSnapshots uint64 `json:"active_future_snapshots"`
+	InstructionsExecuted  uint64 `json:"instructions_executed"`
+	// BytesSerialized is the total size of snapshot and future snapshot dumps.
+	BytesSerialized       uint64 `json:"bytes_serialized"`
+}
+
+// LimitChange reports how one limit moved in SetLimits. Removing a limit counts as
+// raising it; limiting a previously unlimited resource counts as lowering it.
+type LimitChange string
+
+const (
+	LimitUnchanged LimitChange = "unchanged"
+	LimitRaised    LimitChange = "raised"
+	LimitLowered   LimitChange = "lowered"
+)
+
+// LimitChanges mirrors ResourceLimits with the direction each limit moved.
+type LimitChanges struct {
+	MaxInstructions   LimitChange `json:"max_instructions"`
+	MaxAllocations    LimitChange `json:"max_allocations"`
+	MaxMemory         LimitChange `json:"max_memory"`
+	MaxDurationMs     LimitChange `json:"max_duration_ms"`
+	MaxRecursionDepth LimitChange `json:"max_recursion_depth"`
+	MaxCost           LimitChange `json:"max_cost"`
+	MaxObjectSize     LimitChange `json:"max_object_size"`
+	MaxNestingDepth   LimitChange `json:"max_nesting_depth"`
+	MaxPrintBytes     LimitChange `json:"max_print_bytes"`
+}
+
+// Budget is the amount left under each configured limit; nil fields are unlimited.
+type Budget struct {
+	Instructions *uint64 `json:"instructions"`
+	Allocations  *uint64 `json:"allocations"`
+	Memory       *uint64 `json:"memory"`
+	TimeMs       *uint64 `json:"time_ms"`
+	Cost         *uint64 `json:"cost"`
+}
+
+// Options configures a single run started with StartWithOptions.
+type Options struct {
+	Limits         ResourceLimits    `json:"limits"`
+	CallTimeouts   CallTimeouts      `json:"call_timeouts"`
+	// Globals seeds module-level variables for this run only, visible to every
+	// function in the script; they take precedence over attached Globals.
+	Globals        map[string]any    `json:"globals,omitempty"`
+	ReturnMismatch ReturnMismatch    `json:"return_mismatch,omitempty"`
+	// Dataclasses lets calls declared to return one of these classes be resumed
+	// with a plain map keyed by field name instead of a "$dataclass" object.
+	Dataclasses    []DataclassSchema `json:"dataclasses,omitempty"`
+	// ObjectHandles reports results and call arguments as ObjectHandles instead of
+	// JSON, for large values on hot paths.
+	ObjectHandles  bool              `json:"object_handles,omitempty"`
+	// Trace records a Chrome trace of VM steps and external and OS calls, reported
+	// as Progress.Trace on completion and by Snapshot.Trace while suspended.
+	Trace          bool              `json:"trace,omitempty"`
+	// RunID identifies the run in telemetry spans and is kept in snapshot dumps. One
+	// is generated when it is empty and a Tracer is installed.
+	RunID          string            `json:"run_id,omitempty"`
+	// Context parents the run's telemetry spans; see SetTracer.
+	Context        context.Context   `json:"-"`
+	// AuditOsCalls keeps a log of every OS call the script attempts, reported as
+	// Progress.AuditLog on completion and by Snapshot.AuditLog while suspended.
+	AuditOsCalls   bool              `json:"audit_os_calls,omitempty"`
+	// Policy limits which external and OS functions the script may call.
+	Policy         Policy            `json:"policy"`
+	// Pure makes every OS call raise OSError in the script without being reported,
+	// for hosts that only run pure computation and never handle OsCall progress.
+	Pure           bool              `json:"pure,omitempty"`
+	// CostModel weighs the RunStats.Cost counter that ResourceLimits.MaxCost bounds.
+	CostModel      CostModel         `json:"cost_model"`
+	// PrintOverflow selects what happens to output past ResourceLimits.MaxPrintBytes.
+	PrintOverflow  PrintOverflow     `json:"print_overflow,omitempty"`
+	// ErrorFormat selects how an uncaught exception is written to the returned error.
+	ErrorFormat    ErrorFormat       `json:"error_format,omitempty"`
+	// PrintToStdout writes output straight to the process stdout, where concurrent
+	// runs interleave, instead of reporting it as Progress.Output.
+	PrintToStdout  bool              `json:"print_to_stdout,omitempty"`
+	// Resolution selects when FutureSnapshot.Resume lets the script continue.
+	Resolution     Resolution        `json:"future_resolution,omitempty"`
+	CallPriorities CallPriorities    `json:"call_priorities"`
+	// CacheCalls lists external functions without side effects whose results answer
+	// later calls with the same arguments in the same run, so the run doesn't suspend
+	// for them again. The cache is kept in snapshot dumps.
+	CacheCalls     []string          `json:"cache_calls,omitempty"`
+	// Awaitable lists the external and OS functions the script awaits. ResumeBatch
+	// defers calls to them as futures when it has no result for them.
+	Awaitable      []string          `json:"awaitable,omitempty"`
+	// LossyUTF8 decodes inputs and the payloads the run is resumed with using U+FFFD
+	// replacement characters where they are not valid UTF-8, instead of failing. Go's
+	// JSON encoding already replaces invalid UTF-8 in the values it encodes.
+	LossyUTF8      bool              `json:"lossy_utf8,omitempty"`
+}
+
+// CostModel sets what each tracked resource adds to a run's cost, so a budget can
+// reflect real work rather than raw VM steps. Zero fields add nothing, except a nil
+// Instruction, which keeps its default of 1; point it at 0 to stop charging for steps.
+// Weights apply per category: Monty does not report which opcode a step ran.
+type CostModel struct {
+	Instruction  *uint64 `json:"instruction,omitempty"`
+	Allocation   uint64  `json:"allocation,omitempty"`
+	// AllocatedKiB is charged per KiB allocated.
+	AllocatedKiB uint64  `json:"allocated_kib,omitempty"`
+	ExternalCall uint64  `json:"external_call,omitempty"`
+	OsCall       uint64  `json:"os_call,omitempty"`
+}
+
+// Policy lists the external and OS functions a run may call. A call it does not
+// permit is never reported to the host: it raises PermissionError in the script, or
+// fails the run with ViolationFail. The policy is kept in snapshot dumps.
+type Policy struct {
+	// Functions matches external function names as declared (e.g. "db.query").
+	Functions   PolicyRule `json:"functions"`
+	// OsFunctions matches OS function names such as "Path.read_text".
+	OsFunctions PolicyRule `json:"os_functions"`
+	OnViolation Violation  `json:"on_violation,omitempty"`
+}
+
+// PolicyRule permits a name when no Deny entry matches it and Allow is nil or has a
+// matching entry; an empty non-nil Allow permits nothing. Entries match exactly, or
+// by prefix when they end in "*" ("Path.*").
+type PolicyRule struct {
+	Allow []string `json:"allow"`
+	Deny  []string `json:"deny,omitempty"`
+}
+
+// Violation selects what happens when a script calls a function its Policy does not
+// permit.
+type Violation string
+
+const (
+	// ViolationRaise raises PermissionError at the call site in the script (default).
+	ViolationRaise Violation = "raise"
+	// ViolationFail fails the run with an error.
+	ViolationFail Violation = "fail"
+)
+
+// AuditOutcome is how the host resolved an audited OS call.
+type AuditOutcome string
+
+const (
+	// AuditPending means the call has not been resolved (yet).
+	AuditPending AuditOutcome = "pending"
+	// AuditAllowed means the call was resumed with a value.
+	AuditAllowed AuditOutcome = "allowed"
+	// AuditDenied means the call was resumed with an exception.
+	AuditDenied AuditOutcome = "denied"
+)
+
+// AuditEntry records one OS call a script attempted.
+type AuditEntry struct {
+	CallID   uint32       `json:"call_id"`
+	Function string       `json:"function"`
+	// Args is a Python repr of the arguments, cut to 200 characters.
+	Args     string       `json:"args"`
+	// AtUs is when the call was made, in microseconds since the run started.
+	AtUs     uint64       `json:"at_us"`
+	Outcome  AuditOutcome `json:"outcome"`
+	// Error summarizes the exception a denied call was resumed with.
+	Error    string       `json:"error,omitempty"`
+}
+
+// WarningKind classifies a Warning.
+type WarningKind string
+
+const (
+	// WarningOutputTruncated means output past ResourceLimits.MaxPrintBytes was dropped.
+	WarningOutputTruncated WarningKind = "output_truncated"
+	// WarningNearLimit means the run has used 90% of one of its limits; it is reported
+	// once per limit.
+	WarningNearLimit WarningKind = "near_limit"
+)
+
+// Warning is a non-fatal problem a run reports for the host to surface.
+type Warning struct {
+	Kind    WarningKind `json:"kind"`
+	Message string      `json:"message"`
+}
+
+// DataclassSchema describes a dataclass a plain result map can be built into.
+type DataclassSchema struct {
+	Name   string   `json:"name"`
+	TypeID uint64   `json:"type_id"`
+	Fields []string `json:"fields"`
+	Frozen bool     `json:"frozen,omitempty"`
+}
+
+// ReturnMismatch selects what happens when a call is resumed with a value that does
+// not match the return type declared in its signature (e.g. "fetch(url) -> str").
+type ReturnMismatch string
+
+const (
+	// ReturnMismatchRaise raises TypeError at the call site in the script (default).
+	ReturnMismatchRaise ReturnMismatch = "raise"
+	// ReturnMismatchFail makes the resume itself return ErrReturnMismatch, leaving the
+	// snapshot open so the call can be resumed again.
+	ReturnMismatchFail ReturnMismatch = "fail"
+)
+
+// ErrReturnMismatch is returned by a resume rejected under ReturnMismatchFail.
+var ErrReturnMismatch = errors.New("monty: result does not match the declared return type")
+
+// PrintOverflow selects what happens when a script prints more than
+// ResourceLimits.MaxPrintBytes.
+type PrintOverflow string
+
+const (
+	// PrintTruncate prints up to the limit, then an "[output truncated]" marker, and
+	// drops the rest (default).
+	PrintTruncate PrintOverflow = "truncate"
+	// PrintRaise raises RuntimeError from the print call that crosses the limit.
+	PrintRaise PrintOverflow = "raise"
+)
+
+// Resolution selects when results passed to FutureSnapshot.Resume reach the script.
+type Resolution string
+
+const (
+	// ResolveAsCompleted resumes the script with whatever results each Resume passes,
+	// so it sees every future as soon as the host does (default).
+	ResolveAsCompleted Resolution = "as_completed"
+	// ResolveGather holds results back until every pending future has one. Until then
+	// Resume reports ResolveFutures again, with PendingIDs listing the calls still
+	// without a result.
+	ResolveGather Resolution = "gather"
+)
+
+// ErrorFormat selects how an exception that ends a run is reported as an error.
+type ErrorFormat string
+
+const (
+	// ErrorSummary reports one line, "Type: message" (default), for compact logs.
+	ErrorSummary ErrorFormat = "summary"
+	// ErrorTraceback reports the full multi-line traceback, for CLIs.
+	ErrorTraceback ErrorFormat = "traceback"
+)
+
+// CallTimeouts declares how long the host may take to resolve external and OS calls
+// before resuming them with a timeout error. The deadline is reported on each call's
+// Progress (or PendingCall); Monty itself does not enforce it. Timeouts are sent in
+// whole milliseconds, rounded up so a sub-millisecond timeout is not read as none.
+type CallTimeouts struct {
+	// Default applies to calls without an entry in Functions.
+	Default time.Duration
+	// Functions is keyed by external function name or OS function name.
+	Functions map[string]time.Duration
+}
+
+// MarshalJSON encodes the timeouts in the millisecond form the FFI layer expects.
+func (c CallTimeouts) MarshalJSON() ([]byte, error) {
+	payload := map[string]any{}
+	if c.Default > 0 {
+		payload["default_ms"] = ceilMillis(c.Default)
+	}
+	if len(c.Functions) > 0 {
+		functions := make(map[string]int64, len(c.Functions))
+		for name, timeout := range c.Functions {
+			functions[name] = ceilMillis(timeout)
+		}
+		payload["functions"] = functions
+	}
+	return json.Marshal(payload)
+}
+
+// ceilMillis converts d to milliseconds, rounding any remainder up.
+func ceilMillis(d time.Duration) int64 {
+	return int64((d + time.Millisecond - 1) / time.Millisecond)
+}
+
+// CallPriorities hints which external and OS calls the host should run first when it
+// dispatches them to a worker pool; higher runs sooner. Priorities are reported on
+// each call's Progress (or PendingCall) and order the pending calls of a
+// FutureSnapshot; Monty itself does not act on them.
+type CallPriorities struct {
+	// Default applies to calls without an entry in Functions.
+	Default   int            `json:"default,omitempty"`
+	// Functions is keyed by external function name or OS function name.
+	Functions map[string]int `json:"functions,omitempty"`
+}
+
+// ResourceLimits bounds a run's resource usage; zero fields are unlimited.
+// The consumed budget is carried through snapshots, including dumped ones.
+type ResourceLimits struct {
+	MaxInstructions   uint64 `json:"max_instructions,omitempty"`
+	MaxAllocations    uint64 `json:"max_allocations,omitempty"`
+	MaxMemory         uint64 `json:"max_memory,omitempty"`
+	MaxDurationMs     uint64 `json:"max_duration_ms,omitempty"`
+	MaxRecursionDepth uint64 `json:"max_recursion_depth,omitempty"`
+	// MaxCost bounds RunStats.Cost; see Options.CostModel.
+	MaxCost           uint64 `json:"max_cost,omitempty"`
+	// MaxObjectSize caps each allocation in bytes, so a single string, bytes value,
+	// or collection can't exhaust MaxMemory; a larger one raises MemoryError.
+	MaxObjectSize     uint64 `json:"max_object_size,omitempty"`
+	// MaxNestingDepth caps how deeply containers nest in call arguments (raising
+	// ValueError in the script) and in the result (failing the run).
+	MaxNestingDepth   uint64 `json:"max_nesting_depth,omitempty"`
+	// MaxPrintBytes caps the script's printed output; see Options.PrintOverflow.
+	MaxPrintBytes     uint64 `json:"max_print_bytes,omitempty"`
 }
 
 // FutureResult matches the JSON shape accepted by monty_future_snapshot_resume.
+// ErrType names the exception class raised for Err (RuntimeError when empty), and
+// Exception takes precedence over both when set.
+// Setting Cancelled raises CancelledError at the script's await, with Err as its message;
+// setting TimedOut raises TimeoutError the same way, for calls the host gave up on.
 type FutureResult struct {
-	CallID uint32
-	Result any
-	Err    string
+	CallID    uint32
+	Result    any
+	Err       string
+	ErrType   string
+	Exception *Exception
+	Cancelled bool
+	TimedOut  bool
 }
 
 // Monty wraps a compiled MontyRun handle.
@@ -57,19 +448,99 @@ type Monty struct {
 	handle *C.MontyRunHandle
 }
 
+// Globals is a read-only set of named module-level variables, decoded once and
+// shared by reference between every Monty it is attached to. Each run still gets
+// its own copy of the values inside the interpreter.
+type Globals struct {
+	handle *C.GlobalsHandle
+}
+
 // Snapshot holds a paused synchronous execution state.
 type Snapshot struct {
 	handle *C.SnapshotHandle
+	trace  *runTrace
 }
 
 // FutureSnapshot holds a paused async execution state.
 type FutureSnapshot struct {
 	handle  *C.FutureSnapshotHandle
 	pending []uint32
+	trace   *runTrace
+}
+
+// InputRequest holds a run that is waiting for a lazy input before it starts.
+type InputRequest struct {
+	handle *C.InputRequestHandle
+	trace  *runTrace
+}
+
+// SetStrictEncoding turns strict encoding on or off for the whole process. When on,
+// results and call arguments holding a value with no lossless JSON form, such as a
+// function or a cycle, fail with an error naming its type and path (e.g. $['f'])
+// instead of coming out as {"$repr": ...}.
+func SetStrictEncoding(enabled bool) {
+	var flag C.int32_t
+	if enabled {
+		flag = 1
+	}
+	C.monty_set_strict_encoding(flag)
+}
+
+// SetFloatStrings turns float strings on or off for the whole process. When on,
+// floats in results and call arguments come out as {"$float": "0.1"}, the shortest
+// decimal that parses back to the same value, so archived results compare byte for
+// byte. Inputs and results may pass {"$float": ...} either way.
+func SetFloatStrings(enabled bool) {
+	var flag C.int32_t
+	if enabled {
+		flag = 1
+	}
+	C.monty_set_float_strings(flag)
 }
 
-// New compiles Python code into a Monty handle.
+// SetStrictNumbers turns strict number decoding on or off for the whole process. When
+// on, inputs, results, and globals holding a JSON number that would not decode exactly
+// fail instead: an integer too large for 64 bits, which would become a float, or a
+// decimal with more digits than a float holds. Pass those as {"$bigint": "..."} or as
+// strings.
+func SetStrictNumbers(enabled bool) {
+	var flag C.int32_t
+	if enabled {
+		flag = 1
+	}
+	C.monty_set_strict_numbers(flag)
+}
+
+// SetCanonicalEncoding turns canonical encoding on or off for the whole process. When
+// on, results and call arguments that compare equal in Python encode to identical
+// bytes: dict entries and set items are sorted, and numbers are normalized. Otherwise
+// dicts keep their insertion order.
+func SetCanonicalEncoding(enabled bool) {
+	var flag C.int32_t
+	if enabled {
+		flag = 1
+	}
+	C.monty_set_canonical_encoding(flag)
+}
+
+// New compiles Python code into a Monty handle. External functions may use dotted
+// names such as "db.query": the script calls them as db.query(...), and the call
+// is reported with the full dotted FunctionName; calling an undeclared member such
+// as db.drop(...) raises AttributeError in the script. They may also be declared with a
+// Python signature such as "fetch(url, *, retries=3) -> str"; calls that do not
+// match it raise TypeError in the script without being reported, and results that
+// do not match the return type are handled per Options.ReturnMismatch.
 func New(code, scriptName string, inputNames, extFuncs []string) (*Monty, error) {
+	return compile(code, scriptName, inputNames, extFuncs, false)
+}
+
+// NewLossy behaves like New, but code that is not valid UTF-8 is decoded with U+FFFD
+// replacement characters instead of failing.
+func NewLossy(code, scriptName string, inputNames, extFuncs []string) (*Monty, error) {
+	return compile(code, scriptName, inputNames, extFuncs, true)
+}
+
+func compile(code, scriptName string, inputNames, extFuncs []string, lossy bool) (*Monty, error) {
 	cCode, freeCode := cString(code)
 	defer freeCode()
 	cScript, freeScript := cString(scriptName)
@@ -80,230 +551,951 @@ func New(code, scriptName string, inputNames, extFuncs []string) (*Monty, error)
 	defer freeExts()
 
 	var out *C.MontyRunHandle
-	status := C.monty_run_new(cCode, cScript, (**C.char)(inputs), (**C.char)(exts), &out)
+	var status C.MontyStatus
+	if lossy {
+		status = C.monty_run_new_lossy(cCode, cScript, (**C.char)(inputs), (**C.char)(exts), &out)
+	} else {
+		status = C.monty_run_new(cCode, cScript, (**C.char)(inputs), (**C.char)(exts), &out)
+	}
+	if err := statusError(status); err != nil {
+		return nil, err
+	}
+	return newMonty(out), nil
+}
+
+// NewFromBytes restores a Monty handle from postcard bytes.
+func NewFromBytes(data []byte) (*Monty, error) {
+	if len(data) == 0 {
+		return nil, errors.New("monty: empty snapshot")
+	}
+	var out *C.MontyRunHandle
+	status := C.monty_run_load((*C.uint8_t)(unsafe.Pointer(&data[0])), C.size_t(len(data)), &out)
+	if err := statusError(status); err != nil {
+		return nil, err
+	}
+	return newMonty(out), nil
+}
+
+// Dump serializes the compiled Monty run to postcard bytes.
+func (m *Monty) Dump() ([]byte, error) {
+	if m == nil || m.handle == nil {
+		return nil, errors.New("monty: nil handle")
+	}
+	var buf *C.uint8_t
+	var length C.size_t
+	status := C.monty_run_dump(m.handle, &buf, &length)
+	if err := statusError(status); err != nil {
+		return nil, err
+	}
+	return copyBytes(buf, length), nil
+}
+
+// Run executes code to completion in one shot.
+func (m *Monty) Run(inputs ...any) (Object, error) {
+	progress, err := m.Start(inputs...)
+	if err != nil {
+		return nil, err
+	}
+	if progress.Kind != Complete {
+		return nil, fmt.Errorf("monty: execution paused unexpectedly (%v)", progress.Kind)
+	}
+	return progress.Result, nil
+}
+
+// RunResultJSON is Run through monty_run_result_json, the entry point for bindings
+// that can't use ProgressResult: the run must complete without suspending.
+func (m *Monty) RunResultJSON(opts Options, inputs ...any) (Object, error) {
+	if m == nil || m.handle == nil {
+		return nil, errors.New("monty: nil handle")
+	}
+	payload, freePayload, err := marshalInputs(inputs)
+	if err != nil {
+		return nil, err
+	}
+	defer freePayload()
+	if opts.Globals, err = normalizeGlobals(opts.Globals); err != nil {
+		return nil, err
+	}
+	data, err := json.Marshal(opts)
+	if err != nil {
+		return nil, err
+	}
+	optsC, freeOpts := cBytes(data)
+	defer freeOpts()
+
+	out := C.monty_run_result_json(m.handle, payload, optsC)
+	if out == nil {
+		return nil, errors.New("monty: monty_run_result_json returned null")
+	}
+	var envelope struct {
+		Result json.RawMessage `json:"result"`
+		Error  *string         `json:"error"`
+	}
+	if err := json.Unmarshal([]byte(takeString(out)), &envelope); err != nil {
+		return nil, err
+	}
+	if envelope.Error != nil {
+		return nil, errors.New(*envelope.Error)
+	}
+	return decodeObjectString(string(envelope.Result))
+}
+
+// Eval compiles and runs code to completion in one call, for evaluating expressions
+// that need nothing from the host. inputs binds variables by name; a script that makes
+// any external or OS call fails.
+func Eval(code string, inputs map[string]any, limits ResourceLimits) (Object, error) {
+	cCode, freeCode := cString(code)
+	defer freeCode()
+	if inputs == nil {
+		inputs = map[string]any{}
+	}
+	data, err := json.Marshal(inputs)
+	if err != nil {
+		return nil, err
+	}
+	payload, freePayload := cBytes(data)
+	defer freePayload()
+	cLimits, freeLimits, err := marshalLimits(limits)
+	if err != nil {
+		return nil, err
+	}
+	defer freeLimits()
+
+	var out *C.char
+	if err := statusError(C.monty_eval(cCode, payload, cLimits, &out)); err != nil {
+		return nil, err
+	}
+	return decodeObjectString(takeString(out))
+}
+
+// Start begins execution and returns the first progress result.
+func (m *Monty) Start(inputs ...any) (Progress, error) {
+	if m == nil || m.handle == nil {
+		return Progress{}, errors.New("monty: nil handle")
+	}
+	if currentTracer() != nil || hasInputBytes(inputs) {
+		return m.StartWithOptions(Options{}, inputs...)
+	}
+	payload, freePayload, err := marshalInputs(inputs)
+	if err != nil {
+		return Progress{}, err
+	}
+	defer freePayload()
+
+	var raw C.ProgressResult
+	status := C.monty_run_start(m.handle, payload, &raw)
+	defer C.monty_progress_result_free(&raw)
+	if err := statusError(status); err != nil {
+		return Progress{}, err
+	}
+	return convertProgress(&raw)
+}
+
+// StartWithOptions begins execution like Start, applying per-run options such as resource limits.
+func (m *Monty) StartWithOptions(opts Options, inputs ...any) (Progress, error) {
+	if m == nil || m.handle == nil {
+		return Progress{}, errors.New("monty: nil handle")
+	}
+	inputs, byteInputs, freeByteInputs := bindInputBytes(inputs)
+	defer freeByteInputs()
+	payload, freePayload, err := marshalInputs(inputs)
+	if err != nil {
+		return Progress{}, err
+	}
+	defer freePayload()
+	if opts.Globals, err = normalizeGlobals(opts.Globals); err != nil {
+		return Progress{}, err
+	}
+	if opts.RunID == "" && currentTracer() != nil {
+		opts.RunID = newRunID()
+	}
+	data, err := json.Marshal(opts)
+	if err != nil {
+		return Progress{}, err
+	}
+	optsC, freeOpts := cBytes(data)
+	defer freeOpts()
+
+	return startRunTrace(opts.Context, opts.RunID).step(func() (Progress, error) {
+		var raw C.ProgressResult
+		status := C.monty_run_start_with_input_bytes(m.handle, payload, optsC, inputBytesPtr(byteInputs), C.size_t(len(byteInputs)), &raw)
+		defer C.monty_progress_result_free(&raw)
+		if err := statusError(status); err != nil {
+			return Progress{}, err
+		}
+		return convertProgress(&raw)
+	})
+}
+
+// Recover rebuilds a run after a crash from journal, the lines a NewJournalWriter
+// recorder appended: the script is started again with opts and inputs and each call is
+// answered with its journaled resolution. It returns the first progress the journal
+// doesn't resolve, or the completed run, so a workflow can persist only its journal
+// instead of a snapshot at every suspension. A call that differs from the journaled
+// one is an error. Use Recorder.Recover to keep appending to the journal.
+func (m *Monty) Recover(journal []byte, opts Options, inputs ...any) (Progress, error) {
+	if m == nil || m.handle == nil {
+		return Progress{}, errors.New("monty: nil handle")
+	}
+	payload, freePayload, err := marshalInputs(inputs)
+	if err != nil {
+		return Progress{}, err
+	}
+	defer freePayload()
+	if opts.Globals, err = normalizeGlobals(opts.Globals); err != nil {
+		return Progress{}, err
+	}
+	data, err := json.Marshal(opts)
+	if err != nil {
+		return Progress{}, err
+	}
+	optsC, freeOpts := cBytes(data)
+	defer freeOpts()
+
+	var raw C.ProgressResult
+	status := C.monty_run_recover(m.handle, payload, optsC, bytesPtr(journal), C.size_t(len(journal)), &raw)
+	defer C.monty_progress_result_free(&raw)
+	if err := statusError(status); err != nil {
+		return Progress{}, err
+	}
+	return convertProgress(&raw)
+}
+
+// ProtocolVersion reports the wire protocol version spoken by the linked library.
+func ProtocolVersion() uint32 {
+	return uint32(C.monty_protocol_version())
+}
+
+// Header returns the C header the linked library was built with.
+func Header() string {
+	return C.GoString(C.monty_ffi_header())
+}
+
+// ReadMetrics reports the library's process-wide counters.
+func ReadMetrics() (Metrics, error) {
+	var out *C.char
+	if err := statusError(C.monty_metrics_json(&out)); err != nil {
+		return Metrics{}, err
+	}
+	defer C.monty_free_string(out)
+	var metrics Metrics
+	if err := json.Unmarshal([]byte(C.GoString(out)), &metrics); err != nil {
+		return Metrics{}, err
+	}
+	return metrics, nil
+}
+
+// NewGlobals encodes values once into a shareable Globals. Each run it is attached
+// to still copies the values into its own interpreter.
+func NewGlobals(values map[string]any) (*Globals, error) {
+	normalized, err := normalizeGlobals(values)
+	if err != nil {
+		return nil, err
+	}
+	data, err := json.Marshal(normalized)
+	if err != nil {
+		return nil, err
+	}
+	payload, freePayload := cBytes(data)
+	defer freePayload()
+
+	var out *C.GlobalsHandle
+	status := C.monty_globals_new(payload, &out)
+	if err := statusError(status); err != nil {
+		return nil, err
+	}
+	g := &Globals{handle: out}
+	runtime.SetFinalizer(g, func(g *Globals) { g.Close() })
+	return g, nil
+}
+
+// Close releases this reference; Monty handles the globals are attached to keep theirs.
+func (g *Globals) Close() {
+	if g != nil && g.handle != nil {
+		C.monty_globals_free_and_clear(&g.handle)
+	}
+}
+
+// AttachGlobals binds g as module-level variables in every later Start; nil detaches.
+// Monty handles restored with NewFromBytes cannot take globals.
+func (m *Monty) AttachGlobals(g *Globals) error {
+	if m == nil || m.handle == nil {
+		return errors.New("monty: nil handle")
+	}
+	var handle *C.GlobalsHandle
+	if g != nil {
+		if g.handle == nil {
+			return errors.New("monty: globals closed")
+		}
+		handle = g.handle
+	}
+	status := C.monty_run_attach_globals(m.handle, handle)
+	runtime.KeepAlive(g)
+	return statusError(status)
+}
+
+// Builtin is a name scripts can use without declaring it.
+type Builtin struct {
+	Name    string `json:"name"`
+	// Kind is "function", "type", or "host" for the builtins answered through
+	// progress (host_emit, host_sleep).
+	Kind    string `json:"kind"`
+	Enabled bool   `json:"enabled"`
+}
+
+// Builtins lists the builtins available to the script and whether each is enabled,
+// for platforms that publish what scripts may use.
+func (m *Monty) Builtins() ([]Builtin, error) {
+	if m == nil || m.handle == nil {
+		return nil, errors.New("monty: nil handle")
+	}
+	var out *C.char
+	if err := statusError(C.monty_run_list_builtins(m.handle, &out)); err != nil {
+		return nil, err
+	}
+	var builtins []Builtin
+	if err := json.Unmarshal([]byte(takeString(out)), &builtins); err != nil {
+		return nil, err
+	}
+	return builtins, nil
+}
+
+// DisableBuiltins makes calls to the named builtins raise PermissionError in every
+// later Start, replacing the previously disabled set; no names enables them all again.
+// Names must be listed by Builtins and not declared as external functions. The set is
+// not kept by Dump.
+func (m *Monty) DisableBuiltins(names ...string) error {
+	if m == nil || m.handle == nil {
+		return errors.New("monty: nil handle")
+	}
+	cNames, freeNames := cStringArray(names)
+	defer freeNames()
+	return statusError(C.monty_run_disable_builtins(m.handle, (**C.char)(cNames)))
+}
+
+// OverrideBuiltins reports calls to the named builtins (e.g. "print") as FunctionCall
+// progress with Progress.Builtin set in every later Start, so the host can virtualize
+// them without changing scripts; resume them like any external call. It replaces the
+// previously overridden set; no names restores them all. Host builtins can't be
+// overridden, and a disabled builtin stays disabled. The set is not kept by Dump.
+func (m *Monty) OverrideBuiltins(names ...string) error {
+	if m == nil || m.handle == nil {
+		return errors.New("monty: nil handle")
+	}
+	cNames, freeNames := cStringArray(names)
+	defer freeNames()
+	return statusError(C.monty_run_override_builtins(m.handle, (**C.char)(cNames)))
+}
+
+// Close releases the underlying Monty handle.
+func (m *Monty) Close() {
+	if m != nil && m.handle != nil {
+		C.monty_run_free_and_clear(&m.handle)
+	}
+}
+
+// SnapshotFromBytes restores a snapshot from postcard bytes.
+func SnapshotFromBytes(data []byte) (*Snapshot, error) {
+	if len(data) == 0 {
+		return nil, errors.New("monty: empty snapshot bytes")
+	}
+	var out *C.SnapshotHandle
+	status := C.monty_snapshot_load((*C.uint8_t)(unsafe.Pointer(&data[0])), C.size_t(len(data)), &out)
+	if err := statusError(status); err != nil {
+		return nil, err
+	}
+	return newSnapshot(out), nil
+}
+
+// SnapshotFromDelta restores a snapshot from a delta produced by DumpDelta against base.
+func SnapshotFromDelta(base, delta []byte) (*Snapshot, error) {
+	if len(delta) == 0 {
+		return nil, errors.New("monty: empty snapshot delta")
+	}
+	var out *C.SnapshotHandle
+	status := C.monty_snapshot_load_delta(bytesPtr(base), C.size_t(len(base)), bytesPtr(delta), C.size_t(len(delta)), &out)
+	if err := statusError(status); err != nil {
+		return nil, err
+	}
+	return newSnapshot(out), nil
+}
+
+// DumpFormatVersion is the format version snapshot and future snapshot dumps are
+// written with.
+const DumpFormatVersion = 1
+
+// DumpVersion reports the format version a snapshot or future snapshot dump was written
+// with; 0 means the bare VM state dumped by releases before dumps were versioned,
+// which loads as a run started without options.
+func DumpVersion(data []byte) int {
+	return int(C.monty_dump_format_version(bytesPtr(data), C.size_t(len(data))))
+}
+
+// MigrateSnapshot rewrites a snapshot dump from an earlier format version in the
+// current one. Version 0 dumps still load directly; migrating lets stored workflows
+// survive once support for them is dropped.
+func MigrateSnapshot(data []byte) ([]byte, error) {
+	if len(data) == 0 {
+		return nil, errors.New("monty: empty snapshot bytes")
+	}
+	var buf *C.uint8_t
+	var length C.size_t
+	status := C.monty_snapshot_migrate(bytesPtr(data), C.size_t(len(data)), &buf, &length)
+	if err := statusError(status); err != nil {
+		return nil, err
+	}
+	return copyBytes(buf, length), nil
+}
+
+// MigrateFutureSnapshot is MigrateSnapshot for future snapshot dumps.
+func MigrateFutureSnapshot(data []byte) ([]byte, error) {
+	if len(data) == 0 {
+		return nil, errors.New("monty: empty snapshot bytes")
+	}
+	var buf *C.uint8_t
+	var length C.size_t
+	status := C.monty_future_snapshot_migrate(bytesPtr(data), C.size_t(len(data)), &buf, &length)
+	if err := statusError(status); err != nil {
+		return nil, err
+	}
+	return copyBytes(buf, length), nil
+}
+
+// FutureSnapshotFromBytes restores a future snapshot from postcard bytes.
+func FutureSnapshotFromBytes(data []byte) (*FutureSnapshot, error) {
+	if len(data) == 0 {
+		return nil, errors.New("monty: empty snapshot bytes")
+	}
+	var out *C.FutureSnapshotHandle
+	status := C.monty_future_snapshot_load((*C.uint8_t)(unsafe.Pointer(&data[0])), C.size_t(len(data)), &out)
 	if err := statusError(status); err != nil {
 		return nil, err
 	}
-	return newMonty(out), nil
+	return newFutureSnapshot(out, nil), nil
+}
+
+// Dump serializes the snapshot without consuming it.
+func (s *Snapshot) Dump() ([]byte, error) {
+	if s == nil || s.handle == nil {
+		return nil, errors.New("monty: snapshot closed")
+	}
+	var buf *C.uint8_t
+	var length C.size_t
+	status := C.monty_snapshot_dump(s.handle, &buf, &length)
+	if err := statusError(status); err != nil {
+		return nil, err
+	}
+	return copyBytes(buf, length), nil
+}
+
+// EstimatedSize reports how many bytes Dump would return without building the dump,
+// e.g. to choose between inline and blob storage first.
+func (s *Snapshot) EstimatedSize() (int, error) {
+	if s == nil || s.handle == nil {
+		return 0, errors.New("monty: snapshot closed")
+	}
+	var size C.size_t
+	status := C.monty_snapshot_estimated_size(s.handle, &size)
+	if err := statusError(status); err != nil {
+		return 0, err
+	}
+	return int(size), nil
+}
+
+// Hash returns a SHA-256 hex digest of the suspended state, leaving out the run ID,
+// stats, trace, and audit log. Snapshots whose dumps are otherwise identical hash
+// alike; the VM state is hashed as serialized, so equivalent states reached by
+// different paths may not. Stores can use it to dedupe states and drivers to spot a
+// resume that led back to a state already seen.
+func (s *Snapshot) Hash() (string, error) {
+	if s == nil || s.handle == nil {
+		return "", errors.New("monty: snapshot closed")
+	}
+	var out *C.char
+	if err := statusError(C.monty_snapshot_hash(s.handle, &out)); err != nil {
+		return "", err
+	}
+	return takeString(out), nil
+}
+
+// DumpDelta serializes the snapshot as a delta against base, an earlier dump of the
+// same run. Restore it with SnapshotFromDelta and the same base bytes.
+func (s *Snapshot) DumpDelta(base []byte) ([]byte, error) {
+	if s == nil || s.handle == nil {
+		return nil, errors.New("monty: snapshot closed")
+	}
+	var buf *C.uint8_t
+	var length C.size_t
+	status := C.monty_snapshot_dump_delta(bytesPtr(base), C.size_t(len(base)), s.handle, &buf, &length)
+	if err := statusError(status); err != nil {
+		return nil, err
+	}
+	return copyBytes(buf, length), nil
+}
+
+// Dump serializes the future snapshot without consuming it.
+func (fs *FutureSnapshot) Dump() ([]byte, error) {
+	if fs == nil || fs.handle == nil {
+		return nil, errors.New("monty: future snapshot closed")
+	}
+	var buf *C.uint8_t
+	var length C.size_t
+	status := C.monty_future_snapshot_dump(fs.handle, &buf, &length)
+	if err := statusError(status); err != nil {
+		return nil, err
+	}
+	return copyBytes(buf, length), nil
+}
+
+// Hash returns a SHA-256 hex digest of the suspended state; see Snapshot.Hash.
+func (fs *FutureSnapshot) Hash() (string, error) {
+	if fs == nil || fs.handle == nil {
+		return "", errors.New("monty: future snapshot closed")
+	}
+	var out *C.char
+	if err := statusError(C.monty_future_snapshot_hash(fs.handle, &out)); err != nil {
+		return "", err
+	}
+	return takeString(out), nil
+}
+
+// PendingCallIDs returns the cached pending call IDs for the snapshot.
+func (fs *FutureSnapshot) PendingCallIDs() []uint32 {
+	if fs == nil {
+		return nil
+	}
+	return append([]uint32(nil), fs.pending...)
+}
+
+// Resume continues execution of a function call with a result value.
+func (s *Snapshot) Resume(callID uint32, result any) (Progress, error) {
+	return s.resume(callID, result, "", "")
+}
+
+// ResumeError continues execution by raising a RuntimeError with the given message.
+func (s *Snapshot) ResumeError(callID uint32, message string) (Progress, error) {
+	return s.ResumeErrorType(callID, "", message)
+}
+
+// ResumeErrorType continues execution by raising an exception of the named type
+// (e.g. "ValueError", "TimeoutError"); an empty type means RuntimeError.
+func (s *Snapshot) ResumeErrorType(callID uint32, excType, message string) (Progress, error) {
+	if message == "" {
+		return Progress{}, errors.New("monty: empty error message")
+	}
+	return s.resume(callID, nil, message, excType)
+}
+
+// ResumeException continues execution by raising a structured exception.
+func (s *Snapshot) ResumeException(callID uint32, exc Exception) (Progress, error) {
+	if s == nil || s.handle == nil {
+		return Progress{}, errors.New("monty: snapshot closed")
+	}
+	if exc.Type == "" {
+		return Progress{}, errors.New("monty: empty exception type")
+	}
+	data, err := json.Marshal(exc)
+	if err != nil {
+		return Progress{}, err
+	}
+	payload, freePayload := cBytes(data)
+	defer freePayload()
+
+	return s.tracing().step(func() (Progress, error) {
+		var raw C.ProgressResult
+		status := C.monty_snapshot_resume_exception(s.handle, C.uint32_t(callID), payload, &raw)
+		s.handle = nil
+		defer C.monty_progress_result_free(&raw)
+		if err := statusError(status); err != nil {
+			return Progress{}, err
+		}
+		return convertProgress(&raw)
+	}, callAttr(callID))
+}
+
+// ResumeFuture continues execution treating the call as pending (returns ExternalFuture).
+func (s *Snapshot) ResumeFuture(callID uint32) (Progress, error) {
+	return s.resume(callID, nil, "", "")
+}
+
+func (s *Snapshot) resume(callID uint32, result any, errMsg, errType string) (Progress, error) {
+	if s == nil || s.handle == nil {
+		return Progress{}, errors.New("monty: snapshot closed")
+	}
+	var resultJSON *C.char
+	var freeResult func()
+	var err error
+	if errMsg == "" && result != nil {
+		resultJSON, freeResult, err = marshalValue(result)
+		if err != nil {
+			return Progress{}, err
+		}
+		defer freeResult()
+	}
+
+	var errC, errTypeC *C.char
+	if errMsg != "" {
+		var freeErr func()
+		errC, freeErr = cString(errMsg)
+		defer freeErr()
+	}
+	if errType != "" {
+		var freeType func()
+		errTypeC, freeType = cString(errType)
+		defer freeType()
+	}
+
+	return s.tracing().step(func() (Progress, error) {
+		var raw C.ProgressResult
+		var status C.MontyStatus
+		if errTypeC != nil {
+			status = C.monty_snapshot_resume_error(s.handle, C.uint32_t(callID), errTypeC, errC, &raw)
+		} else {
+			status = C.monty_snapshot_resume(s.handle, C.uint32_t(callID), resultJSON, errC, &raw)
+		}
+		if consumed(status) {
+			s.handle = nil
+		}
+		defer C.monty_progress_result_free(&raw)
+		if err := statusError(status); err != nil {
+			return Progress{}, err
+		}
+		return convertProgress(&raw)
+	}, callAttr(callID))
+}
+
+// Interrupt tears down the paused run by raising excType(message) at the suspension
+// point, letting finally blocks run. The snapshot is consumed.
+func (s *Snapshot) Interrupt(excType, message string) (Progress, error) {
+	if s == nil || s.handle == nil {
+		return Progress{}, errors.New("monty: snapshot closed")
+	}
+	cType, freeType := cString(excType)
+	defer freeType()
+	cMessage, freeMessage := cString(message)
+	defer freeMessage()
+
+	return s.tracing().step(func() (Progress, error) {
+		var raw C.ProgressResult
+		status := C.monty_snapshot_interrupt(s.handle, cType, cMessage, &raw)
+		s.handle = nil
+		defer C.monty_progress_result_free(&raw)
+		if err := statusError(status); err != nil {
+			return Progress{}, err
+		}
+		return convertProgress(&raw)
+	})
+}
+
+// ResumeBatch resolves this call and any consecutive function calls listed in results
+// in a single hop. Calls without a result to functions in Options.Awaitable are
+// deferred as futures and reported together in the next Progress.PendingCalls; the
+// batch stops at any other call without a result, which is reported as usual.
+func (s *Snapshot) ResumeBatch(callID uint32, results []FutureResult) (Progress, error) {
+	return s.resumeBatch(callID, results, false)
+}
+
+// ResumeBatchWithOsCalls behaves like ResumeBatch but also batches OS calls.
+func (s *Snapshot) ResumeBatchWithOsCalls(callID uint32, results []FutureResult) (Progress, error) {
+	return s.resumeBatch(callID, results, true)
+}
+
+func (s *Snapshot) resumeBatch(callID uint32, results []FutureResult, osCalls bool) (Progress, error) {
+	if s == nil || s.handle == nil {
+		return Progress{}, errors.New("monty: snapshot closed")
+	}
+	payload, freePayload, err := marshalFutureResults(results)
+	if err != nil {
+		return Progress{}, err
+	}
+	defer freePayload()
+
+	return s.tracing().step(func() (Progress, error) {
+		var raw C.ProgressResult
+		status := C.monty_snapshot_resume_batch(s.handle, C.uint32_t(callID), payload, cBool(osCalls), &raw)
+		if consumed(status) {
+			s.handle = nil
+		}
+		defer C.monty_progress_result_free(&raw)
+		if err := statusError(status); err != nil {
+			return Progress{}, err
+		}
+		return convertProgress(&raw)
+	}, callAttr(callID))
+}
+
+// Resume resumes futures with provided results.
+func (fs *FutureSnapshot) Resume(results []FutureResult) (Progress, error) {
+	if fs == nil || fs.handle == nil {
+		return Progress{}, errors.New("monty: future snapshot closed")
+	}
+	payload, freePayload, err := marshalFutureResults(results)
+	if err != nil {
+		return Progress{}, err
+	}
+	defer freePayload()
+
+	return fs.tracing().step(func() (Progress, error) {
+		var raw C.ProgressResult
+		status := C.monty_future_snapshot_resume(fs.handle, payload, &raw)
+		if consumed(status) {
+			fs.handle = nil
+		}
+		defer C.monty_progress_result_free(&raw)
+		if err := statusError(status); err != nil {
+			return Progress{}, err
+		}
+		return convertProgress(&raw)
+	})
 }
 
-// NewFromBytes restores a Monty handle from postcard bytes.
-func NewFromBytes(data []byte) (*Monty, error) {
-	if len(data) == 0 {
-		return nil, errors.New("monty: empty snapshot")
-	}
-	var out *C.MontyRunHandle
-	status := C.monty_run_load((*C.uint8_t)(unsafe.Pointer(&data[0])), C.size_t(len(data)), &out)
-	if err := statusError(status); err != nil {
-		return nil, err
+// Interrupt raises excType(message) for every pending future. The snapshot is consumed.
+func (fs *FutureSnapshot) Interrupt(excType, message string) (Progress, error) {
+	if fs == nil || fs.handle == nil {
+		return Progress{}, errors.New("monty: future snapshot closed")
 	}
-	return newMonty(out), nil
+	cType, freeType := cString(excType)
+	defer freeType()
+	cMessage, freeMessage := cString(message)
+	defer freeMessage()
+
+	return fs.tracing().step(func() (Progress, error) {
+		var raw C.ProgressResult
+		status := C.monty_future_snapshot_interrupt(fs.handle, cType, cMessage, &raw)
+		fs.handle = nil
+		defer C.monty_progress_result_free(&raw)
+		if err := statusError(status); err != nil {
+			return Progress{}, err
+		}
+		return convertProgress(&raw)
+	})
 }
 
-// Dump serializes the compiled Monty run to postcard bytes.
-func (m *Monty) Dump() ([]byte, error) {
-	if m == nil || m.handle == nil {
-		return nil, errors.New("monty: nil handle")
+// Stats reports the run's tracker counters up to this suspension point.
+func (s *Snapshot) Stats() (RunStats, error) {
+	if s == nil || s.handle == nil {
+		return RunStats{}, errors.New("monty: snapshot closed")
 	}
-	var buf *C.uint8_t
-	var length C.size_t
-	status := C.monty_run_dump(m.handle, &buf, &length)
+	var out *C.char
+	status := C.monty_snapshot_stats(s.handle, &out)
 	if err := statusError(status); err != nil {
-		return nil, err
+		return RunStats{}, err
 	}
-	return copyBytes(buf, length), nil
+	return decodeStats(out)
 }
 
-// Run executes code to completion in one shot.
-func (m *Monty) Run(inputs ...any) (Object, error) {
-	progress, err := m.Start(inputs...)
-	if err != nil {
-		return nil, err
+// Stats reports the run's tracker counters up to this suspension point.
+func (fs *FutureSnapshot) Stats() (RunStats, error) {
+	if fs == nil || fs.handle == nil {
+		return RunStats{}, errors.New("monty: future snapshot closed")
 	}
-	if progress.Kind != Complete {
-		return nil, fmt.Errorf("monty: execution paused unexpectedly (%v)", progress.Kind)
+	var out *C.char
+	status := C.monty_future_snapshot_stats(fs.handle, &out)
+	if err := statusError(status); err != nil {
+		return RunStats{}, err
 	}
-	return progress.Result, nil
+	return decodeStats(out)
 }
 
-// Start begins execution and returns the first progress result.
-func (m *Monty) Start(inputs ...any) (Progress, error) {
-	if m == nil || m.handle == nil {
-		return Progress{}, errors.New("monty: nil handle")
+// Restrict adds a policy every later call of the run must also pass, on top of
+// Options.Policy and earlier restrictions. Restrictions are kept in dumps and can't be
+// lifted, so a snapshot handed to a less-trusted worker can't do more than before.
+func (s *Snapshot) Restrict(policy Policy) error {
+	if s == nil || s.handle == nil {
+		return errors.New("monty: snapshot closed")
 	}
-	payload, freePayload, err := marshalInputs(inputs)
+	data, err := json.Marshal(policy)
 	if err != nil {
-		return Progress{}, err
+		return err
 	}
+	payload, freePayload := cBytes(data)
 	defer freePayload()
-
-	var raw C.ProgressResult
-	status := C.monty_run_start(m.handle, payload, &raw)
-	defer C.monty_progress_result_free_strings(&raw)
-	if err := statusError(status); err != nil {
-		return Progress{}, err
-	}
-	return convertProgress(&raw)
+	return statusError(C.monty_snapshot_restrict(s.handle, payload))
 }
 
-// Close releases the underlying Monty handle.
-func (m *Monty) Close() {
-	if m != nil && m.handle != nil {
-		C.monty_run_free(m.handle)
-		m.handle = nil
+// Restrict adds a policy every later call of the run must also pass; see
+// Snapshot.Restrict.
+func (fs *FutureSnapshot) Restrict(policy Policy) error {
+	if fs == nil || fs.handle == nil {
+		return errors.New("monty: future snapshot closed")
 	}
+	data, err := json.Marshal(policy)
+	if err != nil {
+		return err
+	}
+	payload, freePayload := cBytes(data)
+	defer freePayload()
+	return statusError(C.monty_future_snapshot_restrict(fs.handle, payload))
 }
 
-// SnapshotFromBytes restores a snapshot from postcard bytes.
-func SnapshotFromBytes(data []byte) (*Snapshot, error) {
-	if len(data) == 0 {
-		return nil, errors.New("monty: empty snapshot bytes")
+// Trace returns the run's Chrome trace_event JSON up to this suspension point, or
+// nil when it was started without Options.Trace.
+func (s *Snapshot) Trace() (json.RawMessage, error) {
+	if s == nil || s.handle == nil {
+		return nil, errors.New("monty: snapshot closed")
 	}
-	var out *C.SnapshotHandle
-	status := C.monty_snapshot_load((*C.uint8_t)(unsafe.Pointer(&data[0])), C.size_t(len(data)), &out)
+	var out *C.char
+	status := C.monty_snapshot_trace(s.handle, &out)
 	if err := statusError(status); err != nil {
 		return nil, err
 	}
-	return newSnapshot(out), nil
+	return decodeTrace(out), nil
 }
 
-// FutureSnapshotFromBytes restores a future snapshot from postcard bytes.
-func FutureSnapshotFromBytes(data []byte) (*FutureSnapshot, error) {
-	if len(data) == 0 {
-		return nil, errors.New("monty: empty snapshot bytes")
+// Trace returns the run's Chrome trace_event JSON up to this suspension point, or
+// nil when it was started without Options.Trace.
+func (fs *FutureSnapshot) Trace() (json.RawMessage, error) {
+	if fs == nil || fs.handle == nil {
+		return nil, errors.New("monty: future snapshot closed")
 	}
-	var out *C.FutureSnapshotHandle
-	status := C.monty_future_snapshot_load((*C.uint8_t)(unsafe.Pointer(&data[0])), C.size_t(len(data)), &out)
+	var out *C.char
+	status := C.monty_future_snapshot_trace(fs.handle, &out)
 	if err := statusError(status); err != nil {
 		return nil, err
 	}
-	return newFutureSnapshot(out, nil), nil
+	return decodeTrace(out), nil
 }
 
-// Dump serializes the snapshot without consuming it.
-func (s *Snapshot) Dump() ([]byte, error) {
+// AuditLog lists the OS calls the script attempted up to this suspension point, or
+// nil when the run was started without Options.AuditOsCalls.
+func (s *Snapshot) AuditLog() ([]AuditEntry, error) {
 	if s == nil || s.handle == nil {
 		return nil, errors.New("monty: snapshot closed")
 	}
-	var buf *C.uint8_t
-	var length C.size_t
-	status := C.monty_snapshot_dump(s.handle, &buf, &length)
+	var out *C.char
+	status := C.monty_snapshot_audit_log(s.handle, &out)
 	if err := statusError(status); err != nil {
 		return nil, err
 	}
-	return copyBytes(buf, length), nil
+	return decodeAuditLog(out)
 }
 
-// Dump serializes the future snapshot without consuming it.
-func (fs *FutureSnapshot) Dump() ([]byte, error) {
+// AuditLog lists the OS calls the script attempted up to this suspension point, or
+// nil when the run was started without Options.AuditOsCalls.
+func (fs *FutureSnapshot) AuditLog() ([]AuditEntry, error) {
 	if fs == nil || fs.handle == nil {
 		return nil, errors.New("monty: future snapshot closed")
 	}
-	var buf *C.uint8_t
-	var length C.size_t
-	status := C.monty_future_snapshot_dump(fs.handle, &buf, &length)
+	var out *C.char
+	status := C.monty_future_snapshot_audit_log(fs.handle, &out)
 	if err := statusError(status); err != nil {
 		return nil, err
 	}
-	return copyBytes(buf, length), nil
+	return decodeAuditLog(out)
 }
 
-// PendingCallIDs returns the cached pending call IDs for the snapshot.
-func (fs *FutureSnapshot) PendingCallIDs() []uint32 {
-	if fs == nil {
-		return nil
+// RemainingBudget reports how much of each configured limit the run has left.
+func (s *Snapshot) RemainingBudget() (Budget, error) {
+	if s == nil || s.handle == nil {
+		return Budget{}, errors.New("monty: snapshot closed")
 	}
-	return append([]uint32(nil), fs.pending...)
-}
-
-// Resume continues execution of a function call with a result value.
-func (s *Snapshot) Resume(callID uint32, result any) (Progress, error) {
-	return s.resume(callID, result, "")
-}
-
-// ResumeError continues execution by raising an exception message.
-func (s *Snapshot) ResumeError(callID uint32, message string) (Progress, error) {
-	if message == "" {
-		return Progress{}, errors.New("monty: empty error message")
+	var out *C.char
+	status := C.monty_snapshot_remaining_budget(s.handle, &out)
+	if err := statusError(status); err != nil {
+		return Budget{}, err
 	}
-	return s.resume(callID, nil, message)
+	return decodeBudget(out)
 }
 
-// ResumeFuture continues execution treating the call as pending (returns ExternalFuture).
-func (s *Snapshot) ResumeFuture(callID uint32) (Progress, error) {
-	return s.resume(callID, nil, "")
+// RemainingBudget reports how much of each configured limit the run has left.
+func (fs *FutureSnapshot) RemainingBudget() (Budget, error) {
+	if fs == nil || fs.handle == nil {
+		return Budget{}, errors.New("monty: future snapshot closed")
+	}
+	var out *C.char
+	status := C.monty_future_snapshot_remaining_budget(fs.handle, &out)
+	if err := statusError(status); err != nil {
+		return Budget{}, err
+	}
+	return decodeBudget(out)
 }
 
-func (s *Snapshot) resume(callID uint32, result any, errMsg string) (Progress, error) {
+// SetLimits replaces the run's resource limits before it is resumed. Budget already
+// consumed still counts against the new limits.
+func (s *Snapshot) SetLimits(limits ResourceLimits) (LimitChanges, error) {
 	if s == nil || s.handle == nil {
-		return Progress{}, errors.New("monty: snapshot closed")
-	}
-	var resultJSON *C.char
-	var freeResult func()
-	var err error
-	if errMsg == "" && result != nil {
-		resultJSON, freeResult, err = marshalValue(result)
-		if err != nil {
-			return Progress{}, err
-		}
-		defer freeResult()
+		return LimitChanges{}, errors.New("monty: snapshot closed")
 	}
-
-	var errC *C.char
-	var freeErr func()
-	if errMsg != "" {
-		errC, freeErr = cString(errMsg)
-		defer freeErr()
+	payload, freePayload, err := marshalLimits(limits)
+	if err != nil {
+		return LimitChanges{}, err
 	}
-
-	var raw C.ProgressResult
-	status := C.monty_snapshot_resume(s.handle, C.uint32_t(callID), resultJSON, errC, &raw)
-	s.handle = nil
-	defer C.monty_progress_result_free_strings(&raw)
+	defer freePayload()
+	var out *C.char
+	status := C.monty_snapshot_set_limits(s.handle, payload, &out)
 	if err := statusError(status); err != nil {
-		return Progress{}, err
+		return LimitChanges{}, err
 	}
-	return convertProgress(&raw)
+	return decodeLimitChanges(out)
 }
 
-// Resume resumes futures with provided results.
-func (fs *FutureSnapshot) Resume(results []FutureResult) (Progress, error) {
+// SetLimits replaces the run's resource limits before it is resumed.
+func (fs *FutureSnapshot) SetLimits(limits ResourceLimits) (LimitChanges, error) {
 	if fs == nil || fs.handle == nil {
-		return Progress{}, errors.New("monty: future snapshot closed")
+		return LimitChanges{}, errors.New("monty: future snapshot closed")
 	}
-	payload, freePayload, err := marshalFutureResults(results)
+	payload, freePayload, err := marshalLimits(limits)
 	if err != nil {
-		return Progress{}, err
+		return LimitChanges{}, err
 	}
 	defer freePayload()
-
-	var raw C.ProgressResult
-	status := C.monty_future_snapshot_resume(fs.handle, payload, &raw)
-	fs.handle = nil
-	defer C.monty_progress_result_free_strings(&raw)
+	var out *C.char
+	status := C.monty_future_snapshot_set_limits(fs.handle, payload, &out)
 	if err := statusError(status); err != nil {
-		return Progress{}, err
+		return LimitChanges{}, err
 	}
-	return convertProgress(&raw)
+	return decodeLimitChanges(out)
 }
 
 // Close frees the snapshot handle.
 func (s *Snapshot) Close() {
 	if s != nil && s.handle != nil {
-		C.monty_snapshot_free(s.handle)
-		s.handle = nil
+		C.monty_snapshot_free_and_clear(&s.handle)
+		s.trace.end(nil)
 	}
 }
 
 // Close frees the future snapshot handle.
 func (fs *FutureSnapshot) Close() {
 	if fs != nil && fs.handle != nil {
-		C.monty_future_snapshot_free(fs.handle)
-		fs.handle = nil
+		C.monty_future_snapshot_free_and_clear(&fs.handle)
+		fs.trace.end(nil)
 		fs.pending = nil
 	}
 }
 
+// Provide supplies the requested input's value. The run then either asks for the
+// next lazy input or starts.
+func (r *InputRequest) Provide(value any) (Progress, error) {
+	if r == nil || r.handle == nil {
+		return Progress{}, errors.New("monty: input request closed")
+	}
+	payload, freePayload, err := marshalValue(value)
+	if err != nil {
+		return Progress{}, err
+	}
+	defer freePayload()
+
+	return r.trace.step(func() (Progress, error) {
+		var raw C.ProgressResult
+		status := C.monty_input_request_resume(r.handle, payload, &raw)
+		r.handle = nil
+		defer C.monty_progress_result_free(&raw)
+		if err := statusError(status); err != nil {
+			return Progress{}, err
+		}
+		return convertProgress(&raw)
+	})
+}
+
+// Close abandons the pending run.
+func (r *InputRequest) Close() {
+	if r != nil && r.handle != nil {
+		C.monty_input_request_free_and_clear(&r.handle)
+		r.trace.end(nil)
+	}
+}
+
 func newMonty(handle *C.MontyRunHandle) *Monty {
 	m := &Monty{handle: handle}
 	runtime.SetFinalizer(m, func(m *Monty) { m.Close() })
@@ -316,6 +1508,12 @@ func newSnapshot(handle *C.SnapshotHandle) *Snapshot {
 	return snap
 }
 
+func newInputRequest(handle *C.InputRequestHandle) *InputRequest {
+	req := &InputRequest{handle: handle}
+	runtime.SetFinalizer(req, func(r *InputRequest) { r.Close() })
+	return req
+}
+
 func newFutureSnapshot(handle *C.FutureSnapshotHandle, pending []uint32) *FutureSnapshot {
 	fs := &FutureSnapshot{handle: handle, pending: pending}
 	runtime.SetFinalizer(fs, func(fs *FutureSnapshot) { fs.Close() })
@@ -340,6 +1538,52 @@ func marshalInputs(values []any) (*C.char, func(), error) {
 	return str, free, nil
 }
 
+func hasInputBytes(inputs []any) bool {
+	for _, value := range inputs {
+		if _, ok := value.(inputBytes); ok {
+			return true
+		}
+	}
+	return false
+}
+
+// bindInputBytes replaces each InputBytes among inputs with a marker naming it by
+// position, and returns the data in C memory for monty_run_start_with_input_bytes.
+func bindInputBytes(inputs []any) ([]any, []C.MontyInputBytes, func()) {
+	if !hasInputBytes(inputs) {
+		return inputs, nil, func() {}
+	}
+	bound := make([]any, len(inputs))
+	var items []C.MontyInputBytes
+	for i, value := range inputs {
+		data, ok := value.(inputBytes)
+		if !ok {
+			bound[i] = value
+			continue
+		}
+		name := strconv.Itoa(i)
+		bound[i] = inputBytesRef(name)
+		items = append(items, C.MontyInputBytes{
+			name: C.CString(name),
+			data: (*C.uint8_t)(C.CBytes(data)),
+			len:  C.size_t(len(data)),
+		})
+	}
+	return bound, items, func() {
+		for _, item := range items {
+			C.free(unsafe.Pointer(item.name))
+			C.free(unsafe.Pointer(item.data))
+		}
+	}
+}
+
+func inputBytesPtr(items []C.MontyInputBytes) *C.MontyInputBytes {
+	if len(items) == 0 {
+		return nil
+	}
+	return &items[0]
+}
+
 func marshalValue(value any) (*C.char, func(), error) {
 	normalized, err := normalizeValue(value)
 	if err != nil {
@@ -357,8 +1601,19 @@ func marshalFutureResults(results []FutureResult) (*C.char, func(), error) {
 	payload := make([]map[string]any, 0, len(results))
 	for _, item := range results {
 		entry := map[string]any{"call_id": item.CallID}
-		if item.Err != "" {
+		if item.Cancelled {
+			entry["cancelled"] = true
+		}
+		if item.TimedOut {
+			entry["timed_out"] = true
+		}
+		if item.Exception != nil {
+			entry["error"] = item.Exception
+		} else if item.Err != "" {
 			entry["error"] = item.Err
+			if item.ErrType != "" {
+				entry["error_type"] = item.ErrType
+			}
 		} else if item.Result != nil {
 			normalized, err := normalizeValue(item.Result)
 			if err != nil {
@@ -376,6 +1631,21 @@ func marshalFutureResults(results []FutureResult) (*C.char, func(), error) {
 	return str, free, nil
 }
 
+func normalizeGlobals(values map[string]any) (map[string]any, error) {
+	if values == nil {
+		return nil, nil
+	}
+	normalized := make(map[string]any, len(values))
+	for name, value := range values {
+		v, err := normalizeValue(value)
+		if err != nil {
+			return nil, fmt.Errorf("monty: global %q: %w", name, err)
+		}
+		normalized[name] = v
+	}
+	return normalized, nil
+}
+
 func normalizeValue(value any) (any, error) {
 	if obj, ok := value.(Object); ok {
 		return objectToInterface(obj)
@@ -401,10 +1671,11 @@ func convertProgress(raw *C.ProgressResult) (Progress, error) {
 		Kind:       ProgressKind(raw.kind),
 		CallID:     uint32(raw.call_id),
 		MethodCall: raw.method_call != 0,
+		Builtin:    raw.builtin_override != 0,
 	}
 
 	if raw.result_json != nil {
-		obj, err := decodeObjectString(C.GoString(raw.result_json))
+		obj, err := decodeObjectString(C.GoStringN(raw.result_json, C.int(raw.result_len)))
 		if err != nil {
 			return Progress{}, err
 		}
@@ -417,14 +1688,14 @@ func convertProgress(raw *C.ProgressResult) (Progress, error) {
 		progress.OsFunction = C.GoString(raw.os_function)
 	}
 	if raw.args_json != nil {
-		args, err := decodeObjectArrayString(C.GoString(raw.args_json))
+		args, err := decodeObjectArrayString(C.GoStringN(raw.args_json, C.int(raw.args_len)))
 		if err != nil {
 			return Progress{}, err
 		}
 		progress.Args = args
 	}
 	if raw.kwargs_json != nil {
-		kwargs, err := decodeKwargsString(C.GoString(raw.kwargs_json))
+		kwargs, err := decodeKwargsString(C.GoStringN(raw.kwargs_json, C.int(raw.kwargs_len)))
 		if err != nil {
 			return Progress{}, err
 		}
@@ -437,17 +1708,136 @@ func convertProgress(raw *C.ProgressResult) (Progress, error) {
 		}
 		progress.PendingIDs = ids
 	}
-	if raw.snapshot != nil {
-		progress.Snapshot = newSnapshot(raw.snapshot)
-		raw.snapshot = nil
+	if raw.pending_calls_json != nil {
+		calls, err := decodePendingCallsString(C.GoString(raw.pending_calls_json))
+		if err != nil {
+			return Progress{}, err
+		}
+		progress.PendingCalls = calls
+	}
+	if raw.stats_json != nil {
+		var stats RunStats
+		if err := json.Unmarshal([]byte(C.GoString(raw.stats_json)), &stats); err != nil {
+			return Progress{}, err
+		}
+		progress.Stats = &stats
+	}
+	if raw.trace_json != nil {
+		progress.Trace = json.RawMessage(C.GoString(raw.trace_json))
+	}
+	if raw.audit_json != nil {
+		if err := json.Unmarshal([]byte(C.GoString(raw.audit_json)), &progress.AuditLog); err != nil {
+			return Progress{}, err
+		}
+	}
+	if raw.warnings_json != nil {
+		if err := json.Unmarshal([]byte(C.GoString(raw.warnings_json)), &progress.Warnings); err != nil {
+			return Progress{}, err
+		}
+	}
+	if raw.output != nil {
+		progress.Output = C.GoStringN((*C.char)(unsafe.Pointer(raw.output)), C.int(raw.output_len))
+	}
+	progress.Timeout = time.Duration(raw.timeout_ms) * time.Millisecond
+	progress.Delay = time.Duration(raw.delay_ms) * time.Millisecond
+	if raw.prompt != nil {
+		progress.Prompt = C.GoString(raw.prompt)
+	}
+	if raw.idempotency_key != nil {
+		progress.IdempotencyKey = C.GoString(raw.idempotency_key)
+	}
+	if raw.cache_key != nil {
+		progress.CacheKey = C.GoString(raw.cache_key)
+	}
+	progress.Priority = int(raw.priority)
+	var snapshot *C.SnapshotHandle
+	if err := statusError(C.monty_progress_result_take_snapshot(raw, &snapshot)); err != nil {
+		return Progress{}, err
+	}
+	if snapshot != nil {
+		progress.Snapshot = newSnapshot(snapshot)
+	}
+	var futureSnapshot *C.FutureSnapshotHandle
+	if err := statusError(C.monty_progress_result_take_future_snapshot(raw, &futureSnapshot)); err != nil {
+		return Progress{}, err
+	}
+	if futureSnapshot != nil {
+		progress.FutureSnapshot = newFutureSnapshot(futureSnapshot, progress.PendingIDs)
+	}
+	if raw.input_name != nil {
+		progress.InputName = C.GoString(raw.input_name)
+	}
+	if raw.receiver_json != nil {
+		obj, err := decodeObjectString(C.GoString(raw.receiver_json))
+		if err != nil {
+			return Progress{}, err
+		}
+		progress.Receiver = obj
 	}
-	if raw.future_snapshot != nil {
-		progress.FutureSnapshot = newFutureSnapshot(raw.future_snapshot, progress.PendingIDs)
-		raw.future_snapshot = nil
+	if raw.input_request != nil {
+		progress.InputRequest = newInputRequest(raw.input_request)
+		raw.input_request = nil
 	}
+	progress.ResultHandle = takeObjectHandle(&raw.result_object)
+	progress.ArgsHandle = takeObjectHandle(&raw.args_object)
+	progress.KwargsHandle = takeObjectHandle(&raw.kwargs_object)
 	return progress, nil
 }
 
+func decodeStats(raw *C.char) (RunStats, error) {
+	defer C.monty_free_string(raw)
+	var stats RunStats
+	if err := json.Unmarshal([]byte(C.GoString(raw)), &stats); err != nil {
+		return RunStats{}, err
+	}
+	return stats, nil
+}
+
+func decodeTrace(raw *C.char) json.RawMessage {
+	defer C.monty_free_string(raw)
+	trace := C.GoString(raw)
+	if trace == "null" {
+		return nil
+	}
+	return json.RawMessage(trace)
+}
+
+func decodeAuditLog(raw *C.char) ([]AuditEntry, error) {
+	defer C.monty_free_string(raw)
+	var entries []AuditEntry
+	if err := json.Unmarshal([]byte(C.GoString(raw)), &entries); err != nil {
+		return nil, err
+	}
+	return entries, nil
+}
+
+func decodeBudget(raw *C.char) (Budget, error) {
+	defer C.monty_free_string(raw)
+	var budget Budget
+	if err := json.Unmarshal([]byte(C.GoString(raw)), &budget); err != nil {
+		return Budget{}, err
+	}
+	return budget, nil
+}
+
+func marshalLimits(limits ResourceLimits) (*C.char, func(), error) {
+	data, err := json.Marshal(limits)
+	if err != nil {
+		return nil, nil, err
+	}
+	str, free := cBytes(data)
+	return str, free, nil
+}
+
+func decodeLimitChanges(raw *C.char) (LimitChanges, error) {
+	defer C.monty_free_string(raw)
+	var changes LimitChanges
+	if err := json.Unmarshal([]byte(C.GoString(raw)), &changes); err != nil {
+		return LimitChanges{}, err
+	}
+	return changes, nil
+}
+
 func cString(value string) (*C.char, func()) {
 	cstr := C.CString(value)
 	return cstr, func() {
@@ -464,6 +1854,21 @@ func cBytes(data []byte) (*C.char, func()) {
 	return cstr, func() { C.free(unsafe.Pointer(cstr)) }
 }
 
+// bytesPtr passes data to C without copying; the callee copies what it keeps.
+func bytesPtr(data []byte) *C.uint8_t {
+	if len(data) == 0 {
+		return nil
+	}
+	return (*C.uint8_t)(unsafe.Pointer(&data[0]))
+}
+
+func cBool(value bool) C.int32_t {
+	if value {
+		return 1
+	}
+	return 0
+}
+
 func cStringArray(values []string) (**C.char, func()) {
 	if len(values) == 0 {
 		return nil, func() {}
@@ -480,6 +1885,17 @@ func cStringArray(values []string) (**C.char, func()) {
 	}
 }
 
+const (
+	errorTampered       = C.MONTY_ERROR_TAMPERED
+	errorReturnMismatch = C.MONTY_ERROR_RETURN_MISMATCH
+)
+
+// consumed reports whether a resume took ownership of its snapshot. A result rejected
+// under ReturnMismatchFail leaves the snapshot to be resumed again.
+func consumed(status C.MontyStatus) bool {
+	return status.ok != 0 || status.code != errorReturnMismatch
+}
+
 func statusError(status C.MontyStatus) error {
 	if status.ok != 0 {
 		return nil
@@ -491,5 +1907,11 @@ func statusError(status C.MontyStatus) error {
 	} else {
 		message = "monty: unknown error"
 	}
+	switch status.code {
+	case errorTampered:
+		return ErrSnapshotTampered
+	case errorReturnMismatch:
+		return fmt.Errorf("%w: %s", ErrReturnMismatch, message)
+	}
 	return errors.New(message)
 }
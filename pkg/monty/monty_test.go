@@ -1,6 +1,22 @@
 package monty
 
-import "testing"
+import (
+	"bytes"
+	"context"
+	"database/sql"
+	"database/sql/driver"
+	"encoding/json"
+	"errors"
+	"fmt"
+	"io"
+	"io/fs"
+	"os"
+	"sort"
+	"strings"
+	"sync"
+	"testing"
+	"time"
+)
 
 func TestMontyRunComplete(t *testing.T) {
 	m := newTestMonty(t, "x + 1", []string{"x"}, nil)
@@ -105,6 +121,2934 @@ func TestExternalCallResumeExample(t *testing.T) {
 	if result != (first+second)*2 {
 		t.Fatalf("unexpected result: %d", result)
 	}
+	if resumed.Stats == nil {
+		t.Fatalf("expected run stats on completion")
+	}
+	if resumed.Stats.ExternalCalls != 1 {
+		t.Fatalf("expected one external call, got %d", resumed.Stats.ExternalCalls)
+	}
+}
+
+func TestResumeErrorType(t *testing.T) {
+	const script = `
+try:
+    fetch()
+    outcome = "returned"
+except ValueError as exc:
+    outcome = "caught " + str(exc)
+outcome`
+
+	m := newTestMonty(t, script, nil, []string{"fetch"})
+
+	progress, err := m.Start()
+	if err != nil {
+		t.Fatalf("Start failed: %v", err)
+	}
+	if progress.Kind != FunctionCall {
+		t.Fatalf("expected FunctionCall, got %v", progress.Kind)
+	}
+	resumed, err := progress.Snapshot.ResumeErrorType(progress.CallID, "ValueError", "bad input")
+	if err != nil {
+		t.Fatalf("ResumeErrorType failed: %v", err)
+	}
+	if resumed.Kind != Complete {
+		t.Fatalf("expected Complete, got %v", resumed.Kind)
+	}
+	var got string
+	if err := resumed.Result.Unmarshal(&got); err != nil {
+		t.Fatalf("unmarshal result: %v", err)
+	}
+	if got != "caught bad input" {
+		t.Fatalf("unexpected result: %q", got)
+	}
+}
+
+func TestSnapshotDumpKeepsTrackerState(t *testing.T) {
+	m := newTestMonty(t, "x = [1, 2, 3]\nfetch(x)", nil, []string{"fetch"})
+
+	progress, err := m.StartWithOptions(Options{Limits: ResourceLimits{MaxInstructions: 10000}})
+	if err != nil {
+		t.Fatalf("StartWithOptions failed: %v", err)
+	}
+	if progress.Kind != FunctionCall {
+		t.Fatalf("expected FunctionCall, got %v", progress.Kind)
+	}
+	before, err := progress.Snapshot.Stats()
+	if err != nil {
+		t.Fatalf("Stats failed: %v", err)
+	}
+	data, err := progress.Snapshot.Dump()
+	if err != nil {
+		t.Fatalf("Dump failed: %v", err)
+	}
+	restored, err := SnapshotFromBytes(data)
+	if err != nil {
+		t.Fatalf("SnapshotFromBytes failed: %v", err)
+	}
+	after, err := restored.Stats()
+	if err != nil {
+		t.Fatalf("Stats failed: %v", err)
+	}
+	if before.Instructions == 0 || after.Instructions != before.Instructions {
+		t.Fatalf("expected restored instructions %d, got %d", before.Instructions, after.Instructions)
+	}
+	if after.ExternalCalls != 1 {
+		t.Fatalf("expected one external call after restore, got %d", after.ExternalCalls)
+	}
+}
+
+func TestSnapshotStatsGrowBetweenSuspensions(t *testing.T) {
+	m := newTestMonty(t, "fetch()\nx = [[i] for i in range(1000)]\nfetch()", nil, []string{"fetch"})
+
+	progress, err := m.Start()
+	if err != nil {
+		t.Fatalf("Start failed: %v", err)
+	}
+	first, err := progress.Snapshot.Stats()
+	if err != nil {
+		t.Fatalf("Stats failed: %v", err)
+	}
+	progress, err = progress.Snapshot.Resume(progress.CallID, nil)
+	if err != nil || progress.Kind != FunctionCall {
+		t.Fatalf("expected the second fetch(), got %+v (%v)", progress, err)
+	}
+	defer progress.Snapshot.Close()
+	second, err := progress.Snapshot.Stats()
+	if err != nil {
+		t.Fatalf("Stats failed: %v", err)
+	}
+	if first.ExternalCalls != 1 || second.ExternalCalls != 2 {
+		t.Fatalf("expected 1 then 2 external calls, got %d and %d", first.ExternalCalls, second.ExternalCalls)
+	}
+	if second.Instructions <= first.Instructions || second.Allocations <= first.Allocations {
+		t.Fatalf("expected the list to add instructions and allocations, got %+v then %+v", first, second)
+	}
+	if second.PeakMemory <= first.PeakMemory || second.PeakMemory < second.Memory {
+		t.Fatalf("expected the peak to rise and stay above current memory, got %+v then %+v", first, second)
+	}
+}
+
+func TestFutureSnapshotStats(t *testing.T) {
+	m := newTestMonty(t, "import asyncio\nawait asyncio.gather(fetch(1), fetch(2))", nil, []string{"fetch"})
+
+	progress, err := m.Start()
+	progress, _ = deferCalls(t, progress, err)
+	defer progress.FutureSnapshot.Close()
+	stats, err := progress.FutureSnapshot.Stats()
+	if err != nil {
+		t.Fatalf("Stats failed: %v", err)
+	}
+	if stats.ExternalCalls != 2 || stats.Instructions == 0 {
+		t.Fatalf("expected two external calls and some instructions, got %+v", stats)
+	}
+}
+
+func TestTrace(t *testing.T) {
+	m := newTestMonty(t, "fetch(1) + 1", nil, []string{"fetch"})
+
+	progress, err := m.StartWithOptions(Options{Trace: true})
+	if err != nil {
+		t.Fatalf("StartWithOptions failed: %v", err)
+	}
+	if progress.Kind != FunctionCall {
+		t.Fatalf("expected FunctionCall, got %v", progress.Kind)
+	}
+	data, err := progress.Snapshot.Dump()
+	if err != nil {
+		t.Fatalf("Dump failed: %v", err)
+	}
+	restored, err := SnapshotFromBytes(data)
+	if err != nil {
+		t.Fatalf("SnapshotFromBytes failed: %v", err)
+	}
+	progress, err = restored.Resume(progress.CallID, 41)
+	if err != nil {
+		t.Fatalf("Resume failed: %v", err)
+	}
+	if progress.Kind != Complete {
+		t.Fatalf("expected Complete, got %v", progress.Kind)
+	}
+	var trace struct {
+		TraceEvents []struct {
+			Name  string `json:"name"`
+			Phase string `json:"ph"`
+		} `json:"traceEvents"`
+	}
+	if err := json.Unmarshal(progress.Trace, &trace); err != nil {
+		t.Fatalf("unmarshal trace: %v", err)
+	}
+	var phases []string
+	for _, event := range trace.TraceEvents {
+		phases = append(phases, event.Name+":"+event.Phase)
+	}
+	if got := fmt.Sprint(phases); got != "[step:X fetch:b fetch:e step:X]" {
+		t.Fatalf("unexpected trace events: %s", got)
+	}
+}
+
+type recordingTracer struct {
+	events []string
+	runIDs map[any]bool
+}
+
+type recordingSpan struct {
+	tracer *recordingTracer
+	name   string
+}
+
+func (r *recordingTracer) Start(ctx context.Context, name string, attrs ...Attribute) (context.Context, Span) {
+	r.events = append(r.events, "start "+name)
+	for _, attr := range attrs {
+		if attr.Key == AttrRunID {
+			r.runIDs[attr.Value] = true
+		}
+	}
+	return ctx, recordingSpan{tracer: r, name: name}
+}
+
+func (s recordingSpan) End(error) {
+	s.tracer.events = append(s.tracer.events, "end "+s.name)
+}
+
+func TestTracerSpans(t *testing.T) {
+	tracer := &recordingTracer{runIDs: map[any]bool{}}
+	SetTracer(tracer)
+	defer SetTracer(nil)
+	m := newTestMonty(t, "fetch(1) + 1", nil, []string{"fetch"})
+
+	progress, err := m.Start()
+	if err != nil {
+		t.Fatalf("Start failed: %v", err)
+	}
+	if progress.Kind != FunctionCall {
+		t.Fatalf("expected FunctionCall, got %v", progress.Kind)
+	}
+	runID := progress.Snapshot.RunID()
+	if runID == "" {
+		t.Fatal("expected a generated run id")
+	}
+	if _, err := progress.Snapshot.Resume(progress.CallID, 41); err != nil {
+		t.Fatalf("Resume failed: %v", err)
+	}
+	want := "[start monty.run start monty.resume end monty.resume start monty.call " +
+		"end monty.call start monty.resume end monty.resume end monty.run]"
+	if got := fmt.Sprint(tracer.events); got != want {
+		t.Fatalf("unexpected spans: %s", got)
+	}
+	if len(tracer.runIDs) != 1 || !tracer.runIDs[runID] {
+		t.Fatalf("expected every span to carry run id %q, got %v", runID, tracer.runIDs)
+	}
+}
+
+func TestHeader(t *testing.T) {
+	header := Header()
+	if !strings.Contains(header, "#define MONTY_FFI_H") || !strings.Contains(header, "monty_ffi_header(void)") {
+		t.Fatalf("expected the library's C header, got %d bytes", len(header))
+	}
+}
+
+func TestReadMetrics(t *testing.T) {
+	before, err := ReadMetrics()
+	if err != nil {
+		t.Fatalf("ReadMetrics failed: %v", err)
+	}
+	m := newTestMonty(t, "fetch(1) + 1", nil, []string{"fetch"})
+	progress, err := m.Start()
+	if err != nil {
+		t.Fatalf("Start failed: %v", err)
+	}
+	data, err := progress.Snapshot.Dump()
+	if err != nil {
+		t.Fatalf("Dump failed: %v", err)
+	}
+	if _, err := progress.Snapshot.Resume(progress.CallID, 41); err != nil {
+		t.Fatalf("Resume failed: %v", err)
+	}
+	after, err := ReadMetrics()
+	if err != nil {
+		t.Fatalf("ReadMetrics failed: %v", err)
+	}
+	if after.RunsStarted != before.RunsStarted+1 || after.RunsCompleted != before.RunsCompleted+1 {
+		t.Fatalf("expected one more run started and completed, got %+v then %+v", before, after)
+	}
+	if after.InstructionsExecuted <= before.InstructionsExecuted {
+		t.Fatalf("expected instructions to be counted, got %+v then %+v", before, after)
+	}
+	if got := after.BytesSerialized - before.BytesSerialized; got != uint64(len(data)) {
+		t.Fatalf("expected %d bytes serialized, got %d", len(data), got)
+	}
+}
+
+func TestAuditOsCalls(t *testing.T) {
+	code := "from pathlib import Path\n" +
+		"try:\n    Path('/etc/passwd').read_text()\nexcept PermissionError:\n    pass\n" +
+		"Path('/tmp/data').exists()"
+	m := newTestMonty(t, code, nil, nil)
+
+	progress, err := m.StartWithOptions(Options{AuditOsCalls: true})
+	if err != nil {
+		t.Fatalf("StartWithOptions failed: %v", err)
+	}
+	var functions []string
+	for progress.Kind == OsCall {
+		functions = append(functions, progress.OsFunction)
+		if len(functions) == 1 {
+			progress, err = progress.Snapshot.ResumeErrorType(progress.CallID, "PermissionError", "denied")
+		} else {
+			progress, err = progress.Snapshot.Resume(progress.CallID, true)
+		}
+		if err != nil {
+			t.Fatalf("resume failed: %v", err)
+		}
+	}
+	if progress.Kind != Complete {
+		t.Fatalf("expected Complete, got %v", progress.Kind)
+	}
+	if len(progress.AuditLog) != 2 || len(functions) != 2 {
+		t.Fatalf("expected two audited calls, got %+v", progress.AuditLog)
+	}
+	denied, allowed := progress.AuditLog[0], progress.AuditLog[1]
+	if denied.Function != functions[0] || denied.Outcome != AuditDenied || denied.Error == "" {
+		t.Fatalf("unexpected first entry: %+v", denied)
+	}
+	if !strings.Contains(denied.Args, "/etc/passwd") {
+		t.Fatalf("expected the path in the argument summary, got %q", denied.Args)
+	}
+	if allowed.Function != functions[1] || allowed.Outcome != AuditAllowed || allowed.AtUs < denied.AtUs {
+		t.Fatalf("unexpected second entry: %+v", allowed)
+	}
+}
+
+func TestPolicyDeniesCalls(t *testing.T) {
+	code := "try:\n    fetch(1)\nexcept PermissionError as e:\n    r = str(e)\nr + ' / ' + allowed(2)"
+	m := newTestMonty(t, code, nil, []string{"fetch", "allowed"})
+
+	progress, err := m.StartWithOptions(Options{Policy: Policy{
+		Functions: PolicyRule{Deny: []string{"fetch"}},
+	}})
+	if err != nil {
+		t.Fatalf("StartWithOptions failed: %v", err)
+	}
+	if progress.Kind != FunctionCall || progress.FunctionName != "allowed" {
+		t.Fatalf("expected only the permitted call to be reported, got %v %q", progress.Kind, progress.FunctionName)
+	}
+	progress, err = progress.Snapshot.Resume(progress.CallID, "ok")
+	if err != nil {
+		t.Fatalf("Resume failed: %v", err)
+	}
+	var got string
+	if err := progress.Result.Unmarshal(&got); err != nil {
+		t.Fatalf("unmarshal result: %v", err)
+	}
+	if !strings.Contains(got, "not permitted") || !strings.HasSuffix(got, " / ok") {
+		t.Fatalf("unexpected result: %q", got)
+	}
+
+	_, err = m.StartWithOptions(Options{Policy: Policy{
+		Functions:   PolicyRule{Allow: []string{"allowed"}},
+		OnViolation: ViolationFail,
+	}})
+	if err == nil || !strings.Contains(err.Error(), "not permitted") {
+		t.Fatalf("expected the run to fail on the violation, got %v", err)
+	}
+}
+
+func TestRestrictSurvivesDump(t *testing.T) {
+	code := "a = fetch(1)\ntry:\n    fetch(2)\nexcept PermissionError:\n    a = a + 100\na"
+	m := newTestMonty(t, code, nil, []string{"fetch"})
+
+	progress, err := m.Start()
+	if err != nil {
+		t.Fatalf("Start failed: %v", err)
+	}
+	if err := progress.Snapshot.Restrict(Policy{Functions: PolicyRule{Deny: []string{"fetch"}}}); err != nil {
+		t.Fatalf("Restrict failed: %v", err)
+	}
+	data, err := progress.Snapshot.Dump()
+	if err != nil {
+		t.Fatalf("Dump failed: %v", err)
+	}
+	restored, err := SnapshotFromBytes(data)
+	if err != nil {
+		t.Fatalf("SnapshotFromBytes failed: %v", err)
+	}
+	progress, err = restored.Resume(progress.CallID, 1)
+	if err != nil {
+		t.Fatalf("Resume failed: %v", err)
+	}
+	if progress.Kind != Complete {
+		t.Fatalf("expected the restricted call to raise instead of being reported, got %v", progress.Kind)
+	}
+	var got int
+	if err := progress.Result.Unmarshal(&got); err != nil {
+		t.Fatalf("unmarshal result: %v", err)
+	}
+	if got != 101 {
+		t.Fatalf("expected 101, got %d", got)
+	}
+}
+
+func TestPureModeDisablesOsCalls(t *testing.T) {
+	code := "from pathlib import Path\n" +
+		"try:\n    Path('/etc/passwd').read_text()\nexcept OSError as e:\n    r = str(e)\nr"
+	m := newTestMonty(t, code, nil, nil)
+
+	progress, err := m.StartWithOptions(Options{Pure: true})
+	if err != nil {
+		t.Fatalf("StartWithOptions failed: %v", err)
+	}
+	if progress.Kind != Complete {
+		t.Fatalf("expected the OS call to raise instead of being reported, got %v", progress.Kind)
+	}
+	var got string
+	if err := progress.Result.Unmarshal(&got); err != nil {
+		t.Fatalf("unmarshal result: %v", err)
+	}
+	if !strings.Contains(got, "pure mode") {
+		t.Fatalf("unexpected result: %q", got)
+	}
+}
+
+func TestSnapshotDeltaDump(t *testing.T) {
+	m := newTestMonty(t, "a = fetch(1)\nb = fetch(2)\na + b", nil, []string{"fetch"})
+
+	progress, err := m.Start()
+	if err != nil {
+		t.Fatalf("Start failed: %v", err)
+	}
+	base, err := progress.Snapshot.Dump()
+	if err != nil {
+		t.Fatalf("Dump failed: %v", err)
+	}
+	next, err := progress.Snapshot.Resume(progress.CallID, 40)
+	if err != nil {
+		t.Fatalf("Resume failed: %v", err)
+	}
+	delta, err := next.Snapshot.DumpDelta(base)
+	if err != nil {
+		t.Fatalf("DumpDelta failed: %v", err)
+	}
+	restored, err := SnapshotFromDelta(base, delta)
+	if err != nil {
+		t.Fatalf("SnapshotFromDelta failed: %v", err)
+	}
+	done, err := restored.Resume(next.CallID, 2)
+	if err != nil {
+		t.Fatalf("Resume failed: %v", err)
+	}
+	var got int
+	if err := done.Result.Unmarshal(&got); err != nil {
+		t.Fatalf("unmarshal result: %v", err)
+	}
+	if got != 42 {
+		t.Fatalf("expected 42, got %d", got)
+	}
+	if _, err := SnapshotFromDelta(delta, delta); err == nil {
+		t.Fatalf("expected a delta against the wrong base to fail")
+	}
+}
+
+func TestSnapshotDumpTo(t *testing.T) {
+	m := newTestMonty(t, "fetch(x)", []string{"x"}, []string{"fetch"})
+
+	progress, err := m.Start(7)
+	if err != nil {
+		t.Fatalf("Start failed: %v", err)
+	}
+	var buf bytes.Buffer
+	if err := progress.Snapshot.DumpTo(&buf); err != nil {
+		t.Fatalf("DumpTo failed: %v", err)
+	}
+	data, err := progress.Snapshot.Dump()
+	if err != nil {
+		t.Fatalf("Dump failed: %v", err)
+	}
+	if !bytes.Equal(buf.Bytes(), data) {
+		t.Fatalf("expected streamed dump to match Dump")
+	}
+	if _, err := SnapshotFromBytes(buf.Bytes()); err != nil {
+		t.Fatalf("SnapshotFromBytes failed: %v", err)
+	}
+}
+
+func TestSealedSnapshot(t *testing.T) {
+	m := newTestMonty(t, "fetch(x)", []string{"x"}, []string{"fetch"})
+	sealer, err := NewAESGCMSealer(bytes.Repeat([]byte{7}, 32))
+	if err != nil {
+		t.Fatalf("NewAESGCMSealer failed: %v", err)
+	}
+
+	progress, err := m.Start(7)
+	if err != nil {
+		t.Fatalf("Start failed: %v", err)
+	}
+	sealed, err := progress.Snapshot.DumpSealed(sealer)
+	if err != nil {
+		t.Fatalf("DumpSealed failed: %v", err)
+	}
+	restored, err := SnapshotFromSealed(sealed, sealer)
+	if err != nil {
+		t.Fatalf("SnapshotFromSealed failed: %v", err)
+	}
+	next, err := restored.Resume(progress.CallID, 42)
+	if err != nil {
+		t.Fatalf("Resume failed: %v", err)
+	}
+	var got int
+	if err := next.Result.Unmarshal(&got); err != nil {
+		t.Fatalf("unmarshal result: %v", err)
+	}
+	if got != 42 {
+		t.Fatalf("expected 42, got %d", got)
+	}
+
+	sealed[len(sealed)-1] ^= 1
+	if _, err := SnapshotFromSealed(sealed, sealer); !errors.Is(err, ErrSnapshotTampered) {
+		t.Fatalf("expected ErrSnapshotTampered, got %v", err)
+	}
+}
+
+func TestMigrateSnapshot(t *testing.T) {
+	m := newTestMonty(t, "fetch(x)", []string{"x"}, []string{"fetch"})
+
+	progress, err := m.Start(7)
+	if err != nil {
+		t.Fatalf("Start failed: %v", err)
+	}
+	data, err := progress.Snapshot.Dump()
+	if err != nil {
+		t.Fatalf("Dump failed: %v", err)
+	}
+	if got := DumpVersion(data); got != DumpFormatVersion {
+		t.Fatalf("expected format version %d, got %d", DumpFormatVersion, got)
+	}
+	migrated, err := MigrateSnapshot(data)
+	if err != nil {
+		t.Fatalf("MigrateSnapshot failed: %v", err)
+	}
+	if got := DumpVersion(migrated); got != DumpFormatVersion {
+		t.Fatalf("expected migrated format version %d, got %d", DumpFormatVersion, got)
+	}
+	if _, err := SnapshotFromBytes(migrated); err != nil {
+		t.Fatalf("SnapshotFromBytes failed: %v", err)
+	}
+}
+
+func TestLoadUnversionedSnapshot(t *testing.T) {
+	raw, err := os.ReadFile("testdata/snapshot-v0.json")
+	if errors.Is(err, fs.ErrNotExist) {
+		t.Skip("no version 0 fixture; generate it with make testdata")
+	}
+	if err != nil {
+		t.Fatalf("read fixture: %v", err)
+	}
+	var fixture struct {
+		CallID uint32 `json:"call_id"`
+		Dump   []byte `json:"dump"`
+	}
+	if err := json.Unmarshal(raw, &fixture); err != nil {
+		t.Fatalf("decode fixture: %v", err)
+	}
+	if got := DumpVersion(fixture.Dump); got != 0 {
+		t.Fatalf("expected an unversioned dump, got version %d", got)
+	}
+	migrated, err := MigrateSnapshot(fixture.Dump)
+	if err != nil {
+		t.Fatalf("MigrateSnapshot failed: %v", err)
+	}
+	if got := DumpVersion(migrated); got != DumpFormatVersion {
+		t.Fatalf("expected migrated format version %d, got %d", DumpFormatVersion, got)
+	}
+
+	for _, dump := range [][]byte{fixture.Dump, migrated} {
+		snapshot, err := SnapshotFromBytes(dump)
+		if err != nil {
+			t.Fatalf("SnapshotFromBytes failed: %v", err)
+		}
+		progress, err := snapshot.Resume(fixture.CallID, 41)
+		if err != nil {
+			t.Fatalf("Resume failed: %v", err)
+		}
+		var got int
+		if err := progress.Result.Unmarshal(&got); err != nil || got != 42 {
+			t.Fatalf("expected 42, got %d (%v)", got, err)
+		}
+	}
+}
+
+func TestLoadRejectsUnknownDumpVersion(t *testing.T) {
+	m := newTestMonty(t, "fetch(x)", []string{"x"}, []string{"fetch"})
+
+	progress, err := m.Start(7)
+	if err != nil {
+		t.Fatalf("Start failed: %v", err)
+	}
+	data, err := progress.Snapshot.Dump()
+	if err != nil {
+		t.Fatalf("Dump failed: %v", err)
+	}
+	data[len("MONTYSTR")] = DumpFormatVersion + 1
+	if _, err := SnapshotFromBytes(data); err == nil || !strings.Contains(err.Error(), "unsupported snapshot format version") {
+		t.Fatalf("expected the version to be rejected, got %v", err)
+	}
+	if _, err := MigrateSnapshot(data); err == nil {
+		t.Fatal("expected migrating an unknown version to fail")
+	}
+}
+
+func TestSnapshotEstimatedSize(t *testing.T) {
+	m := newTestMonty(t, "fetch(x)", []string{"x"}, []string{"fetch"})
+
+	progress, err := m.Start(7)
+	if err != nil {
+		t.Fatalf("Start failed: %v", err)
+	}
+	size, err := progress.Snapshot.EstimatedSize()
+	if err != nil {
+		t.Fatalf("EstimatedSize failed: %v", err)
+	}
+	data, err := progress.Snapshot.Dump()
+	if err != nil {
+		t.Fatalf("Dump failed: %v", err)
+	}
+	if size != len(data) {
+		t.Fatalf("expected estimate %d to match dump length %d", size, len(data))
+	}
+}
+
+func TestSnapshotHash(t *testing.T) {
+	m := newTestMonty(t, "fetch(x)\nfetch(x)", []string{"x"}, []string{"fetch"})
+
+	first, err := m.StartWithOptions(Options{RunID: "a"}, 7)
+	if err != nil {
+		t.Fatalf("Start failed: %v", err)
+	}
+	second, err := m.StartWithOptions(Options{RunID: "b"}, 7)
+	if err != nil {
+		t.Fatalf("Start failed: %v", err)
+	}
+	hashA, err := first.Snapshot.Hash()
+	if err != nil {
+		t.Fatalf("Hash failed: %v", err)
+	}
+	hashB, _ := second.Snapshot.Hash()
+	if len(hashA) != 64 || hashA != hashB {
+		t.Fatalf("expected equal states to hash alike, got %q and %q", hashA, hashB)
+	}
+	next, err := first.Snapshot.Resume(first.CallID, nil)
+	if err != nil {
+		t.Fatalf("Resume failed: %v", err)
+	}
+	if hashC, _ := next.Snapshot.Hash(); hashC == hashA {
+		t.Fatal("expected a later state to hash differently")
+	}
+}
+
+func TestStoreDedupesDumps(t *testing.T) {
+	m := newTestMonty(t, "fetch(x)", []string{"x"}, []string{"fetch"})
+	store, err := NewStore()
+	if err != nil {
+		t.Fatalf("NewStore failed: %v", err)
+	}
+	defer store.Close()
+
+	progress, err := m.Start(7)
+	if err != nil {
+		t.Fatalf("Start failed: %v", err)
+	}
+	hash, err := store.Put(progress.Snapshot)
+	if err != nil {
+		t.Fatalf("Put failed: %v", err)
+	}
+	again, _ := store.Put(progress.Snapshot)
+	if again != hash || store.Len() != 1 {
+		t.Fatalf("expected one stored dump under %q, got %q and %d", hash, again, store.Len())
+	}
+	restored, err := store.Get(hash)
+	if err != nil {
+		t.Fatalf("Get failed: %v", err)
+	}
+	defer restored.Close()
+	next, err := restored.Resume(progress.CallID, 35)
+	if err != nil || next.Kind != Complete {
+		t.Fatalf("expected the restored snapshot to finish, got %+v (%v)", next, err)
+	}
+	if _, err := store.Get("missing"); err == nil {
+		t.Fatal("expected Get of an unknown hash to fail")
+	}
+}
+
+func TestStoreDirSurvivesReopen(t *testing.T) {
+	m := newTestMonty(t, "fetch(x)", []string{"x"}, []string{"fetch"})
+	dir := t.TempDir()
+	store, err := OpenStoreDir(dir)
+	if err != nil {
+		t.Fatalf("OpenStoreDir failed: %v", err)
+	}
+	progress, err := m.StartWithOptions(Options{RunID: "wf-1"}, 7)
+	if err != nil {
+		t.Fatalf("Start failed: %v", err)
+	}
+	hash, err := store.Put(progress.Snapshot)
+	if err != nil {
+		t.Fatalf("Put failed: %v", err)
+	}
+	store.Close()
+
+	reopened, err := OpenStoreDir(dir)
+	if err != nil {
+		t.Fatalf("OpenStoreDir failed: %v", err)
+	}
+	defer reopened.Close()
+	hashes, err := reopened.List("wf-1")
+	if err != nil || len(hashes) != 1 || hashes[0] != hash {
+		t.Fatalf("expected [%s], got %v (%v)", hash, hashes, err)
+	}
+	restored, err := reopened.Get(hash)
+	if err != nil {
+		t.Fatalf("Get failed: %v", err)
+	}
+	restored.Close()
+	if err := reopened.Delete(hash); err != nil {
+		t.Fatalf("Delete failed: %v", err)
+	}
+	if hashes, _ := reopened.List("wf-1"); len(hashes) != 0 || reopened.Len() != 0 {
+		t.Fatalf("expected the store to be empty after Delete, got %v", hashes)
+	}
+}
+
+// fakeRunTable is a database/sql driver that understands just the statements RunDB
+// runs, keeping the monty_runs table in memory.
+type fakeRunTable struct {
+	mu   sync.Mutex
+	rows map[string][]driver.Value
+}
+
+func (f *fakeRunTable) Open(string) (driver.Conn, error) { return fakeRunConn{f}, nil }
+
+func (f *fakeRunTable) Connect(context.Context) (driver.Conn, error) { return fakeRunConn{f}, nil }
+
+func (f *fakeRunTable) Driver() driver.Driver { return f }
+
+type fakeRunConn struct{ table *fakeRunTable }
+
+func (c fakeRunConn) Prepare(query string) (driver.Stmt, error) {
+	return fakeRunStmt{c.table, strings.TrimSpace(query)}, nil
+}
+
+func (c fakeRunConn) Close() error { return nil }
+
+func (c fakeRunConn) Begin() (driver.Tx, error) { return nil, errors.New("fake: no transactions") }
+
+type fakeRunStmt struct {
+	table *fakeRunTable
+	query string
+}
+
+func (s fakeRunStmt) Close() error { return nil }
+
+func (s fakeRunStmt) NumInput() int { return -1 }
+
+func (s fakeRunStmt) Exec(args []driver.Value) (driver.Result, error) {
+	s.table.mu.Lock()
+	defer s.table.mu.Unlock()
+	switch {
+	case strings.HasPrefix(s.query, "CREATE TABLE"):
+	case strings.HasPrefix(s.query, "INSERT OR REPLACE"):
+		// run_id, program, state, snapshot, pending_ids, result, updated_at
+		s.table.rows[args[0].(string)] = append([]driver.Value(nil), args...)
+	case strings.HasPrefix(s.query, "DELETE"):
+		delete(s.table.rows, args[0].(string))
+	default:
+		return nil, fmt.Errorf("fake: unexpected statement %q", s.query)
+	}
+	return driver.RowsAffected(1), nil
+}
+
+func (s fakeRunStmt) Query(args []driver.Value) (driver.Rows, error) {
+	s.table.mu.Lock()
+	defer s.table.mu.Unlock()
+	rows := &fakeRunRows{}
+	switch {
+	case strings.HasPrefix(s.query, "SELECT program"):
+		rows.columns = []string{"program", "state", "snapshot", "pending_ids", "result", "updated_at"}
+		if row, ok := s.table.rows[args[0].(string)]; ok {
+			rows.values = append(rows.values, row[1:])
+		}
+	case strings.HasPrefix(s.query, "SELECT run_id"):
+		rows.columns = []string{"run_id"}
+		var saved [][]driver.Value
+		for _, row := range s.table.rows {
+			if row[2] != args[0] {
+				saved = append(saved, row)
+			}
+		}
+		sort.Slice(saved, func(i, j int) bool {
+			if saved[i][6] != saved[j][6] {
+				return saved[i][6].(int64) < saved[j][6].(int64)
+			}
+			return saved[i][0].(string) < saved[j][0].(string)
+		})
+		for _, row := range saved {
+			rows.values = append(rows.values, row[:1])
+		}
+	default:
+		return nil, fmt.Errorf("fake: unexpected query %q", s.query)
+	}
+	return rows, nil
+}
+
+type fakeRunRows struct {
+	columns []string
+	values  [][]driver.Value
+}
+
+func (r *fakeRunRows) Columns() []string { return r.columns }
+
+func (r *fakeRunRows) Close() error { return nil }
+
+func (r *fakeRunRows) Next(dest []driver.Value) error {
+	if len(r.values) == 0 {
+		return io.EOF
+	}
+	copy(dest, r.values[0])
+	r.values = r.values[1:]
+	return nil
+}
+
+func TestRunDB(t *testing.T) {
+	db := sql.OpenDB(&fakeRunTable{rows: map[string][]driver.Value{}})
+	defer db.Close()
+	runs, err := OpenRunDB(db)
+	if err != nil {
+		t.Fatalf("OpenRunDB failed: %v", err)
+	}
+
+	m := newTestMonty(t, "fetch(x)", []string{"x"}, []string{"fetch"})
+	suspended, err := m.Start(7)
+	if err != nil {
+		t.Fatalf("Start failed: %v", err)
+	}
+	defer suspended.Snapshot.Close()
+	if err := runs.Save("wf-1", m, suspended); err != nil {
+		t.Fatalf("Save failed: %v", err)
+	}
+	// Saves in the same millisecond would list by run ID instead.
+	time.Sleep(2 * time.Millisecond)
+
+	async := newTestMonty(t, "import asyncio\nawait asyncio.gather(fetch(1), fetch(2))", nil, []string{"fetch"})
+	waiting, err := async.Start()
+	waiting, _ = deferCalls(t, waiting, err)
+	defer waiting.FutureSnapshot.Close()
+	if err := runs.Save("wf-2", async, waiting); err != nil {
+		t.Fatalf("Save failed: %v", err)
+	}
+	if ids, err := runs.Resumable(); err != nil || len(ids) != 2 || ids[0] != "wf-1" || ids[1] != "wf-2" {
+		t.Fatalf("expected [wf-1 wf-2], got %v (%v)", ids, err)
+	}
+
+	saved, err := runs.Load("wf-1")
+	if err != nil {
+		t.Fatalf("Load failed: %v", err)
+	}
+	defer saved.Program.Close()
+	if saved.Snapshot == nil || len(saved.PendingCallIDs) != 1 || saved.PendingCallIDs[0] != suspended.CallID {
+		t.Fatalf("expected a snapshot waiting on call %d, got %+v", suspended.CallID, saved)
+	}
+	done, err := saved.Snapshot.Resume(suspended.CallID, 35)
+	if err != nil || done.Kind != Complete {
+		t.Fatalf("expected the loaded run to finish, got %+v (%v)", done, err)
+	}
+	if err := runs.Save("wf-1", saved.Program, done); err != nil {
+		t.Fatalf("Save failed: %v", err)
+	}
+	if ids, _ := runs.Resumable(); len(ids) != 1 || ids[0] != "wf-2" {
+		t.Fatalf("expected only wf-2 resumable, got %v", ids)
+	}
+	completed, err := runs.Load("wf-1")
+	if err != nil || string(completed.Result) != "35" || completed.Snapshot != nil {
+		t.Fatalf("expected the saved result 35, got %+v (%v)", completed, err)
+	}
+	completed.Program.Close()
+
+	futures, err := runs.Load("wf-2")
+	if err != nil || futures.FutureSnapshot == nil {
+		t.Fatalf("expected a future snapshot, got %+v (%v)", futures, err)
+	}
+	futures.FutureSnapshot.Close()
+	futures.Program.Close()
+	if len(futures.PendingCallIDs) != len(waiting.PendingIDs) {
+		t.Fatalf("expected pending calls %v, got %v", waiting.PendingIDs, futures.PendingCallIDs)
+	}
+
+	if err := runs.Delete("wf-2"); err != nil {
+		t.Fatalf("Delete failed: %v", err)
+	}
+	if ids, _ := runs.Resumable(); len(ids) != 0 {
+		t.Fatalf("expected nothing resumable after Delete, got %v", ids)
+	}
+	if _, err := runs.Load("wf-2"); err == nil {
+		t.Fatal("expected Load of a deleted run to fail")
+	}
+}
+
+func TestVerifyReplay(t *testing.T) {
+	m := newTestMonty(t, "a = fetch(1)\nb = fetch(a, scale=2)\na + b", nil, []string{"fetch"})
+
+	var journal Journal
+	progress, err := m.Start()
+	for _, result := range []int{10, 20} {
+		if err != nil {
+			t.Fatalf("run failed: %v", err)
+		}
+		entry := JournalEntry{CallID: progress.CallID, FunctionName: progress.FunctionName, Result: []byte(fmt.Sprint(result))}
+		for _, arg := range progress.Args {
+			entry.Args = append(entry.Args, []byte(arg))
+		}
+		for _, kv := range progress.Kwargs {
+			entry.Kwargs = append(entry.Kwargs, [2]json.RawMessage{[]byte(kv.Key), []byte(kv.Value)})
+		}
+		journal.Calls = append(journal.Calls, entry)
+		progress, err = progress.Snapshot.Resume(progress.CallID, result)
+	}
+	if err != nil {
+		t.Fatalf("run failed: %v", err)
+	}
+	journal.Result = []byte(progress.Result)
+
+	if err := m.VerifyReplay(journal, Options{}); err != nil {
+		t.Fatalf("expected replay to match, got %v", err)
+	}
+
+	journal.Calls[0].Result = []byte("11")
+	var divergence *Divergence
+	if err := m.VerifyReplay(journal, Options{}); !errors.As(err, &divergence) {
+		t.Fatalf("expected a divergence, got %v", err)
+	}
+	if divergence.Index != 1 {
+		t.Fatalf("expected divergence at call 1, got %d (%v)", divergence.Index, divergence)
+	}
+}
+
+func TestRecordAndReplay(t *testing.T) {
+	m := newTestMonty(t, "a = fetch(1)\nb = fetch(a, scale=2)\na + b", nil, []string{"fetch"})
+
+	recorder := NewRecorder()
+	progress, err := recorder.Start(m, Options{})
+	for _, result := range []int{10, 20} {
+		if err != nil {
+			t.Fatalf("run failed: %v", err)
+		}
+		progress, err = recorder.Resume(progress, result)
+	}
+	if err != nil {
+		t.Fatalf("run failed: %v", err)
+	}
+	data, err := json.Marshal(recorder.Journal())
+	if err != nil {
+		t.Fatalf("marshal journal: %v", err)
+	}
+	var journal Journal
+	if err := json.Unmarshal(data, &journal); err != nil {
+		t.Fatalf("unmarshal journal: %v", err)
+	}
+	if len(journal.Calls) != 2 {
+		t.Fatalf("expected two recorded calls, got %d", len(journal.Calls))
+	}
+
+	replayed, err := m.Replay(journal, Options{})
+	if err != nil {
+		t.Fatalf("Replay failed: %v", err)
+	}
+	var got int
+	if err := replayed.Result.Unmarshal(&got); err != nil || got != 30 {
+		t.Fatalf("expected replayed result 30, got %d (%v)", got, err)
+	}
+
+	journal.Calls = journal.Calls[:1]
+	partial, err := m.Replay(journal, Options{})
+	if err != nil {
+		t.Fatalf("Replay failed: %v", err)
+	}
+	if partial.Kind != FunctionCall || len(partial.Args) != 1 {
+		t.Fatalf("expected the unrecorded second call, got %+v", partial)
+	}
+	if err := partial.Args[0].Unmarshal(&got); err != nil || got != 10 {
+		t.Fatalf("expected the second call to receive 10, got %d (%v)", got, err)
+	}
+	partial.Snapshot.Close()
+}
+
+func TestJournalWriter(t *testing.T) {
+	m := newTestMonty(t, "a = fetch(1)\nb = fetch(a, scale=2)\na + b", nil, []string{"fetch"})
+
+	var log bytes.Buffer
+	recorder := NewJournalWriter(&log)
+	progress, err := recorder.Start(m, Options{})
+	if err != nil {
+		t.Fatalf("run failed: %v", err)
+	}
+	progress, err = recorder.Resume(progress, 10)
+	if err != nil {
+		t.Fatalf("run failed: %v", err)
+	}
+
+	// Cut off mid-run, the log already holds the second call, unresolved.
+	crashed := log.String()
+	if lines := strings.Count(crashed, "\n"); lines != 3 {
+		t.Fatalf("expected call, resolve, call lines, got %d: %s", lines, crashed)
+	}
+	journal, err := ReadJournal(strings.NewReader(crashed + `{"event":"resol`))
+	if err != nil {
+		t.Fatalf("ReadJournal failed: %v", err)
+	}
+	if len(journal.Calls) != 2 || string(journal.Calls[0].Result) != "10" || journal.Calls[1].Result != nil {
+		t.Fatalf("unexpected journal after crash: %+v", journal)
+	}
+
+	if _, err := recorder.Resume(progress, 20); err != nil {
+		t.Fatalf("run failed: %v", err)
+	}
+	journal, err = ReadJournal(&log)
+	if err != nil {
+		t.Fatalf("ReadJournal failed: %v", err)
+	}
+	recorded, _ := json.Marshal(recorder.Journal())
+	rebuilt, _ := json.Marshal(journal)
+	if !bytes.Equal(recorded, rebuilt) {
+		t.Fatalf("expected the log to rebuild the journal\n%s\ngot\n%s", recorded, rebuilt)
+	}
+	if err := m.VerifyReplay(journal, Options{}); err != nil {
+		t.Fatalf("expected the rebuilt journal to replay, got %v", err)
+	}
+}
+
+func TestRecoverFromJournal(t *testing.T) {
+	m := newTestMonty(t, "a = fetch(1)\nb = fetch(a, scale=2)\na + b", nil, []string{"fetch"})
+
+	var log bytes.Buffer
+	progress, err := NewJournalWriter(&log).Start(m, Options{})
+	if err != nil {
+		t.Fatalf("run failed: %v", err)
+	}
+	progress, err = progress.Snapshot.Resume(progress.CallID, 10)
+	if err != nil {
+		t.Fatalf("run failed: %v", err)
+	}
+	progress.Snapshot.Close()
+	// The resolution was never journaled, so recovery stops at the first call again.
+	recovered, err := m.Recover(log.Bytes(), Options{})
+	if err != nil {
+		t.Fatalf("Recover failed: %v", err)
+	}
+	var arg int
+	if recovered.Kind != FunctionCall || len(recovered.Args) != 1 {
+		t.Fatalf("expected the unresolved first call, got %+v", recovered)
+	}
+	if err := recovered.Args[0].Unmarshal(&arg); err != nil || arg != 1 {
+		t.Fatalf("expected the first call to receive 1, got %d (%v)", arg, err)
+	}
+	recovered.Snapshot.Close()
+
+	log.Reset()
+	recorder := NewJournalWriter(&log)
+	progress, err = recorder.Start(m, Options{})
+	if err == nil {
+		progress, err = recorder.Resume(progress, 10)
+	}
+	if err != nil {
+		t.Fatalf("run failed: %v", err)
+	}
+	progress.Snapshot.Close()
+
+	// The process "crashes" here; a new recorder picks the run up from its journal.
+	recorder = NewJournalWriter(&log)
+	recovered, err = recorder.Recover(m, append([]byte(nil), log.Bytes()...), Options{})
+	if err != nil {
+		t.Fatalf("Recover failed: %v", err)
+	}
+	if recovered.Kind != FunctionCall || len(recovered.Args) != 1 {
+		t.Fatalf("expected the second call, got %+v", recovered)
+	}
+	if err := recovered.Args[0].Unmarshal(&arg); err != nil || arg != 10 {
+		t.Fatalf("expected the second call to receive 10, got %d (%v)", arg, err)
+	}
+	done, err := recorder.Resume(recovered, 20)
+	if err != nil {
+		t.Fatalf("run failed: %v", err)
+	}
+	var got int
+	if err := done.Result.Unmarshal(&got); err != nil || got != 30 {
+		t.Fatalf("expected 30, got %d (%v)", got, err)
+	}
+	journal, err := ReadJournal(&log)
+	if err != nil {
+		t.Fatalf("ReadJournal failed: %v", err)
+	}
+	if len(journal.Calls) != 2 || string(journal.Calls[1].Result) != "20" || string(journal.Result) != "30" {
+		t.Fatalf("expected the journal to cover the whole run, got %+v", journal)
+	}
+
+	other := newTestMonty(t, "fetch(2)", nil, []string{"fetch"})
+	if _, err := other.Recover([]byte(`{"event":"call","entry":{"call_id":0,"function_name":"fetch","args":[1]}}`+"\n"+`{"event":"resolve","entry":{"call_id":0,"result":1}}`+"\n"), Options{}); err == nil || !strings.Contains(err.Error(), "diverged") {
+		t.Fatalf("expected a divergence, got %v", err)
+	}
+}
+
+func TestIdempotencyKeys(t *testing.T) {
+	m := newTestMonty(t, "charge(1)\ncharge(2, idempotency_key='pay-2')", nil, []string{"charge"})
+
+	first := func(runID string) Progress {
+		progress, err := m.StartWithOptions(Options{RunID: runID})
+		if err != nil {
+			t.Fatalf("StartWithOptions failed: %v", err)
+		}
+		t.Cleanup(progress.Snapshot.Close)
+		return progress
+	}
+	a, again, other := first("order-42"), first("order-42"), first("order-43")
+	if a.IdempotencyKey == "" || a.IdempotencyKey != again.IdempotencyKey {
+		t.Fatalf("expected the same key for the same run and call, got %q and %q", a.IdempotencyKey, again.IdempotencyKey)
+	}
+	if a.IdempotencyKey == other.IdempotencyKey {
+		t.Fatalf("expected different runs to get different keys, got %q", a.IdempotencyKey)
+	}
+
+	dump, err := again.Snapshot.Dump()
+	if err != nil {
+		t.Fatalf("Dump failed: %v", err)
+	}
+	restored, err := SnapshotFromBytes(dump)
+	if err != nil {
+		t.Fatalf("SnapshotFromBytes failed: %v", err)
+	}
+	second, err := restored.Resume(again.CallID, nil)
+	if err != nil {
+		t.Fatalf("Resume failed: %v", err)
+	}
+	defer second.Snapshot.Close()
+	if second.IdempotencyKey != "pay-2" {
+		t.Fatalf("expected the script's key, got %q", second.IdempotencyKey)
+	}
+	if none := first(""); none.IdempotencyKey != "" {
+		t.Fatalf("expected no key without a run ID, got %q", none.IdempotencyKey)
+	}
+}
+
+func TestCacheCalls(t *testing.T) {
+	m := newTestMonty(t, "rate('EUR') + rate('EUR') + rate(currency='USD')", nil, []string{"rate"})
+	opts := Options{CacheCalls: []string{"rate"}}
+
+	progress, err := m.StartWithOptions(opts)
+	if err != nil {
+		t.Fatalf("StartWithOptions failed: %v", err)
+	}
+	key := progress.CacheKey
+	if key == "" {
+		t.Fatalf("expected a cache key for rate('EUR')")
+	}
+	progress, err = progress.Snapshot.Resume(progress.CallID, 2)
+	if err != nil {
+		t.Fatalf("Resume failed: %v", err)
+	}
+	// The second rate('EUR') is answered from the cache.
+	var currency string
+	if progress.Kind != FunctionCall || len(progress.Kwargs) != 1 {
+		t.Fatalf("expected rate(currency='USD'), got %+v", progress)
+	}
+	if err := progress.Kwargs[0].Value.Unmarshal(&currency); err != nil || currency != "USD" {
+		t.Fatalf("expected rate(currency='USD'), got %q (%v)", currency, err)
+	}
+	if progress.CacheKey == key {
+		t.Fatalf("expected different arguments to get a different key")
+	}
+	progress, err = progress.Snapshot.Resume(progress.CallID, 3)
+	if err != nil {
+		t.Fatalf("Resume failed: %v", err)
+	}
+	var got int
+	if err := progress.Result.Unmarshal(&got); err != nil || got != 7 {
+		t.Fatalf("expected 7, got %d (%v)", got, err)
+	}
+
+	again, err := m.StartWithOptions(opts)
+	if err != nil {
+		t.Fatalf("StartWithOptions failed: %v", err)
+	}
+	defer again.Snapshot.Close()
+	if again.CacheKey != key {
+		t.Fatalf("expected the same key in a new run, got %q and %q", key, again.CacheKey)
+	}
+}
+
+func TestResumeBatchStopsAtUnresolvedCall(t *testing.T) {
+	code := "a = fetch(1)\nb = fetch(2)\nc = fetch(3)\na + b + c + 1"
+	m := newTestMonty(t, code, nil, []string{"fetch"})
+
+	progress, err := m.Start()
+	if err != nil {
+		t.Fatalf("Start failed: %v", err)
+	}
+	first := progress.CallID
+	results := []FutureResult{{CallID: first, Result: 10}, {CallID: first + 1, Result: 20}}
+	progress, err = progress.Snapshot.ResumeBatch(first, results)
+	if err != nil {
+		t.Fatalf("ResumeBatch failed: %v", err)
+	}
+	if progress.Kind != FunctionCall || len(progress.PendingCalls) != 0 {
+		t.Fatalf("expected fetch(3) reported as a plain call, got %v with %+v", progress.Kind, progress.PendingCalls)
+	}
+	if string(progress.Args[0]) != "3" {
+		t.Fatalf("expected fetch(3), got args %s", progress.Args)
+	}
+	progress, err = progress.Snapshot.Resume(progress.CallID, 30)
+	if err != nil {
+		t.Fatalf("Resume failed: %v", err)
+	}
+	var got int
+	if err := progress.Result.Unmarshal(&got); err != nil {
+		t.Fatalf("unmarshal result: %v", err)
+	}
+	if got != 61 {
+		t.Fatalf("expected 61, got %d", got)
+	}
+}
+
+func TestResumeBatchUsesCallCache(t *testing.T) {
+	code := "a = rate('EUR')\nb = rate('USD')\nc = rate('EUR')\nd = rate('GBP')\na + b + c + d"
+	m := newTestMonty(t, code, nil, []string{"rate"})
+
+	progress, err := m.StartWithOptions(Options{CacheCalls: []string{"rate"}})
+	if err != nil {
+		t.Fatalf("StartWithOptions failed: %v", err)
+	}
+	first := progress.CallID
+	results := []FutureResult{{CallID: first, Result: 2}, {CallID: first + 1, Result: 3}}
+	progress, err = progress.Snapshot.ResumeBatch(first, results)
+	if err != nil {
+		t.Fatalf("ResumeBatch failed: %v", err)
+	}
+	// The second rate('EUR') is answered with the result the batch cached.
+	if progress.Kind != FunctionCall || string(progress.Args[0]) != `"GBP"` {
+		t.Fatalf("expected rate('GBP'), got %v with args %s", progress.Kind, progress.Args)
+	}
+	progress, err = progress.Snapshot.Resume(progress.CallID, 5)
+	if err != nil {
+		t.Fatalf("Resume failed: %v", err)
+	}
+	var got int
+	if err := progress.Result.Unmarshal(&got); err != nil || got != 12 {
+		t.Fatalf("expected 12, got %d (%v)", got, err)
+	}
+}
+
+func TestResumeBatchDefersAwaitableCalls(t *testing.T) {
+	code := "import asyncio\na = fetch(1)\nb, c = await asyncio.gather(load(2), load(3))\na + b + c"
+	m := newTestMonty(t, code, nil, []string{"fetch", "load"})
+
+	progress, err := m.StartWithOptions(Options{Awaitable: []string{"load"}})
+	if err != nil {
+		t.Fatalf("StartWithOptions failed: %v", err)
+	}
+	progress, err = progress.Snapshot.ResumeBatch(progress.CallID, []FutureResult{{CallID: progress.CallID, Result: 1}})
+	if err != nil {
+		t.Fatalf("ResumeBatch failed: %v", err)
+	}
+	if progress.Kind != ResolveFutures || len(progress.PendingCalls) != 2 {
+		t.Fatalf("expected both loads deferred, got %v with %+v", progress.Kind, progress.PendingCalls)
+	}
+	var results []FutureResult
+	for i, call := range progress.PendingCalls {
+		if call.FunctionName != "load" {
+			t.Fatalf("unexpected deferred call %+v", call)
+		}
+		results = append(results, FutureResult{CallID: call.CallID, Result: i + 2})
+	}
+	progress, err = progress.FutureSnapshot.Resume(results)
+	if err != nil {
+		t.Fatalf("Resume failed: %v", err)
+	}
+	var got int
+	if err := progress.Result.Unmarshal(&got); err != nil {
+		t.Fatalf("unmarshal result: %v", err)
+	}
+	if got != 6 {
+		t.Fatalf("expected 6, got %d", got)
+	}
+}
+
+func TestResumeBatchDefersOsCalls(t *testing.T) {
+	code := "import asyncio\nfrom pathlib import Path\n" +
+		"n = Path('n').read_text()\n" +
+		"a, b = await asyncio.gather(Path('a').read_text(), Path('b').read_text())\n" +
+		"n + a + b"
+	m := newTestMonty(t, code, nil, nil)
+
+	progress, err := m.StartWithOptions(Options{Awaitable: []string{"Path.read_text"}})
+	if err != nil {
+		t.Fatalf("StartWithOptions failed: %v", err)
+	}
+	if progress.Kind != OsCall {
+		t.Fatalf("expected OsCall, got %v", progress.Kind)
+	}
+	results := []FutureResult{{CallID: progress.CallID, Result: "n"}}
+	progress, err = progress.Snapshot.ResumeBatchWithOsCalls(progress.CallID, results)
+	if err != nil {
+		t.Fatalf("ResumeBatchWithOsCalls failed: %v", err)
+	}
+	if progress.Kind != ResolveFutures || len(progress.PendingCalls) != 2 {
+		t.Fatalf("expected both reads deferred, got %v with %+v", progress.Kind, progress.PendingCalls)
+	}
+	results = nil
+	for _, call := range progress.PendingCalls {
+		if call.OsFunction != "Path.read_text" {
+			t.Fatalf("unexpected deferred call %+v", call)
+		}
+		var path map[string]string
+		if err := call.Args[0].Unmarshal(&path); err != nil {
+			t.Fatalf("unmarshal path: %v", err)
+		}
+		results = append(results, FutureResult{CallID: call.CallID, Result: "<" + path["$path"] + ">"})
+	}
+	progress, err = progress.FutureSnapshot.Resume(results)
+	if err != nil {
+		t.Fatalf("Resume failed: %v", err)
+	}
+	var got string
+	if err := progress.Result.Unmarshal(&got); err != nil {
+		t.Fatalf("unmarshal result: %v", err)
+	}
+	if got != "n<a><b>" {
+		t.Fatalf("expected n<a><b>, got %q", got)
+	}
+}
+
+func TestResumeBatchStopsAtOsCallsUnlessIncluded(t *testing.T) {
+	code := "from pathlib import Path\nn = fetch()\na = Path('a').read_text()\nn + a"
+	m := newTestMonty(t, code, nil, []string{"fetch"})
+
+	progress, err := m.Start()
+	if err != nil {
+		t.Fatalf("Start failed: %v", err)
+	}
+	results := []FutureResult{{CallID: progress.CallID, Result: "n"}}
+	progress, err = progress.Snapshot.ResumeBatch(progress.CallID, results)
+	if err != nil {
+		t.Fatalf("ResumeBatch failed: %v", err)
+	}
+	if progress.Kind != OsCall || progress.OsFunction != "Path.read_text" || len(progress.PendingCalls) != 0 {
+		t.Fatalf("expected the read reported as a plain OS call, got %v with %+v", progress.Kind, progress.PendingCalls)
+	}
+	progress, err = progress.Snapshot.Resume(progress.CallID, "a")
+	if err != nil {
+		t.Fatalf("Resume failed: %v", err)
+	}
+	var got string
+	if err := progress.Result.Unmarshal(&got); err != nil || got != "na" {
+		t.Fatalf("expected na, got %q (%v)", got, err)
+	}
+}
+
+func TestExceptionCauseReportedWhenPropagated(t *testing.T) {
+	m := newTestMonty(t, "fetch()", nil, []string{"fetch"})
+
+	progress, err := m.Start()
+	if err != nil {
+		t.Fatalf("Start failed: %v", err)
+	}
+	_, err = progress.Snapshot.ResumeException(progress.CallID, Exception{
+		Type:    "ValueError",
+		Message: "bad response",
+		Cause:   &Exception{Type: "ConnectionError", Message: "reset by peer"},
+	})
+	if err == nil {
+		t.Fatal("expected the run to fail")
+	}
+	msg := err.Error()
+	cause := strings.Index(msg, "ConnectionError: reset by peer")
+	if cause < 0 || !strings.Contains(msg, "direct cause") || strings.Index(msg, "ValueError: bad response") < cause {
+		t.Fatalf("expected the cause printed before the error, got %q", msg)
+	}
+}
+
+func TestExceptionCauseDroppedWhenHandled(t *testing.T) {
+	code := "try:\n    fetch()\nexcept ValueError:\n    raise KeyError('missing')"
+	m := newTestMonty(t, code, nil, []string{"fetch"})
+
+	progress, err := m.Start()
+	if err != nil {
+		t.Fatalf("Start failed: %v", err)
+	}
+	_, err = progress.Snapshot.ResumeException(progress.CallID, Exception{
+		Type:    "ValueError",
+		Message: "bad response",
+		Cause:   &Exception{Type: "ConnectionError", Message: "reset by peer"},
+	})
+	if err == nil || !strings.Contains(err.Error(), "missing") {
+		t.Fatalf("expected the KeyError to fail the run, got %v", err)
+	}
+	if strings.Contains(err.Error(), "ConnectionError") {
+		t.Fatalf("expected no host cause for an exception the script raised, got %q", err)
+	}
+}
+
+func TestExceptionDataRoundTrips(t *testing.T) {
+	code := "try:\n    fetch()\nexcept ValueError as e:\n    saved = e\nwait()\nreport(saved)"
+	m := newTestMonty(t, code, nil, []string{"fetch", "wait", "report"})
+
+	progress, err := m.Start()
+	if err != nil {
+		t.Fatalf("Start failed: %v", err)
+	}
+	progress, err = progress.Snapshot.ResumeException(progress.CallID, Exception{
+		Type:       "ValueError",
+		Args:       []any{"bad response", 503},
+		Attributes: map[string]any{"retry_after": 30},
+	})
+	if err != nil || progress.FunctionName != "wait" {
+		t.Fatalf("expected the wait() call, got %+v (%v)", progress, err)
+	}
+	// The data is kept with the run across a dump.
+	dump, err := progress.Snapshot.Dump()
+	if err != nil {
+		t.Fatalf("Dump failed: %v", err)
+	}
+	progress.Snapshot.Close()
+	restored, err := SnapshotFromBytes(dump)
+	if err != nil {
+		t.Fatalf("SnapshotFromBytes failed: %v", err)
+	}
+	progress, err = restored.Resume(progress.CallID, nil)
+	if err != nil || progress.FunctionName != "report" {
+		t.Fatalf("expected the report() call, got %+v (%v)", progress, err)
+	}
+	defer progress.Snapshot.Close()
+
+	var reported struct {
+		Exception struct {
+			Type       string         `json:"type"`
+			Message    string         `json:"message"`
+			Args       []any          `json:"args"`
+			Attributes map[string]any `json:"attributes"`
+		} `json:"$exception"`
+	}
+	if err := progress.Args[0].Unmarshal(&reported); err != nil {
+		t.Fatalf("unmarshal exception: %v", err)
+	}
+	exc := reported.Exception
+	if exc.Type != "ValueError" || exc.Message != "('bad response', 503)" {
+		t.Fatalf("expected ValueError('bad response', 503), got %+v", exc)
+	}
+	if len(exc.Args) != 2 || exc.Args[0] != "bad response" || exc.Args[1] != float64(503) {
+		t.Fatalf("expected the host's args back, got %v", exc.Args)
+	}
+	if exc.Attributes["retry_after"] != float64(30) {
+		t.Fatalf("expected the host's attributes back, got %v", exc.Attributes)
+	}
+}
+
+func TestExceptionCauseOnlyFromFailingFuture(t *testing.T) {
+	code := "import asyncio\na, b = fetch(1), fetch(2)\nawait b\nawait a"
+	m := newTestMonty(t, code, nil, []string{"fetch"})
+
+	progress, err := m.Start()
+	var ids []uint32
+	for err == nil && progress.Kind == FunctionCall {
+		ids = append(ids, progress.CallID)
+		progress, err = progress.Snapshot.ResumeFuture(progress.CallID)
+	}
+	if err != nil {
+		t.Fatalf("run failed: %v", err)
+	}
+	if progress.Kind != ResolveFutures || len(ids) != 2 {
+		t.Fatalf("expected two pending futures, got %v with %v", progress.Kind, ids)
+	}
+	_, err = progress.FutureSnapshot.Resume([]FutureResult{
+		{CallID: ids[0], Exception: &Exception{
+			Type: "ValueError", Message: "first",
+			Cause: &Exception{Type: "ConnectionError", Message: "first cause"},
+		}},
+		{CallID: ids[1], Exception: &Exception{
+			Type: "KeyError", Message: "second",
+			Cause: &Exception{Type: "ConnectionError", Message: "second cause"},
+		}},
+	})
+	if err == nil || !strings.Contains(err.Error(), "second cause") {
+		t.Fatalf("expected the awaited future's cause, got %v", err)
+	}
+	if strings.Contains(err.Error(), "first cause") {
+		t.Fatalf("expected the other future's cause left out, got %q", err)
+	}
+}
+
+// resolveOneFuture starts code, defers its single fetch() call, and resolves it with
+// result.
+func resolveOneFuture(t *testing.T, code string, result FutureResult) (Progress, error) {
+	t.Helper()
+	m := newTestMonty(t, code, nil, []string{"fetch"})
+	progress, err := m.Start()
+	if err != nil || progress.Kind != FunctionCall {
+		t.Fatalf("expected the fetch() call, got %+v (%v)", progress, err)
+	}
+	result.CallID = progress.CallID
+	progress, err = progress.Snapshot.ResumeFuture(progress.CallID)
+	if err != nil || progress.Kind != ResolveFutures {
+		t.Fatalf("expected a pending future, got %+v (%v)", progress, err)
+	}
+	return progress.FutureSnapshot.Resume([]FutureResult{result})
+}
+
+func TestCancelledFutureRaisesCancelledError(t *testing.T) {
+	code := "import asyncio\nf = fetch()\ntry:\n    await f\nexcept BaseException as e:\n    r = repr(e)\nr"
+	progress, err := resolveOneFuture(t, code, FutureResult{Cancelled: true, Err: "gave up"})
+	if err != nil || progress.Kind != Complete {
+		t.Fatalf("expected the script to catch the cancellation, got %+v (%v)", progress, err)
+	}
+	var got string
+	if err := progress.Result.Unmarshal(&got); err != nil {
+		t.Fatalf("unmarshal result: %v", err)
+	}
+	if !strings.HasPrefix(got, "CancelledError") || !strings.Contains(got, "gave up") {
+		t.Fatalf("expected CancelledError('gave up'), got %q", got)
+	}
+}
+
+func TestTimedOutFutureRaisesTimeoutError(t *testing.T) {
+	code := "import asyncio\nf = fetch()\ntry:\n    await f\nexcept TimeoutError as e:\n    r = 'retry: ' + str(e)\nr"
+	progress, err := resolveOneFuture(t, code, FutureResult{TimedOut: true, Err: "no reply in 5s"})
+	if err != nil || progress.Kind != Complete {
+		t.Fatalf("expected the script to catch the timeout, got %+v (%v)", progress, err)
+	}
+	var got string
+	if err := progress.Result.Unmarshal(&got); err != nil {
+		t.Fatalf("unmarshal result: %v", err)
+	}
+	if got != "retry: no reply in 5s" {
+		t.Fatalf("expected the TimeoutError's message, got %q", got)
+	}
+}
+
+// deferCalls answers each reported call with a future until the run needs them
+// resolved, returning the call IDs in order.
+func deferCalls(t *testing.T, progress Progress, err error) (Progress, []uint32) {
+	t.Helper()
+	var ids []uint32
+	for err == nil && progress.Kind == FunctionCall {
+		ids = append(ids, progress.CallID)
+		progress, err = progress.Snapshot.ResumeFuture(progress.CallID)
+	}
+	if err != nil || progress.Kind != ResolveFutures {
+		t.Fatalf("expected pending futures, got %+v (%v)", progress, err)
+	}
+	return progress, ids
+}
+
+func TestGatherHoldsPartialResults(t *testing.T) {
+	code := "import asyncio\nawait asyncio.gather(fetch(1), fetch(2), fetch(3))"
+	m := newTestMonty(t, code, nil, []string{"fetch"})
+
+	progress, err := m.StartWithOptions(Options{Resolution: ResolveGather})
+	progress, ids := deferCalls(t, progress, err)
+	if len(ids) != 3 {
+		t.Fatalf("expected three calls, got %v", ids)
+	}
+	progress, err = progress.FutureSnapshot.Resume([]FutureResult{
+		{CallID: ids[0], Result: 10},
+		{CallID: ids[2], Result: 30},
+	})
+	if err != nil || progress.Kind != ResolveFutures {
+		t.Fatalf("expected the partial batch to be held back, got %+v (%v)", progress, err)
+	}
+	if len(progress.PendingIDs) != 1 || progress.PendingIDs[0] != ids[1] {
+		t.Fatalf("expected only %d still pending, got %v", ids[1], progress.PendingIDs)
+	}
+
+	// The held results are kept in the dump.
+	dump, err := progress.FutureSnapshot.Dump()
+	if err != nil {
+		t.Fatalf("Dump failed: %v", err)
+	}
+	progress.FutureSnapshot.Close()
+	restored, err := FutureSnapshotFromBytes(dump)
+	if err != nil {
+		t.Fatalf("FutureSnapshotFromBytes failed: %v", err)
+	}
+	progress, err = restored.Resume([]FutureResult{{CallID: ids[1], Result: 20}})
+	if err != nil || progress.Kind != Complete {
+		t.Fatalf("expected the run to finish, got %+v (%v)", progress, err)
+	}
+	var got []int
+	if err := progress.Result.Unmarshal(&got); err != nil {
+		t.Fatalf("unmarshal result: %v", err)
+	}
+	if len(got) != 3 || got[0] != 10 || got[1] != 20 || got[2] != 30 {
+		t.Fatalf("expected [10 20 30], got %v", got)
+	}
+}
+
+func TestGatherKeepsResultsForLaterCalls(t *testing.T) {
+	code := "import asyncio\na = await asyncio.gather(fetch(1))\nb = await asyncio.gather(fetch(2))\na + b"
+	m := newTestMonty(t, code, nil, []string{"fetch"})
+	options := Options{Resolution: ResolveGather}
+
+	// Learn the second call's ID from a first run; IDs are the same on every run.
+	progress, err := m.StartWithOptions(options)
+	progress, first := deferCalls(t, progress, err)
+	progress, err = progress.FutureSnapshot.Resume([]FutureResult{{CallID: first[0], Result: 1}})
+	progress, second := deferCalls(t, progress, err)
+	progress.FutureSnapshot.Close()
+
+	// A result for the second call, passed before it is made, waits for it.
+	progress, err = m.StartWithOptions(options)
+	progress, _ = deferCalls(t, progress, err)
+	progress, err = progress.FutureSnapshot.Resume([]FutureResult{
+		{CallID: first[0], Result: 10},
+		{CallID: second[0], Result: 20},
+	})
+	progress, again := deferCalls(t, progress, err)
+	if len(again) != 1 || again[0] != second[0] {
+		t.Fatalf("expected call %d again, got %v", second[0], again)
+	}
+	progress, err = progress.FutureSnapshot.Resume(nil)
+	if err != nil || progress.Kind != Complete {
+		t.Fatalf("expected the kept result to finish the run, got %+v (%v)", progress, err)
+	}
+	var got []int
+	if err := progress.Result.Unmarshal(&got); err != nil {
+		t.Fatalf("unmarshal result: %v", err)
+	}
+	if len(got) != 2 || got[0] != 10 || got[1] != 20 {
+		t.Fatalf("expected [10 20], got %v", got)
+	}
+}
+
+func TestInterruptRunsFinallyBlocks(t *testing.T) {
+	code := "try:\n    fetch()\nfinally:\n    cleanup()"
+	m := newTestMonty(t, code, nil, []string{"fetch", "cleanup"})
+
+	progress, err := m.Start()
+	if err != nil {
+		t.Fatalf("Start failed: %v", err)
+	}
+	progress, err = progress.Snapshot.Interrupt("RuntimeError", "shutting down")
+	if err != nil || progress.Kind != FunctionCall || progress.FunctionName != "cleanup" {
+		t.Fatalf("expected the finally block to call cleanup(), got %+v (%v)", progress, err)
+	}
+	_, err = progress.Snapshot.Resume(progress.CallID, nil)
+	if err == nil || !strings.Contains(err.Error(), "RuntimeError: shutting down") {
+		t.Fatalf("expected the interrupt to end the run, got %v", err)
+	}
+}
+
+func TestInterruptPendingFutures(t *testing.T) {
+	code := "import asyncio\ntry:\n    await asyncio.gather(fetch(1), fetch(2))\nexcept ValueError as e:\n    r = 'stopped: ' + str(e)\nr"
+	m := newTestMonty(t, code, nil, []string{"fetch"})
+
+	progress, err := m.Start()
+	progress, _ = deferCalls(t, progress, err)
+	progress, err = progress.FutureSnapshot.Interrupt("ValueError", "deadline")
+	if err != nil || progress.Kind != Complete {
+		t.Fatalf("expected the script to catch the interrupt, got %+v (%v)", progress, err)
+	}
+	var got string
+	if err := progress.Result.Unmarshal(&got); err != nil || got != "stopped: deadline" {
+		t.Fatalf("expected %q, got %q (%v)", "stopped: deadline", got, err)
+	}
+}
+
+func TestRemainingBudget(t *testing.T) {
+	m := newTestMonty(t, "fetch()", nil, []string{"fetch"})
+
+	progress, err := m.StartWithOptions(Options{Limits: ResourceLimits{MaxInstructions: 1000}})
+	if err != nil {
+		t.Fatalf("StartWithOptions failed: %v", err)
+	}
+	budget, err := progress.Snapshot.RemainingBudget()
+	if err != nil {
+		t.Fatalf("RemainingBudget failed: %v", err)
+	}
+	if budget.Instructions == nil || *budget.Instructions > 1000 {
+		t.Fatalf("expected an instruction budget within the limit, got %v", budget.Instructions)
+	}
+	if budget.Memory != nil {
+		t.Fatalf("expected unlimited memory, got %d", *budget.Memory)
+	}
+}
+
+func TestSetLimitsReportsChanges(t *testing.T) {
+	m := newTestMonty(t, "fetch()", nil, []string{"fetch"})
+
+	progress, err := m.StartWithOptions(Options{Limits: ResourceLimits{MaxInstructions: 1000, MaxMemory: 1 << 20}})
+	if err != nil {
+		t.Fatalf("StartWithOptions failed: %v", err)
+	}
+	changes, err := progress.Snapshot.SetLimits(ResourceLimits{MaxInstructions: 5000, MaxAllocations: 100})
+	if err != nil {
+		t.Fatalf("SetLimits failed: %v", err)
+	}
+	if changes.MaxInstructions != LimitRaised {
+		t.Fatalf("expected instructions raised, got %q", changes.MaxInstructions)
+	}
+	if changes.MaxMemory != LimitRaised {
+		t.Fatalf("expected removed memory limit to count as raised, got %q", changes.MaxMemory)
+	}
+	if changes.MaxAllocations != LimitLowered {
+		t.Fatalf("expected new allocation limit to count as lowered, got %q", changes.MaxAllocations)
+	}
+	if changes.MaxDurationMs != LimitUnchanged {
+		t.Fatalf("expected duration unchanged, got %q", changes.MaxDurationMs)
+	}
+}
+
+func TestMaxObjectSize(t *testing.T) {
+	m := newTestMonty(t, "small = 'a' * 100\nbig = 'a' * 10_000_000\nlen(big)", nil, nil)
+
+	_, err := m.StartWithOptions(Options{Limits: ResourceLimits{MaxObjectSize: 1 << 20}})
+	if err == nil || !strings.Contains(err.Error(), "size limit") {
+		t.Fatalf("expected the large string to exceed the object size limit, got %v", err)
+	}
+}
+
+func TestMaxNestingDepth(t *testing.T) {
+	code := "x = []\nfor _ in range(50):\n    x = [x]\ntry:\n    fetch(x)\nexcept ValueError as e:\n    'rejected: ' + str(e)"
+	m := newTestMonty(t, code, nil, []string{"fetch"})
+
+	progress, err := m.StartWithOptions(Options{Limits: ResourceLimits{MaxNestingDepth: 10}})
+	if err != nil {
+		t.Fatalf("StartWithOptions failed: %v", err)
+	}
+	if progress.Kind != Complete {
+		t.Fatalf("expected the call to be rejected in the script, got %v", progress.Kind)
+	}
+	var got string
+	if err := progress.Result.Unmarshal(&got); err != nil {
+		t.Fatalf("unmarshal result: %v", err)
+	}
+	if !strings.Contains(got, "rejected: fetch() argument nests") {
+		t.Fatalf("unexpected result: %q", got)
+	}
+}
+
+func TestMaxPrintBytesRaise(t *testing.T) {
+	m := newTestMonty(t, "for i in range(1000):\n    print('spam')", nil, nil)
+
+	opts := Options{Limits: ResourceLimits{MaxPrintBytes: 100}, PrintOverflow: PrintRaise}
+	if _, err := m.StartWithOptions(opts); err == nil || !strings.Contains(err.Error(), "print output limit") {
+		t.Fatalf("expected printing to exceed the output limit, got %v", err)
+	}
+}
+
+func TestWarnings(t *testing.T) {
+	m := newTestMonty(t, "for i in range(100):\n    print('spam')\nfetch()", nil, []string{"fetch"})
+
+	progress, err := m.StartWithOptions(Options{Limits: ResourceLimits{MaxPrintBytes: 100}})
+	if err != nil {
+		t.Fatalf("StartWithOptions failed: %v", err)
+	}
+	kinds := map[WarningKind]bool{}
+	for _, warning := range progress.Warnings {
+		kinds[warning.Kind] = true
+	}
+	if !kinds[WarningOutputTruncated] || !kinds[WarningNearLimit] {
+		t.Fatalf("expected truncation and near-limit warnings, got %+v", progress.Warnings)
+	}
+	next, err := progress.Snapshot.Resume(progress.CallID, nil)
+	if err != nil {
+		t.Fatalf("Resume failed: %v", err)
+	}
+	if len(next.Warnings) != 0 {
+		t.Fatalf("expected warnings to be reported once, got %+v", next.Warnings)
+	}
+}
+
+func TestOutputIsPerRun(t *testing.T) {
+	m := newTestMonty(t, "print('before', name)\nfetch()\nprint('after', name)", []string{"name"}, []string{"fetch"})
+
+	a, err := m.Start("a")
+	if err != nil {
+		t.Fatalf("Start failed: %v", err)
+	}
+	b, err := m.Start("b")
+	if err != nil {
+		t.Fatalf("Start failed: %v", err)
+	}
+	if a.Output != "before a\n" || b.Output != "before b\n" {
+		t.Fatalf("expected each run's own output, got %q and %q", a.Output, b.Output)
+	}
+	b, err = b.Snapshot.Resume(b.CallID, nil)
+	if err != nil {
+		t.Fatalf("Resume failed: %v", err)
+	}
+	a, err = a.Snapshot.Resume(a.CallID, nil)
+	if err != nil {
+		t.Fatalf("Resume failed: %v", err)
+	}
+	if a.Output != "after a\n" || b.Output != "after b\n" {
+		t.Fatalf("expected output since the last progress, got %q and %q", a.Output, b.Output)
+	}
+
+	progress, err := m.StartWithOptions(Options{PrintToStdout: true}, "c")
+	if err != nil {
+		t.Fatalf("StartWithOptions failed: %v", err)
+	}
+	defer progress.Snapshot.Close()
+	if progress.Output != "" {
+		t.Fatalf("expected output on stdout only, got %q", progress.Output)
+	}
+}
+
+func TestErrorFormat(t *testing.T) {
+	m := newTestMonty(t, "def f():\n    raise ValueError('boom')\nf()", nil, nil)
+
+	_, err := m.StartWithOptions(Options{})
+	if err == nil || strings.Contains(err.Error(), "\n") || !strings.Contains(err.Error(), "ValueError: boom") {
+		t.Fatalf("expected a one-line summary, got %v", err)
+	}
+	_, err = m.StartWithOptions(Options{ErrorFormat: ErrorTraceback})
+	if err == nil || !strings.Contains(err.Error(), "Traceback") || !strings.Contains(err.Error(), "ValueError: boom") {
+		t.Fatalf("expected a full traceback, got %v", err)
+	}
+}
+
+func TestCostModelWeighsAllocations(t *testing.T) {
+	m := newTestMonty(t, "fetch()\nx = [[i] for i in range(1000)]\nlen(x)", nil, []string{"fetch"})
+
+	progress, err := m.StartWithOptions(Options{
+		Limits:    ResourceLimits{MaxInstructions: 100000, MaxCost: 100000},
+		CostModel: CostModel{Allocation: 1000, ExternalCall: 50},
+	})
+	if err != nil {
+		t.Fatalf("StartWithOptions failed: %v", err)
+	}
+	if progress.Kind != FunctionCall {
+		t.Fatalf("expected FunctionCall, got %v", progress.Kind)
+	}
+	stats, err := progress.Snapshot.Stats()
+	if err != nil {
+		t.Fatalf("Stats failed: %v", err)
+	}
+	if stats.Cost < stats.Instructions {
+		t.Fatalf("expected cost to include every instruction, got %+v", stats)
+	}
+	if _, err := progress.Snapshot.Resume(progress.CallID, nil); err == nil || !strings.Contains(err.Error(), "cost limit") {
+		t.Fatalf("expected allocations to exhaust the cost budget, got %v", err)
+	}
+}
+
+func TestCostModelZeroInstructionWeight(t *testing.T) {
+	m := newTestMonty(t, "fetch()\nx = 0\nfor i in range(100):\n    x += i\nx", nil, []string{"fetch"})
+
+	zero := uint64(0)
+	progress, err := m.StartWithOptions(Options{
+		CostModel: CostModel{Instruction: &zero, ExternalCall: 7},
+	})
+	if err != nil {
+		t.Fatalf("StartWithOptions failed: %v", err)
+	}
+	if progress.Kind != FunctionCall {
+		t.Fatalf("expected FunctionCall, got %v", progress.Kind)
+	}
+	resumed, err := progress.Snapshot.Resume(progress.CallID, nil)
+	if err != nil {
+		t.Fatalf("Resume failed: %v", err)
+	}
+	if resumed.Kind != Complete || resumed.Stats == nil {
+		t.Fatalf("expected completion with stats, got %+v", resumed)
+	}
+	if resumed.Stats.Instructions == 0 || resumed.Stats.Cost != 7 {
+		t.Fatalf("expected only the external call to cost, got %+v", *resumed.Stats)
+	}
+}
+
+func TestQuotaGroupSharedBudget(t *testing.T) {
+	group, err := NewQuotaGroup(QuotaLimits{MaxInstructions: 200})
+	if err != nil {
+		t.Fatalf("NewQuotaGroup failed: %v", err)
+	}
+	defer group.Close()
+	m := newTestMonty(t, "x = 0\nfor i in range(10000):\n    x += i\nx", nil, nil)
+	if err := m.AttachQuotaGroup(group); err != nil {
+		t.Fatalf("AttachQuotaGroup failed: %v", err)
+	}
+
+	if _, err := m.Run(); err == nil || !strings.Contains(err.Error(), "quota") {
+		t.Fatalf("expected the group budget to stop the run, got %v", err)
+	}
+	usage, err := group.Usage()
+	if err != nil {
+		t.Fatalf("Usage failed: %v", err)
+	}
+	if usage.Instructions < 200 || usage.RemainingInstructions == nil || *usage.RemainingInstructions != 0 {
+		t.Fatalf("unexpected usage: %+v", usage)
+	}
+	if _, err := m.Run(); err == nil {
+		t.Fatal("expected a second run in the exhausted group to fail")
+	}
+}
+
+func TestCallTimeoutReported(t *testing.T) {
+	m := newTestMonty(t, "fetch()", nil, []string{"fetch"})
+
+	opts := Options{CallTimeouts: CallTimeouts{
+		Default:   time.Second,
+		Functions: map[string]time.Duration{"fetch": 250 * time.Millisecond},
+	}}
+	progress, err := m.StartWithOptions(opts)
+	if err != nil {
+		t.Fatalf("StartWithOptions failed: %v", err)
+	}
+	if progress.Kind != FunctionCall {
+		t.Fatalf("expected FunctionCall, got %v", progress.Kind)
+	}
+	if progress.Timeout != 250*time.Millisecond {
+		t.Fatalf("expected 250ms timeout, got %v", progress.Timeout)
+	}
+}
+
+func TestSubMillisecondCallTimeoutRoundsUp(t *testing.T) {
+	m := newTestMonty(t, "fetch()", nil, []string{"fetch"})
+
+	opts := Options{CallTimeouts: CallTimeouts{Default: 500 * time.Microsecond}}
+	progress, err := m.StartWithOptions(opts)
+	if err != nil {
+		t.Fatalf("StartWithOptions failed: %v", err)
+	}
+	if progress.Kind != FunctionCall {
+		t.Fatalf("expected FunctionCall, got %v", progress.Kind)
+	}
+	if progress.Timeout != time.Millisecond {
+		t.Fatalf("expected the timeout to round up to 1ms, got %v", progress.Timeout)
+	}
+}
+
+func TestCallPrioritiesReported(t *testing.T) {
+	code := "import asyncio\nawait asyncio.gather(log(), fetch())"
+	m := newTestMonty(t, code, nil, []string{"log", "fetch"})
+
+	opts := Options{CallPriorities: CallPriorities{Functions: map[string]int{"fetch": 5}}}
+	progress, err := m.StartWithOptions(opts)
+	var fetchID uint32
+	for err == nil && progress.Kind == FunctionCall {
+		if progress.FunctionName == "fetch" {
+			if progress.Priority != 5 {
+				t.Fatalf("expected priority 5 for fetch, got %d", progress.Priority)
+			}
+			fetchID = progress.CallID
+		}
+		progress, err = progress.Snapshot.ResumeFuture(progress.CallID)
+	}
+	if err != nil {
+		t.Fatalf("run failed: %v", err)
+	}
+	if progress.Kind != ResolveFutures {
+		t.Fatalf("expected ResolveFutures, got %v", progress.Kind)
+	}
+	if len(progress.PendingIDs) != 2 || progress.PendingIDs[0] != fetchID {
+		t.Fatalf("expected fetch's call %d listed first, got %v", fetchID, progress.PendingIDs)
+	}
+}
+
+func TestListBuiltins(t *testing.T) {
+	m := newTestMonty(t, "1", nil, nil)
+
+	builtins, err := m.Builtins()
+	if err != nil {
+		t.Fatalf("Builtins failed: %v", err)
+	}
+	kinds := map[string]string{}
+	for _, builtin := range builtins {
+		if !builtin.Enabled {
+			t.Fatalf("expected %s to be enabled", builtin.Name)
+		}
+		kinds[builtin.Name] = builtin.Kind
+	}
+	if kinds["len"] != "function" || kinds["int"] != "type" || kinds["host_emit"] != "host" {
+		t.Fatalf("unexpected builtin kinds: %v", kinds)
+	}
+}
+
+func TestDisableBuiltins(t *testing.T) {
+	m := newTestMonty(t, "len([1, 2])", nil, nil)
+
+	if err := m.DisableBuiltins("len"); err != nil {
+		t.Fatalf("DisableBuiltins failed: %v", err)
+	}
+	_, err := m.Run()
+	if err == nil || !strings.Contains(err.Error(), "PermissionError") {
+		t.Fatalf("expected PermissionError, got %v", err)
+	}
+	builtins, err := m.Builtins()
+	if err != nil {
+		t.Fatalf("Builtins failed: %v", err)
+	}
+	for _, builtin := range builtins {
+		if builtin.Name == "len" && builtin.Enabled {
+			t.Fatal("expected len to be listed as disabled")
+		}
+	}
+
+	if err := m.DisableBuiltins(); err != nil {
+		t.Fatalf("DisableBuiltins failed: %v", err)
+	}
+	if _, err := m.Run(); err != nil {
+		t.Fatalf("expected len to work again, got %v", err)
+	}
+	if err := m.DisableBuiltins("open"); err == nil || !strings.Contains(err.Error(), "unknown builtin") {
+		t.Fatalf("expected unknown builtin error, got %v", err)
+	}
+}
+
+func TestOverrideBuiltins(t *testing.T) {
+	m := newTestMonty(t, "print('hi')\n1", nil, nil)
+
+	if err := m.OverrideBuiltins("print"); err != nil {
+		t.Fatalf("OverrideBuiltins failed: %v", err)
+	}
+	progress, err := m.Start()
+	if err != nil {
+		t.Fatalf("Start failed: %v", err)
+	}
+	if progress.Kind != FunctionCall || progress.FunctionName != "print" || !progress.Builtin {
+		t.Fatalf("expected overridden print call, got %v %q (builtin=%v)", progress.Kind, progress.FunctionName, progress.Builtin)
+	}
+	progress, err = progress.Snapshot.Resume(progress.CallID, nil)
+	if err != nil {
+		t.Fatalf("Resume failed: %v", err)
+	}
+	if progress.Kind != Complete || progress.Output != "" {
+		t.Fatalf("expected completion without output, got %v %q", progress.Kind, progress.Output)
+	}
+	if err := m.OverrideBuiltins("host_emit"); err == nil {
+		t.Fatal("expected overriding a host builtin to fail")
+	}
+}
+
+func TestOsFunctionsHaveSchemas(t *testing.T) {
+	functions, err := OsFunctions()
+	if err != nil {
+		t.Fatalf("OsFunctions failed: %v", err)
+	}
+	if len(functions) == 0 {
+		t.Fatal("expected at least one OS function")
+	}
+	for _, function := range functions {
+		schema, err := OsCallSchemaFor(function.Name)
+		if err != nil {
+			t.Fatalf("no schema for %s: %v", function.Name, err)
+		}
+		if schema.Category != function.Category {
+			t.Fatalf("%s: category %q != %q", function.Name, schema.Category, function.Category)
+		}
+	}
+}
+
+func TestOsCallSchema(t *testing.T) {
+	schema, err := OsCallSchemaFor("Path.write_text")
+	if err != nil {
+		t.Fatalf("OsCallSchemaFor failed: %v", err)
+	}
+	if len(schema.Args) != 2 || schema.Args[1].Type != "str" || schema.Returns != "int" {
+		t.Fatalf("unexpected schema: %+v", schema)
+	}
+	if _, err := OsCallSchemaFor("Path.nope"); err == nil || !strings.Contains(err.Error(), "unknown OS function") {
+		t.Fatalf("expected unknown OS function error, got %v", err)
+	}
+}
+
+func TestProgressMessage(t *testing.T) {
+	m := newTestMonty(t, "print('hi')\nfetch(x, scale=2)", []string{"x"}, []string{"fetch"})
+
+	data, err := startMessage(m, 7)
+	if err != nil {
+		t.Fatalf("startMessage failed: %v", err)
+	}
+	var message struct {
+		Version      uint32               `json:"version"`
+		Kind         string               `json:"kind"`
+		FunctionName string               `json:"function_name"`
+		Args         []json.RawMessage    `json:"args"`
+		Kwargs       [][2]json.RawMessage `json:"kwargs"`
+		CallID       *uint32              `json:"call_id"`
+		Output       string               `json:"output"`
+	}
+	if err := json.Unmarshal([]byte(data), &message); err != nil {
+		t.Fatalf("unmarshal %s: %v", data, err)
+	}
+	if message.Version != ProtocolVersion() || message.Kind != "function_call" || message.FunctionName != "fetch" {
+		t.Fatalf("expected a version %d fetch call, got %s", ProtocolVersion(), data)
+	}
+	if len(message.Args) != 1 || string(message.Args[0]) != "7" {
+		t.Fatalf("expected args [7], got %s", data)
+	}
+	if len(message.Kwargs) != 1 || string(message.Kwargs[0][0]) != `"scale"` || string(message.Kwargs[0][1]) != "2" {
+		t.Fatalf("expected kwargs scale=2, got %s", data)
+	}
+	if message.CallID == nil || message.Output != "hi\n" {
+		t.Fatalf("expected a call ID and the printed output, got %s", data)
+	}
+}
+
+func TestReportLeaks(t *testing.T) {
+	TrackHandles(true)
+	defer TrackHandles(false)
+
+	m := newTestMonty(t, "fetch()", nil, []string{"fetch"})
+	progress, err := m.Start()
+	if err != nil {
+		t.Fatalf("Start failed: %v", err)
+	}
+	leaks, err := ReportLeaks()
+	if err != nil {
+		t.Fatalf("ReportLeaks failed: %v", err)
+	}
+	if leaks["run"] != 1 || leaks["snapshot"] != 1 {
+		t.Fatalf("expected the run and snapshot to be outstanding, got %v", leaks)
+	}
+	progress.Snapshot.Close()
+	m.Close()
+	if leaks, err = ReportLeaks(); err != nil || len(leaks) != 0 {
+		t.Fatalf("expected no leaks after closing, got %v (%v)", leaks, err)
+	}
+}
+
+func TestProgressResultFreeReleasesUntakenHandles(t *testing.T) {
+	TrackHandles(true)
+	defer TrackHandles(false)
+
+	m := newTestMonty(t, "fetch()", nil, []string{"fetch"})
+	if err := startAndFree(m); err != nil {
+		t.Fatalf("startAndFree failed: %v", err)
+	}
+	leaks, err := ReportLeaks()
+	if err != nil {
+		t.Fatalf("ReportLeaks failed: %v", err)
+	}
+	if leaks["run"] != 1 || len(leaks) != 1 {
+		t.Fatalf("expected only the run outstanding, got %v", leaks)
+	}
+}
+
+func TestProgressResultTakeSnapshot(t *testing.T) {
+	TrackHandles(true)
+	defer TrackHandles(false)
+
+	m := newTestMonty(t, "fetch() + 1", nil, []string{"fetch"})
+	snap, callID, err := startAndTake(m)
+	if err != nil {
+		t.Fatalf("startAndTake failed: %v", err)
+	}
+	if leaks, _ := ReportLeaks(); leaks["snapshot"] != 1 {
+		t.Fatalf("expected the taken snapshot to outlive the result, got %v", leaks)
+	}
+	progress, err := snap.Resume(callID, 41)
+	if err != nil || string(progress.Result) != "42" {
+		t.Fatalf("expected the taken snapshot to resume to 42, got %+v (%v)", progress, err)
+	}
+	if leaks, _ := ReportLeaks(); leaks["snapshot"] != 0 {
+		t.Fatalf("expected no snapshot outstanding after resuming, got %v", leaks)
+	}
+}
+
+func TestListHandles(t *testing.T) {
+	TrackHandles(true)
+	defer TrackHandles(false)
+
+	m := newTestMonty(t, "data = [0] * 1000\nfetch()", nil, []string{"fetch"})
+	progress, err := m.Start()
+	if err != nil {
+		t.Fatalf("Start failed: %v", err)
+	}
+	defer progress.Snapshot.Close()
+	held, err := ListHandles()
+	if err != nil {
+		t.Fatalf("ListHandles failed: %v", err)
+	}
+	if len(held) != 2 || held[0].Kind != "run" || held[1].Kind != "snapshot" {
+		t.Fatalf("expected the run then its snapshot, got %+v", held)
+	}
+	if held[1].Size == nil || *held[1].Size == 0 {
+		t.Fatalf("expected the snapshot's heap size, got %+v", held[1])
+	}
+}
+
+func TestCloseTwiceIsNoOp(t *testing.T) {
+	m := newTestMonty(t, "fetch()", nil, []string{"fetch"})
+	progress, err := m.Start()
+	if err != nil {
+		t.Fatalf("Start failed: %v", err)
+	}
+	progress.Snapshot.Close()
+	progress.Snapshot.Close()
+	m.Close()
+	m.Close()
+	if _, err := m.Start(); err == nil {
+		t.Fatal("expected Start on a closed handle to fail")
+	}
+}
+
+func TestLossyUTF8(t *testing.T) {
+	code := "'caf\xe9'"
+	if _, err := New(code, "test.py", nil, nil); err == nil || !strings.Contains(err.Error(), "UTF-8") {
+		t.Fatalf("expected invalid UTF-8 code to fail, got %v", err)
+	}
+
+	m, err := NewLossy(code, "test.py", nil, nil)
+	if err != nil {
+		t.Fatalf("NewLossy failed: %v", err)
+	}
+	defer m.Close()
+	// Lossy decoding applies to that call only.
+	if _, err := New(code, "test.py", nil, nil); err == nil {
+		t.Fatal("expected New after NewLossy to stay strict")
+	}
+	result, err := m.Run()
+	if err != nil {
+		t.Fatalf("Run failed: %v", err)
+	}
+	var got string
+	if err := result.Unmarshal(&got); err != nil {
+		t.Fatalf("unmarshal result: %v", err)
+	}
+	if got != "caf\uFFFD" {
+		t.Fatalf("expected a replacement character, got %q", got)
+	}
+}
+
+func TestStringsWithNULCross(t *testing.T) {
+	m := newTestMonty(t, "fetch('a\\x00b')", nil, []string{"fetch"})
+	progress, err := m.Start()
+	if err != nil {
+		t.Fatalf("Start failed: %v", err)
+	}
+	var arg string
+	if err := progress.Args[0].Unmarshal(&arg); err != nil {
+		t.Fatalf("unmarshal arg: %v", err)
+	}
+	if arg != "a\x00b" {
+		t.Fatalf("expected the NUL to survive in the argument, got %q", arg)
+	}
+	progress, err = progress.Snapshot.Resume(progress.CallID, arg+"c")
+	if err != nil {
+		t.Fatalf("Resume failed: %v", err)
+	}
+	var result string
+	if err := progress.Result.Unmarshal(&result); err != nil {
+		t.Fatalf("unmarshal result: %v", err)
+	}
+	if result != "a\x00bc" {
+		t.Fatalf("expected the NUL to survive in the result, got %q", result)
+	}
+}
+
+func TestEval(t *testing.T) {
+	result, err := Eval("x * 2 + len(name)", map[string]any{"x": 20, "name": "ab"}, ResourceLimits{MaxInstructions: 1000})
+	if err != nil {
+		t.Fatalf("Eval failed: %v", err)
+	}
+	var got int
+	if err := result.Unmarshal(&got); err != nil {
+		t.Fatalf("unmarshal result: %v", err)
+	}
+	if got != 42 {
+		t.Fatalf("expected 42, got %d", got)
+	}
+	if _, err := Eval("from pathlib import Path\nPath('/etc/passwd').read_text()", nil, ResourceLimits{}); err == nil || !strings.Contains(err.Error(), "OS call") {
+		t.Fatalf("expected the OS call to fail the eval, got %v", err)
+	}
+}
+
+func TestRunResultJSON(t *testing.T) {
+	m := newTestMonty(t, "x + 1", []string{"x"}, []string{"fetch"})
+	result, err := m.RunResultJSON(Options{}, 41)
+	if err != nil {
+		t.Fatalf("RunResultJSON failed: %v", err)
+	}
+	var got int
+	if err := result.Unmarshal(&got); err != nil {
+		t.Fatalf("unmarshal result: %v", err)
+	}
+	if got != 42 {
+		t.Fatalf("expected 42, got %d", got)
+	}
+
+	m = newTestMonty(t, "fetch()", nil, []string{"fetch"})
+	if _, err := m.RunResultJSON(Options{}); err == nil || !strings.Contains(err.Error(), "fetch()") {
+		t.Fatalf("expected the call to fail the run, got %v", err)
+	}
+}
+
+func TestDriveAnswersCallsWithHandlers(t *testing.T) {
+	code := "print('start')\ntry:\n    fetch(1)\nexcept ValueError as e:\n    caught = str(e)\nadd(20, b=22), caught"
+	m := newTestMonty(t, code, nil, []string{"add", "fetch"})
+
+	var printed strings.Builder
+	result, err := m.Drive(Handlers{
+		Function: func(name string, args []Object, kwargs []KV) (any, error) {
+			if name == "fetch" {
+				return Exception{Type: "ValueError", Message: "offline"}, nil
+			}
+			var a, b int
+			if err := args[0].Unmarshal(&a); err != nil {
+				return nil, err
+			}
+			if err := kwargs[0].Value.Unmarshal(&b); err != nil {
+				return nil, err
+			}
+			return a + b, nil
+		},
+		Print: func(text string) { printed.WriteString(text) },
+	})
+	if err != nil {
+		t.Fatalf("Drive failed: %v", err)
+	}
+	if got := string(result); got != `{"$tuple":[42,"offline"]}` {
+		t.Fatalf("unexpected result: %s", got)
+	}
+	if printed.String() != "start\n" {
+		t.Fatalf("expected output to reach the print handler, got %q", printed.String())
+	}
+}
+
+func TestDriveSleepCountsAgainstTimeLimit(t *testing.T) {
+	code := "def nap(seconds):\n    try:\n        host_sleep(seconds)\n        return 'slept'\n    except TimeoutError:\n        return 'timed out'\n" +
+		"nap(0.05), nap(0.05), nap(60)"
+	m := newTestMonty(t, code, nil, nil)
+
+	opts := Options{Limits: ResourceLimits{MaxDurationMs: 1000}}
+	started := time.Now()
+	result, err := m.DriveWithOptions(opts, Handlers{})
+	if err != nil {
+		t.Fatalf("DriveWithOptions failed: %v", err)
+	}
+	if got := string(result); got != `{"$tuple":["slept","slept","timed out"]}` {
+		t.Fatalf("unexpected result: %s", got)
+	}
+	if elapsed := time.Since(started); elapsed < 100*time.Millisecond || elapsed > 900*time.Millisecond {
+		t.Fatalf("expected only the short sleeps to be waited out, took %v", elapsed)
+	}
+
+	opts = Options{CallTimeouts: CallTimeouts{Functions: map[string]time.Duration{"host_sleep": 10 * time.Millisecond}}}
+	result, err = m.DriveWithOptions(opts, Handlers{})
+	if err != nil {
+		t.Fatalf("DriveWithOptions failed: %v", err)
+	}
+	if got := string(result); got != `{"$tuple":["timed out","timed out","timed out"]}` {
+		t.Fatalf("expected the call timeout to cap every sleep, got %s", got)
+	}
+}
+
+func TestTimeLimitStopsLongBuiltins(t *testing.T) {
+	// Each program spends nearly all its time inside builtins that run far past the limit.
+	for _, code := range []string{
+		"len(str(7 ** 1000000))",
+		"x = list(range(3000000))\nx.reverse()\nlen(sorted(sorted(x, reverse=True)))",
+	} {
+		m := newTestMonty(t, code, nil, nil)
+		_, err := m.StartWithOptions(Options{Limits: ResourceLimits{MaxDurationMs: 20}})
+		if err == nil || !strings.Contains(strings.ToLower(err.Error()), "time") {
+			t.Fatalf("expected %q to hit the time limit, got %v", code, err)
+		}
+	}
+}
+
+func TestCallbacksMayCallBackIn(t *testing.T) {
+	inner := newTestMonty(t, "print('inner')\nx * 2", []string{"x"}, nil)
+	outer := newTestMonty(t, "print('outer')\ndouble(21)", nil, []string{"double"})
+
+	var printed strings.Builder
+	var innerOutput string
+	result, err := outer.Drive(Handlers{
+		Function: func(name string, args []Object, kwargs []KV) (any, error) {
+			var x int
+			if err := args[0].Unmarshal(&x); err != nil {
+				return nil, err
+			}
+			progress, err := inner.Start(x)
+			if err != nil {
+				return nil, err
+			}
+			innerOutput = progress.Output
+			return progress.Result, nil
+		},
+		Print: func(text string) { printed.WriteString(text) },
+	})
+	if err != nil {
+		t.Fatalf("Drive failed: %v", err)
+	}
+	if string(result) != "42" {
+		t.Fatalf("unexpected result: %s", result)
+	}
+	if printed.String() != "outer\n" || innerOutput != "inner\n" {
+		t.Fatalf("expected each run to keep its own output, got %q and %q", printed.String(), innerOutput)
+	}
+
+	m := newTestMonty(t, "fetch()", nil, []string{"fetch"})
+	progress, err := m.Start()
+	if err != nil {
+		t.Fatalf("Start failed: %v", err)
+	}
+	snapshot := progress.Snapshot
+	var restrictErr error
+	err = snapshot.DumpTo(writerFunc(func(p []byte) (int, error) {
+		if snapshot.handle != nil {
+			restrictErr = snapshot.Restrict(Policy{})
+			snapshot.Close()
+		}
+		return len(p), nil
+	}))
+	if err != nil {
+		t.Fatalf("DumpTo failed: %v", err)
+	}
+	if restrictErr == nil || !strings.Contains(restrictErr.Error(), "in use") {
+		t.Fatalf("expected changing the snapshot being dumped to fail, got %v", restrictErr)
+	}
+}
+
+type writerFunc func(p []byte) (int, error)
+
+func (f writerFunc) Write(p []byte) (int, error) { return f(p) }
+
+func TestHostEmitStreamsPartialResults(t *testing.T) {
+	m := newTestMonty(t, "for i in range(3):\n    host_emit({'done': i})\n'finished'", nil, nil)
+
+	progress, err := m.Start()
+	if err != nil {
+		t.Fatalf("Start failed: %v", err)
+	}
+	var emitted []int
+	for progress.Kind == Emit {
+		var partial map[string]int
+		if err := progress.Result.Unmarshal(&partial); err != nil {
+			t.Fatalf("unmarshal emitted value: %v", err)
+		}
+		emitted = append(emitted, partial["done"])
+		if progress, err = progress.Snapshot.Resume(progress.CallID, nil); err != nil {
+			t.Fatalf("Resume failed: %v", err)
+		}
+	}
+	if progress.Kind != Complete || len(emitted) != 3 || emitted[2] != 2 {
+		t.Fatalf("expected three emits then completion, got %v after %v", progress.Kind, emitted)
+	}
+}
+
+func TestInputPrompts(t *testing.T) {
+	m := newTestMonty(t, "name = input('name? ')\n'hi ' + name", nil, nil)
+
+	progress, err := m.Start()
+	if err != nil {
+		t.Fatalf("Start failed: %v", err)
+	}
+	if progress.Kind != Prompt || progress.Prompt != "name? " {
+		t.Fatalf("expected Prompt with prompt, got %v %q", progress.Kind, progress.Prompt)
+	}
+	progress, err = progress.Snapshot.Resume(progress.CallID, "ada")
+	if err != nil {
+		t.Fatalf("Resume failed: %v", err)
+	}
+	var out string
+	if err := progress.Result.Unmarshal(&out); err != nil || out != "hi ada" {
+		t.Fatalf("expected 'hi ada', got %q (%v)", out, err)
+	}
+}
+
+func TestHostSleepReportsDelay(t *testing.T) {
+	m := newTestMonty(t, "host_sleep(0.25)\ntry:\n    host_sleep(-1)\nexcept ValueError:\n    pass\n'done'", nil, nil)
+
+	progress, err := m.Start()
+	if err != nil {
+		t.Fatalf("Start failed: %v", err)
+	}
+	if progress.Kind != Sleep || progress.Delay != 250*time.Millisecond {
+		t.Fatalf("expected a 250ms sleep, got %v for %v", progress.Kind, progress.Delay)
+	}
+	progress, err = progress.Snapshot.Resume(progress.CallID, nil)
+	if err != nil {
+		t.Fatalf("Resume failed: %v", err)
+	}
+	if progress.Kind != Complete || string(progress.Result) != `"done"` {
+		t.Fatalf("expected completion after the sleep, got %v %s", progress.Kind, progress.Result)
+	}
+}
+
+func TestLazyInputs(t *testing.T) {
+	m := newTestMonty(t, "len(data)", []string{"data", "unused"}, nil)
+
+	progress, err := m.Start(Lazy("data"), Lazy("unused"))
+	if err != nil {
+		t.Fatalf("Start failed: %v", err)
+	}
+	if progress.Kind != LazyInput {
+		t.Fatalf("expected LazyInput, got %v", progress.Kind)
+	}
+	if progress.InputName != "data" {
+		t.Fatalf("expected request for data, got %q", progress.InputName)
+	}
+	done, err := progress.InputRequest.Provide([]int{1, 2, 3})
+	if err != nil {
+		t.Fatalf("Provide failed: %v", err)
+	}
+	if done.Kind != Complete {
+		t.Fatalf("expected Complete, got %v", done.Kind)
+	}
+	var got int
+	if err := done.Result.Unmarshal(&got); err != nil {
+		t.Fatalf("unmarshal result: %v", err)
+	}
+	if got != 3 {
+		t.Fatalf("expected 3, got %d", got)
+	}
+}
+
+func TestLazyInputScansDeclaredName(t *testing.T) {
+	m := newTestMonty(t, "len(data)", []string{"data", "unused"}, nil)
+
+	progress, err := m.Start(Lazy("blob-1"), Lazy("data"))
+	if err != nil {
+		t.Fatalf("Start failed: %v", err)
+	}
+	if progress.Kind != LazyInput || progress.InputName != "blob-1" {
+		t.Fatalf("expected a request for blob-1, got %v %q", progress.Kind, progress.InputName)
+	}
+	done, err := progress.InputRequest.Provide("abcd")
+	if err != nil {
+		t.Fatalf("Provide failed: %v", err)
+	}
+	if done.Kind != Complete {
+		t.Fatalf("expected Complete without a request for the unused input, got %v", done.Kind)
+	}
+}
+
+func TestInputBytes(t *testing.T) {
+	m := newTestMonty(t, "len(blob) + tag", []string{"blob", "tag"}, nil)
+
+	var wg sync.WaitGroup
+	for i := 1; i <= 8; i++ {
+		wg.Add(1)
+		go func(size int) {
+			defer wg.Done()
+			progress, err := m.Start(InputBytes(make([]byte, size)), 0)
+			if err != nil {
+				t.Errorf("Start failed: %v", err)
+				return
+			}
+			var got int
+			if err := progress.Result.Unmarshal(&got); err != nil {
+				t.Errorf("unmarshal result: %v", err)
+				return
+			}
+			if got != size {
+				t.Errorf("expected %d, got %d", size, got)
+			}
+		}(i * 512)
+	}
+	wg.Wait()
+
+	if _, err := m.Drive(Handlers{}, InputBytes([]byte("x")), 0); err == nil {
+		t.Fatal("expected Drive to reject InputBytes")
+	}
+}
+
+func TestSharedGlobals(t *testing.T) {
+	globals, err := NewGlobals(map[string]any{"RATES": map[string]any{"eur": 2}})
+	if err != nil {
+		t.Fatalf("NewGlobals failed: %v", err)
+	}
+	defer globals.Close()
+
+	for _, amount := range []int{3, 5} {
+		m := newTestMonty(t, "def convert(x):\n    return x * RATES['eur']\nconvert(amount)", []string{"amount"}, nil)
+		if err := m.AttachGlobals(globals); err != nil {
+			t.Fatalf("AttachGlobals failed: %v", err)
+		}
+		progress, err := m.Start(amount)
+		if err != nil {
+			t.Fatalf("Start failed: %v", err)
+		}
+		var got int
+		if err := progress.Result.Unmarshal(&got); err != nil {
+			t.Fatalf("unmarshal result: %v", err)
+		}
+		if got != amount*2 {
+			t.Fatalf("expected %d, got %d", amount*2, got)
+		}
+	}
+}
+
+func TestSeededGlobals(t *testing.T) {
+	m := newTestMonty(t, "def greet():\n    return PREFIX + name\ngreet()", []string{"name"}, nil)
+
+	progress, err := m.StartWithOptions(Options{Globals: map[string]any{"PREFIX": "hi "}}, "ada")
+	if err != nil {
+		t.Fatalf("StartWithOptions failed: %v", err)
+	}
+	var got string
+	if err := progress.Result.Unmarshal(&got); err != nil {
+		t.Fatalf("unmarshal result: %v", err)
+	}
+	if got != "hi ada" {
+		t.Fatalf("unexpected result: %q", got)
+	}
+}
+
+func TestMethodCallReceiver(t *testing.T) {
+	m := newTestMonty(t, "client.fetch(1)", []string{"client"}, nil)
+
+	client := map[string]any{"$dataclass": map[string]any{
+		"name":        "Client",
+		"type_id":     1,
+		"field_names": []string{"base"},
+		"attrs":       [][2]any{{"base", "https://example.com"}},
+	}}
+	progress, err := m.Start(client)
+	if err != nil {
+		t.Fatalf("Start failed: %v", err)
+	}
+	if progress.Kind != FunctionCall || !progress.MethodCall {
+		t.Fatalf("expected method call, got %v (method=%v)", progress.Kind, progress.MethodCall)
+	}
+	var receiver map[string]any
+	if err := progress.Receiver.Unmarshal(&receiver); err != nil {
+		t.Fatalf("unmarshal receiver: %v", err)
+	}
+	if _, ok := receiver["$dataclass"]; !ok {
+		t.Fatalf("expected dataclass receiver, got %v", receiver)
+	}
+}
+
+func TestNamespacedExternalFunction(t *testing.T) {
+	m := newTestMonty(t, "db.users.get(7)", nil, []string{"db.users.get"})
+
+	progress, err := m.Start()
+	if err != nil {
+		t.Fatalf("Start failed: %v", err)
+	}
+	if progress.Kind != FunctionCall {
+		t.Fatalf("expected FunctionCall, got %v", progress.Kind)
+	}
+	if progress.FunctionName != "db.users.get" || progress.MethodCall {
+		t.Fatalf("unexpected call %q (method=%v)", progress.FunctionName, progress.MethodCall)
+	}
+	if len(progress.Args) != 1 {
+		t.Fatalf("expected the namespace receiver to be dropped, got %d args", len(progress.Args))
+	}
+}
+
+func TestNamespaceRejectsUndeclaredMember(t *testing.T) {
+	code := "try:\n    db.drop('users')\nexcept AttributeError as e:\n    result = str(e)\nresult"
+	m := newTestMonty(t, code, nil, []string{"db.query"})
+
+	progress, err := m.Start()
+	if err != nil {
+		t.Fatalf("Start failed: %v", err)
+	}
+	if progress.Kind != Complete {
+		t.Fatalf("expected the call to be rejected without being reported, got %v %q", progress.Kind, progress.FunctionName)
+	}
+	var got string
+	if err := progress.Result.Unmarshal(&got); err != nil {
+		t.Fatalf("unmarshal result: %v", err)
+	}
+	if want := "'db' namespace has no attribute 'drop'"; got != want {
+		t.Fatalf("expected %q, got %q", want, got)
+	}
+}
+
+func TestDeclaredSignatureRejectsBadCall(t *testing.T) {
+	code := `
+try:
+    fetch(1, 2)
+except TypeError as e:
+    result = str(e)
+result`
+	m := newTestMonty(t, code, nil, []string{"fetch(url)"})
+
+	progress, err := m.Start()
+	if err != nil {
+		t.Fatalf("Start failed: %v", err)
+	}
+	if progress.Kind != Complete {
+		t.Fatalf("expected the bad call to be rejected in the script, got %v", progress.Kind)
+	}
+	var got string
+	if err := progress.Result.Unmarshal(&got); err != nil {
+		t.Fatalf("unmarshal result: %v", err)
+	}
+	if want := "fetch() takes 1 positional argument but 2 were given"; got != want {
+		t.Fatalf("expected %q, got %q", want, got)
+	}
+}
+
+func TestDeclaredReturnTypeChecked(t *testing.T) {
+	m := newTestMonty(t, "fetch('x')", nil, []string{"fetch(url) -> str"})
+
+	progress, err := m.StartWithOptions(Options{ReturnMismatch: ReturnMismatchFail})
+	if err != nil {
+		t.Fatalf("Start failed: %v", err)
+	}
+	if progress.Kind != FunctionCall {
+		t.Fatalf("expected FunctionCall, got %v", progress.Kind)
+	}
+	if _, err := progress.Snapshot.Resume(progress.CallID, 42); !errors.Is(err, ErrReturnMismatch) {
+		t.Fatalf("expected ErrReturnMismatch, got %v", err)
+	}
+
+	next, err := progress.Snapshot.Resume(progress.CallID, "ok")
+	if err != nil {
+		t.Fatalf("resume after mismatch failed: %v", err)
+	}
+	var got string
+	if err := next.Result.Unmarshal(&got); err != nil || got != "ok" {
+		t.Fatalf("expected \"ok\", got %q (%v)", got, err)
+	}
+}
+
+func TestDataclassBuiltFromPlainResult(t *testing.T) {
+	m := newTestMonty(t, "u = get_user(1)\nu.name", nil, []string{"get_user(id) -> User"})
+
+	progress, err := m.StartWithOptions(Options{Dataclasses: []DataclassSchema{
+		{Name: "User", TypeID: 7, Fields: []string{"id", "name"}, Frozen: true},
+	}})
+	if err != nil {
+		t.Fatalf("Start failed: %v", err)
+	}
+	if progress.Kind != FunctionCall {
+		t.Fatalf("expected FunctionCall, got %v", progress.Kind)
+	}
+	next, err := progress.Snapshot.Resume(progress.CallID, map[string]any{"name": "ada", "id": 1})
+	if err != nil {
+		t.Fatalf("Resume failed: %v", err)
+	}
+	var got string
+	if err := next.Result.Unmarshal(&got); err != nil {
+		t.Fatalf("unmarshal result: %v", err)
+	}
+	if got != "ada" {
+		t.Fatalf("expected ada, got %q", got)
+	}
+}
+
+func TestReprInputsAreParsed(t *testing.T) {
+	m := newTestMonty(t, "x[1]['k']", []string{"x"}, nil)
+
+	result, err := m.Run(map[string]any{"$repr": "(1, {'k': [2.5, None]})"})
+	if err != nil {
+		t.Fatalf("Run failed: %v", err)
+	}
+	var out []any
+	if err := result.Unmarshal(&out); err != nil || len(out) != 2 || out[0] != 2.5 {
+		t.Fatalf("expected [2.5, nil], got %v (%v)", out, err)
+	}
+	_, err = m.Run(map[string]any{"$repr": "<function f at 0x1>"})
+	if err == nil || !strings.Contains(err.Error(), "can't be reconstructed") {
+		t.Fatalf("expected a reconstruction error, got %v", err)
+	}
+}
+
+func TestStrictEncoding(t *testing.T) {
+	m := newTestMonty(t, "{'n': 1, 'f': len}", nil, nil)
+
+	if _, err := m.Run(); err != nil {
+		t.Fatalf("Run failed without strict encoding: %v", err)
+	}
+	SetStrictEncoding(true)
+	defer SetStrictEncoding(false)
+	_, err := m.Run()
+	if err == nil || !strings.Contains(err.Error(), "$['f']") || !strings.Contains(err.Error(), "no lossless JSON form") {
+		t.Fatalf("expected a strict encoding error naming $['f'], got %v", err)
+	}
+}
+
+func TestFloatStrings(t *testing.T) {
+	m := newTestMonty(t, "x + 0.2", []string{"x"}, nil)
+
+	SetFloatStrings(true)
+	defer SetFloatStrings(false)
+	result, err := m.Run(map[string]any{"$float": "0.1"})
+	if err != nil {
+		t.Fatalf("Run failed: %v", err)
+	}
+	if got := string(result); got != `{"$float":"0.30000000000000004"}` {
+		t.Fatalf("expected a shortest round-trip float string, got %s", got)
+	}
+}
+
+func TestStrictNumbers(t *testing.T) {
+	m := newTestMonty(t, "x", []string{"x"}, nil)
+
+	SetStrictNumbers(true)
+	defer SetStrictNumbers(false)
+	if _, err := m.Run([]any{json.Number("0.10"), json.Number("18446744073709551615")}); err != nil {
+		t.Fatalf("expected exact numbers to decode, got %v", err)
+	}
+	_, err := m.Run(json.Number("123456789012345678901234567890"))
+	if err == nil || !strings.Contains(err.Error(), "$bigint") {
+		t.Fatalf("expected an oversized integer to fail, got %v", err)
+	}
+	_, err = m.Run(json.Number("0.12345678901234567890"))
+	if err == nil || !strings.Contains(err.Error(), "can't be held exactly") {
+		t.Fatalf("expected a precision-losing decimal to fail, got %v", err)
+	}
+}
+
+func TestDictOrderAndCanonicalEncoding(t *testing.T) {
+	m := newTestMonty(t, "x['z'] = 0\nx", []string{"x"}, nil)
+
+	result, err := m.Run(json.RawMessage(`{"b": 1, "a": 2}`))
+	if err != nil {
+		t.Fatalf("Run failed: %v", err)
+	}
+	if got := string(result); got != `{"$dict":[["b",1],["a",2],["z",0]]}` {
+		t.Fatalf("expected insertion order to be kept, got %s", got)
+	}
+
+	SetCanonicalEncoding(true)
+	defer SetCanonicalEncoding(false)
+	first, err := m.Run(json.RawMessage(`{"b": 1, "a": -0.0}`))
+	if err != nil {
+		t.Fatalf("Run failed: %v", err)
+	}
+	second, err := m.Run(json.RawMessage(`{"$dict": [["a", 0.0], ["b", 1]]}`))
+	if err != nil {
+		t.Fatalf("Run failed: %v", err)
+	}
+	if string(first) != string(second) {
+		t.Fatalf("expected equal dicts to encode identically, got %s and %s", first, second)
+	}
+}
+
+func TestObjectHandles(t *testing.T) {
+	m := newTestMonty(t, "sum(load())", nil, []string{"load"})
+
+	progress, err := m.StartWithOptions(Options{ObjectHandles: true})
+	if err != nil {
+		t.Fatalf("Start failed: %v", err)
+	}
+	if progress.Kind != FunctionCall || progress.ArgsHandle.Len() != 0 {
+		t.Fatalf("expected a FunctionCall with an empty args handle, got %+v", progress)
+	}
+	items := make([]*ObjectHandle, 1000)
+	for i := range items {
+		items[i] = IntHandle(int64(i))
+	}
+	list, err := ListHandle(items...)
+	if err != nil {
+		t.Fatalf("ListHandle failed: %v", err)
+	}
+	next, err := progress.Snapshot.ResumeHandle(progress.CallID, list)
+	if err != nil {
+		t.Fatalf("ResumeHandle failed: %v", err)
+	}
+	got, err := next.ResultHandle.Int()
+	if err != nil {
+		t.Fatalf("result: %v", err)
+	}
+	if got != 499500 {
+		t.Fatalf("expected 499500, got %d", got)
+	}
+}
+
+func TestDictHandleFailureKeepsHandles(t *testing.T) {
+	key := IntHandle(1)
+	defer key.Close()
+	value := IntHandle(2)
+	value.Close()
+
+	if _, err := DictHandle([]*ObjectHandle{key}, []*ObjectHandle{value}); err == nil {
+		t.Fatal("expected DictHandle with a closed value to fail")
+	}
+	// The key was not consumed, so it can still be read and freed.
+	if got, err := key.Int(); err != nil || got != 1 {
+		t.Fatalf("expected the key to survive, got %d (%v)", got, err)
+	}
+}
+
+func TestObjectHandleAccessors(t *testing.T) {
+	value, err := NewObjectHandle(map[string]any{"a": 1, "b": 2})
+	if err != nil {
+		t.Fatalf("NewObjectHandle failed: %v", err)
+	}
+	defer value.Close()
+
+	if typ, err := value.Type(); err != nil || typ != "dict" {
+		t.Fatalf("expected dict, got %q (%v)", typ, err)
+	}
+	key, err := StringHandle("b")
+	if err != nil {
+		t.Fatalf("StringHandle failed: %v", err)
+	}
+	item, err := value.GetItem(key)
+	if err != nil {
+		t.Fatalf("GetItem failed: %v", err)
+	}
+	if got, _ := item.Int(); got != 2 {
+		t.Fatalf("expected 2, got %d", got)
+	}
+	it, err := value.Iter()
+	if err != nil {
+		t.Fatalf("Iter failed: %v", err)
+	}
+	defer it.Close()
+	count := 0
+	for {
+		next, err := it.Next()
+		if err != nil {
+			t.Fatalf("Next failed: %v", err)
+		}
+		if next == nil {
+			break
+		}
+		count++
+	}
+	if count != 2 {
+		t.Fatalf("expected 2 keys, got %d", count)
+	}
+}
+
+func TestObjectBuilders(t *testing.T) {
+	list := EmptyListHandle()
+	defer list.Close()
+	for i := 0; i < 3; i++ {
+		if err := list.Append(IntHandle(int64(i))); err != nil {
+			t.Fatalf("Append failed: %v", err)
+		}
+	}
+	dict := EmptyDictHandle()
+	defer dict.Close()
+	for _, v := range []int64{1, 2} {
+		key, _ := StringHandle("k")
+		if err := dict.SetItem(key, IntHandle(v)); err != nil {
+			t.Fatalf("SetItem failed: %v", err)
+		}
+	}
+	if list.Len() != 3 || dict.Len() != 1 {
+		t.Fatalf("expected 3 items and 1 key, got %d and %d", list.Len(), dict.Len())
+	}
+}
+
+func TestSharedInputObject(t *testing.T) {
+	m := newTestMonty(t, "len(table)", []string{"table"}, nil)
+	table, err := NewObjectHandle([]int{1, 2, 3})
+	if err != nil {
+		t.Fatalf("NewObjectHandle failed: %v", err)
+	}
+	defer table.Close()
+	if err := m.SetInputObject("table", table); err != nil {
+		t.Fatalf("SetInputObject failed: %v", err)
+	}
+
+	for run := 0; run < 2; run++ {
+		progress, err := m.Start(InputObject("table"))
+		if err != nil {
+			t.Fatalf("Start %d failed: %v", run, err)
+		}
+		var got int
+		if err := progress.Result.Unmarshal(&got); err != nil {
+			t.Fatalf("unmarshal result: %v", err)
+		}
+		if got != 3 {
+			t.Fatalf("expected 3, got %d", got)
+		}
+	}
 }
 
 func newTestMonty(t *testing.T, code string, inputs, exts []string) *Monty {
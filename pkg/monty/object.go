@@ -2,7 +2,9 @@ package monty
 
 import (
 	"encoding/json"
+	"errors"
 	"fmt"
+	"time"
 )
 
 // Object is a thin wrapper around JSON returned by the FFI layer.
@@ -16,6 +18,101 @@ type KV struct {
 	Value Object
 }
 
+// PendingCall describes an external or OS call that was deferred as a future.
+type PendingCall struct {
+	CallID         uint32
+	FunctionName   string
+	OsFunction     string
+	Args           []Object
+	Kwargs         []KV
+	MethodCall     bool
+	Receiver       Object
+	Timeout        time.Duration
+	Priority       int
+	// Builtin is set for a call to a builtin overridden with Monty.OverrideBuiltins.
+	Builtin        bool
+	// IdempotencyKey is as in Progress.
+	IdempotencyKey string
+}
+
+// Lazy stands in for an input value passed to Start. Before the run starts, the
+// source text is scanned for the input's declared name; if it appears, the host is
+// asked for the value with a LazyInput progress whose InputName is name, and
+// otherwise the input is bound to None and never serialized.
+func Lazy(name string) any {
+	return lazyInput(name)
+}
+
+type lazyInput string
+
+func (l lazyInput) MarshalJSON() ([]byte, error) {
+	return json.Marshal(map[string]string{"$lazy": string(l)})
+}
+
+// InputBytes stands in for an input value passed to Start or StartWithOptions,
+// handing data to the script as a Python bytes value without JSON encoding.
+func InputBytes(data []byte) any {
+	return inputBytes(data)
+}
+
+type inputBytes []byte
+
+func (inputBytes) MarshalJSON() ([]byte, error) {
+	return nil, errors.New("monty: InputBytes is only supported by Start and StartWithOptions")
+}
+
+// inputBytesRef is the marker an InputBytes input is replaced with when the run starts.
+type inputBytesRef string
+
+func (b inputBytesRef) MarshalJSON() ([]byte, error) {
+	return json.Marshal(map[string]string{"$input_bytes": string(b)})
+}
+
+// InputObject stands in for an input value passed to Start, binding the object
+// handle set under name with Monty.SetInputObject.
+func InputObject(name string) any {
+	return inputObject(name)
+}
+
+type inputObject string
+
+func (o inputObject) MarshalJSON() ([]byte, error) {
+	return json.Marshal(map[string]string{"$input_object": string(o)})
+}
+
+// Exception describes a Python exception raised into the script on resume.
+// When Message is empty, Args are rendered the way str(exc) would. Cause chains
+// the host error that led to this one; if the run then fails with this exception,
+// the chain is included in the returned error like a Python __cause__ traceback.
+// The script itself does not see the chain as __cause__. Args and Attributes are
+// kept with the run and put back on the exception's encoding when the script hands it
+// to the host again.
+type Exception struct {
+	Type       string
+	Message    string
+	Args       []any
+	Attributes map[string]any
+	Cause      *Exception
+}
+
+// MarshalJSON encodes the exception as a $exception envelope.
+func (e Exception) MarshalJSON() ([]byte, error) {
+	inner := map[string]any{"type": e.Type}
+	if e.Message != "" {
+		inner["message"] = e.Message
+	}
+	if len(e.Args) > 0 {
+		inner["args"] = e.Args
+	}
+	if len(e.Attributes) > 0 {
+		inner["attributes"] = e.Attributes
+	}
+	if e.Cause != nil {
+		inner["cause"] = e.Cause
+	}
+	return json.Marshal(map[string]any{"$exception": inner})
+}
+
 // Unmarshal decodes the JSON payload into the provided target.
 func (o Object) Unmarshal(target any) error {
 	if len(o) == 0 {
@@ -78,6 +175,55 @@ func decodeUint32ArrayString(s string) ([]uint32, error) {
 	return ids, nil
 }
 
+func decodePendingCallsString(s string) ([]PendingCall, error) {
+	if s == "" {
+		return nil, nil
+	}
+	var raw []struct {
+		CallID       uint32               `json:"call_id"`
+		FunctionName string               `json:"function_name"`
+		OsFunction   string               `json:"os_function"`
+		Args         []json.RawMessage    `json:"args"`
+		Kwargs       [][2]json.RawMessage `json:"kwargs"`
+		MethodCall   bool                 `json:"method_call"`
+		Receiver     json.RawMessage      `json:"receiver"`
+		TimeoutMs    uint64               `json:"timeout_ms"`
+		Priority     int                  `json:"priority"`
+		Builtin      bool                 `json:"builtin_override"`
+		Key          string               `json:"idempotency_key"`
+	}
+	if err := json.Unmarshal([]byte(s), &raw); err != nil {
+		return nil, err
+	}
+	calls := make([]PendingCall, len(raw))
+	for i, item := range raw {
+		call := PendingCall{
+			CallID:         item.CallID,
+			FunctionName:   item.FunctionName,
+			OsFunction:     item.OsFunction,
+			MethodCall:     item.MethodCall,
+			Timeout:        time.Duration(item.TimeoutMs) * time.Millisecond,
+			Priority:       item.Priority,
+			Builtin:        item.Builtin,
+			IdempotencyKey: item.Key,
+		}
+		if len(item.Receiver) > 0 {
+			call.Receiver = append(Object{}, item.Receiver...)
+		}
+		for _, arg := range item.Args {
+			call.Args = append(call.Args, append(Object{}, arg...))
+		}
+		for _, pair := range item.Kwargs {
+			call.Kwargs = append(call.Kwargs, KV{
+				Key:   append(Object{}, pair[0]...),
+				Value: append(Object{}, pair[1]...),
+			})
+		}
+		calls[i] = call
+	}
+	return calls, nil
+}
+
 func objectToInterface(obj Object) (any, error) {
 	if len(obj) == 0 {
 		return nil, nil
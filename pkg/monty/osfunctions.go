@@ -0,0 +1,64 @@
+package monty
+
+/*
+#include "monty_ffi.h"
+*/
+import "C"
+
+import "encoding/json"
+
+// OsCallSchema describes the arguments an OS function's OsCall progress carries and
+// the value to resume it with, for hosts that validate and dispatch OS calls
+// generically. Types are Python annotations such as "str | None".
+type OsCallSchema struct {
+	Name     string  `json:"name"`
+	Category string  `json:"category"`
+	// Args are positional; the first of a Path method is the path it was called on.
+	Args     []OsArg `json:"args"`
+	Kwargs   []OsArg `json:"kwargs"`
+	Returns  string  `json:"returns"`
+}
+
+// OsArg is one argument of an OsCallSchema.
+type OsArg struct {
+	Name     string `json:"name"`
+	Type     string `json:"type"`
+	Required bool   `json:"required"`
+}
+
+// OsFunction is an OS function Monty can report as OsCall progress.
+type OsFunction struct {
+	Name     string `json:"name"`
+	// Category is "filesystem" or "environment".
+	Category string `json:"category"`
+}
+
+// OsFunctions lists every OS function Monty can report, so a host can check at
+// startup that its OsCall dispatcher covers each one and fail closed on any other.
+func OsFunctions() ([]OsFunction, error) {
+	var out *C.char
+	if err := statusError(C.monty_list_os_functions(&out)); err != nil {
+		return nil, err
+	}
+	var functions []OsFunction
+	if err := json.Unmarshal([]byte(takeString(out)), &functions); err != nil {
+		return nil, err
+	}
+	return functions, nil
+}
+
+// OsCallSchemaFor returns the schema of the OS function called name (e.g.
+// "Path.read_text"), or an error if Monty has no OS function by that name.
+func OsCallSchemaFor(name string) (OsCallSchema, error) {
+	cName, freeName := cString(name)
+	defer freeName()
+	var out *C.char
+	if err := statusError(C.monty_os_call_schema(cName, &out)); err != nil {
+		return OsCallSchema{}, err
+	}
+	var schema OsCallSchema
+	if err := json.Unmarshal([]byte(takeString(out)), &schema); err != nil {
+		return OsCallSchema{}, err
+	}
+	return schema, nil
+}
@@ -0,0 +1,152 @@
+package monty
+
+/*
+#include "monty_ffi.h"
+*/
+import "C"
+
+import (
+	"encoding/json"
+	"errors"
+	"runtime"
+	"time"
+)
+
+// QuotaLimits bounds what every run in a QuotaGroup may use together; zero fields are
+// unlimited.
+type QuotaLimits struct {
+	MaxInstructions uint64
+	// MaxMemory caps the memory held at once by the group's unfinished runs.
+	MaxMemory uint64
+	// Window makes MaxInstructions a rate ("10M instructions per minute"): the
+	// instruction count starts over when a window ends.
+	Window time.Duration
+}
+
+// MarshalJSON encodes the limits in the form the FFI layer expects.
+func (l QuotaLimits) MarshalJSON() ([]byte, error) {
+	payload := map[string]any{}
+	if l.MaxInstructions > 0 {
+		payload["max_instructions"] = l.MaxInstructions
+	}
+	if l.MaxMemory > 0 {
+		payload["max_memory"] = l.MaxMemory
+	}
+	if l.Window > 0 {
+		payload["window_ms"] = l.Window.Milliseconds()
+	}
+	return json.Marshal(payload)
+}
+
+// QuotaUsage reports what a QuotaGroup's runs have used; nil remaining fields are
+// unlimited.
+type QuotaUsage struct {
+	// Instructions executed in the current window.
+	Instructions          uint64  `json:"instructions"`
+	// Memory held by the group's unfinished runs.
+	Memory                uint64  `json:"memory"`
+	RemainingInstructions *uint64 `json:"remaining_instructions"`
+	RemainingMemory       *uint64 `json:"remaining_memory"`
+}
+
+// QuotaGroup is an instruction and memory budget shared by every run attached to it,
+// e.g. one per tenant regardless of how many scripts the tenant launches. Budgets are
+// checked at each start and resume, so concurrent runs may overshoot by one step each.
+type QuotaGroup struct {
+	handle *C.QuotaGroupHandle
+}
+
+// NewQuotaGroup creates a quota group with the given limits.
+func NewQuotaGroup(limits QuotaLimits) (*QuotaGroup, error) {
+	data, err := json.Marshal(limits)
+	if err != nil {
+		return nil, err
+	}
+	payload, freePayload := cBytes(data)
+	defer freePayload()
+
+	var out *C.QuotaGroupHandle
+	if err := statusError(C.monty_quota_group_new(payload, &out)); err != nil {
+		return nil, err
+	}
+	g := &QuotaGroup{handle: out}
+	runtime.SetFinalizer(g, func(g *QuotaGroup) { g.Close() })
+	return g, nil
+}
+
+// Usage reports what the group's runs have used so far.
+func (g *QuotaGroup) Usage() (QuotaUsage, error) {
+	if g == nil || g.handle == nil {
+		return QuotaUsage{}, errors.New("monty: quota group closed")
+	}
+	var out *C.char
+	if err := statusError(C.monty_quota_group_usage(g.handle, &out)); err != nil {
+		return QuotaUsage{}, err
+	}
+	defer C.monty_free_string(out)
+	var usage QuotaUsage
+	if err := json.Unmarshal([]byte(C.GoString(out)), &usage); err != nil {
+		return QuotaUsage{}, err
+	}
+	return usage, nil
+}
+
+// Close releases this reference; runs attached to the group keep theirs.
+func (g *QuotaGroup) Close() {
+	if g != nil && g.handle != nil {
+		C.monty_quota_group_free_and_clear(&g.handle)
+	}
+}
+
+// AttachQuotaGroup makes every later Start draw from g; nil detaches.
+func (m *Monty) AttachQuotaGroup(g *QuotaGroup) error {
+	if m == nil || m.handle == nil {
+		return errors.New("monty: nil handle")
+	}
+	handle, err := g.cHandle()
+	if err != nil {
+		return err
+	}
+	status := C.monty_run_attach_quota_group(m.handle, handle)
+	runtime.KeepAlive(g)
+	return statusError(status)
+}
+
+// AttachQuotaGroup moves the suspended run into g; nil removes it from its group.
+// Membership is not kept in dumps, so attach restored snapshots again.
+func (s *Snapshot) AttachQuotaGroup(g *QuotaGroup) error {
+	if s == nil || s.handle == nil {
+		return errors.New("monty: snapshot closed")
+	}
+	handle, err := g.cHandle()
+	if err != nil {
+		return err
+	}
+	status := C.monty_snapshot_attach_quota_group(s.handle, handle)
+	runtime.KeepAlive(g)
+	return statusError(status)
+}
+
+// AttachQuotaGroup moves the suspended run into g; see Snapshot.AttachQuotaGroup.
+func (fs *FutureSnapshot) AttachQuotaGroup(g *QuotaGroup) error {
+	if fs == nil || fs.handle == nil {
+		return errors.New("monty: future snapshot closed")
+	}
+	handle, err := g.cHandle()
+	if err != nil {
+		return err
+	}
+	status := C.monty_future_snapshot_attach_quota_group(fs.handle, handle)
+	runtime.KeepAlive(g)
+	return statusError(status)
+}
+
+func (g *QuotaGroup) cHandle() (*C.QuotaGroupHandle, error) {
+	if g == nil {
+		return nil, nil
+	}
+	if g.handle == nil {
+		return nil, errors.New("monty: quota group closed")
+	}
+	return g.handle, nil
+}
@@ -0,0 +1,547 @@
+package monty
+
+import (
+	"bufio"
+	"bytes"
+	"encoding/json"
+	"errors"
+	"fmt"
+	"io"
+	"strings"
+)
+
+// Journal is the recorded history of a run: every external and OS call in the order
+// the script made them, how each was resolved, and how the run ended.
+type Journal struct {
+	Calls  []JournalEntry  `json:"calls"`
+	// Result is the value the run completed with; Error is set instead if it failed.
+	Result json.RawMessage `json:"result,omitempty"`
+	Error  string          `json:"error,omitempty"`
+}
+
+// JournalEntry is one external or OS call and its resolution. Args, kwargs, and the
+// result use the same JSON encoding as Object.
+type JournalEntry struct {
+	CallID       uint32               `json:"call_id"`
+	FunctionName string               `json:"function_name,omitempty"`
+	OsFunction   string               `json:"os_function,omitempty"`
+	Args         []json.RawMessage    `json:"args,omitempty"`
+	Kwargs       [][2]json.RawMessage `json:"kwargs,omitempty"`
+	// Deferred marks a call the host resumed as a future and resolved later.
+	Deferred     bool                 `json:"deferred,omitempty"`
+	Result       json.RawMessage      `json:"result,omitempty"`
+	Error        string               `json:"error,omitempty"`
+	ErrorType    string               `json:"error_type,omitempty"`
+	Cancelled    bool                 `json:"cancelled,omitempty"`
+	TimedOut     bool                 `json:"timed_out,omitempty"`
+}
+
+// Divergence is returned by VerifyReplay when a replayed run stops matching its
+// journal. Index is the position in Journal.Calls where it happened, or
+// len(Journal.Calls) when the calls matched but the outcome did not.
+type Divergence struct {
+	Index    int
+	Expected string
+	Got      string
+}
+
+func (d *Divergence) Error() string {
+	return fmt.Sprintf("monty: replay diverged at call %d: expected %s, got %s", d.Index, d.Expected, d.Got)
+}
+
+// Recorder drives a run the way the Snapshot and FutureSnapshot resume methods do,
+// logging every call and its resolution into a Journal that a Replayer or
+// VerifyReplay can answer from later.
+type Recorder struct {
+	journal Journal
+	calls   map[uint32]int
+	log     io.Writer
+}
+
+// NewRecorder returns an empty recorder.
+func NewRecorder() *Recorder {
+	return &Recorder{calls: make(map[uint32]int)}
+}
+
+// NewJournalWriter returns a recorder that also appends every event to w as it
+// happens, one JSON line each: a call the run reports, a call deferred as a future,
+// the resolution supplied on resume (written before the run is resumed with it), and
+// how the run ended. w is typically a file opened with os.O_APPEND; ReadJournal
+// rebuilds the Journal from what was written, even if the process died mid-run. A
+// failed write is returned by the resume method that caused it, and the run is not
+// resumed.
+func NewJournalWriter(w io.Writer) *Recorder {
+	return &Recorder{calls: make(map[uint32]int), log: w}
+}
+
+// journalEvent is one line of an append-only journal.
+type journalEvent struct {
+	Event  string          `json:"event"`
+	Entry  *JournalEntry   `json:"entry,omitempty"`
+	Result json.RawMessage `json:"result,omitempty"`
+	Error  string          `json:"error,omitempty"`
+}
+
+const (
+	eventCall     = "call"
+	eventDefer    = "defer"
+	eventResolve  = "resolve"
+	eventComplete = "complete"
+	eventError    = "error"
+)
+
+// ReadJournal rebuilds a Journal from the lines a NewJournalWriter recorder appended.
+// A final line cut short by a crash is ignored.
+func ReadJournal(r io.Reader) (Journal, error) {
+	var journal Journal
+	calls := make(map[uint32]int)
+	reader := bufio.NewReader(r)
+	for {
+		line, err := reader.ReadBytes('\n')
+		if len(bytes.TrimSpace(line)) > 0 {
+			var event journalEvent
+			if jsonErr := json.Unmarshal(line, &event); jsonErr != nil {
+				if err == io.EOF {
+					return journal, nil
+				}
+				return Journal{}, fmt.Errorf("monty: invalid journal line: %w", jsonErr)
+			}
+			if err := applyEvent(&journal, calls, event); err != nil {
+				return Journal{}, err
+			}
+		}
+		if err == io.EOF {
+			return journal, nil
+		}
+		if err != nil {
+			return Journal{}, err
+		}
+	}
+}
+
+func applyEvent(journal *Journal, calls map[uint32]int, event journalEvent) error {
+	switch event.Event {
+	case eventComplete:
+		journal.Result = event.Result
+		return nil
+	case eventError:
+		journal.Error = event.Error
+		return nil
+	}
+	if event.Entry == nil {
+		return fmt.Errorf("monty: journal %s event has no entry", event.Event)
+	}
+	if event.Event == eventCall {
+		calls[event.Entry.CallID] = len(journal.Calls)
+		journal.Calls = append(journal.Calls, *event.Entry)
+		return nil
+	}
+	i, ok := calls[event.Entry.CallID]
+	if !ok {
+		return fmt.Errorf("monty: journal %s event for unrecorded call %d", event.Event, event.Entry.CallID)
+	}
+	entry := &journal.Calls[i]
+	switch event.Event {
+	case eventDefer:
+		entry.Deferred = true
+	case eventResolve:
+		entry.Result, entry.Error, entry.ErrorType = event.Entry.Result, event.Entry.Error, event.Entry.ErrorType
+		entry.Cancelled, entry.TimedOut = event.Entry.Cancelled, event.Entry.TimedOut
+	default:
+		return fmt.Errorf("monty: unknown journal event %q", event.Event)
+	}
+	return nil
+}
+
+// Journal returns what has been recorded so far.
+func (r *Recorder) Journal() Journal {
+	return r.journal
+}
+
+// Start starts m and records the calls it reports. Object handles are disabled so
+// arguments can be logged.
+func (r *Recorder) Start(m *Monty, opts Options, inputs ...any) (Progress, error) {
+	opts.ObjectHandles = false
+	return r.observe(m.StartWithOptions(opts, inputs...))
+}
+
+// Recover rebuilds a crashed run from journal like Monty.Recover, after loading the
+// journal into the recorder, so a NewJournalWriter recorder on the same log carries on
+// appending where the run left off. Object handles are disabled as for Start.
+func (r *Recorder) Recover(m *Monty, journal []byte, opts Options, inputs ...any) (Progress, error) {
+	recorded, err := ReadJournal(bytes.NewReader(journal))
+	if err != nil {
+		return Progress{}, err
+	}
+	r.journal = recorded
+	r.calls = make(map[uint32]int, len(recorded.Calls))
+	for i, entry := range recorded.Calls {
+		r.calls[entry.CallID] = i
+	}
+	opts.ObjectHandles = false
+	return r.observe(m.Recover(journal, opts, inputs...))
+}
+
+// Resume resolves the call progress reports with result, like Snapshot.Resume.
+func (r *Recorder) Resume(progress Progress, result any) (Progress, error) {
+	if err := r.resolve(FutureResult{CallID: progress.CallID, Result: result}); err != nil {
+		return Progress{}, err
+	}
+	return r.observe(progress.Snapshot.Resume(progress.CallID, result))
+}
+
+// ResumeError raises a RuntimeError at the call progress reports, like Snapshot.ResumeError.
+func (r *Recorder) ResumeError(progress Progress, message string) (Progress, error) {
+	return r.ResumeErrorType(progress, "", message)
+}
+
+// ResumeErrorType raises excType(message) at the call progress reports, like
+// Snapshot.ResumeErrorType.
+func (r *Recorder) ResumeErrorType(progress Progress, excType, message string) (Progress, error) {
+	if err := r.resolve(FutureResult{CallID: progress.CallID, Err: message, ErrType: excType}); err != nil {
+		return Progress{}, err
+	}
+	return r.observe(progress.Snapshot.ResumeErrorType(progress.CallID, excType, message))
+}
+
+// ResumeFuture defers the call progress reports, like Snapshot.ResumeFuture; record
+// its result later with ResolveFutures.
+func (r *Recorder) ResumeFuture(progress Progress) (Progress, error) {
+	if i, ok := r.calls[progress.CallID]; ok {
+		r.journal.Calls[i].Deferred = true
+		if err := r.append(journalEvent{Event: eventDefer, Entry: &JournalEntry{CallID: progress.CallID}}); err != nil {
+			return Progress{}, err
+		}
+	}
+	return r.observe(progress.Snapshot.ResumeFuture(progress.CallID))
+}
+
+// ResolveFutures resolves pending futures, like FutureSnapshot.Resume.
+func (r *Recorder) ResolveFutures(progress Progress, results []FutureResult) (Progress, error) {
+	for _, result := range results {
+		if err := r.resolve(result); err != nil {
+			return Progress{}, err
+		}
+	}
+	return r.observe(progress.FutureSnapshot.Resume(results))
+}
+
+func (r *Recorder) observe(progress Progress, err error) (Progress, error) {
+	if err != nil {
+		r.journal.Error = err.Error()
+		if logErr := r.append(journalEvent{Event: eventError, Error: r.journal.Error}); logErr != nil {
+			return progress, errors.Join(err, logErr)
+		}
+		return progress, err
+	}
+	switch progress.Kind {
+	case Complete:
+		r.journal.Result = append(json.RawMessage{}, progress.Result...)
+		err = r.append(journalEvent{Event: eventComplete, Result: r.journal.Result})
+	case FunctionCall, OsCall, Prompt:
+		err = r.record(progress.pendingCall(), false)
+	case ResolveFutures:
+		for _, call := range progress.PendingCalls {
+			if err = r.record(call, true); err != nil {
+				break
+			}
+		}
+	}
+	return progress, err
+}
+
+// append writes event to the recorder's log, if it has one.
+func (r *Recorder) append(event journalEvent) error {
+	if r.log == nil {
+		return nil
+	}
+	line, err := json.Marshal(event)
+	if err != nil {
+		return err
+	}
+	if _, err := r.log.Write(append(line, '\n')); err != nil {
+		return fmt.Errorf("monty: append to journal: %w", err)
+	}
+	return nil
+}
+
+func (r *Recorder) record(call PendingCall, deferred bool) error {
+	if _, ok := r.calls[call.CallID]; ok {
+		// Already journaled: a recovered run stopped at the call it crashed on.
+		return nil
+	}
+	entry := JournalEntry{
+		CallID:       call.CallID,
+		FunctionName: call.FunctionName,
+		OsFunction:   call.OsFunction,
+		Deferred:     deferred,
+	}
+	for _, arg := range call.Args {
+		entry.Args = append(entry.Args, json.RawMessage(arg))
+	}
+	for _, kv := range call.Kwargs {
+		entry.Kwargs = append(entry.Kwargs, [2]json.RawMessage{json.RawMessage(kv.Key), json.RawMessage(kv.Value)})
+	}
+	r.calls[call.CallID] = len(r.journal.Calls)
+	r.journal.Calls = append(r.journal.Calls, entry)
+	return r.append(journalEvent{Event: eventCall, Entry: &entry})
+}
+
+func (r *Recorder) resolve(result FutureResult) error {
+	i, ok := r.calls[result.CallID]
+	if !ok {
+		return nil
+	}
+	entry := &r.journal.Calls[i]
+	switch {
+	case result.Cancelled:
+		entry.Cancelled = true
+	case result.TimedOut:
+		entry.TimedOut = true
+	case result.Exception != nil:
+		entry.Error, entry.ErrorType = result.Exception.Message, result.Exception.Type
+		if entry.Error == "" {
+			entry.Error = result.Exception.Type
+		}
+	case result.Err != "":
+		entry.Error, entry.ErrorType = result.Err, result.ErrType
+	case result.Result != nil:
+		normalized, err := normalizeValue(result.Result)
+		if err != nil {
+			return err
+		}
+		data, err := json.Marshal(normalized)
+		if err != nil {
+			return err
+		}
+		entry.Result = data
+	}
+	resolution := *entry
+	resolution.FunctionName, resolution.OsFunction, resolution.Args, resolution.Kwargs = "", "", nil, nil
+	return r.append(journalEvent{Event: eventResolve, Entry: &resolution})
+}
+
+// ErrJournalExhausted is returned by Replayer.Step for a call past the end of the
+// journal; the host can resolve it live.
+var ErrJournalExhausted = errors.New("monty: journal has no entry for this call")
+
+// Replayer answers a run's calls from a journal instead of the host, checking each
+// call against the recorded one.
+type Replayer struct {
+	journal  Journal
+	next     int
+	deferred map[uint32]JournalEntry
+}
+
+// NewReplayer returns a replayer positioned at the start of journal.
+func NewReplayer(journal Journal) *Replayer {
+	return &Replayer{journal: journal, deferred: make(map[uint32]JournalEntry)}
+}
+
+// Step resolves whatever progress is waiting on from the journal and returns the
+// run's next progress. A call that doesn't match its entry yields a *Divergence; a
+// call past the end of the journal yields ErrJournalExhausted and is left unresolved.
+func (r *Replayer) Step(progress Progress) (Progress, error) {
+	switch progress.Kind {
+	case FunctionCall, OsCall, Prompt:
+		if r.next >= len(r.journal.Calls) {
+			return progress, ErrJournalExhausted
+		}
+		entry, divergence := matchCall(r.journal, r.next, progress.pendingCall())
+		if divergence != nil {
+			return progress, divergence
+		}
+		r.next++
+		if entry.Deferred {
+			r.deferred[entry.CallID] = entry
+			return progress.Snapshot.ResumeFuture(progress.CallID)
+		}
+		return resumeFromEntry(progress.Snapshot, progress.CallID, entry)
+	case ResolveFutures:
+		for _, call := range progress.PendingCalls {
+			if r.next >= len(r.journal.Calls) {
+				return progress, ErrJournalExhausted
+			}
+			entry, divergence := matchCall(r.journal, r.next, call)
+			if divergence != nil {
+				return progress, divergence
+			}
+			r.next++
+			r.deferred[entry.CallID] = entry
+		}
+		results := make([]FutureResult, 0, len(progress.PendingIDs))
+		for _, id := range progress.PendingIDs {
+			entry, ok := r.deferred[id]
+			if !ok {
+				return progress, &Divergence{Index: r.next, Expected: "no pending call", Got: fmt.Sprintf("pending call %d", id)}
+			}
+			results = append(results, futureFromEntry(entry))
+		}
+		return progress.FutureSnapshot.Resume(results)
+	case Emit, Sleep:
+		return progress.Snapshot.Resume(progress.CallID, nil)
+	case Complete:
+		return progress, nil
+	default:
+		return progress, errors.New("monty: replay cannot provide lazy inputs")
+	}
+}
+
+// Replay starts the script and answers its calls from journal until the run ends or
+// reaches a call the journal doesn't cover, which is returned for the host to resolve.
+// This reproduces a recorded run locally without the services it called.
+func (m *Monty) Replay(journal Journal, opts Options, inputs ...any) (Progress, error) {
+	opts.ObjectHandles = false
+	replayer := NewReplayer(journal)
+	progress, err := m.StartWithOptions(opts, inputs...)
+	for err == nil && progress.Kind != Complete {
+		progress, err = replayer.Step(progress)
+	}
+	if errors.Is(err, ErrJournalExhausted) {
+		return progress, nil
+	}
+	return progress, err
+}
+
+// VerifyReplay re-executes the script, answering every call from journal, and checks
+// that the run makes the same calls in the same order and ends the same way. It
+// returns a *Divergence describing the first mismatch, or nil if the run is
+// deterministic with respect to the journal.
+func (m *Monty) VerifyReplay(journal Journal, opts Options, inputs ...any) error {
+	opts.ObjectHandles = false
+	replayer := NewReplayer(journal)
+	progress, err := m.StartWithOptions(opts, inputs...)
+	for err == nil && progress.Kind != Complete {
+		progress, err = replayer.Step(progress)
+	}
+	next := replayer.next
+	var divergence *Divergence
+	switch {
+	case errors.As(err, &divergence):
+		return err
+	case errors.Is(err, ErrJournalExhausted):
+		return &Divergence{Index: next, Expected: "completion", Got: describeCall(progress.pendingCall())}
+	case next < len(journal.Calls):
+		got := "completion"
+		if err != nil {
+			got = "error " + err.Error()
+		}
+		return &Divergence{Index: next, Expected: describeEntry(journal.Calls[next]), Got: got}
+	}
+	expected := "result " + string(journal.Result)
+	if journal.Error != "" {
+		expected = "error " + journal.Error
+	}
+	if err != nil {
+		if journal.Error != err.Error() {
+			return &Divergence{Index: next, Expected: expected, Got: "error " + err.Error()}
+		}
+		return nil
+	}
+	if journal.Error != "" || !sameJSON(journal.Result, progress.Result) {
+		return &Divergence{Index: next, Expected: expected, Got: "result " + string(progress.Result)}
+	}
+	return nil
+}
+
+// matchCall checks call against the journal entry at index.
+func matchCall(journal Journal, index int, call PendingCall) (JournalEntry, *Divergence) {
+	got := describeCall(call)
+	if index >= len(journal.Calls) {
+		return JournalEntry{}, &Divergence{Index: index, Expected: "completion", Got: got}
+	}
+	entry := journal.Calls[index]
+	same := entry.CallID == call.CallID &&
+		entry.FunctionName == call.FunctionName &&
+		entry.OsFunction == call.OsFunction &&
+		len(entry.Args) == len(call.Args) &&
+		len(entry.Kwargs) == len(call.Kwargs)
+	for i := 0; same && i < len(call.Args); i++ {
+		same = sameJSON(entry.Args[i], call.Args[i])
+	}
+	for i := 0; same && i < len(call.Kwargs); i++ {
+		same = sameJSON(entry.Kwargs[i][0], call.Kwargs[i].Key) && sameJSON(entry.Kwargs[i][1], call.Kwargs[i].Value)
+	}
+	if !same {
+		return JournalEntry{}, &Divergence{Index: index, Expected: describeEntry(entry), Got: got}
+	}
+	return entry, nil
+}
+
+func resumeFromEntry(snapshot *Snapshot, callID uint32, entry JournalEntry) (Progress, error) {
+	if entry.Error != "" {
+		return snapshot.ResumeErrorType(callID, entry.ErrorType, entry.Error)
+	}
+	if len(entry.Result) == 0 {
+		return snapshot.Resume(callID, nil)
+	}
+	return snapshot.Resume(callID, Object(entry.Result))
+}
+
+func futureFromEntry(entry JournalEntry) FutureResult {
+	result := FutureResult{CallID: entry.CallID, Err: entry.Error, ErrType: entry.ErrorType, Cancelled: entry.Cancelled, TimedOut: entry.TimedOut}
+	if entry.Error == "" && len(entry.Result) > 0 {
+		result.Result = Object(entry.Result)
+	}
+	return result
+}
+
+// sameJSON compares two JSON encodings, ignoring insignificant whitespace.
+func sameJSON(a, b []byte) bool {
+	if len(a) == 0 || len(b) == 0 {
+		return len(a) == len(b)
+	}
+	var left, right bytes.Buffer
+	if json.Compact(&left, a) != nil || json.Compact(&right, b) != nil {
+		return bytes.Equal(a, b)
+	}
+	return bytes.Equal(left.Bytes(), right.Bytes())
+}
+
+func describeEntry(entry JournalEntry) string {
+	call := PendingCall{CallID: entry.CallID, FunctionName: entry.FunctionName, OsFunction: entry.OsFunction}
+	for _, arg := range entry.Args {
+		call.Args = append(call.Args, Object(arg))
+	}
+	for _, kv := range entry.Kwargs {
+		call.Kwargs = append(call.Kwargs, KV{Key: Object(kv[0]), Value: Object(kv[1])})
+	}
+	return describeCall(call)
+}
+
+// pendingCall describes the call a FunctionCall or OsCall progress reports.
+func (p Progress) pendingCall() PendingCall {
+	return PendingCall{
+		CallID:         p.CallID,
+		FunctionName:   p.FunctionName,
+		OsFunction:     p.OsFunction,
+		Args:           p.Args,
+		Kwargs:         p.Kwargs,
+		MethodCall:     p.MethodCall,
+		Receiver:       p.Receiver,
+		Timeout:        p.Timeout,
+		Builtin:        p.Builtin,
+		IdempotencyKey: p.IdempotencyKey,
+	}
+}
+
+// describeCall renders a call as name(args, key=value) #id for divergence reports.
+func describeCall(call PendingCall) string {
+	name := call.FunctionName
+	if call.OsFunction != "" {
+		name = "os:" + call.OsFunction
+	}
+	parts := make([]string, 0, len(call.Args)+len(call.Kwargs))
+	for _, arg := range call.Args {
+		parts = append(parts, string(arg))
+	}
+	for _, kv := range call.Kwargs {
+		key := string(kv.Key)
+		var text string
+		if json.Unmarshal(kv.Key, &text) == nil {
+			key = text
+		}
+		parts = append(parts, key+"="+string(kv.Value))
+	}
+	return fmt.Sprintf("%s(%s) #%d", name, strings.Join(parts, ", "), call.CallID)
+}
@@ -0,0 +1,176 @@
+package monty
+
+import (
+	"database/sql"
+	"encoding/json"
+	"errors"
+	"fmt"
+	"time"
+)
+
+// RunDB persists suspended runs in a SQLite database keyed by run ID: the compiled
+// program, the snapshot or future snapshot, the call IDs it waits on, and the result
+// once the run completes. It lets a single-node agent pick its runs up again after a
+// restart. The host opens the database with the SQLite driver of its choice, so the
+// package itself stays free of dependencies:
+//
+//	db, _ := sql.Open("sqlite", "runs.db")
+//	runs, _ := monty.OpenRunDB(db)
+type RunDB struct {
+	db *sql.DB
+}
+
+// SavedRun is a run loaded from a RunDB. Exactly one of Snapshot, FutureSnapshot, and
+// Result is set.
+type SavedRun struct {
+	RunID          string
+	Program        *Monty
+	Snapshot       *Snapshot
+	FutureSnapshot *FutureSnapshot
+	// PendingCallIDs are the calls the run waits on: the suspended call for a
+	// Snapshot, the awaited futures for a FutureSnapshot.
+	PendingCallIDs []uint32
+	Result         Object
+	UpdatedAt      time.Time
+}
+
+const runDBSchema = `CREATE TABLE IF NOT EXISTS monty_runs (
+	run_id      TEXT PRIMARY KEY,
+	program     BLOB NOT NULL,
+	state       TEXT NOT NULL,
+	snapshot    BLOB,
+	pending_ids TEXT NOT NULL,
+	result      TEXT,
+	updated_at  INTEGER NOT NULL
+)`
+
+const (
+	runStateSnapshot = "snapshot"
+	runStateFutures  = "futures"
+	runStateComplete = "complete"
+)
+
+// OpenRunDB creates the monty_runs table in db if it does not exist yet.
+func OpenRunDB(db *sql.DB) (*RunDB, error) {
+	if _, err := db.Exec(runDBSchema); err != nil {
+		return nil, fmt.Errorf("monty: create run table: %w", err)
+	}
+	return &RunDB{db: db}, nil
+}
+
+// Save records where run runID has got to, replacing what was saved before. m is the
+// program the run was started from; progress must hold a Snapshot or FutureSnapshot,
+// or be Complete. Neither is consumed.
+func (r *RunDB) Save(runID string, m *Monty, progress Progress) error {
+	program, err := m.Dump()
+	if err != nil {
+		return err
+	}
+	var state string
+	var dump []byte
+	var result []byte
+	pending := []uint32{}
+	switch {
+	case progress.Kind == Complete:
+		state = runStateComplete
+		result = progress.Result
+	case progress.FutureSnapshot != nil:
+		state = runStateFutures
+		dump, err = progress.FutureSnapshot.Dump()
+		pending = progress.PendingIDs
+	case progress.Snapshot != nil:
+		state = runStateSnapshot
+		dump, err = progress.Snapshot.Dump()
+		pending = []uint32{progress.CallID}
+	default:
+		return fmt.Errorf("monty: %v progress has no state to save", progress.Kind)
+	}
+	if err != nil {
+		return err
+	}
+	ids, err := json.Marshal(pending)
+	if err != nil {
+		return err
+	}
+	_, err = r.db.Exec(
+		`INSERT OR REPLACE INTO monty_runs (run_id, program, state, snapshot, pending_ids, result, updated_at)
+		 VALUES (?, ?, ?, ?, ?, ?, ?)`,
+		runID, program, state, dump, string(ids), nullableText(result), time.Now().UnixMilli(),
+	)
+	return err
+}
+
+// Load restores run runID; the returned handles are new and owned by the caller.
+func (r *RunDB) Load(runID string) (SavedRun, error) {
+	var program, dump []byte
+	var state, ids string
+	var result sql.NullString
+	var updated int64
+	err := r.db.QueryRow(
+		`SELECT program, state, snapshot, pending_ids, result, updated_at FROM monty_runs WHERE run_id = ?`,
+		runID,
+	).Scan(&program, &state, &dump, &ids, &result, &updated)
+	if errors.Is(err, sql.ErrNoRows) {
+		return SavedRun{}, fmt.Errorf("monty: no saved run %q", runID)
+	}
+	if err != nil {
+		return SavedRun{}, err
+	}
+	saved := SavedRun{RunID: runID, UpdatedAt: time.UnixMilli(updated)}
+	if err := json.Unmarshal([]byte(ids), &saved.PendingCallIDs); err != nil {
+		return SavedRun{}, err
+	}
+	if saved.Program, err = NewFromBytes(program); err != nil {
+		return SavedRun{}, err
+	}
+	switch state {
+	case runStateSnapshot:
+		saved.Snapshot, err = SnapshotFromBytes(dump)
+	case runStateFutures:
+		saved.FutureSnapshot, err = FutureSnapshotFromBytes(dump)
+	case runStateComplete:
+		saved.Result = Object(result.String)
+	default:
+		err = fmt.Errorf("monty: saved run %q has unknown state %q", runID, state)
+	}
+	if err != nil {
+		saved.Program.Close()
+		return SavedRun{}, err
+	}
+	return saved, nil
+}
+
+// Resumable lists the IDs of saved runs that have not completed, least recently
+// saved first.
+func (r *RunDB) Resumable() ([]string, error) {
+	rows, err := r.db.Query(
+		`SELECT run_id FROM monty_runs WHERE state != ? ORDER BY updated_at, run_id`,
+		runStateComplete,
+	)
+	if err != nil {
+		return nil, err
+	}
+	defer rows.Close()
+	var runIDs []string
+	for rows.Next() {
+		var runID string
+		if err := rows.Scan(&runID); err != nil {
+			return nil, err
+		}
+		runIDs = append(runIDs, runID)
+	}
+	return runIDs, rows.Err()
+}
+
+// Delete forgets run runID; deleting a run that isn't saved does nothing.
+func (r *RunDB) Delete(runID string) error {
+	_, err := r.db.Exec(`DELETE FROM monty_runs WHERE run_id = ?`, runID)
+	return err
+}
+
+func nullableText(data []byte) any {
+	if data == nil {
+		return nil
+	}
+	return string(data)
+}
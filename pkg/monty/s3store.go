@@ -0,0 +1,448 @@
+//go:build monty_s3
+
+package monty
+
+import (
+	"bytes"
+	"context"
+	"crypto/hmac"
+	"crypto/sha256"
+	"encoding/hex"
+	"encoding/xml"
+	"errors"
+	"fmt"
+	"io"
+	"net/http"
+	"net/url"
+	"sort"
+	"strconv"
+	"strings"
+	"time"
+)
+
+// S3Store keeps snapshot dumps in an S3-compatible bucket by the SHA-256 hex digest of
+// their bytes, laid out like a Store opened with OpenStoreDir: blobs/<hash> holds each
+// dump, and runs/<hash of the run ID>/ holds an empty marker for each put. Serverless
+// hosts can park workflows in object storage with it instead of a local directory.
+//
+// Requests are signed with AWS Signature Version 4 and use path-style URLs
+// (Endpoint/Bucket/key), which S3, MinIO, R2, and other S3-compatible services accept.
+// Requests that fail with a network error, a 5xx, or a 429 are retried with backoff,
+// and dumps larger than PartSize are sent as a multipart upload. It is only built with
+// the monty_s3 build tag. S3Store is a SnapshotStore; the Context variants of its
+// methods take a context for cancellation.
+type S3Store struct {
+	// Endpoint is the service URL, such as "https://s3.us-east-1.amazonaws.com".
+	Endpoint string
+	Bucket   string
+	// Prefix is put before every key, such as "workflows/".
+	Prefix          string
+	Region          string
+	AccessKeyID     string
+	SecretAccessKey string
+	// SessionToken is set with temporary credentials.
+	SessionToken string
+	// Client sends the requests; nil means http.DefaultClient.
+	Client *http.Client
+	// Retries is how many times a failed request is retried; 0 means 3, and a
+	// negative value means none.
+	Retries int
+	// PartSize is the largest dump uploaded in one request and the size of each part
+	// of larger ones; 0 means 8 MiB. S3 rejects parts under 5 MiB other than the last.
+	PartSize int
+}
+
+var _ SnapshotStore = (*S3Store)(nil)
+
+var errS3NotFound = errors.New("monty: object not found")
+
+// Put is PutContext with context.Background().
+func (s *S3Store) Put(snap *Snapshot) (string, error) {
+	return s.PutContext(context.Background(), snap)
+}
+
+// Get is GetContext with context.Background().
+func (s *S3Store) Get(hash string) (*Snapshot, error) {
+	return s.GetContext(context.Background(), hash)
+}
+
+// Delete is DeleteContext with context.Background().
+func (s *S3Store) Delete(hash string) error {
+	return s.DeleteContext(context.Background(), hash)
+}
+
+// List is ListContext with context.Background().
+func (s *S3Store) List(runID string) ([]string, error) {
+	return s.ListContext(context.Background(), runID)
+}
+
+// PutContext dumps snap into the bucket without consuming it and returns the dump's
+// hash, which is also listed under the snapshot's run ID (see ListContext). A dump that
+// is already stored is not uploaded again.
+func (s *S3Store) PutContext(ctx context.Context, snap *Snapshot) (string, error) {
+	dump, err := snap.Dump()
+	if err != nil {
+		return "", err
+	}
+	sum := sha256.Sum256(dump)
+	hash := hex.EncodeToString(sum[:])
+	stored, err := s.exists(ctx, s.blobKey(hash))
+	if err != nil {
+		return "", err
+	}
+	if !stored {
+		if err := s.upload(ctx, s.blobKey(hash), dump); err != nil {
+			return "", err
+		}
+	}
+	// Markers sort in the order they were put.
+	marker := fmt.Sprintf("%s%020d-%s", s.runPrefix(snap.RunID()), time.Now().UnixNano(), hash)
+	if _, err := s.do(ctx, http.MethodPut, marker, nil, nil); err != nil {
+		return "", err
+	}
+	return hash, nil
+}
+
+// GetContext loads a new snapshot from the dump stored under hash, after checking the
+// downloaded bytes against it.
+func (s *S3Store) GetContext(ctx context.Context, hash string) (*Snapshot, error) {
+	if !isDumpHash(hash) {
+		return nil, fmt.Errorf("monty: no snapshot stored under %s", hash)
+	}
+	resp, err := s.do(ctx, http.MethodGet, s.blobKey(hash), nil, nil)
+	if errors.Is(err, errS3NotFound) {
+		return nil, fmt.Errorf("monty: no snapshot stored under %s", hash)
+	}
+	if err != nil {
+		return nil, err
+	}
+	if sum := sha256.Sum256(resp.body); hex.EncodeToString(sum[:]) != hash {
+		return nil, fmt.Errorf("monty: snapshot stored under %s does not match its hash", hash)
+	}
+	return SnapshotFromBytes(resp.body)
+}
+
+// DeleteContext removes the dump stored under hash; deleting one that isn't stored
+// does nothing. Run markers are left in place, and ListContext skips them from then on.
+func (s *S3Store) DeleteContext(ctx context.Context, hash string) error {
+	if !isDumpHash(hash) {
+		return nil
+	}
+	_, err := s.do(ctx, http.MethodDelete, s.blobKey(hash), nil, nil)
+	if errors.Is(err, errS3NotFound) {
+		return nil
+	}
+	return err
+}
+
+// ListContext returns the hashes of the stored snapshots of the run runID (empty for
+// runs started without Options.RunID), in the order they were first put. Besides one
+// LIST request per 1,000 markers, it sends a HEAD request for each distinct hash to
+// skip deleted dumps, so a listing costs one request per snapshot of the run.
+func (s *S3Store) ListContext(ctx context.Context, runID string) ([]string, error) {
+	prefix := s.runPrefix(runID)
+	var keys []string
+	token := ""
+	for {
+		query := url.Values{"list-type": {"2"}, "prefix": {prefix}}
+		if token != "" {
+			query.Set("continuation-token", token)
+		}
+		resp, err := s.do(ctx, http.MethodGet, "", query, nil)
+		if err != nil {
+			return nil, err
+		}
+		var page struct {
+			Contents []struct {
+				Key string `xml:"Key"`
+			} `xml:"Contents"`
+			IsTruncated           bool   `xml:"IsTruncated"`
+			NextContinuationToken string `xml:"NextContinuationToken"`
+		}
+		if err := xml.Unmarshal(resp.body, &page); err != nil {
+			return nil, fmt.Errorf("monty: S3 list: %w", err)
+		}
+		for _, object := range page.Contents {
+			keys = append(keys, object.Key)
+		}
+		if !page.IsTruncated || page.NextContinuationToken == "" {
+			break
+		}
+		token = page.NextContinuationToken
+	}
+	sort.Strings(keys)
+	hashes := []string{}
+	seen := map[string]bool{}
+	for _, key := range keys {
+		_, hash, ok := strings.Cut(strings.TrimPrefix(key, prefix), "-")
+		if !ok || seen[hash] || !isDumpHash(hash) {
+			continue
+		}
+		seen[hash] = true
+		stored, err := s.exists(ctx, s.blobKey(hash))
+		if err != nil {
+			return nil, err
+		}
+		if stored {
+			hashes = append(hashes, hash)
+		}
+	}
+	return hashes, nil
+}
+
+func (s *S3Store) blobKey(hash string) string {
+	return s.Prefix + "blobs/" + hash
+}
+
+func (s *S3Store) runPrefix(runID string) string {
+	sum := sha256.Sum256([]byte(runID))
+	return s.Prefix + "runs/" + hex.EncodeToString(sum[:]) + "/"
+}
+
+func (s *S3Store) exists(ctx context.Context, key string) (bool, error) {
+	_, err := s.do(ctx, http.MethodHead, key, nil, nil)
+	if errors.Is(err, errS3NotFound) {
+		return false, nil
+	}
+	return err == nil, err
+}
+
+// upload puts dump under key in one request, or as a multipart upload when it is
+// larger than PartSize. A failed multipart upload is aborted so its parts aren't
+// billed.
+func (s *S3Store) upload(ctx context.Context, key string, dump []byte) error {
+	partSize := s.PartSize
+	if partSize <= 0 {
+		partSize = 8 << 20
+	}
+	if len(dump) <= partSize {
+		_, err := s.do(ctx, http.MethodPut, key, nil, dump)
+		return err
+	}
+	resp, err := s.do(ctx, http.MethodPost, key, url.Values{"uploads": {""}}, nil)
+	if err != nil {
+		return err
+	}
+	var created struct {
+		UploadID string `xml:"UploadId"`
+	}
+	if err := xml.Unmarshal(resp.body, &created); err != nil || created.UploadID == "" {
+		return fmt.Errorf("monty: S3 multipart upload of %s was not started: %s", key, resp.body)
+	}
+	err = s.uploadParts(ctx, key, created.UploadID, dump, partSize)
+	if err != nil {
+		query := url.Values{"uploadId": {created.UploadID}}
+		_, _ = s.do(context.WithoutCancel(ctx), http.MethodDelete, key, query, nil)
+	}
+	return err
+}
+
+type s3CompletedUpload struct {
+	XMLName xml.Name `xml:"CompleteMultipartUpload"`
+	Parts   []s3Part `xml:"Part"`
+}
+
+type s3Part struct {
+	Number int    `xml:"PartNumber"`
+	ETag   string `xml:"ETag"`
+}
+
+func (s *S3Store) uploadParts(ctx context.Context, key, uploadID string, dump []byte, partSize int) error {
+	var completed s3CompletedUpload
+	for start, number := 0, 1; start < len(dump); start, number = start+partSize, number+1 {
+		end := min(start+partSize, len(dump))
+		query := url.Values{"partNumber": {strconv.Itoa(number)}, "uploadId": {uploadID}}
+		resp, err := s.do(ctx, http.MethodPut, key, query, dump[start:end])
+		if err != nil {
+			return err
+		}
+		completed.Parts = append(completed.Parts, s3Part{Number: number, ETag: resp.header.Get("ETag")})
+	}
+	body, err := xml.Marshal(completed)
+	if err != nil {
+		return err
+	}
+	resp, err := s.do(ctx, http.MethodPost, key, url.Values{"uploadId": {uploadID}}, body)
+	if err != nil {
+		return err
+	}
+	// S3 can report a failed completion in a 200 response.
+	if bytes.Contains(resp.body, []byte("<Error>")) {
+		return s3Error(http.MethodPost, key, resp)
+	}
+	return nil
+}
+
+type s3Response struct {
+	status int
+	header http.Header
+	body   []byte
+}
+
+// do sends a request, retrying network errors, 5xx, and 429 with exponential backoff.
+// A 404 is returned as errS3NotFound and any other failure as an error with S3's code
+// and message.
+func (s *S3Store) do(ctx context.Context, method, key string, query url.Values, body []byte) (*s3Response, error) {
+	retries := s.Retries
+	if retries == 0 {
+		retries = 3
+	}
+	for attempt := 0; ; attempt++ {
+		resp, err := s.send(ctx, method, key, query, body)
+		switch {
+		case err != nil:
+		case resp.status < 300:
+			return resp, nil
+		case resp.status == http.StatusNotFound:
+			return nil, errS3NotFound
+		default:
+			err = s3Error(method, key, resp)
+		}
+		retry := resp == nil || resp.status >= 500 || resp.status == http.StatusTooManyRequests
+		if !retry || attempt >= retries || ctx.Err() != nil {
+			return nil, err
+		}
+		select {
+		case <-ctx.Done():
+			return nil, ctx.Err()
+		case <-time.After((100 * time.Millisecond) << attempt):
+		}
+	}
+}
+
+func (s *S3Store) send(ctx context.Context, method, key string, query url.Values, body []byte) (*s3Response, error) {
+	target := strings.TrimSuffix(s.Endpoint, "/") + "/" + s3Escape(s.Bucket, false)
+	if key != "" {
+		target += "/" + s3Escape(key, true)
+	}
+	if len(query) > 0 {
+		target += "?" + s3Query(query)
+	}
+	req, err := http.NewRequestWithContext(ctx, method, target, bytes.NewReader(body))
+	if err != nil {
+		return nil, err
+	}
+	sum := sha256.Sum256(body)
+	s.sign(req, hex.EncodeToString(sum[:]), time.Now())
+	client := s.Client
+	if client == nil {
+		client = http.DefaultClient
+	}
+	resp, err := client.Do(req)
+	if err != nil {
+		return nil, err
+	}
+	defer resp.Body.Close()
+	data, err := io.ReadAll(resp.Body)
+	if err != nil {
+		return nil, err
+	}
+	return &s3Response{status: resp.StatusCode, header: resp.Header, body: data}, nil
+}
+
+func s3Error(method, key string, resp *s3Response) error {
+	var failure struct {
+		Code    string `xml:"Code"`
+		Message string `xml:"Message"`
+	}
+	if xml.Unmarshal(resp.body, &failure) != nil || failure.Code == "" {
+		return fmt.Errorf("monty: S3 %s %s: HTTP %d", method, key, resp.status)
+	}
+	return fmt.Errorf("monty: S3 %s %s: %s: %s", method, key, failure.Code, failure.Message)
+}
+
+// sign adds Signature Version 4 headers for a request whose body hashes to
+// payloadHash, made at now. The query must already be in s3Query's canonical form.
+func (s *S3Store) sign(req *http.Request, payloadHash string, now time.Time) {
+	stamp := now.UTC().Format("20060102T150405Z")
+	day := stamp[:8]
+	req.Header.Set("x-amz-date", stamp)
+	req.Header.Set("x-amz-content-sha256", payloadHash)
+	if s.SessionToken != "" {
+		req.Header.Set("x-amz-security-token", s.SessionToken)
+	}
+	headers := map[string]string{"host": req.URL.Host}
+	for name, values := range req.Header {
+		name = strings.ToLower(name)
+		if name == "range" || name == "content-type" || name == "content-md5" || strings.HasPrefix(name, "x-amz-") {
+			headers[name] = strings.TrimSpace(strings.Join(values, ","))
+		}
+	}
+	names := make([]string, 0, len(headers))
+	for name := range headers {
+		names = append(names, name)
+	}
+	sort.Strings(names)
+	var canonicalHeaders strings.Builder
+	for _, name := range names {
+		canonicalHeaders.WriteString(name + ":" + headers[name] + "\n")
+	}
+	signedHeaders := strings.Join(names, ";")
+	canonical := strings.Join([]string{
+		req.Method,
+		req.URL.EscapedPath(),
+		req.URL.RawQuery,
+		canonicalHeaders.String(),
+		signedHeaders,
+		payloadHash,
+	}, "\n")
+	scope := day + "/" + s.Region + "/s3/aws4_request"
+	digest := sha256.Sum256([]byte(canonical))
+	toSign := "AWS4-HMAC-SHA256\n" + stamp + "\n" + scope + "\n" + hex.EncodeToString(digest[:])
+	key := []byte("AWS4" + s.SecretAccessKey)
+	for _, part := range []string{day, s.Region, "s3", "aws4_request"} {
+		key = hmacSHA256(key, part)
+	}
+	signature := hex.EncodeToString(hmacSHA256(key, toSign))
+	req.Header.Set("Authorization", fmt.Sprintf(
+		"AWS4-HMAC-SHA256 Credential=%s/%s, SignedHeaders=%s, Signature=%s",
+		s.AccessKeyID, scope, signedHeaders, signature,
+	))
+}
+
+func hmacSHA256(key []byte, data string) []byte {
+	mac := hmac.New(sha256.New, key)
+	mac.Write([]byte(data))
+	return mac.Sum(nil)
+}
+
+// s3Query encodes query sorted by key, as Signature Version 4 signs it.
+func s3Query(query url.Values) string {
+	keys := make([]string, 0, len(query))
+	for key := range query {
+		keys = append(keys, key)
+	}
+	sort.Strings(keys)
+	var parts []string
+	for _, key := range keys {
+		for _, value := range query[key] {
+			parts = append(parts, s3Escape(key, false)+"="+s3Escape(value, false))
+		}
+	}
+	return strings.Join(parts, "&")
+}
+
+// s3Escape percent-encodes everything but unreserved characters, and slashes too
+// unless keepSlash is set, as Signature Version 4 expects.
+func s3Escape(s string, keepSlash bool) string {
+	var b strings.Builder
+	for i := 0; i < len(s); i++ {
+		c := s[i]
+		unreserved := 'A' <= c && c <= 'Z' || 'a' <= c && c <= 'z' || '0' <= c && c <= '9' ||
+			c == '-' || c == '_' || c == '.' || c == '~'
+		if unreserved || (c == '/' && keepSlash) {
+			b.WriteByte(c)
+		} else {
+			fmt.Fprintf(&b, "%%%02X", c)
+		}
+	}
+	return b.String()
+}
+
+func isDumpHash(hash string) bool {
+	if len(hash) != 64 {
+		return false
+	}
+	_, err := hex.DecodeString(hash)
+	return err == nil
+}
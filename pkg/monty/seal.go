@@ -0,0 +1,127 @@
+package monty
+
+/*
+#include "monty_ffi.h"
+
+int32_t montyTransform(void *userdata, uint8_t *data, size_t len, MontyWriteCallback emit, void *sink);
+
+static inline int32_t montyEmit(MontyWriteCallback emit, void *sink, const uint8_t *data, size_t len) {
+	return emit(sink, data, len);
+}
+*/
+import "C"
+
+import (
+	"crypto/aes"
+	"crypto/cipher"
+	"crypto/rand"
+	"errors"
+	"fmt"
+	"runtime/cgo"
+	"unsafe"
+)
+
+// ErrSnapshotTampered is returned when a sealed snapshot fails its Sealer's Open.
+var ErrSnapshotTampered = errors.New("monty: sealed snapshot failed authentication")
+
+// Sealer protects persisted snapshots: Seal produces the stored form (for example AEAD
+// ciphertext) and Open reverses it, failing when the data was modified.
+type Sealer interface {
+	Seal(dump []byte) ([]byte, error)
+	Open(sealed []byte) ([]byte, error)
+}
+
+// AEADSealer seals snapshots with an AEAD cipher, prefixing each dump with a random nonce.
+type AEADSealer struct {
+	AEAD cipher.AEAD
+}
+
+// NewAESGCMSealer returns an AEADSealer using AES-GCM with a 16, 24, or 32 byte key.
+func NewAESGCMSealer(key []byte) (AEADSealer, error) {
+	block, err := aes.NewCipher(key)
+	if err != nil {
+		return AEADSealer{}, err
+	}
+	aead, err := cipher.NewGCM(block)
+	if err != nil {
+		return AEADSealer{}, err
+	}
+	return AEADSealer{AEAD: aead}, nil
+}
+
+// Seal encrypts and authenticates dump.
+func (a AEADSealer) Seal(dump []byte) ([]byte, error) {
+	size := a.AEAD.NonceSize()
+	nonce := make([]byte, size, size+len(dump)+a.AEAD.Overhead())
+	if _, err := rand.Read(nonce); err != nil {
+		return nil, err
+	}
+	return a.AEAD.Seal(nonce, nonce, dump, nil), nil
+}
+
+// Open verifies and decrypts a dump produced by Seal.
+func (a AEADSealer) Open(sealed []byte) ([]byte, error) {
+	size := a.AEAD.NonceSize()
+	if len(sealed) < size {
+		return nil, errors.New("monty: sealed snapshot too short")
+	}
+	return a.AEAD.Open(nil, sealed[:size], sealed[size:], nil)
+}
+
+// transform is the Go side of a seal or open callback; err keeps the Sealer's failure.
+type transform struct {
+	fn  func([]byte) ([]byte, error)
+	err error
+}
+
+//export montyTransform
+func montyTransform(userdata unsafe.Pointer, data *C.uint8_t, length C.size_t, emit C.MontyWriteCallback, sink unsafe.Pointer) C.int32_t {
+	t := (*(*cgo.Handle)(userdata)).Value().(*transform)
+	out, err := t.fn(C.GoBytes(unsafe.Pointer(data), C.int(length)))
+	if err != nil {
+		t.err = err
+		return 1
+	}
+	return C.montyEmit(emit, sink, bytesPtr(out), C.size_t(len(out)))
+}
+
+// DumpSealed serializes the snapshot and passes the bytes through sealer.Seal.
+func (s *Snapshot) DumpSealed(sealer Sealer) ([]byte, error) {
+	if s == nil || s.handle == nil {
+		return nil, errors.New("monty: snapshot closed")
+	}
+	t := &transform{fn: sealer.Seal}
+	handle := cgo.NewHandle(t)
+	defer handle.Delete()
+	var buf *C.uint8_t
+	var length C.size_t
+	status := C.monty_snapshot_dump_sealed(s.handle, C.MontyTransformCallback(C.montyTransform), unsafe.Pointer(&handle), &buf, &length)
+	err := statusError(status)
+	if t.err != nil {
+		return nil, t.err
+	}
+	if err != nil {
+		return nil, err
+	}
+	return copyBytes(buf, length), nil
+}
+
+// SnapshotFromSealed restores a snapshot dumped with DumpSealed. Data that sealer.Open
+// rejects yields an error matching ErrSnapshotTampered.
+func SnapshotFromSealed(data []byte, sealer Sealer) (*Snapshot, error) {
+	if len(data) == 0 {
+		return nil, errors.New("monty: empty snapshot bytes")
+	}
+	t := &transform{fn: sealer.Open}
+	handle := cgo.NewHandle(t)
+	defer handle.Delete()
+	var out *C.SnapshotHandle
+	status := C.monty_snapshot_load_sealed(bytesPtr(data), C.size_t(len(data)), C.MontyTransformCallback(C.montyTransform), unsafe.Pointer(&handle), &out)
+	if err := statusError(status); err != nil {
+		if errors.Is(err, ErrSnapshotTampered) && t.err != nil {
+			return nil, fmt.Errorf("%w: %v", ErrSnapshotTampered, t.err)
+		}
+		return nil, err
+	}
+	return newSnapshot(out), nil
+}
@@ -0,0 +1,139 @@
+package monty
+
+/*
+#include "monty_ffi.h"
+*/
+import "C"
+
+import (
+	"encoding/json"
+	"errors"
+	"runtime"
+)
+
+// SnapshotStore keeps snapshot dumps by the SHA-256 hex digest of their bytes. Store
+// implements it in memory or in a directory, and S3Store (built with the monty_s3 tag)
+// in an S3-compatible bucket, so hosts can swap one backend for another.
+type SnapshotStore interface {
+	Put(snap *Snapshot) (string, error)
+	Get(hash string) (*Snapshot, error)
+	Delete(hash string) error
+	List(runID string) ([]string, error)
+}
+
+var _ SnapshotStore = (*Store)(nil)
+
+// Store keeps snapshot dumps by the SHA-256 hex digest of their bytes, so identical
+// dumps (a retried step, a run that returns to an earlier state) are stored once. It
+// is a building block for durable execution backends: persist the hash of each state
+// a workflow reaches and get the snapshot back by it.
+type Store struct {
+	handle *C.StoreHandle
+}
+
+// NewStore creates an empty in-memory store.
+func NewStore() (*Store, error) {
+	var out *C.StoreHandle
+	if err := statusError(C.monty_store_new(&out)); err != nil {
+		return nil, err
+	}
+	return newStore(out), nil
+}
+
+// OpenStoreDir opens a store kept as files under dir, creating it if needed, so
+// stored snapshots survive restarts. Dumps are written atomically, and stores in
+// several processes may share a directory.
+func OpenStoreDir(dir string) (*Store, error) {
+	cDir, freeDir := cString(dir)
+	defer freeDir()
+	var out *C.StoreHandle
+	if err := statusError(C.monty_store_open_dir(cDir, &out)); err != nil {
+		return nil, err
+	}
+	return newStore(out), nil
+}
+
+func newStore(handle *C.StoreHandle) *Store {
+	s := &Store{handle: handle}
+	runtime.SetFinalizer(s, func(s *Store) { s.Close() })
+	return s
+}
+
+// Put dumps snap into the store without consuming it and returns the dump's hash,
+// which is also listed under the snapshot's run ID (see List).
+func (s *Store) Put(snap *Snapshot) (string, error) {
+	if s == nil || s.handle == nil {
+		return "", errors.New("monty: store closed")
+	}
+	if snap == nil || snap.handle == nil {
+		return "", errors.New("monty: snapshot closed")
+	}
+	var out *C.char
+	status := C.monty_store_put(s.handle, snap.handle, &out)
+	runtime.KeepAlive(snap)
+	if err := statusError(status); err != nil {
+		return "", err
+	}
+	return takeString(out), nil
+}
+
+// Get loads a new snapshot from the dump stored under hash.
+func (s *Store) Get(hash string) (*Snapshot, error) {
+	if s == nil || s.handle == nil {
+		return nil, errors.New("monty: store closed")
+	}
+	cHash, freeHash := cString(hash)
+	defer freeHash()
+	var out *C.SnapshotHandle
+	if err := statusError(C.monty_store_get(s.handle, cHash, &out)); err != nil {
+		return nil, err
+	}
+	return newSnapshot(out), nil
+}
+
+// Delete removes the dump stored under hash; deleting one that isn't stored does
+// nothing.
+func (s *Store) Delete(hash string) error {
+	if s == nil || s.handle == nil {
+		return errors.New("monty: store closed")
+	}
+	cHash, freeHash := cString(hash)
+	defer freeHash()
+	return statusError(C.monty_store_delete(s.handle, cHash))
+}
+
+// List returns the hashes of the stored snapshots of the run runID (empty for runs
+// started without Options.RunID), in the order they were put, e.g. to find the
+// latest state of each workflow after a restart.
+func (s *Store) List(runID string) ([]string, error) {
+	if s == nil || s.handle == nil {
+		return nil, errors.New("monty: store closed")
+	}
+	cRunID, freeRunID := cString(runID)
+	defer freeRunID()
+	var out *C.char
+	if err := statusError(C.monty_store_list(s.handle, cRunID, &out)); err != nil {
+		return nil, err
+	}
+	var hashes []string
+	if err := json.Unmarshal([]byte(takeString(out)), &hashes); err != nil {
+		return nil, err
+	}
+	return hashes, nil
+}
+
+// Len reports how many distinct dumps the store holds.
+func (s *Store) Len() int {
+	if s == nil || s.handle == nil {
+		return 0
+	}
+	return int(C.monty_store_count(s.handle))
+}
+
+// Close frees the store, and the dumps in it unless it keeps them in a directory;
+// snapshots got from it stay valid.
+func (s *Store) Close() {
+	if s != nil && s.handle != nil {
+		C.monty_store_free_and_clear(&s.handle)
+	}
+}
@@ -0,0 +1,49 @@
+package monty
+
+/*
+#include "monty_ffi.h"
+
+int32_t montyWriteChunk(void *userdata, uint8_t *data, size_t len);
+*/
+import "C"
+
+import (
+	"errors"
+	"io"
+	"runtime/cgo"
+	"unsafe"
+)
+
+// streamSink is the Go side of a streamed dump; err keeps the writer's failure so it
+// can be returned instead of the generic callback status.
+type streamSink struct {
+	w   io.Writer
+	err error
+}
+
+//export montyWriteChunk
+func montyWriteChunk(userdata unsafe.Pointer, data *C.uint8_t, length C.size_t) C.int32_t {
+	sink := (*(*cgo.Handle)(userdata)).Value().(*streamSink)
+	if _, err := sink.w.Write(unsafe.Slice((*byte)(unsafe.Pointer(data)), int(length))); err != nil {
+		sink.err = err
+		return 1
+	}
+	return 0
+}
+
+// DumpTo streams the snapshot's serialized form to w in chunks instead of building it
+// in memory. The bytes are the same Dump returns.
+func (s *Snapshot) DumpTo(w io.Writer) error {
+	if s == nil || s.handle == nil {
+		return errors.New("monty: snapshot closed")
+	}
+	sink := &streamSink{w: w}
+	handle := cgo.NewHandle(sink)
+	defer handle.Delete()
+	status := C.monty_snapshot_dump_stream(s.handle, C.MontyWriteCallback(C.montyWriteChunk), unsafe.Pointer(&handle))
+	err := statusError(status)
+	if sink.err != nil {
+		return sink.err
+	}
+	return err
+}
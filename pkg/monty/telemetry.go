@@ -0,0 +1,198 @@
+package monty
+
+/*
+#include "monty_ffi.h"
+*/
+import "C"
+
+import (
+	"context"
+	"crypto/rand"
+	"encoding/hex"
+	"sync/atomic"
+)
+
+// Tracer creates spans for runs, external and OS calls, and resumes. Its shape follows
+// OpenTelemetry's trace.Tracer so an adapter is a few lines (see the README); the
+// package itself has no telemetry dependency. Install one with SetTracer.
+type Tracer interface {
+	Start(ctx context.Context, name string, attrs ...Attribute) (context.Context, Span)
+}
+
+// Span is a span started by a Tracer. End records err (nil on success) as its status.
+type Span interface {
+	End(err error)
+}
+
+// Attribute is a key/value pair attached to a span.
+type Attribute struct {
+	Key   string
+	Value any
+}
+
+// Span names and attribute keys used by the instrumentation.
+const (
+	SpanRun        = "monty.run"
+	SpanCall       = "monty.call"
+	SpanResume     = "monty.resume"
+	AttrRunID      = "monty.run_id"
+	AttrCallID     = "monty.call_id"
+	AttrFunction   = "monty.function"
+	AttrOsFunction = "monty.os_function"
+)
+
+type tracerHolder struct{ tracer Tracer }
+
+var installedTracer atomic.Value
+
+// SetTracer installs the Tracer used for every run started or resumed from then on;
+// nil turns instrumentation off again. Without a tracer no spans are created.
+//
+// A run gets a "monty.run" span from StartWithOptions (a child of Options.Context)
+// until it completes or fails, a "monty.resume" span around each VM step, and a
+// "monty.call" span for each external or OS call from when it is reported until the
+// host resumes it. Every span carries the run id; a snapshot loaded from a dump
+// continues under a new run span with the same id.
+func SetTracer(t Tracer) {
+	installedTracer.Store(tracerHolder{t})
+}
+
+func currentTracer() Tracer {
+	holder, _ := installedTracer.Load().(tracerHolder)
+	return holder.tracer
+}
+
+// runTrace follows one run through its snapshots while a Tracer is installed.
+type runTrace struct {
+	tracer Tracer
+	ctx    context.Context
+	runID  string
+	run    Span
+	call   Span
+}
+
+func newRunID() string {
+	var id [16]byte
+	if _, err := rand.Read(id[:]); err != nil {
+		return ""
+	}
+	return hex.EncodeToString(id[:])
+}
+
+func startRunTrace(ctx context.Context, runID string) *runTrace {
+	tracer := currentTracer()
+	if tracer == nil {
+		return nil
+	}
+	if ctx == nil {
+		ctx = context.Background()
+	}
+	t := &runTrace{tracer: tracer, runID: runID}
+	t.ctx, t.run = tracer.Start(ctx, SpanRun, t.attrs()...)
+	return t
+}
+
+func (t *runTrace) attrs(extra ...Attribute) []Attribute {
+	return append([]Attribute{{Key: AttrRunID, Value: t.runID}}, extra...)
+}
+
+// step runs one VM step in a "monty.resume" span, ending the call span it resolves
+// and handing the trace on to whatever the step produced.
+func (t *runTrace) step(run func() (Progress, error), attrs ...Attribute) (Progress, error) {
+	if t == nil {
+		return run()
+	}
+	if t.call != nil {
+		t.call.End(nil)
+		t.call = nil
+	}
+	_, span := t.tracer.Start(t.ctx, SpanResume, t.attrs(attrs...)...)
+	progress, err := run()
+	span.End(err)
+	t.follow(progress, err)
+	return progress, err
+}
+
+func (t *runTrace) follow(progress Progress, err error) {
+	switch {
+	case err != nil || progress.Kind == Complete:
+		t.end(err)
+	case progress.Snapshot != nil:
+		function := Attribute{Key: AttrFunction, Value: progress.FunctionName}
+		if progress.Kind == OsCall {
+			function = Attribute{Key: AttrOsFunction, Value: progress.OsFunction}
+		}
+		_, t.call = t.tracer.Start(t.ctx, SpanCall, t.attrs(callAttr(progress.CallID), function)...)
+		progress.Snapshot.trace = t
+	case progress.FutureSnapshot != nil:
+		progress.FutureSnapshot.trace = t
+	case progress.InputRequest != nil:
+		progress.InputRequest.trace = t
+	}
+}
+
+// end closes the open spans, for a run that finished or left this process.
+func (t *runTrace) end(err error) {
+	if t == nil {
+		return
+	}
+	if t.call != nil {
+		t.call.End(nil)
+		t.call = nil
+	}
+	if t.run != nil {
+		t.run.End(err)
+		t.run = nil
+	}
+}
+
+// tracing returns the snapshot's trace, starting one for a snapshot loaded from a
+// dump when a Tracer has been installed since.
+func (s *Snapshot) tracing() *runTrace {
+	if s.trace == nil && s.handle != nil && currentTracer() != nil {
+		s.trace = startRunTrace(nil, s.RunID())
+	}
+	return s.trace
+}
+
+func (fs *FutureSnapshot) tracing() *runTrace {
+	if fs.trace == nil && fs.handle != nil && currentTracer() != nil {
+		fs.trace = startRunTrace(nil, fs.RunID())
+	}
+	return fs.trace
+}
+
+// RunID reports the id the run was started with (Options.RunID, or one generated
+// while a Tracer was installed); empty when it had none.
+func (s *Snapshot) RunID() string {
+	if s == nil || s.handle == nil {
+		return ""
+	}
+	var out *C.char
+	if statusError(C.monty_snapshot_run_id(s.handle, &out)) != nil {
+		return ""
+	}
+	return takeString(out)
+}
+
+// RunID reports the id the run was started with (Options.RunID, or one generated
+// while a Tracer was installed); empty when it had none.
+func (fs *FutureSnapshot) RunID() string {
+	if fs == nil || fs.handle == nil {
+		return ""
+	}
+	var out *C.char
+	if statusError(C.monty_future_snapshot_run_id(fs.handle, &out)) != nil {
+		return ""
+	}
+	return takeString(out)
+}
+
+func callAttr(callID uint32) Attribute {
+	return Attribute{Key: AttrCallID, Value: callID}
+}
+
+func takeString(raw *C.char) string {
+	defer C.monty_free_string(raw)
+	return C.GoString(raw)
+}
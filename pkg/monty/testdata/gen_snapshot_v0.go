@@ -0,0 +1,39 @@
+//go:build ignore
+
+// Writes the snapshot-v0.json fixture: a snapshot dumped by the last release before
+// dumps were versioned, with the id of the call it is suspended on. It only builds
+// against that release; `make testdata` checks it out and runs this there.
+package main
+
+import (
+	"encoding/json"
+	"log"
+	"os"
+
+	"github.com/ricochet1k/monty-go/pkg/monty"
+)
+
+func main() {
+	if len(os.Args) != 2 {
+		log.Fatal("usage: go run gen_snapshot_v0.go <output>")
+	}
+	m, err := monty.New("fetch(x) + 1", "fixture.py", []string{"x"}, []string{"fetch"})
+	if err != nil {
+		log.Fatal(err)
+	}
+	progress, err := m.Start(7)
+	if err != nil {
+		log.Fatal(err)
+	}
+	dump, err := progress.Snapshot.Dump()
+	if err != nil {
+		log.Fatal(err)
+	}
+	data, err := json.MarshalIndent(map[string]any{"call_id": progress.CallID, "dump": dump}, "", "  ")
+	if err != nil {
+		log.Fatal(err)
+	}
+	if err := os.WriteFile(os.Args[1], append(data, '\n'), 0o644); err != nil {
+		log.Fatal(err)
+	}
+}